@@ -0,0 +1,43 @@
+use chrono::DateTime;
+
+// Version details for bug reports, filled in by build.rs
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_HASH: &str = env!("GIT_HASH");
+pub const BUILD_TARGET: &str = env!("BUILD_TARGET");
+
+pub fn build_time() -> String {
+    env!("BUILD_TIMESTAMP")
+        .parse()
+        .ok()
+        .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+        .map_or_else(|| "unknown".to_string(), |time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+}
+
+// One line for the log, e.g. "Schrijver 0.1.0 (4aff5e3)"
+pub fn version_line() -> String {
+    format!("Schrijver {} ({})", VERSION, GIT_HASH)
+}
+
+// What the About dialog shows, in a form that can be pasted into a bug report
+pub fn about_text() -> String {
+    format!(
+        "Disk Image to USB Writer\n\nVersion: {}\nCommit: {}\nBuilt: {}\nTarget: {}",
+        VERSION,
+        GIT_HASH,
+        build_time(),
+        BUILD_TARGET,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_about_text() {
+        let about = about_text();
+        assert!(about.contains(&format!("Version: {}", env!("CARGO_PKG_VERSION"))));
+        assert!(about.contains(&format!("Commit: {}", GIT_HASH)));
+        assert!(about.contains(&format!("Target: {}", BUILD_TARGET)));
+    }
+}
@@ -0,0 +1,159 @@
+use sha2::Digest;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl ChecksumAlgorithm {
+    // Checksum files rarely say which algorithm they use, but the digest
+    // length gives it away
+    fn from_hex_len(len: usize) -> Option<Self> {
+        match len {
+            32 => Some(ChecksumAlgorithm::Md5),
+            40 => Some(ChecksumAlgorithm::Sha1),
+            64 => Some(ChecksumAlgorithm::Sha256),
+            128 => Some(ChecksumAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    pub fn hasher(&self) -> Box<dyn Hasher> {
+        match self {
+            ChecksumAlgorithm::Md5 => Box::new(md5::Md5::new()),
+            ChecksumAlgorithm::Sha1 => Box::new(sha1::Sha1::new()),
+            ChecksumAlgorithm::Sha256 => Box::new(sha2::Sha256::new()),
+            ChecksumAlgorithm::Sha512 => Box::new(sha2::Sha512::new()),
+        }
+    }
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChecksumAlgorithm::Md5 => "md5",
+            ChecksumAlgorithm::Sha1 => "sha1",
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Sha512 => "sha512",
+        })
+    }
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().replace('-', "").as_str() {
+            "md5" => Ok(ChecksumAlgorithm::Md5),
+            "sha1" => Ok(ChecksumAlgorithm::Sha1),
+            "sha256" => Ok(ChecksumAlgorithm::Sha256),
+            "sha512" => Ok(ChecksumAlgorithm::Sha512),
+            _ => Err(format!("unsupported checksum algorithm '{}'", value)),
+        }
+    }
+}
+
+// Object-safe wrapper so the algorithm can be picked at runtime
+pub trait Hasher: Send {
+    fn update(&mut self, data: &[u8]);
+    fn finalize_hex(self: Box<Self>) -> String;
+}
+
+impl<D: Digest + Send> Hasher for D {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data);
+    }
+
+    fn finalize_hex(self: Box<Self>) -> String {
+        self.finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpectedChecksum {
+    pub algorithm: ChecksumAlgorithm,
+    pub hex: String,
+}
+
+impl fmt::Display for ExpectedChecksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm, self.hex)
+    }
+}
+
+fn is_hex(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// Accepts "<algo>:<hex>", a bare hex digest, or the contents of a checksum
+// file such as SHA256SUMS, in which case the entry for `file_name` is used
+pub fn parse_checksum(input: &str, file_name: &str) -> Result<ExpectedChecksum, String> {
+    let input = input.trim();
+
+    if let Some((algorithm, hex)) = input.split_once(':') {
+        if is_hex(hex.trim()) && !hex.contains(char::is_whitespace) {
+            return Ok(ExpectedChecksum {
+                algorithm: algorithm.trim().parse()?,
+                hex: hex.trim().to_lowercase(),
+            });
+        }
+    }
+
+    let hex = if is_hex(input) {
+        input.to_string()
+    } else {
+        find_file_checksum(input, file_name)
+            .ok_or_else(|| format!("no checksum for '{}' found", file_name))?
+    };
+
+    let algorithm = ChecksumAlgorithm::from_hex_len(hex.len())
+        .ok_or_else(|| format!("'{}' is not a md5, sha1, sha256 or sha512 digest", hex))?;
+
+    Ok(ExpectedChecksum { algorithm, hex: hex.to_lowercase() })
+}
+
+// Checksum files list "<hex>  <name>" (or "<hex> *<name>" for binary mode).
+// Matching on whitespace-separated tokens also copes with text fields that
+// flatten newlines when pasting.
+fn find_file_checksum(contents: &str, file_name: &str) -> Option<String> {
+    let tokens: Vec<&str> = contents.split_whitespace().collect();
+
+    tokens.windows(2).find_map(|pair| {
+        let name = pair[1].trim_start_matches('*').trim_start_matches("./");
+        (name == file_name && is_hex(pair[0])).then(|| pair[0].to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_checksum() {
+        let md5 = "900150983cd24fb0d6963f7d28e17f72";
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+        let mut hasher = ChecksumAlgorithm::Md5.hasher();
+        hasher.update(b"abc");
+        assert_eq!(hasher.finalize_hex(), md5);
+
+        assert_eq!(
+            parse_checksum(&format!("MD5:{}", md5.to_uppercase()), "x.iso").unwrap(),
+            ExpectedChecksum { algorithm: ChecksumAlgorithm::Md5, hex: md5.to_string() }
+        );
+        assert_eq!(parse_checksum(sha256, "x.iso").unwrap().algorithm, ChecksumAlgorithm::Sha256);
+
+        let sums = format!("{}  debian-12.iso\n{} *ubuntu-24.04.iso\n", "0".repeat(64), sha256);
+        assert_eq!(parse_checksum(&sums, "ubuntu-24.04.iso").unwrap().hex, sha256);
+        assert!(parse_checksum(&sums, "fedora.iso").is_err());
+        assert!(parse_checksum("crc32:1234abcd", "x.iso").is_err());
+    }
+}
@@ -0,0 +1,460 @@
+use clap::{ArgGroup, Parser};
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::settings::UserSettings;
+use schrijver::checksum::{parse_checksum, ExpectedChecksum};
+use schrijver::device::{platform_backend, validate_device_for_writing, UsbDevice};
+use schrijver::error::WriterError;
+use schrijver::persistence::{PersistenceLayout, PersistenceOptions};
+use schrijver::resume;
+use schrijver::writer::{check_source_not_on_device, retry_while_busy, validate_buffer_size, write_iso_to_device, CancelToken, PauseToken, Phase, IoMode, ProgressTrigger, VerifyMode, WriteOptions, WriteProgress, WriteSummary};
+
+#[derive(Parser, Debug)]
+#[command(version = concat!(env!("CARGO_PKG_VERSION"), " (", env!("GIT_HASH"), ")"), about = "Write disk images to USB devices")]
+#[command(group(ArgGroup::new("target").args(["device", "output_image"])))]
+pub struct Cli {
+    /// Disk image to write: .iso, .img, .raw, .bin, .dmg or .vhd, optionally .gz/.xz/.zst
+    /// compressed (runs headless, without the GUI)
+    #[arg(long, requires = "target")]
+    pub iso: Option<PathBuf>,
+
+    /// Target device, e.g. /dev/sdb
+    #[arg(long, requires = "iso")]
+    pub device: Option<String>,
+
+    /// Write to this file instead of a device, e.g. a disk for a VM. It is
+    /// created, or truncated, to --size first.
+    #[arg(long, requires_all = ["iso", "size"])]
+    pub output_image: Option<PathBuf>,
+
+    /// Size of the --output-image file, e.g. 8G
+    #[arg(long, requires = "output_image", value_parser = parse_size)]
+    pub size: Option<usize>,
+
+    /// How to check the device: none, quick or full after writing, or inline while writing
+    #[arg(long, requires = "iso", default_value = "full")]
+    pub verify: VerifyMode,
+
+    /// Skip verifying the device contents after writing (same as --verify=none)
+    #[arg(long, requires = "iso", conflicts_with = "verify")]
+    pub no_verify: bool,
+
+    /// Verify against per-block hashes taken while writing instead of
+    /// reading the image again
+    #[arg(long, requires = "iso", conflicts_with = "no_verify")]
+    pub verify_hashes: bool,
+
+    /// How to open the device: buffered, direct (O_DIRECT, Linux only) or
+    /// sync (O_SYNC). Each ends with an fsync.
+    #[arg(long, requires = "iso", default_value = "buffered")]
+    pub io_mode: IoMode,
+
+    /// Don't ask for confirmation before erasing the device
+    #[arg(long, requires = "iso")]
+    pub yes: bool,
+
+    /// Size of each write, e.g. 512K, 4M (defaults to the saved GUI setting)
+    #[arg(long, requires = "iso", value_parser = parse_size)]
+    pub block_size: Option<usize>,
+
+    /// Validate everything and read the whole image, but don't write to the device
+    #[arg(long, requires = "iso")]
+    pub dry_run: bool,
+
+    /// Cap the write speed in bytes per second, e.g. 20M
+    #[arg(long, requires = "iso", value_parser = parse_size)]
+    pub limit_rate: Option<usize>,
+
+    /// Save the progress every 32 MB so an interrupted write can be resumed,
+    /// and resume one of the same image to the same device
+    #[arg(long, requires = "iso", conflicts_with = "dry_run")]
+    pub resumable: bool,
+
+    /// Add an ext4 partition for a live system's changes after the image,
+    /// e.g. 4G, taking the rest of the device without a size (Linux only)
+    #[arg(long, requires = "iso", conflicts_with_all = ["dry_run", "output_image"], num_args = 0..=1, value_parser = parse_size)]
+    pub persistence: Option<Option<usize>>,
+
+    /// Which live system the persistence partition is for: ubuntu (casper-rw)
+    /// or debian (persistence)
+    #[arg(long, requires = "persistence", default_value = "ubuntu")]
+    pub persistence_layout: PersistenceLayout,
+
+    /// Sync to the device every 32 MB so progress shows what the device has
+    /// actually stored, rather than what the page cache has accepted
+    #[arg(long, requires = "iso")]
+    pub accurate_progress: bool,
+
+    /// How long to keep retrying a device that is still busy, e.g. right
+    /// after it was unmounted, in milliseconds (0 fails straight away)
+    #[arg(long, requires = "iso", default_value_t = 2000)]
+    pub busy_timeout_ms: u64,
+
+    /// Minimum time between progress updates, in milliseconds
+    #[arg(long, requires = "iso")]
+    pub progress_interval_ms: Option<u64>,
+
+    /// Report progress each time it passes a multiple of this many percent
+    /// (5 unless --progress-interval-ms is given)
+    #[arg(long, requires = "iso")]
+    pub progress_percent: Option<f32>,
+
+    /// Write the image this far into the device, e.g. 1M (must be a multiple of 512)
+    #[arg(long, requires = "iso", value_parser = parse_size)]
+    pub offset: Option<usize>,
+
+    /// List the detected devices and exit, without the GUI
+    #[arg(long, conflicts_with_all = ["iso", "select_iso", "select_device"])]
+    pub list_devices: bool,
+
+    /// Print the --list-devices output as JSON
+    #[arg(long, requires = "list_devices")]
+    pub json: bool,
+
+    /// Start the GUI with this image selected (used when relaunching as root)
+    #[arg(long, conflicts_with = "iso")]
+    pub select_iso: Option<PathBuf>,
+
+    /// Start the GUI with this device selected, once it is detected
+    #[arg(long, conflicts_with = "iso")]
+    pub select_device: Option<String>,
+
+    /// Write the image even if it has no ISO 9660 or boot signature
+    #[arg(long, requires = "iso")]
+    pub allow_raw: bool,
+
+    /// Write even if the device reports being smaller than the image, for
+    /// misdetected devices and sparse files
+    #[arg(long, requires = "iso")]
+    pub force: bool,
+
+    /// Expected checksum of the image as <algo>:<hex> (md5, sha1, sha256, sha512),
+    /// or the path of a checksum file such as SHA256SUMS
+    #[arg(long, requires = "iso")]
+    pub checksum: Option<String>,
+}
+
+// Parse a byte count with an optional binary K/M/G suffix, e.g. "4M"
+pub(crate) fn parse_size(value: &str) -> Result<usize, String> {
+    let value = value.trim();
+    let (digits, multiplier) = match value.char_indices().last() {
+        Some((index, 'K' | 'k')) => (&value[..index], 1024),
+        Some((index, 'M' | 'm')) => (&value[..index], 1024 * 1024),
+        Some((index, 'G' | 'g')) => (&value[..index], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size '{}'", value))
+}
+
+// Percentages by default, so a log gets the same tidy 5% steps on any device
+fn progress_trigger(interval_ms: Option<u64>, percent: Option<f32>) -> ProgressTrigger {
+    match (interval_ms.map(Duration::from_millis), percent) {
+        (Some(interval), Some(percent)) => ProgressTrigger::Both(interval, percent),
+        (Some(interval), None) => ProgressTrigger::EveryDuration(interval),
+        (None, percent) => ProgressTrigger::EveryPercent(percent.unwrap_or(5.0)),
+    }
+}
+
+impl Cli {
+    pub fn is_headless(&self) -> bool {
+        self.iso.is_some() || self.list_devices
+    }
+}
+
+pub fn run(args: Cli) -> ExitCode {
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(error) => {
+            eprintln!("Error: failed to start runtime: {}", error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if args.list_devices {
+        return list_devices(&runtime, args.json);
+    }
+
+    let device_path = args.device
+        .clone()
+        .or_else(|| args.output_image.as_ref().map(|path| path.to_string_lossy().to_string()))
+        .unwrap_or_default();
+
+    match runtime.block_on(run_write(args)) {
+        Ok(summary) if summary.dry_run => {
+            eprintln!("Dry run OK: would write {} bytes to {}", summary.bytes_written, device_path);
+            ExitCode::SUCCESS
+        }
+        Ok(summary) => {
+            eprintln!("Done ({}). sha256 {}", summary.verify_mode, summary.digest);
+            eprintln!("Wrote {:.1} MB in {:.1} s ({:.1} MB/s average)",
+                      summary.bytes_written as f64 / (1024.0 * 1024.0),
+                      summary.duration.as_secs_f64(),
+                      summary.average_speed_mbps);
+            if let Some(node) = summary.persistence_partition {
+                eprintln!("Persistence partition: {}", node);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("Error: {}", error.user_friendly_message());
+            ExitCode::from(error.exit_code())
+        }
+    }
+}
+
+async fn run_write(args: Cli) -> Result<WriteSummary, WriterError> {
+    let Some(iso_path) = args.iso else {
+        return Err(WriterError::Unknown("--iso is required".to_string()));
+    };
+
+    let backend = platform_backend();
+    let device = if let Some(output_image) = &args.output_image {
+        UsbDevice::image_file(output_image.to_string_lossy(), args.size.unwrap_or(0) as u64)
+    } else {
+        let Some(device_path) = args.device else {
+            return Err(WriterError::Unknown("either --device or --output-image is required".to_string()));
+        };
+
+        // Prefer the detected device so the prompt shows real details
+        let detected = backend.detect().await.unwrap_or_else(|error| {
+            tracing::warn!("{}", error);
+            Vec::new()
+        });
+        detected
+            .into_iter()
+            .find(|d| d.device_path == device_path)
+            .unwrap_or_else(|| UsbDevice {
+                name: device_path.clone(),
+                device_path: device_path.clone(),
+                size: 0,
+                vendor: "Unknown".to_string(),
+                model: "Device".to_string(),
+                serial: String::new(),
+                labels: Vec::new(),
+                partitions: Vec::new(),
+                is_removable: false,
+                is_image_file: false,
+            })
+    };
+
+    let busy_timeout = Duration::from_millis(args.busy_timeout_ms);
+    let report_waiting = |_| eprintln!("The device is still busy, retrying...");
+
+    // Before the mount check, which would only ask to unmount the image's disk
+    check_source_not_on_device(&iso_path, &device.device_path).await?;
+
+    // Validating an image file creates or truncates it, so that waits for the
+    // confirmation
+    if !device.is_image_file {
+        retry_while_busy(busy_timeout, report_waiting, || validate_device_for_writing(&*backend, &device)).await?;
+    }
+
+    // Only informs, a drive that reports failing can still be written
+    if !device.is_image_file {
+        if let Some(warning) = schrijver::health::device_health(&device.device_path).await.and_then(|health| health.warning()) {
+            eprintln!("WARNING: {}", warning);
+        }
+    }
+
+    // A dry run never touches the device, so there is nothing to confirm
+    let large_disk = UserSettings::load().is_large_disk(&device);
+    if !args.yes && !args.dry_run && !confirm(&iso_path, &device, large_disk)? {
+        return Err(WriterError::Cancelled);
+    }
+
+    if device.is_image_file {
+        validate_device_for_writing(&*backend, &device).await?;
+    }
+
+    // The saved setting is already validated, and may be Auto
+    let buffer_size = match args.block_size {
+        Some(block_size) => validate_buffer_size(block_size)?,
+        None => UserSettings::load().buffer_size,
+    };
+
+    let checksum = args.checksum
+        .as_deref()
+        .map(|value| expected_checksum(value, &iso_path))
+        .transpose()?;
+
+    // Devices are recognized by their serial number, so one without can't be resumed
+    let start_offset = args.offset.unwrap_or(0) as u64;
+    let resume_device = match args.resumable {
+        true => resume::device_identity(&device.device_path, &device.serial),
+        false => None,
+    };
+    if args.resumable && resume_device.is_none() {
+        eprintln!("WARNING: {} has no serial number to recognize it by, so the write can't be resumed", device.device_path);
+    }
+    let resume_from = resume_device.as_deref().and_then(|identity| resume::find_interrupted(&iso_path, identity, start_offset));
+    if let Some(state) = &resume_from {
+        eprintln!("Found an interrupted write, resuming after its first {:.1} MB once they check out", state.committed as f64 / (1024.0 * 1024.0));
+    }
+
+    let options = WriteOptions {
+        buffer_size,
+        io_mode: args.io_mode,
+        verify_mode: if args.no_verify { VerifyMode::None } else { args.verify },
+        block_hashes: args.verify_hashes,
+        dry_run: args.dry_run,
+        allow_raw_image: args.allow_raw,
+        ignore_size_check: args.force,
+        checksum,
+        max_speed_mbps: args.limit_rate.map(|rate| rate as f64 / (1024.0 * 1024.0)),
+        accurate_progress: args.accurate_progress,
+        progress_trigger: progress_trigger(args.progress_interval_ms, args.progress_percent),
+        start_offset,
+        busy_timeout,
+        resume_device,
+        resume_from,
+        persistence: args.persistence.map(|size| PersistenceOptions {
+            layout: args.persistence_layout,
+            size: size.map(|size| size as u64),
+        }),
+        ..WriteOptions::default()
+    };
+
+    let (progress_sender, mut progress_receiver) = mpsc::unbounded_channel::<WriteProgress>();
+    let reporter = tokio::spawn(async move {
+        let mut phase = Phase::Writing;
+        while let Some(progress) = progress_receiver.recv().await {
+            if progress.phase == Phase::Waiting {
+                eprintln!("The device is still busy, retrying...");
+                continue;
+            }
+            if let Phase::Validating(step) = progress.phase {
+                eprintln!("{}...", step);
+                continue;
+            }
+            if progress.phase == Phase::Persistence {
+                eprintln!();
+                eprintln!("{}...", progress.phase);
+                continue;
+            }
+
+            // Keep the finished writing line and start a new one for verifying
+            if progress.phase != phase {
+                eprintln!();
+                phase = progress.phase;
+            }
+
+            let written_mb = progress.bytes_written as f64 / (1024.0 * 1024.0);
+            match progress.progress_percent {
+                Some(percent) => eprint!("\r{}: {:5.1}% ({:.1} MB, {:.1} MB/s, {:.1} MB/s average)   ", phase, percent, written_mb, progress.instant_speed_mbps, progress.speed_mbps),
+                None => eprint!("\r{}: {:.1} MB ({:.1} MB/s, {:.1} MB/s average)   ", phase, written_mb, progress.instant_speed_mbps, progress.speed_mbps),
+            }
+        }
+        eprintln!();
+    });
+
+    let result = write_iso_to_device(&iso_path, &device.device_path, options, CancelToken::new(), PauseToken::new(), progress_sender).await;
+    let _ = reporter.await;
+
+    result
+}
+
+// Print the detected devices to stdout, for scripts to pick a target from
+fn list_devices(runtime: &tokio::runtime::Runtime, json: bool) -> ExitCode {
+    let devices = match runtime.block_on(platform_backend().detect()) {
+        Ok(devices) => devices,
+        Err(error) => {
+            eprintln!("Error: {}", error.user_friendly_message());
+            return ExitCode::from(error.exit_code());
+        }
+    };
+
+    if !json {
+        print!("{}", device_table(&devices));
+        return ExitCode::SUCCESS;
+    }
+    match serde_json::to_string_pretty(&devices) {
+        Ok(output) => {
+            println!("{}", output);
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// One line per device under a header, in columns wide enough for the longest
+// value. Sizes are in bytes, like the JSON, so the table parses the same way.
+pub(crate) fn device_table(devices: &[UsbDevice]) -> String {
+    let or_dash = |value: &str| if value.is_empty() { "-".to_string() } else { value.to_string() };
+    let mut rows = vec![["PATH", "SIZE", "VENDOR", "MODEL", "SERIAL", "REMOVABLE", "NAME"].map(String::from)];
+    rows.extend(devices.iter().map(|device| {
+        [
+            device.device_path.clone(),
+            device.size.to_string(),
+            or_dash(&device.vendor),
+            or_dash(&device.model),
+            or_dash(&device.serial),
+            if device.is_removable { "yes" } else { "no" }.to_string(),
+            device.name.clone(),
+        ]
+    }));
+
+    let mut widths = [0; 7];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    rows.iter()
+        .map(|row| {
+            let cells: Vec<String> = row.iter().zip(widths).map(|(cell, width)| format!("{:width$}", cell)).collect();
+            format!("{}\n", cells.join("  ").trim_end())
+        })
+        .collect()
+}
+
+// `value` is either "<algo>:<hex>" or a checksum file listing the image
+fn expected_checksum(value: &str, iso_path: &std::path::Path) -> Result<ExpectedChecksum, WriterError> {
+    let file_name = schrijver::download::file_path(iso_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let input = if std::path::Path::new(value).is_file() {
+        std::fs::read_to_string(value).map_err(WriterError::from_io)?
+    } else {
+        value.to_string()
+    };
+
+    parse_checksum(&input, &file_name).map_err(WriterError::InvalidChecksum)
+}
+
+fn confirm(iso_path: &std::path::Path, device: &UsbDevice, large_disk: bool) -> Result<bool, WriterError> {
+    eprintln!("About to write {} to:", iso_path.display());
+    eprintln!("  {}", device);
+    eprintln!("ALL DATA ON THIS DEVICE WILL BE ERASED.");
+    if large_disk {
+        eprint!("This is a large disk. Type its size ({}) or name to continue (or pass --yes): ", device.human_size());
+    } else {
+        eprint!("Type 'yes' to continue (or pass --yes): ");
+    }
+    io::stderr().flush().map_err(WriterError::from_io)?;
+
+    let mut answer = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(WriterError::from_io)?;
+
+    if large_disk {
+        return Ok(device.is_confirmed_by(&answer));
+    }
+    Ok(answer.trim().eq_ignore_ascii_case("yes"))
+}
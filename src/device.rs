@@ -1,112 +1,770 @@
 use std::fmt;
-use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use crate::error::WriterError;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
 pub struct UsbDevice {
     pub name: String,
     pub device_path: String,
     pub size: u64,
     pub vendor: String,
     pub model: String,
+    // Empty when the device or platform doesn't report one
+    pub serial: String,
+    // Filesystem labels of the partitions currently on the device
+    pub labels: Vec<String>,
+    // What the device currently holds, to warn before erasing a data drive
+    pub partitions: Vec<PartitionInfo>,
     pub is_removable: bool,
+    // A regular file written like a device, e.g. a disk image for a VM
+    pub is_image_file: bool,
+}
+
+// A partition found on a device (or the whole device, when it has a
+// filesystem but no partition table)
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct PartitionInfo {
+    pub device_path: String,
+    pub size: u64,
+    // None when no filesystem was recognized, or the partition couldn't be read
+    pub fstype: Option<String>,
+    pub label: Option<String>,
+}
+
+// Small cards read better in MB and big enclosures in TB
+pub fn human_size(bytes: u64) -> String {
+    let mb = bytes as f64 / (1024.0 * 1024.0);
+    if mb < 1024.0 {
+        format!("{:.0} MB", mb)
+    } else if mb < 1024.0 * 1024.0 {
+        format!("{:.1} GB", mb / 1024.0)
+    } else {
+        format!("{:.2} TB", mb / (1024.0 * 1024.0))
+    }
+}
+
+impl UsbDevice {
+    // A target file of `size` bytes, created or truncated to that size by
+    // validate_device_for_writing. With a size of 0 it grows as it is written.
+    pub fn image_file(path: impl Into<String>, size: u64) -> Self {
+        let device_path = path.into();
+        let file_name = std::path::Path::new(&device_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| device_path.clone());
+        UsbDevice {
+            name: format!("Image file {}", file_name),
+            device_path,
+            size,
+            vendor: String::new(),
+            model: "Image file".to_string(),
+            serial: String::new(),
+            labels: Vec::new(),
+            partitions: Vec::new(),
+            is_removable: false,
+            is_image_file: true,
+        }
+    }
+
+    pub fn human_size(&self) -> String {
+        human_size(self.size)
+    }
+
+    // Filesystems that look like someone's data, e.g. "ext4 'backups'". A
+    // device with an ISO 9660 partition holds a written installer image, whose
+    // EFI partition isn't worth a warning.
+    pub fn existing_data(&self) -> Vec<String> {
+        if self.partitions.iter().any(|partition| partition.fstype.as_deref() == Some("iso9660")) {
+            return Vec::new();
+        }
+
+        self.partitions
+            .iter()
+            .filter_map(|partition| {
+                let fstype = partition.fstype.as_deref()?;
+                Some(match &partition.label {
+                    Some(label) => format!("{} '{}'", fstype, label),
+                    None => fstype.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    // Whether `typed` spells out the device's size (as shown, e.g. "119.5 GB")
+    // or its name, which big drives need before they are erased
+    pub fn is_confirmed_by(&self, typed: &str) -> bool {
+        let typed = typed.trim();
+        !typed.is_empty() && (typed.eq_ignore_ascii_case(&self.human_size()) || typed.eq_ignore_ascii_case(self.name.trim()))
+    }
+
+    // The same physical device, even under another path after a replug:
+    // /dev/sdX names are handed out again, so only a serial number can tell.
+    // Devices without one have to keep their path as well.
+    pub fn is_same_device(&self, other: &UsbDevice) -> bool {
+        let same_details = self.name == other.name
+            && self.vendor == other.vendor
+            && self.model == other.model
+            && self.size == other.size
+            && self.serial == other.serial;
+        same_details && (!self.serial.is_empty() || self.device_path == other.device_path)
+    }
+
+    // Last few characters of the serial, enough to tell identical sticks apart
+    pub fn short_serial(&self) -> Option<String> {
+        if self.serial.is_empty() {
+            return None;
+        }
+        let chars: Vec<char> = self.serial.chars().collect();
+        Some(chars[chars.len().saturating_sub(6)..].iter().collect())
+    }
 }
 
 impl fmt::Display for UsbDevice {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} ({:.1} GB) - {}",
-               self.name,
-               self.size as f64 / (1024.0 * 1024.0 * 1024.0),
-               self.device_path
-        )
+        write!(f, "{}", self.name)?;
+
+        if !self.labels.is_empty() {
+            write!(f, " [{}]", self.labels.join(", "))?;
+        }
+
+        match self.short_serial() {
+            Some(serial) => write!(f, " (…{}, {})", serial, self.human_size())?,
+            None => write!(f, " ({})", self.human_size())?,
+        }
+
+        write!(f, " - {}", self.device_path)
     }
 }
 
-pub async fn detect_usb_devices() -> Vec<UsbDevice> {
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// The platform-specific device queries, behind a trait so tests can inject
+// fake device lists and mount states instead of needing root and real sticks
+pub trait DeviceBackend: fmt::Debug + Send + Sync {
+    // Fails only when the platform's device list can't be read at all, so an
+    // empty list really means no device is plugged in
+    fn detect(&self) -> BoxFuture<'_, Result<Vec<UsbDevice>, WriterError>>;
+    fn exists(&self, device_path: &str) -> bool;
+    fn is_mounted(&self, device_path: &str) -> bool;
+    // Some SD-card-booted systems report their boot disk as removable, so this
+    // checks where its partitions are mounted rather than trusting that flag
+    fn is_system_disk(&self, device_path: &str) -> bool;
+    fn size(&self, device_path: &str) -> io::Result<u64>;
+    // Partitions of a detected disk, for writing to one instead of the whole
+    // disk. Backends that can't list them offer the whole disk only.
+    fn partitions(&self, _device: &UsbDevice) -> Vec<UsbDevice> {
+        Vec::new()
+    }
+    // The device's preferred I/O granularity, for picking a buffer size
+    fn io_sizes(&self, _device_path: &str) -> io::Result<IoSizes> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "I/O size hints not supported on this platform"))
+    }
+    // Write-protected, e.g. by the lock switch of an SD card. Backends that
+    // can't tell leave it to the write to fail.
+    fn is_read_only(&self, _device_path: &str) -> bool {
+        false
+    }
+    // Whether detect() can see devices here at all, so an empty list can be
+    // told apart from detection that can't work, e.g. in a container
+    fn diagnostics(&self) -> DetectionDiagnostics {
+        DetectionDiagnostics::Available
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DetectionDiagnostics {
+    Available,
+    // Detection can't find any device in this environment, and why
+    Unavailable(String),
+}
+
+// I/O size hints in bytes, as the block layer reports them. 0 means unknown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoSizes {
+    pub minimum: u64,
+    pub optimal: u64,
+}
+
+// The backend for the platform we were built for
+pub fn platform_backend() -> Arc<dyn DeviceBackend> {
     #[cfg(target_os = "linux")]
     {
-        detect_linux_usb_devices().await
+        Arc::new(LinuxBackend)
     }
 
-    #[cfg(not(target_os = "linux"))]
+    #[cfg(target_os = "windows")]
     {
-        // Fallback for non-Linux systems (for development/testing)
-        vec![
-            UsbDevice {
-                name: "Mock USB Drive".to_string(),
-                device_path: "/dev/mock".to_string(),
-                size: 8 * 1024 * 1024 * 1024, // 8GB
-                vendor: "Mock".to_string(),
-                model: "Test Drive".to_string(),
-                is_removable: true,
+        Arc::new(WindowsBackend)
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        Arc::new(MacosBackend)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
+    {
+        // Fallback for other systems (for development/testing), with a
+        // sparse image file to write to instead of a device
+        let mock_image = std::env::temp_dir().join("schrijver-mock.img");
+        Arc::new(MockBackend {
+            devices: vec![UsbDevice::image_file(mock_image.to_string_lossy(), 8 * 1024 * 1024 * 1024)],
+            ..MockBackend::default()
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct LinuxBackend;
+
+#[cfg(target_os = "linux")]
+impl DeviceBackend for LinuxBackend {
+    fn detect(&self) -> BoxFuture<'_, Result<Vec<UsbDevice>, WriterError>> {
+        Box::pin(detect_linux_usb_devices())
+    }
+
+    fn diagnostics(&self) -> DetectionDiagnostics {
+        sysfs_diagnostics(std::path::Path::new("/sys/block"))
+    }
+
+    fn exists(&self, device_path: &str) -> bool {
+        std::path::Path::new(device_path).exists()
+    }
+
+    fn is_mounted(&self, device_path: &str) -> bool {
+        std::fs::read_to_string("/proc/mounts")
+            .map(|mounts| !mounted_partitions(&mounts, device_path).is_empty())
+            .unwrap_or(false)
+    }
+
+    fn is_system_disk(&self, device_path: &str) -> bool {
+        std::fs::read_to_string("/proc/mounts")
+            .map(|mounts| mounts_system_partition(&mounts, device_path))
+            .unwrap_or(false)
+    }
+
+    fn size(&self, device_path: &str) -> io::Result<u64> {
+        get_device_size(device_path)
+    }
+
+    fn io_sizes(&self, device_path: &str) -> io::Result<IoSizes> {
+        use std::fs::File;
+        use std::os::unix::io::AsRawFd;
+
+        // Define the BLKIOMIN and BLKIOOPT ioctl commands (not provided by libc)
+        const BLKIOMIN: libc::c_ulong = 0x1278;
+        const BLKIOOPT: libc::c_ulong = 0x1279;
+
+        let file = File::open(device_path)?;
+        let fd = file.as_raw_fd();
+
+        let query = |request: libc::c_ulong| unsafe {
+            let mut value: libc::c_uint = 0;
+            if libc::ioctl(fd, request, &mut value) == -1 {
+                return Err(io::Error::last_os_error());
             }
-        ]
+            Ok(value as u64)
+        };
+
+        Ok(IoSizes { minimum: query(BLKIOMIN)?, optimal: query(BLKIOOPT)? })
+    }
+
+    fn is_read_only(&self, device_path: &str) -> bool {
+        use std::fs::File;
+        use std::os::unix::io::AsRawFd;
+
+        // Not provided by libc
+        const BLKROGET: libc::c_ulong = 0x125e;
+
+        // The card reader reports the lock switch as the block device's ro flag
+        let name = std::path::Path::new(device_path).file_name().unwrap_or_default().to_string_lossy();
+        if std::fs::read_to_string(format!("/sys/class/block/{}/ro", name)).is_ok_and(|flag| flag.trim() == "1") {
+            return true;
+        }
+
+        // Also covers a device marked read-only with blockdev --setro
+        let Ok(file) = File::open(device_path) else {
+            return false;
+        };
+        let mut read_only: libc::c_int = 0;
+        unsafe { libc::ioctl(file.as_raw_fd(), BLKROGET, &mut read_only) == 0 && read_only != 0 }
+    }
+
+    fn partitions(&self, device: &UsbDevice) -> Vec<UsbDevice> {
+        let Some(disk) = device.device_path.strip_prefix("/dev/") else {
+            return Vec::new();
+        };
+
+        partition_entries(std::path::Path::new("/sys/block"), disk)
+            .into_iter()
+            .map(|(name, number, size)| {
+                let device_path = format!("/dev/{}", name);
+                UsbDevice {
+                    name: format!("{} partition {}", device.name, number),
+                    labels: partition_labels(std::path::Path::new("/dev/disk/by-label"), &device_path),
+                    partitions: device.partitions
+                        .iter()
+                        .filter(|partition| partition.device_path == device_path)
+                        .cloned()
+                        .collect(),
+                    device_path,
+                    size,
+                    ..device.clone()
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(target_os = "windows")]
+#[derive(Debug)]
+pub struct WindowsBackend;
+
+#[cfg(target_os = "windows")]
+impl DeviceBackend for WindowsBackend {
+    fn detect(&self) -> BoxFuture<'_, Result<Vec<UsbDevice>, WriterError>> {
+        Box::pin(async { Ok(detect_windows_usb_devices().await) })
+    }
+
+    fn exists(&self, device_path: &str) -> bool {
+        open_windows_device(device_path).is_ok()
+    }
+
+    fn is_mounted(&self, device_path: &str) -> bool {
+        let disk_number = device_path
+            .trim_start_matches("\\\\.\\PhysicalDrive")
+            .parse::<u32>();
+
+        match disk_number {
+            Ok(disk_number) => windows_volume_disk_numbers()
+                .iter()
+                .any(|(_, number)| *number == disk_number),
+            Err(_) => false,
+        }
+    }
+
+    fn is_system_disk(&self, _device_path: &str) -> bool {
+        false
+    }
+
+    fn size(&self, device_path: &str) -> io::Result<u64> {
+        get_device_size(device_path)
+    }
+
+    fn is_read_only(&self, device_path: &str) -> bool {
+        use windows::Win32::System::Ioctl::IOCTL_DISK_IS_WRITABLE;
+
+        // ERROR_WRITE_PROTECT
+        const WRITE_PROTECTED: i32 = 19;
+
+        let Ok(file) = open_windows_device(device_path) else {
+            return false;
+        };
+        matches!(windows_ioctl::<()>(&file, IOCTL_DISK_IS_WRITABLE, None),
+                 Err(error) if error.raw_os_error() == Some(WRITE_PROTECTED))
+    }
+}
+
+#[cfg(target_os = "macos")]
+#[derive(Debug)]
+pub struct MacosBackend;
+
+#[cfg(target_os = "macos")]
+impl DeviceBackend for MacosBackend {
+    fn detect(&self) -> BoxFuture<'_, Result<Vec<UsbDevice>, WriterError>> {
+        Box::pin(detect_macos_usb_devices())
+    }
+
+    fn exists(&self, device_path: &str) -> bool {
+        std::path::Path::new(device_path).exists()
+    }
+
+    fn is_mounted(&self, device_path: &str) -> bool {
+        let block_device = macos_block_device(device_path);
+
+        // Lines look like "/dev/disk4s1 on /Volumes/UNTITLED (msdos, local, ...)"
+        std::process::Command::new("mount")
+            .output()
+            .map(|output| {
+                String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .filter_map(|line| line.split_whitespace().next())
+                    .any(|source| is_macos_slice_of(source, &block_device))
+            })
+            .unwrap_or(false)
+    }
+
+    fn is_system_disk(&self, _device_path: &str) -> bool {
+        false
+    }
+
+    fn size(&self, device_path: &str) -> io::Result<u64> {
+        get_device_size(device_path)
+    }
+
+    fn is_read_only(&self, device_path: &str) -> bool {
+        use std::fs::File;
+        use std::os::unix::io::AsRawFd;
+
+        // _IOR('d', 29, uint32_t) from <sys/disk.h>
+        const DKIOCISWRITABLE: libc::c_ulong = 0x4004641d;
+
+        let Ok(file) = File::open(device_path) else {
+            return false;
+        };
+        let mut writable: u32 = 1;
+        unsafe { libc::ioctl(file.as_raw_fd(), DKIOCISWRITABLE, &mut writable) == 0 && writable == 0 }
+    }
+}
+
+// Size of the device in bytes, as the OS reports it for the raw disk
+#[cfg(target_os = "linux")]
+pub fn get_device_size(device_path: &str) -> io::Result<u64> {
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+
+    // Define the BLKGETSIZE64 ioctl command (not provided by libc)
+    const BLKGETSIZE64: libc::c_ulong = 0x80081272;
+
+    let file = File::open(device_path)?;
+    let fd = file.as_raw_fd();
+
+    // Use ioctl to get device size
+    unsafe {
+        let mut size: u64 = 0;
+        let result = libc::ioctl(fd, BLKGETSIZE64, &mut size);
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(size)
+    }
+}
+
+#[cfg(target_os = "windows")]
+pub fn get_device_size(device_path: &str) -> io::Result<u64> {
+    use windows::Win32::System::Ioctl::{GET_LENGTH_INFORMATION, IOCTL_DISK_GET_LENGTH_INFO};
+
+    let file = open_windows_device(device_path)?;
+    windows_ioctl::<GET_LENGTH_INFORMATION>(&file, IOCTL_DISK_GET_LENGTH_INFO, None)
+        .map(|info| info.Length as u64)
+}
+
+#[cfg(target_os = "macos")]
+pub fn get_device_size(device_path: &str) -> io::Result<u64> {
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+
+    // _IOR('d', 25, uint64_t) and _IOR('d', 24, uint32_t) from <sys/disk.h>
+    const DKIOCGETBLOCKCOUNT: libc::c_ulong = 0x40086419;
+    const DKIOCGETBLOCKSIZE: libc::c_ulong = 0x40046418;
+
+    let file = File::open(device_path)?;
+    let fd = file.as_raw_fd();
+
+    unsafe {
+        let mut block_count: u64 = 0;
+        let mut block_size: u32 = 0;
+        if libc::ioctl(fd, DKIOCGETBLOCKCOUNT, &mut block_count) == -1
+            || libc::ioctl(fd, DKIOCGETBLOCKSIZE, &mut block_size) == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(block_count * block_size as u64)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
+pub fn get_device_size(_device_path: &str) -> io::Result<u64> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Device size detection not supported on this platform"))
+}
+
+// Serves a fixed set of devices, for tests (including those of programs
+// using the library) and platforms without a backend
+#[derive(Debug, Clone, Default)]
+pub struct MockBackend {
+    pub devices: Vec<UsbDevice>,
+    pub mounted: Vec<String>,
+    pub system_disks: Vec<String>,
+    pub read_only: Vec<String>,
+    // Set to pretend detection can't work, with this reason
+    pub unavailable: Option<String>,
+}
+
+impl DeviceBackend for MockBackend {
+    fn detect(&self) -> BoxFuture<'_, Result<Vec<UsbDevice>, WriterError>> {
+        let devices = self.devices.clone();
+        Box::pin(async move { Ok(devices) })
+    }
+
+    fn exists(&self, device_path: &str) -> bool {
+        self.devices.iter().any(|device| device.device_path == device_path)
+    }
+
+    fn is_mounted(&self, device_path: &str) -> bool {
+        self.mounted.iter().any(|path| path == device_path)
+    }
+
+    fn is_system_disk(&self, device_path: &str) -> bool {
+        self.system_disks.iter().any(|path| path == device_path)
+    }
+
+    fn is_read_only(&self, device_path: &str) -> bool {
+        self.read_only.iter().any(|path| path == device_path)
+    }
+
+    fn diagnostics(&self) -> DetectionDiagnostics {
+        match &self.unavailable {
+            Some(reason) => DetectionDiagnostics::Unavailable(reason.clone()),
+            None => DetectionDiagnostics::Available,
+        }
+    }
+
+    fn size(&self, device_path: &str) -> io::Result<u64> {
+        self.devices
+            .iter()
+            .find(|device| device.device_path == device_path)
+            .map(|device| device.size)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, device_path.to_string()))
+    }
+}
+
+// Both lsblk and the sysfs fallback read /sys/block, which sandboxes like
+// Docker or Flatpak may not mount even though /proc/partitions is there
+#[cfg(target_os = "linux")]
+fn sysfs_diagnostics(sys_block: &std::path::Path) -> DetectionDiagnostics {
+    if sys_block.is_dir() {
+        DetectionDiagnostics::Available
+    } else {
+        DetectionDiagnostics::Unavailable(format!("sysfs is not mounted ({} is missing)", sys_block.display()))
+    }
+}
+
+#[cfg(target_os = "linux")]
+async fn detect_linux_usb_devices() -> Result<Vec<UsbDevice>, WriterError> {
+    if let DetectionDiagnostics::Unavailable(reason) = sysfs_diagnostics(std::path::Path::new("/sys/block")) {
+        tracing::warn!("Device detection unavailable: {}", reason);
+        return Ok(Vec::new());
+    }
+
+    // lsblk knows the transport, so internal disks that claim to be removable
+    // aren't offered. Minimal systems may not ship it, hence the fallback.
+    match detect_lsblk_usb_devices() {
+        Some(devices) => Ok(devices),
+        None => detect_sysfs_usb_devices().await,
     }
 }
 
 #[cfg(target_os = "linux")]
-async fn detect_linux_usb_devices() -> Vec<UsbDevice> {
+fn detect_lsblk_usb_devices() -> Option<Vec<UsbDevice>> {
+    let output = std::process::Command::new("lsblk")
+        .args(["-J", "-b", "-o", "NAME,PATH,SIZE,TYPE,TRAN,RM,MODEL,VENDOR,SERIAL,FSTYPE,LABEL"])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+    parse_lsblk(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(target_os = "linux")]
+#[derive(serde::Deserialize)]
+struct LsblkOutput {
+    blockdevices: Vec<LsblkDevice>,
+}
+
+#[cfg(target_os = "linux")]
+#[derive(serde::Deserialize)]
+struct LsblkDevice {
+    name: String,
+    path: Option<String>,
+    size: Option<LsblkValue>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    tran: Option<String>,
+    rm: Option<LsblkValue>,
+    model: Option<String>,
+    vendor: Option<String>,
+    serial: Option<String>,
+    fstype: Option<String>,
+    label: Option<String>,
+    #[serde(default)]
+    children: Vec<LsblkDevice>,
+}
+
+// Older lsblk versions print every column as a string, e.g. "rm": "1"
+#[cfg(target_os = "linux")]
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum LsblkValue {
+    Bool(bool),
+    Number(u64),
+    Text(String),
+}
+
+#[cfg(target_os = "linux")]
+impl LsblkValue {
+    fn as_u64(&self) -> Option<u64> {
+        match self {
+            LsblkValue::Bool(value) => Some(u64::from(*value)),
+            LsblkValue::Number(value) => Some(*value),
+            LsblkValue::Text(value) => value.trim().parse().ok(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn parse_lsblk(json: &str) -> Option<Vec<UsbDevice>> {
+    let output: LsblkOutput = serde_json::from_str(json).ok()?;
+
+    let non_empty = |value: &Option<String>| {
+        value.as_deref().map(str::trim).filter(|value| !value.is_empty()).map(str::to_string)
+    };
+
+    let devices = output.blockdevices
+        .into_iter()
+        .filter(|device| device.kind.as_deref() == Some("disk") && device.tran.as_deref() == Some("usb"))
+        .map(|device| {
+            let vendor = non_empty(&device.vendor).unwrap_or_else(|| "Unknown".to_string());
+            let model = non_empty(&device.model).unwrap_or_else(|| "Device".to_string());
+            let mut labels: Vec<String> = device.children.iter().filter_map(|child| non_empty(&child.label)).collect();
+            labels.sort();
+
+            // A filesystem on the disk itself means there's no partition table
+            let partitions = std::iter::once(&device)
+                .filter(|device| non_empty(&device.fstype).is_some())
+                .chain(&device.children)
+                .map(|partition| PartitionInfo {
+                    device_path: partition.path.clone().unwrap_or_else(|| format!("/dev/{}", partition.name)),
+                    size: partition.size.as_ref().and_then(LsblkValue::as_u64).unwrap_or(0),
+                    fstype: non_empty(&partition.fstype),
+                    label: non_empty(&partition.label),
+                })
+                .collect();
+
+            UsbDevice {
+                name: format!("{} {}", vendor, model),
+                device_path: device.path.clone().unwrap_or_else(|| format!("/dev/{}", device.name)),
+                size: device.size.as_ref().and_then(LsblkValue::as_u64).unwrap_or(0),
+                vendor,
+                model,
+                serial: non_empty(&device.serial).unwrap_or_default(),
+                labels,
+                partitions,
+                is_removable: device.rm.as_ref().and_then(LsblkValue::as_u64) == Some(1),
+                is_image_file: false,
+            }
+        })
+        .collect();
+
+    Some(devices)
+}
+
+#[cfg(target_os = "linux")]
+async fn detect_sysfs_usb_devices() -> Result<Vec<UsbDevice>, WriterError> {
     use std::fs;
     use std::path::Path;
 
     let mut devices = Vec::new();
 
     // Read /proc/partitions to find block devices
-    if let Ok(partitions) = fs::read_to_string("/proc/partitions") {
-        for line in partitions.lines().skip(2) { // Skip header lines
-            let fields: Vec<&str> = line.split_whitespace().collect();
-            if fields.len() >= 4 {
-                let device_name = fields[3];
-
-                // FIXSkip devices that DO end with numbers (partitions)
-                if device_name.chars().last().unwrap_or('0').is_ascii_digit() {
-                    continue;
-                }
+    let partitions = fs::read_to_string("/proc/partitions")
+        .map_err(|e| WriterError::DetectionFailed(format!("could not read /proc/partitions: {}", e)))?;
+    for device_name in removable_disk_names(&partitions, Path::new("/sys/block")) {
+        let device_path = format!("/dev/{}", device_name);
 
-                let device_path = format!("/dev/{}", device_name);
-
-                // Check if device is removable
-                let removable_path = format!("/sys/block/{}/removable", device_name);
-                let is_removable = fs::read_to_string(&removable_path)
-                    .map(|content| content.trim() == "1")
-                    .unwrap_or(false);
-
-                if is_removable {
-                    // Get device size
-                    let size_path = format!("/sys/block/{}/size", device_name);
-                    let size_sectors = fs::read_to_string(&size_path)
-                        .ok()
-                        .and_then(|s| s.trim().parse::<u64>().ok())
-                        .unwrap_or(0);
-
-                    let size_bytes = size_sectors * 512; // Sector size is typically 512 bytes
-
-                    // Get vendor and model information
-                    let (vendor, model) = get_device_info(&device_name).await;
-
-                    let device = UsbDevice {
-                        name: format!("{} {}", vendor, model),
-                        device_path,
-                        size: size_bytes,
-                        vendor,
-                        model,
-                        is_removable: true,
-                    };
-
-                    devices.push(device);
-                }
-            }
-        }
+        // Get device size
+        let size_path = format!("/sys/block/{}/size", device_name);
+        let size_sectors = fs::read_to_string(&size_path)
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+
+        let size_bytes = size_sectors * 512; // sysfs always reports 512-byte sectors
+
+        // Get vendor, model and serial information
+        let (vendor, model, serial) = get_device_info(&device_name).await;
+        let labels = partition_labels(Path::new("/dev/disk/by-label"), &device_path);
+        let partitions = probe_partitions(Path::new("/sys/block"), &device_name, size_bytes);
+
+        let device = UsbDevice {
+            name: format!("{} {}", vendor, model),
+            device_path,
+            size: size_bytes,
+            vendor,
+            model,
+            serial,
+            labels,
+            partitions,
+            is_removable: true,
+            is_image_file: false,
+        };
+
+        devices.push(device);
     }
 
-    devices
+    Ok(devices)
+}
+
+// Whole disks show up as entries directly under /sys/block, while partitions
+// only live beneath their parent disk (/sys/block/<disk>/<part>). This works for
+// names like nvme0n1 and mmcblk0 that end in a digit, unlike a suffix check.
+#[cfg(target_os = "linux")]
+fn removable_disk_names(partitions: &str, sys_block: &std::path::Path) -> Vec<String> {
+    use std::fs;
+
+    partitions
+        .lines()
+        .skip(2) // Skip header lines
+        .filter_map(|line| line.split_whitespace().nth(3))
+        .filter(|name| sys_block.join(name).exists())
+        .filter(|name| {
+            fs::read_to_string(sys_block.join(name).join("removable"))
+                .map(|content| content.trim() == "1")
+                .unwrap_or(false)
+        })
+        .map(|name| name.to_string())
+        .collect()
+}
+
+// Name, number and size in bytes of each partition of `disk`, which sysfs
+// lists as subdirectories of the disk that have a `partition` file
+#[cfg(target_os = "linux")]
+fn partition_entries(sys_block: &std::path::Path, disk: &str) -> Vec<(String, u32, u64)> {
+    use std::fs;
+
+    let read_number = |path: std::path::PathBuf| {
+        fs::read_to_string(path).ok().and_then(|s| s.trim().parse::<u64>().ok())
+    };
+
+    let Ok(entries) = fs::read_dir(sys_block.join(disk)) else {
+        return Vec::new();
+    };
+
+    let mut partitions: Vec<(String, u32, u64)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let number = read_number(entry.path().join("partition"))?;
+            let sectors = read_number(entry.path().join("size")).unwrap_or(0);
+            Some((entry.file_name().to_string_lossy().to_string(), number as u32, sectors * 512))
+        })
+        .collect();
+
+    partitions.sort_by_key(|&(_, number, _)| number);
+    partitions
 }
 
 #[cfg(target_os = "linux")]
-async fn get_device_info(device_name: &str) -> (String, String) {
+async fn get_device_info(device_name: &str) -> (String, String, String) {
     use std::fs;
 
     let vendor_path = format!("/sys/block/{}/device/vendor", device_name);
     let model_path = format!("/sys/block/{}/device/model", device_name);
+    let serial_path = format!("/sys/block/{}/device/serial", device_name);
 
     let vendor = fs::read_to_string(&vendor_path)
         .map(|s| s.trim().to_string())
@@ -116,44 +774,1042 @@ async fn get_device_info(device_name: &str) -> (String, String) {
         .map(|s| s.trim().to_string())
         .unwrap_or_else(|_| "Device".to_string());
 
-    (vendor, model)
+    // USB mass storage only exposes the serial on the USB parent, which udev
+    // already resolves for us
+    let serial = fs::read_to_string(&serial_path)
+        .map(|s| s.trim().to_string())
+        .ok()
+        .filter(|s| !s.is_empty())
+        .or_else(|| {
+            udev::Device::from_subsystem_sysname("block".to_string(), device_name.to_string())
+                .ok()?
+                .property_value("ID_SERIAL_SHORT")
+                .map(|s| s.to_string_lossy().trim().to_string())
+        })
+        .unwrap_or_default();
+
+    (vendor, model, serial)
+}
+
+// The disk's partitions with their filesystems read straight from the
+// superblocks, for when lsblk isn't around. Without partitions the disk
+// itself may carry one.
+#[cfg(target_os = "linux")]
+fn probe_partitions(sys_block: &std::path::Path, disk: &str, disk_size: u64) -> Vec<PartitionInfo> {
+    let probe = |name: &str, size: u64| {
+        let device_path = format!("/dev/{}", name);
+        PartitionInfo {
+            fstype: probe_filesystem(std::path::Path::new(&device_path)).map(str::to_string),
+            device_path,
+            size,
+            label: None,
+        }
+    };
+
+    let partitions: Vec<PartitionInfo> = partition_entries(sys_block, disk)
+        .into_iter()
+        .map(|(name, _, size)| probe(&name, size))
+        .collect();
+    if !partitions.is_empty() {
+        return partitions;
+    }
+
+    let whole_disk = probe(disk, disk_size);
+    whole_disk.fstype.is_some().then_some(whole_disk).into_iter().collect()
 }
 
-pub fn is_device_mounted(device_path: &str) -> bool {
+// Reading a partition usually needs root, so unreadable ones stay unknown
+#[cfg(target_os = "linux")]
+fn probe_filesystem(path: &std::path::Path) -> Option<&'static str> {
+    use std::io::Read;
+
+    let mut header = Vec::new();
+    std::fs::File::open(path).ok()?.take(FILESYSTEM_PROBE_SIZE).read_to_end(&mut header).ok()?;
+    filesystem_type(&header)
+}
+
+// Enough of the partition to reach the Btrfs superblock at 64 KiB
+#[cfg(target_os = "linux")]
+const FILESYSTEM_PROBE_SIZE: u64 = 0x10048;
+
+// Recognize the common filesystems by the magic in their superblock
+#[cfg(target_os = "linux")]
+fn filesystem_type(header: &[u8]) -> Option<&'static str> {
+    let has = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+    let le32 = |offset: usize| {
+        header.get(offset..offset + 4).map_or(0, |bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+
+    if has(0x438, &[0x53, 0xEF]) {
+        // ext4 is told apart by extents, ext3 by its journal
+        return Some(if le32(0x460) & 0x40 != 0 {
+            "ext4"
+        } else if le32(0x45C) & 0x4 != 0 {
+            "ext3"
+        } else {
+            "ext2"
+        });
+    }
+
+    [
+        (0x10040, b"_BHRfS_M".as_slice(), "btrfs"),
+        (0, b"XFSB", "xfs"),
+        (3, b"NTFS    ", "ntfs"),
+        (3, b"EXFAT   ", "exfat"),
+        (82, b"FAT32   ", "vfat"),
+        (54, b"FAT16   ", "vfat"),
+        (54, b"FAT12   ", "vfat"),
+        (0x8001, b"CD001", "iso9660"),
+    ]
+        .into_iter()
+        .find(|(offset, magic, _)| has(*offset, magic))
+        .map(|(_, _, name)| name)
+}
+
+// Labels of the device's partitions, from the /dev/disk/by-label symlinks
+// that resolve to one of them
+#[cfg(target_os = "linux")]
+fn partition_labels(by_label: &std::path::Path, device_path: &str) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(by_label) else {
+        return Vec::new();
+    };
+
+    let mut labels: Vec<String> = entries
+        .flatten()
+        .filter(|entry| {
+            std::fs::canonicalize(entry.path())
+                .is_ok_and(|target| is_partition_of(&target.to_string_lossy(), device_path))
+        })
+        .map(|entry| unescape_udev_label(&entry.file_name().to_string_lossy()))
+        .collect();
+
+    labels.sort();
+    labels
+}
+
+// udev escapes spaces and other unsafe characters in link names, e.g. "My\x20Disk"
+#[cfg(target_os = "linux")]
+fn unescape_udev_label(name: &str) -> String {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = (byte == b'\\' && tail.first() == Some(&b'x'))
+            .then(|| tail.get(1..3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+
+        match escaped {
+            Some(value) => {
+                bytes.push(value);
+                rest = &tail[3..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// Windows has no /dev tree to probe: `\\.\PhysicalDriveN` paths are checked for
+// existence by opening them without requesting any access rights.
+#[cfg(target_os = "windows")]
+fn open_windows_device(device_path: &str) -> std::io::Result<std::fs::File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use windows::Win32::Storage::FileSystem::{FILE_SHARE_READ, FILE_SHARE_WRITE};
+
+    std::fs::OpenOptions::new()
+        .access_mode(0)
+        .share_mode(FILE_SHARE_READ.0 | FILE_SHARE_WRITE.0)
+        .open(device_path)
+}
+
+#[cfg(target_os = "windows")]
+fn windows_ioctl<T: Default>(file: &std::fs::File, control_code: u32, input: Option<&[u8]>) -> std::io::Result<T> {
+    use std::os::windows::io::AsRawHandle;
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::System::IO::DeviceIoControl;
+
+    let mut output = T::default();
+    let mut bytes_returned = 0u32;
+
+    unsafe {
+        DeviceIoControl(
+            HANDLE(file.as_raw_handle()),
+            control_code,
+            input.map(|i| i.as_ptr() as *const std::ffi::c_void),
+            input.map(|i| i.len() as u32).unwrap_or(0),
+            Some(&mut output as *mut T as *mut std::ffi::c_void),
+            std::mem::size_of::<T>() as u32,
+            Some(&mut bytes_returned),
+            None,
+        )
+    }
+    .map_err(|e| std::io::Error::from_raw_os_error(e.code().0))?;
+
+    Ok(output)
+}
+
+#[cfg(target_os = "windows")]
+async fn detect_windows_usb_devices() -> Vec<UsbDevice> {
+    use windows::Win32::Storage::FileSystem::BusTypeUsb;
+    use windows::Win32::System::Ioctl::{
+        PropertyStandardQuery, StorageDeviceProperty, GET_LENGTH_INFORMATION,
+        IOCTL_DISK_GET_LENGTH_INFO, IOCTL_STORAGE_QUERY_PROPERTY, STORAGE_DEVICE_DESCRIPTOR,
+        STORAGE_PROPERTY_QUERY,
+    };
+
+    // Large enough for the descriptor plus the vendor/product strings that follow it
+    #[repr(C, align(8))]
+    struct DescriptorBuffer([u8; 1024]);
+
+    impl Default for DescriptorBuffer {
+        fn default() -> Self {
+            Self([0; 1024])
+        }
+    }
+
+    fn read_string(buffer: &[u8], offset: u32) -> Option<String> {
+        let bytes = buffer.get(offset as usize..).filter(|_| offset != 0)?;
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        let value = String::from_utf8_lossy(&bytes[..end]).trim().to_string();
+        (!value.is_empty()).then_some(value)
+    }
+
+    let mut devices = Vec::new();
+
+    // Physical drives are numbered densely from 0, but gaps appear after unplugging
+    for index in 0..32 {
+        let device_path = format!("\\\\.\\PhysicalDrive{}", index);
+        let Ok(file) = open_windows_device(&device_path) else {
+            continue;
+        };
+
+        let query = STORAGE_PROPERTY_QUERY {
+            PropertyId: StorageDeviceProperty,
+            QueryType: PropertyStandardQuery,
+            AdditionalParameters: [0],
+        };
+        let query_bytes = unsafe {
+            std::slice::from_raw_parts(
+                &query as *const STORAGE_PROPERTY_QUERY as *const u8,
+                std::mem::size_of::<STORAGE_PROPERTY_QUERY>(),
+            )
+        };
+
+        let Ok(buffer) = windows_ioctl::<DescriptorBuffer>(&file, IOCTL_STORAGE_QUERY_PROPERTY, Some(query_bytes)) else {
+            continue;
+        };
+        let descriptor = unsafe { &*(buffer.0.as_ptr() as *const STORAGE_DEVICE_DESCRIPTOR) };
+
+        let is_removable = descriptor.RemovableMedia.as_bool() || descriptor.BusType == BusTypeUsb;
+        if !is_removable {
+            continue;
+        }
+
+        // Same byte count the Linux path derives from sysfs sectors
+        let size = windows_ioctl::<GET_LENGTH_INFORMATION>(&file, IOCTL_DISK_GET_LENGTH_INFO, None)
+            .map(|info| info.Length as u64)
+            .unwrap_or(0);
+
+        let vendor = read_string(&buffer.0, descriptor.VendorIdOffset).unwrap_or_else(|| "Unknown".to_string());
+        let model = read_string(&buffer.0, descriptor.ProductIdOffset).unwrap_or_else(|| "Device".to_string());
+        let serial = read_string(&buffer.0, descriptor.SerialNumberOffset).unwrap_or_default();
+
+        devices.push(UsbDevice {
+            name: format!("{} {}", vendor, model),
+            device_path,
+            size,
+            vendor,
+            model,
+            serial,
+            labels: Vec::new(),
+            partitions: Vec::new(),
+            is_removable: true,
+            is_image_file: false,
+        });
+    }
+
+    devices
+}
+
+#[cfg(target_os = "macos")]
+fn diskutil_plist(args: &[&str]) -> Option<plist::Dictionary> {
+    let output = std::process::Command::new("diskutil").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    plist::Value::from_reader(std::io::Cursor::new(output.stdout))
+        .ok()?
+        .into_dictionary()
+}
+
+#[cfg(target_os = "macos")]
+async fn detect_macos_usb_devices() -> Result<Vec<UsbDevice>, WriterError> {
+    let Some(list) = diskutil_plist(&["list", "-plist", "external", "physical"]) else {
+        return Err(WriterError::DetectionFailed("diskutil could not list the external disks".to_string()));
+    };
+
+    let whole_disks: Vec<String> = list
+        .get("WholeDisks")
+        .and_then(|disks| disks.as_array())
+        .map(|disks| disks.iter().filter_map(|d| d.as_string()).map(String::from).collect())
+        .unwrap_or_default();
+
+    let mut devices = Vec::new();
+
+    for disk in whole_disks {
+        let Some(info) = diskutil_plist(&["info", "-plist", &disk]) else {
+            continue;
+        };
+
+        let string = |key: &str| info.get(key).and_then(|v| v.as_string()).map(str::trim).map(String::from);
+        let flag = |key: &str| info.get(key).and_then(|v| v.as_boolean()).unwrap_or(false);
+
+        let is_removable = flag("Removable") || flag("RemovableMedia") || flag("Ejectable")
+            || string("BusProtocol").as_deref() == Some("USB");
+        if !is_removable {
+            continue;
+        }
+
+        let size = info.get("TotalSize")
+            .or_else(|| info.get("Size"))
+            .and_then(|v| v.as_unsigned_integer())
+            .unwrap_or(0);
+
+        // diskutil only reports a combined media name, e.g. "SanDisk Ultra Media"
+        let media_name = string("MediaName").unwrap_or_default();
+        let (vendor, model) = match media_name.split_once(' ') {
+            Some((vendor, model)) => (vendor.to_string(), model.to_string()),
+            None if !media_name.is_empty() => ("Unknown".to_string(), media_name),
+            None => ("Unknown".to_string(), "Device".to_string()),
+        };
+
+        devices.push(UsbDevice {
+            name: format!("{} {}", vendor, model),
+            // The raw node bypasses the buffer cache and is an order of magnitude
+            // faster to write than /dev/diskN
+            device_path: format!("/dev/r{}", disk),
+            size,
+            vendor,
+            model,
+            serial: String::new(),
+            labels: Vec::new(),
+            partitions: Vec::new(),
+            is_removable: true,
+            is_image_file: false,
+        });
+    }
+
+    Ok(devices)
+}
+
+// Mount tables and diskutil refer to the buffered /dev/diskN node
+#[cfg(target_os = "macos")]
+fn macos_block_device(device_path: &str) -> String {
+    device_path.replacen("/dev/rdisk", "/dev/disk", 1)
+}
+
+// True for /dev/disk4 itself and its slices, e.g. /dev/disk4s1, but not /dev/disk40
+#[cfg(target_os = "macos")]
+fn is_macos_slice_of(source: &str, block_device: &str) -> bool {
+    match source.strip_prefix(block_device) {
+        Some(suffix) => suffix.is_empty() || suffix.strip_prefix('s').is_some_and(|slice| slice.chars().all(|c| c.is_ascii_digit())),
+        None => false,
+    }
+}
+
+// Map every drive letter back to the physical disk number it lives on
+#[cfg(target_os = "windows")]
+fn windows_volume_disk_numbers() -> Vec<(char, u32)> {
+    use windows::Win32::Storage::FileSystem::GetLogicalDrives;
+    use windows::Win32::System::Ioctl::{IOCTL_STORAGE_GET_DEVICE_NUMBER, STORAGE_DEVICE_NUMBER};
+
+    let drives = unsafe { GetLogicalDrives() };
+
+    (0..26u8)
+        .filter(|bit| drives & (1 << bit) != 0)
+        .filter_map(|bit| {
+            let letter = (b'A' + bit) as char;
+            let file = open_windows_device(&format!("\\\\.\\{}:", letter)).ok()?;
+            let number = windows_ioctl::<STORAGE_DEVICE_NUMBER>(&file, IOCTL_STORAGE_GET_DEVICE_NUMBER, None).ok()?;
+            Some((letter, number.DeviceNumber))
+        })
+        .collect()
+}
+
+// Mount points that mean the disk holds the running OS or user data
+#[cfg(target_os = "linux")]
+const SYSTEM_MOUNT_POINTS: [&str; 3] = ["/", "/boot", "/home"];
+
+// True if `source` is the disk itself or one of its partitions, e.g. /dev/sda1
+// or /dev/nvme0n1p2 for /dev/sda and /dev/nvme0n1, but not /dev/sdaa. Disks
+// whose name ends in a digit number their partitions after a `p`, so
+// /dev/loop10 is another disk rather than a partition of /dev/loop1
+#[cfg(target_os = "linux")]
+fn is_partition_of(source: &str, device_path: &str) -> bool {
+    let Some(suffix) = source.strip_prefix(device_path) else {
+        return false;
+    };
+    if suffix.is_empty() {
+        return true;
+    }
+    let number = if device_path.ends_with(|c: char| c.is_ascii_digit()) {
+        match suffix.strip_prefix('p') {
+            Some(number) => number,
+            None => return false,
+        }
+    } else {
+        suffix
+    };
+    !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+}
+
+// The disk's mounted partitions, or the disk itself when its filesystem has no
+// partition table, as (source, mount point)
+#[cfg(target_os = "linux")]
+fn mounted_partitions(mounts: &str, device_path: &str) -> Vec<(String, String)> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next()) {
+                (Some(source), Some(mountpoint)) if is_partition_of(source, device_path) => {
+                    Some((source.to_string(), unescape_mount_path(mountpoint)))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+fn mounts_system_partition(mounts: &str, device_path: &str) -> bool {
+    mounts.lines().any(|line| {
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next()) {
+            (Some(source), Some(mountpoint)) => {
+                is_partition_of(source, device_path)
+                    && SYSTEM_MOUNT_POINTS.contains(&unescape_mount_path(mountpoint).as_str())
+            }
+            _ => false,
+        }
+    })
+}
+
+// Unmount every mounted partition of the device so it can be written to
+pub async fn unmount_device_partitions(device_path: &str) -> Result<(), crate::error::WriterError> {
     #[cfg(target_os = "linux")]
     {
+        use crate::error::WriterError;
+        use std::ffi::CString;
         use std::fs;
 
-        // Check /proc/mounts
-        if let Ok(mounts) = fs::read_to_string("/proc/mounts") {
-            for line in mounts.lines() {
-                let fields: Vec<&str> = line.split_whitespace().collect();
-                if !fields.is_empty() && fields[0].starts_with(device_path) {
-                    return true;
-                }
+        let mounts = fs::read_to_string("/proc/mounts")
+            .map_err(WriterError::from_io)?;
+
+        // Don't pull /boot or /home out from under the running system
+        if mounts_system_partition(&mounts, device_path) {
+            return Err(WriterError::SystemDiskProtected(device_path.to_string()));
+        }
+
+        let targets = mounted_partitions(&mounts, device_path);
+
+        // Never touch the root filesystem, even if someone points us at the system disk
+        if let Some((source, _)) = targets.iter().find(|(_, mount_point)| mount_point == "/") {
+            return Err(WriterError::DeviceMounted(format!("{} (mounted at /)", source)));
+        }
+
+        for (source, mount_point) in targets {
+            let target = CString::new(mount_point.clone())
+                .map_err(|e| WriterError::Unknown(e.to_string()))?;
+
+            tracing::info!("Unmounting {} from {}", source, mount_point);
+
+            if unsafe { libc::umount2(target.as_ptr(), 0) } != 0 {
+                let error = std::io::Error::last_os_error();
+                return Err(match error.raw_os_error() {
+                    Some(libc::EBUSY) => WriterError::DeviceBusy(mount_point),
+                    Some(libc::EPERM) => WriterError::PermissionDenied,
+                    _ => WriterError::IoError {
+                        kind: error.kind(),
+                        message: format!("Failed to unmount {}: {}", mount_point, error),
+                    },
+                });
             }
         }
-        false
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        use crate::error::WriterError;
+
+        let block_device = macos_block_device(device_path);
+        tracing::info!("Unmounting all volumes on {}", block_device);
+
+        let output = std::process::Command::new("diskutil")
+            .args(["unmountDisk", &block_device])
+            .output()
+            .map_err(WriterError::from_io)?;
+
+        if !output.status.success() {
+            let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            return Err(if message.to_lowercase().contains("busy") || message.contains("dissented") {
+                WriterError::DeviceBusy(message)
+            } else {
+                WriterError::IoError {
+                    kind: std::io::ErrorKind::Other,
+                    message: format!("diskutil unmountDisk failed: {}", message),
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        let _ = device_path;
+        Ok(())
+    }
+}
+
+// Flush a freshly written device, make the kernel pick up its new partition
+// table and power it off so it can be unplugged safely
+pub async fn eject_device(device_path: &str) -> Result<(), crate::error::WriterError> {
+    #[cfg(target_os = "linux")]
+    {
+        use crate::error::WriterError;
+        use std::os::unix::io::AsRawFd;
+
+        // Define the BLKRRPART ioctl command (not provided by libc)
+        const BLKRRPART: libc::c_ulong = 0x125F;
+
+        let file = std::fs::File::open(device_path).map_err(WriterError::from_io)?;
+        let fd = file.as_raw_fd();
+
+        if unsafe { libc::fsync(fd) } == -1 {
+            let error = std::io::Error::last_os_error();
+            return Err(WriterError::IoError {
+                kind: error.kind(),
+                message: format!("fsync failed: {}", error),
+            });
+        }
+
+        // Not fatal: the device is powered off next anyway, this only keeps the
+        // partition list accurate until then
+        if unsafe { libc::ioctl(fd, BLKRRPART, 0) } == -1 {
+            tracing::warn!("Failed to reread the partition table of {}: {}",
+                           device_path, std::io::Error::last_os_error());
+        }
+        drop(file);
+
+        tracing::info!("Powering off {}", device_path);
+
+        let output = std::process::Command::new("udisksctl")
+            .args(["power-off", "-b", device_path])
+            .output()
+            .map_err(WriterError::from_io)?;
+
+        if !output.status.success() {
+            let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            return Err(WriterError::IoError {
+                kind: std::io::ErrorKind::Other,
+                message: format!("udisksctl power-off failed: {}", message),
+            });
+        }
+
+        Ok(())
     }
 
     #[cfg(not(target_os = "linux"))]
-    false
+    {
+        let _ = device_path;
+        Err(crate::error::WriterError::Unknown("ejecting is only supported on Linux".to_string()))
+    }
+}
+
+// /proc/mounts escapes whitespace and backslashes as octal sequences (e.g. "\040")
+#[cfg(target_os = "linux")]
+fn unescape_mount_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let is_escape = bytes[i] == b'\\'
+            && i + 3 < bytes.len()
+            && bytes[i + 1..i + 4].iter().all(|b| (b'0'..=b'7').contains(b));
+
+        if is_escape {
+            let octal = std::str::from_utf8(&bytes[i + 1..i + 4]).unwrap_or("0");
+            result.push(u8::from_str_radix(octal, 8).unwrap_or(b'?'));
+            i += 4;
+        } else {
+            result.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8_lossy(&result).to_string()
 }
 
-pub async fn validate_device_for_writing(device: &UsbDevice) -> Result<(), crate::error::WriterError> {
+// USB devices currently plugged in, as found by the platform backend
+pub async fn detect_usb_devices() -> Result<Vec<UsbDevice>, WriterError> {
+    platform_backend().detect().await
+}
+
+pub async fn validate_device_for_writing(
+    backend: &dyn DeviceBackend,
+    device: &UsbDevice,
+) -> Result<(), crate::error::WriterError> {
     use crate::error::WriterError;
 
+    // Image files don't need to exist yet, and are never mounted or the system disk
+    if device.is_image_file {
+        return create_image_file(&device.device_path, device.size).map_err(WriterError::from);
+    }
+
     // Check if device exists
-    if !std::path::Path::new(&device.device_path).exists() {
+    if !backend.exists(&device.device_path) {
         return Err(WriterError::DeviceNotFound(device.device_path.clone()));
     }
 
+    // Never touch the disk the OS is running from, even if it claims to be removable
+    if backend.is_system_disk(&device.device_path) {
+        return Err(WriterError::SystemDiskProtected(device.device_path.clone()));
+    }
+
     // Check if device is mounted
-    if is_device_mounted(&device.device_path) {
+    if backend.is_mounted(&device.device_path) {
         return Err(WriterError::DeviceMounted(device.device_path.clone()));
     }
 
+    // A locked card otherwise only fails once the write starts, with an error
+    // that looks like missing permissions
+    if backend.is_read_only(&device.device_path) {
+        return Err(WriterError::DeviceReadOnly(device.device_path.clone()));
+    }
+
     // TODO: Check write permissions
 
+    check_not_busy(&device.device_path)
+}
+
+// Opening a block device exclusively fails with EBUSY while it is mounted,
+// held by the kernel (e.g. device mapper or md) or opened exclusively by
+// another program. Anything else is left for opening it for writing to report.
+#[cfg(target_os = "linux")]
+pub fn check_not_busy(device_path: &str) -> Result<(), crate::error::WriterError> {
+    use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+
+    // O_EXCL without O_CREAT only means this for block devices
+    if !std::fs::metadata(device_path).is_ok_and(|metadata| metadata.file_type().is_block_device()) {
+        return Ok(());
+    }
+
+    match std::fs::OpenOptions::new().read(true).custom_flags(libc::O_EXCL).open(device_path) {
+        Err(error) if error.raw_os_error() == Some(libc::EBUSY) => {
+            Err(crate::error::WriterError::DeviceBusy(format!("{} is still in use", device_path)))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn check_not_busy(_device_path: &str) -> Result<(), crate::error::WriterError> {
     Ok(())
-}
\ No newline at end of file
+}
+
+// Whether writing `device_path` would overwrite the image at `iso_path`, because
+// the image is stored on that disk or one of its partitions, or it is the
+// target image file itself. Anything it can't trace back to a disk, such as a
+// network share or a btrfs subvolume, is taken to be somewhere else.
+pub fn is_source_on_device(iso_path: &std::path::Path, device_path: &str) -> bool {
+    let Ok(source) = std::fs::canonicalize(iso_path) else {
+        return false;
+    };
+    // Windows' \\.\PhysicalDriveN paths can't be canonicalized, only files can
+    let target = std::path::Path::new(device_path);
+    if target.is_file() {
+        return std::fs::canonicalize(target).is_ok_and(|target| target == source);
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+        match (std::fs::metadata(&source), std::fs::metadata(target)) {
+            (Ok(source), Ok(target)) if target.file_type().is_block_device() => {
+                block_device_holds(std::path::Path::new("/sys/dev/block"), target.rdev(), source.dev())
+            }
+            _ => false,
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        // The mount's source, e.g. /dev/disk4s1
+        let Ok(path) = std::ffi::CString::new(source.as_os_str().as_bytes()) else {
+            return false;
+        };
+        let mut stats: libc::statfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statfs(path.as_ptr(), &mut stats) } != 0 {
+            return false;
+        }
+        let mounted_from = unsafe { std::ffi::CStr::from_ptr(stats.f_mntfromname.as_ptr()) };
+        is_macos_slice_of(&mounted_from.to_string_lossy(), &macos_block_device(device_path))
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        // canonicalize gives \\?\E:\..., and \\?\UNC\... for shares
+        let source = source.to_string_lossy();
+        let letter = match source.trim_start_matches("\\\\?\\").as_bytes() {
+            [letter, b':', ..] => letter.to_ascii_uppercase() as char,
+            _ => return false,
+        };
+        let disk_number = device_path.trim_start_matches("\\\\.\\PhysicalDrive").parse::<u32>();
+        windows_volume_disk_numbers()
+            .iter()
+            .any(|(drive, number)| *drive == letter && Ok(*number) == disk_number)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+    {
+        false
+    }
+}
+
+// True if the file system on block device `source` (a file's st_dev) is on
+// block device `device` (the target's st_rdev). Partitions sit below their
+// disk in sysfs, e.g. /sys/devices/.../block/sdb/sdb1, which /sys/dev/block
+// links to by device number.
+#[cfg(target_os = "linux")]
+fn block_device_holds(sys_dev_block: &std::path::Path, device: u64, source: u64) -> bool {
+    let sysfs_dir = |dev: u64| {
+        sys_dev_block
+            .join(format!("{}:{}", libc::major(dev), libc::minor(dev)))
+            .canonicalize()
+            .ok()
+    };
+    match (sysfs_dir(device), sysfs_dir(source)) {
+        (Some(device), Some(source)) => source.starts_with(device),
+        _ => false,
+    }
+}
+
+// Create the file behind an image file target, or truncate an existing one,
+// and size it like the device it stands in for. Refuses anything but a
+// regular file, so a mistyped path can't truncate a real device.
+pub fn create_image_file(path: &str, size: u64) -> io::Result<()> {
+    if std::fs::metadata(path).is_ok_and(|metadata| !metadata.is_file()) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a regular file", path)));
+    }
+
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    file.set_len(size)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    fn test_stick(path: &str, size: u64) -> UsbDevice {
+        UsbDevice {
+            name: "Test Stick".to_string(),
+            device_path: path.to_string(),
+            size,
+            vendor: String::new(),
+            model: String::new(),
+            serial: String::new(),
+            labels: Vec::new(),
+            partitions: Vec::new(),
+            is_removable: true,
+            is_image_file: false,
+        }
+    }
+    use crate::error::WriterError;
+    use std::fs;
+
+    #[test]
+    fn test_removable_disk_names_skips_partitions() {
+        let sys_block = std::env::temp_dir().join(format!("schrijver-sys-block-{}", std::process::id()));
+        for (name, removable) in [("sda", "1"), ("sdb", "0"), ("nvme0n1", "1"), ("mmcblk0", "1")] {
+            fs::create_dir_all(sys_block.join(name)).unwrap();
+            fs::write(sys_block.join(name).join("removable"), removable).unwrap();
+        }
+
+        let partitions = "\
+major minor  #blocks  name
+
+   8        0   15633408 sda
+   8        1   15632384 sda1
+   8       16  976762584 sdb
+ 259        0  500107608 nvme0n1
+ 259        1     524288 nvme0n1p1
+ 179        1   31166976 mmcblk0p1
+";
+
+        let names = removable_disk_names(partitions, &sys_block);
+        fs::remove_dir_all(&sys_block).unwrap();
+
+        assert_eq!(names, vec!["sda".to_string(), "nvme0n1".to_string()]);
+    }
+
+    #[test]
+    fn test_mounts_system_partition() {
+        let mounts = "\
+/dev/mmcblk0p2 / ext4 rw,noatime 0 0
+/dev/mmcblk0p1 /boot vfat rw,relatime 0 0
+/dev/sda1 /media/usb vfat rw,nosuid,nodev 0 0
+/dev/sdaa1 /home ext4 rw,relatime 0 0
+proc /proc proc rw,nosuid,nodev,noexec 0 0
+";
+
+        assert!(mounts_system_partition(mounts, "/dev/mmcblk0"));
+        assert!(!mounts_system_partition(mounts, "/dev/sda"));
+        assert!(mounts_system_partition(mounts, "/dev/sdaa"));
+        assert!(!mounts_system_partition(mounts, "/dev/sdb"));
+    }
+
+    #[test]
+    fn test_mounted_partitions_match_whole_partition_names() {
+        let mounts = "\
+/dev/sdb1 /media/stick vfat rw,nosuid,nodev 0 0
+/dev/sdbb1 /media/other\\040disk ext4 rw,relatime 0 0
+/dev/sdc /media/superfloppy vfat rw 0 0
+/dev/nvme0n1p2 / ext4 rw,relatime 0 0
+/dev/mmcblk0p1 /boot vfat rw,relatime 0 0
+/dev/loop10 /snap/core/1 squashfs ro,nodev 0 0
+/dev/nvme0n12 /srv ext4 rw,relatime 0 0
+proc /proc proc rw,nosuid,nodev,noexec 0 0
+";
+        let sources = |device_path| {
+            mounted_partitions(mounts, device_path)
+                .into_iter()
+                .map(|(source, mount_point)| format!("{} {}", source, mount_point))
+                .collect::<Vec<_>>()
+        };
+
+        // Only a partition mounted still counts for the whole disk
+        assert_eq!(sources("/dev/sdb"), vec!["/dev/sdb1 /media/stick"]);
+        assert_eq!(sources("/dev/sdbb"), vec!["/dev/sdbb1 /media/other disk"]);
+        assert_eq!(sources("/dev/sdb1"), vec!["/dev/sdb1 /media/stick"]);
+        assert_eq!(sources("/dev/sdc"), vec!["/dev/sdc /media/superfloppy"]);
+        assert_eq!(sources("/dev/nvme0n1"), vec!["/dev/nvme0n1p2 /"]);
+        assert_eq!(sources("/dev/mmcblk0"), vec!["/dev/mmcblk0p1 /boot"]);
+        assert!(sources("/dev/sd").is_empty());
+        assert!(sources("/dev/sdd").is_empty());
+        assert!(sources("/dev/nvme0").is_empty());
+        // A disk ending in a digit isn't a prefix match for one with more digits
+        assert!(sources("/dev/loop1").is_empty());
+        assert_eq!(sources("/dev/loop10"), vec!["/dev/loop10 /snap/core/1"]);
+        assert_eq!(sources("/dev/nvme0n12"), vec!["/dev/nvme0n12 /srv"]);
+    }
+
+    #[test]
+    fn test_partition_labels() {
+        let root = std::env::temp_dir().join(format!("schrijver-by-label-{}", std::process::id()));
+        let by_label = root.join("by-label");
+        fs::create_dir_all(&by_label).unwrap();
+        for partition in ["sdb1", "sdb2", "sdc1"] {
+            fs::write(root.join(partition), "").unwrap();
+        }
+        std::os::unix::fs::symlink(root.join("sdb1"), by_label.join("UBUNTU")).unwrap();
+        std::os::unix::fs::symlink(root.join("sdb2"), by_label.join("My\\x20Data")).unwrap();
+        std::os::unix::fs::symlink(root.join("sdc1"), by_label.join("OTHER")).unwrap();
+
+        let device_path = fs::canonicalize(&root).unwrap().join("sdb");
+        let labels = partition_labels(&by_label, &device_path.to_string_lossy());
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(labels, vec!["My Data".to_string(), "UBUNTU".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_lsblk() {
+        let json = r#"{
+            "blockdevices": [
+                {"name": "sda", "path": "/dev/sda", "size": 512110190592, "type": "disk", "tran": "sata",
+                 "rm": true, "model": "Internal SSD", "vendor": "ATA     ", "serial": "S1", "label": null, "mountpoint": null},
+                {"name": "sdb", "path": "/dev/sdb", "size": 16008609792, "type": "disk", "tran": "usb",
+                 "rm": true, "model": "Cruzer Blade", "vendor": "SanDisk ", "serial": "4C530001", "label": null, "mountpoint": null,
+                 "children": [
+                    {"name": "sdb1", "path": "/dev/sdb1", "size": 16007561216, "type": "part", "tran": null,
+                     "rm": true, "model": null, "vendor": null, "serial": null, "fstype": "vfat", "label": "UBUNTU", "mountpoint": "/media/UBUNTU"}
+                 ]},
+                {"name": "sr0", "size": "1073741312", "type": "rom", "tran": "usb", "rm": "1",
+                 "model": null, "vendor": null, "serial": null, "label": null, "mountpoint": null}
+            ]
+        }"#;
+
+        let devices = parse_lsblk(json).unwrap();
+        assert_eq!(devices, vec![UsbDevice {
+            name: "SanDisk Cruzer Blade".to_string(),
+            vendor: "SanDisk".to_string(),
+            model: "Cruzer Blade".to_string(),
+            serial: "4C530001".to_string(),
+            labels: vec!["UBUNTU".to_string()],
+            partitions: vec![PartitionInfo {
+                device_path: "/dev/sdb1".to_string(),
+                size: 16007561216,
+                fstype: Some("vfat".to_string()),
+                label: Some("UBUNTU".to_string()),
+            }],
+            ..test_stick("/dev/sdb", 16008609792)
+        }]);
+
+        assert!(parse_lsblk("lsblk: unknown column").is_none());
+    }
+
+    #[test]
+    fn test_human_size() {
+        let device = |size| test_stick("/dev/sdb", size);
+
+        assert_eq!(device(256 * 1024 * 1024).human_size(), "256 MB");
+        assert_eq!(device(16_008_609_792).human_size(), "14.9 GB");
+        assert_eq!(device(2 * 1024 * 1024 * 1024 * 1024).human_size(), "2.00 TB");
+    }
+
+    #[test]
+    fn test_filesystem_type() {
+        let mut header = vec![0u8; FILESYSTEM_PROBE_SIZE as usize];
+        assert_eq!(filesystem_type(&header), None);
+
+        header[0x438..0x43A].copy_from_slice(&[0x53, 0xEF]);
+        assert_eq!(filesystem_type(&header), Some("ext2"));
+        header[0x460] = 0x40; // extents
+        assert_eq!(filesystem_type(&header), Some("ext4"));
+
+        let mut header = vec![0u8; FILESYSTEM_PROBE_SIZE as usize];
+        header[0x10040..0x10048].copy_from_slice(b"_BHRfS_M");
+        assert_eq!(filesystem_type(&header), Some("btrfs"));
+        // A short read can't hold the Btrfs superblock
+        assert_eq!(filesystem_type(&header[..4096]), None);
+    }
+
+    #[test]
+    fn test_existing_data() {
+        let partition = |fstype: &str, label: Option<&str>| PartitionInfo {
+            device_path: "/dev/sdb1".to_string(),
+            size: 0,
+            fstype: Some(fstype.to_string()),
+            label: label.map(str::to_string),
+        };
+        let mut device = UsbDevice {
+            partitions: vec![partition("ext4", Some("backups")), partition("ntfs", None)],
+            ..test_stick("/dev/sdb", 0)
+        };
+        assert_eq!(device.existing_data(), vec!["ext4 'backups'".to_string(), "ntfs".to_string()]);
+
+        // A previously written installer isn't data worth warning about
+        device.partitions = vec![partition("iso9660", Some("Ubuntu")), partition("vfat", Some("ESP"))];
+        assert!(device.existing_data().is_empty());
+    }
+
+    #[test]
+    fn test_partition_entries() {
+        let sys_block = std::env::temp_dir().join(format!("schrijver-partitions-{}", std::process::id()));
+        let disk = sys_block.join("sdb");
+        for (name, number, sectors) in [("sdb2", "2", "2048"), ("sdb1", "1", "4096")] {
+            fs::create_dir_all(disk.join(name)).unwrap();
+            fs::write(disk.join(name).join("partition"), number).unwrap();
+            fs::write(disk.join(name).join("size"), sectors).unwrap();
+        }
+        // Other entries, such as the queue settings, aren't partitions
+        fs::create_dir_all(disk.join("queue")).unwrap();
+
+        let entries = partition_entries(&sys_block, "sdb");
+        assert_eq!(sysfs_diagnostics(&sys_block), DetectionDiagnostics::Available);
+        fs::remove_dir_all(&sys_block).unwrap();
+        assert!(matches!(sysfs_diagnostics(&sys_block), DetectionDiagnostics::Unavailable(_)));
+
+        assert_eq!(entries, vec![
+            ("sdb1".to_string(), 1, 4096 * 512),
+            ("sdb2".to_string(), 2, 2048 * 512),
+        ]);
+    }
+
+    #[test]
+    fn test_validate_device_with_mock_backend() {
+        let device = |device_path: &str| test_stick(device_path, 8 * 1024 * 1024 * 1024);
+        let backend = MockBackend {
+            devices: vec![device("/dev/sdb"), device("/dev/sdc"), device("/dev/mmcblk0"), device("/dev/mmcblk1")],
+            mounted: vec!["/dev/sdc".to_string()],
+            system_disks: vec!["/dev/mmcblk0".to_string()],
+            read_only: vec!["/dev/mmcblk1".to_string()],
+            ..MockBackend::default()
+        };
+        let validate = |device_path| {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            runtime.block_on(validate_device_for_writing(&backend, &device(device_path)))
+        };
+
+        assert!(validate("/dev/sdb").is_ok());
+        assert!(matches!(validate("/dev/sdc"), Err(WriterError::DeviceMounted(_))));
+        assert!(matches!(validate("/dev/mmcblk0"), Err(WriterError::SystemDiskProtected(_))));
+        assert!(matches!(validate("/dev/mmcblk1"), Err(WriterError::DeviceReadOnly(_))));
+        assert!(matches!(validate("/dev/sdz"), Err(WriterError::DeviceNotFound(_))));
+        assert_eq!(backend.size("/dev/sdb").unwrap(), 8 * 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_block_device_holds_its_partitions_only() {
+        let root = std::env::temp_dir().join(format!("schrijver-sys-dev-{}", std::process::id()));
+        let disks = root.join("devices/pci0000:00/usb1/block");
+        let sys_dev_block = root.join("dev/block");
+        fs::create_dir_all(&sys_dev_block).unwrap();
+        for (dev, dir) in [("8:16", "sdb"), ("8:17", "sdb/sdb1"), ("8:32", "sdc"), ("8:33", "sdc/sdc1"), ("65:16", "sdbb")] {
+            fs::create_dir_all(disks.join(dir)).unwrap();
+            std::os::unix::fs::symlink(disks.join(dir), sys_dev_block.join(dev)).unwrap();
+        }
+
+        // st_dev of a file on the mounted partition, st_rdev of the target node
+        let holds = |device, source| block_device_holds(&sys_dev_block, device, source);
+        let sdb = libc::makedev(8, 16);
+        let sdb1 = libc::makedev(8, 17);
+        assert!(holds(sdb, sdb1));
+        assert!(holds(sdb1, sdb1));
+        assert!(holds(sdb, sdb));
+        assert!(!holds(sdb1, sdb));
+        assert!(!holds(libc::makedev(8, 32), sdb1));
+        assert!(!holds(libc::makedev(65, 16), sdb1));
+        assert!(!holds(sdb, libc::makedev(8, 33)));
+        // A btrfs subvolume or network share has no block device behind it
+        assert!(!holds(sdb, libc::makedev(0, 45)));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_validate_creates_image_file() {
+        let dir = std::env::temp_dir().join(format!("schrijver-image-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("disk.img").to_string_lossy().to_string();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let validate = |device: UsbDevice| runtime.block_on(validate_device_for_writing(&MockBackend::default(), &device));
+
+        // Not detected by the backend, so only accepted as an image file
+        assert!(validate(UsbDevice::image_file(&path, 1024 * 1024)).is_ok());
+        assert_eq!(fs::metadata(&path).unwrap().len(), 1024 * 1024);
+        fs::write(&path, vec![1u8; 4096]).unwrap();
+        assert!(validate(UsbDevice::image_file(&path, 8192)).is_ok());
+        assert_eq!(fs::read(&path).unwrap(), vec![0u8; 8192]);
+        assert!(validate(UsbDevice::image_file(dir.to_string_lossy(), 8192)).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
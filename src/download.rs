@@ -0,0 +1,40 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::error::WriterError;
+
+// Sources starting with http:// or https:// are streamed instead of opened
+pub fn is_url(source: &Path) -> bool {
+    source
+        .to_str()
+        .is_some_and(|s| s.starts_with("http://") || s.starts_with("https://"))
+}
+
+// The part of the source that names the file, so extension checks aren't
+// thrown off by a query string or fragment on a URL
+pub fn file_path(source: &Path) -> PathBuf {
+    match source.to_str() {
+        Some(url) if is_url(source) => {
+            let end = url.find(['?', '#']).unwrap_or(url.len());
+            PathBuf::from(&url[..end])
+        }
+        _ => source.to_path_buf(),
+    }
+}
+
+// Open a streaming GET request. Redirects are followed, and the size is
+// taken from Content-Length when the server sends one.
+pub fn open_url(url: &str) -> Result<(Box<dyn Read + Send>, Option<u64>), WriterError> {
+    info!("Downloading {}", url);
+
+    let response = reqwest::blocking::Client::builder()
+        .timeout(None)
+        .build()
+        .and_then(|client| client.get(url).send())
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| WriterError::DownloadFailed(e.to_string()))?;
+
+    let content_length = response.content_length();
+    Ok((Box::new(response), content_length))
+}
@@ -0,0 +1,144 @@
+use std::ffi::OsString;
+use std::path::Path;
+
+use schrijver::tr;
+
+// Whether we can open raw devices for writing. Windows doesn't let us check
+// cheaply, so it counts as not elevated and the relaunch is always offered.
+pub fn is_elevated() -> bool {
+    #[cfg(unix)]
+    {
+        unsafe { libc::geteuid() == 0 }
+    }
+
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+// Arguments that bring the relaunched GUI back to the same selection
+pub fn relaunch_args(iso_path: Option<&Path>, device_path: Option<&str>) -> Vec<OsString> {
+    let mut args = Vec::new();
+    if let Some(iso_path) = iso_path {
+        args.push(OsString::from("--select-iso"));
+        args.push(iso_path.as_os_str().to_owned());
+    }
+    if let Some(device_path) = device_path {
+        args.push(OsString::from("--select-device"));
+        args.push(OsString::from(device_path));
+    }
+    args
+}
+
+// Start a second, elevated instance with the same selection and wait for it
+// to exit, so a dismissed password prompt leaves this instance as it was
+pub async fn relaunch_elevated(iso_path: Option<&Path>, device_path: Option<&str>) -> Result<(), String> {
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    let args = relaunch_args(iso_path, device_path);
+
+    let status = elevated_command(&exe, &args)
+        .status()
+        .await
+        .map_err(|e| tr!("error.relaunch_start_failed", program = exe.display(), error = e))?;
+
+    match status.code() {
+        Some(0) => Ok(()),
+        // pkexec's codes for a dismissed or failed authentication
+        Some(126) | Some(127) => Err(tr!("error.relaunch_cancelled")),
+        _ => Err(tr!("error.relaunch_exited", status = status)),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn elevated_command(exe: &Path, args: &[OsString]) -> tokio::process::Command {
+    // pkexec starts from a clean environment, so hand over what a GUI needs
+    // to reach the user's display
+    let display_vars = ["DISPLAY", "XAUTHORITY", "WAYLAND_DISPLAY", "XDG_RUNTIME_DIR"];
+
+    let (program, mut command) = if which("pkexec") {
+        let mut command = tokio::process::Command::new("pkexec");
+        command.arg("env");
+        for var in display_vars {
+            if let Some(value) = std::env::var_os(var) {
+                let mut assignment = OsString::from(format!("{}=", var));
+                assignment.push(value);
+                command.arg(assignment);
+            }
+        }
+        ("pkexec", command)
+    } else {
+        let mut command = tokio::process::Command::new("sudo");
+        command.arg(format!("--preserve-env={}", display_vars.join(",")));
+        ("sudo", command)
+    };
+
+    tracing::info!("Relaunching {} through {}", exe.display(), program);
+    command.arg(exe).args(args);
+    command
+}
+
+#[cfg(target_os = "linux")]
+fn which(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+#[cfg(target_os = "macos")]
+fn elevated_command(exe: &Path, args: &[OsString]) -> tokio::process::Command {
+    // AppleScript shows the standard administrator password prompt
+    let quote = |value: &std::ffi::OsStr| format!("'{}'", value.to_string_lossy().replace('\'', "'\\''"));
+    let shell_command = std::iter::once(quote(exe.as_os_str()))
+        .chain(args.iter().map(|arg| quote(arg)))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let script = format!(
+        "do shell script \"{}\" with administrator privileges",
+        shell_command.replace('\\', "\\\\").replace('"', "\\\"")
+    );
+
+    let mut command = tokio::process::Command::new("osascript");
+    command.arg("-e").arg(script);
+    command
+}
+
+#[cfg(target_os = "windows")]
+fn elevated_command(exe: &Path, args: &[OsString]) -> tokio::process::Command {
+    // Start-Process -Verb RunAs triggers the UAC prompt
+    let quote = |value: &std::ffi::OsStr| format!("'{}'", value.to_string_lossy().replace('\'', "''"));
+    let mut script = format!("Start-Process -Wait -Verb RunAs -FilePath {}", quote(exe.as_os_str()));
+    if !args.is_empty() {
+        // Each argument is quoted again for the elevated process's command line
+        let arg_list = args
+            .iter()
+            .map(|arg| quote(std::ffi::OsStr::new(&format!("\"{}\"", arg.to_string_lossy()))))
+            .collect::<Vec<_>>()
+            .join(",");
+        script.push_str(&format!(" -ArgumentList {}", arg_list));
+    }
+
+    let mut command = tokio::process::Command::new("powershell");
+    command.args(["-NoProfile", "-NonInteractive", "-Command", &script]);
+    command
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn elevated_command(exe: &Path, args: &[OsString]) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new("sudo");
+    command.arg(exe).args(args);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relaunch_args() {
+        assert_eq!(
+            relaunch_args(Some(Path::new("/home/user/debian.iso")), Some("/dev/sdb")),
+            vec!["--select-iso", "/home/user/debian.iso", "--select-device", "/dev/sdb"]
+        );
+        assert!(relaunch_args(None, None).is_empty());
+    }
+}
@@ -1,7 +1,8 @@
-use std::fmt;
 use std::io;
 use thiserror::Error;
 
+use crate::tr;
+
 #[derive(Error, Debug, Clone)]
 pub enum WriterError {
     #[error("ISO file not found: {0}")]
@@ -16,73 +17,159 @@ pub enum WriterError {
     #[error("Permission denied. Root privileges may be required.")]
     PermissionDenied,
 
-    #[error("Insufficient space on device")]
-    InsufficientSpace,
+    // Carries the offset at which the device stopped accepting data, when the
+    // shortage only showed up during the write
+    #[error("Insufficient space on device{}", .0.map(|offset| format!(" (full at byte {})", offset)).unwrap_or_default())]
+    InsufficientSpace(Option<u64>),
+
+    #[error("Image partition table needs {declared} bytes but the device has {device}")]
+    PartitionTableTooLarge { declared: u64, device: u64 },
+
+    #[error("Write verification failed at byte {offset}")]
+    VerificationFailed { offset: u64 },
+
+    #[error("Checksum mismatch: expected {expected}, got {actual}")]
+    HashMismatch { expected: String, actual: String },
+
+    #[error("IO error: {message}")]
+    IoError { kind: io::ErrorKind, message: String },
+
+    #[error("Device is busy or in use: {0}")]
+    DeviceBusy(String),
+
+    // Carries where it was lost, when that is known
+    #[error("Device was disconnected: {device}{}", .offset.map(|offset| format!(" at byte {}", offset)).unwrap_or_default())]
+    DeviceDisconnected { device: String, offset: Option<u64> },
+
+    #[error("Device is write-protected: {0}")]
+    DeviceReadOnly(String),
 
-    #[error("Write verification failed")]
-    VerificationFailed,
+    #[error("Couldn't add the persistence partition: {0}")]
+    PersistenceFailed(String),
 
-    #[error("IO error: {0}")]
-    IoError(String),
+    #[error("Formatting failed: {0}")]
+    FormatFailed(String),
 
-    #[error("Device is busy or in use")]
-    DeviceBusy,
+    #[error("The image is stored on the target device: {0}")]
+    SourceIsTarget(String),
 
-    #[error("Invalid ISO file format")]
+    #[error("Invalid image format")]
     InvalidIsoFormat,
 
+    #[error("Unsupported image format: {0}")]
+    UnsupportedImageFormat(String),
+
     #[error("Operation was cancelled")]
     Cancelled,
 
+    #[error("Refusing to write to the system disk: {0}")]
+    SystemDiskProtected(String),
+
+    #[error("Download failed: {0}")]
+    DownloadFailed(String),
+
+    #[error("Device detection failed: {0}")]
+    DetectionFailed(String),
+
+    #[error("Invalid checksum: {0}")]
+    InvalidChecksum(String),
+
+    #[error("Invalid buffer size: {0} bytes (must be a non-zero multiple of 512)")]
+    InvalidBufferSize(usize),
+
+    #[error("Invalid start offset: {0} bytes (must be a multiple of 512)")]
+    InvalidStartOffset(u64),
+
     #[error("Unknown error: {0}")]
     Unknown(String),
 }
 
 impl WriterError {
     pub fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            WriterError::DeviceMounted(_)
+                | WriterError::DeviceBusy(_)
+                | WriterError::Cancelled
+                | WriterError::IoError { kind: io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock, .. }
+        )
+    }
+
+    // Keep the error kind around so callers can tell transient failures
+    // (e.g. EINTR) apart from fatal ones like ENOSPC or EIO
+    pub fn from_io(error: io::Error) -> Self {
+        WriterError::IoError {
+            kind: error.kind(),
+            message: error.to_string(),
+        }
+    }
+
+    // Process exit code used by the headless CLI. 1 is reserved for unknown
+    // failures and 2 for argument errors (as reported by clap).
+    pub fn exit_code(&self) -> u8 {
         match self {
-            WriterError::DeviceMounted(_) => true,
-            WriterError::DeviceBusy => true,
-            WriterError::Cancelled => true,
-            _ => false,
+            WriterError::Unknown(_) => 1,
+            WriterError::IsoNotFound(_) => 3,
+            WriterError::DeviceNotFound(_) => 4,
+            WriterError::DeviceMounted(_) => 5,
+            WriterError::PermissionDenied => 6,
+            WriterError::InsufficientSpace(_) => 7,
+            WriterError::PartitionTableTooLarge { .. } => 7,
+            WriterError::VerificationFailed { .. } => 8,
+            WriterError::HashMismatch { .. } => 8,
+            WriterError::IoError { .. } => 9,
+            WriterError::DeviceBusy(_) => 10,
+            WriterError::InvalidIsoFormat => 11,
+            WriterError::UnsupportedImageFormat(_) => 11,
+            WriterError::Cancelled => 12,
+            WriterError::InvalidBufferSize(_) => 2,
+            WriterError::InvalidStartOffset(_) => 2,
+            WriterError::InvalidChecksum(_) => 2,
+            WriterError::SystemDiskProtected(_) => 13,
+            WriterError::DownloadFailed(_) => 14,
+            WriterError::DetectionFailed(_) => 15,
+            WriterError::DeviceDisconnected { .. } => 16,
+            WriterError::DeviceReadOnly(_) => 17,
+            WriterError::PersistenceFailed(_) => 18,
+            WriterError::FormatFailed(_) => 19,
+            WriterError::SourceIsTarget(_) => 20,
         }
     }
 
+    // In the language picked with i18n::set_language
     pub fn user_friendly_message(&self) -> String {
         match self {
-            WriterError::IsoNotFound(path) => {
-                format!("The ISO file '{}' could not be found. Please check if the file exists and try again.", path)
-            }
-            WriterError::DeviceNotFound(device) => {
-                format!("The USB device '{}' could not be found. Please ensure the device is connected and try refreshing the device list.", device)
-            }
-            WriterError::DeviceMounted(device) => {
-                format!("The device '{}' is currently mounted. Please unmount all partitions on this device before writing.", device)
-            }
-            WriterError::PermissionDenied => {
-                "Permission denied. You may need to run this application with administrator/root privileges to write to USB devices.".to_string()
-            }
-            WriterError::InsufficientSpace => {
-                "The USB device does not have enough space for this ISO file. Please use a larger USB device.".to_string()
-            }
-            WriterError::VerificationFailed => {
-                "The write operation completed, but verification failed. The data on the USB device may be corrupted. Please try again.".to_string()
-            }
-            WriterError::DeviceBusy => {
-                "The USB device is currently busy. Please wait a moment and try again.".to_string()
-            }
-            WriterError::InvalidIsoFormat => {
-                "The selected file does not appear to be a valid ISO file. Please select a proper ISO image.".to_string()
-            }
-            WriterError::Cancelled => {
-                "The operation was cancelled by the user.".to_string()
-            }
-            WriterError::IoError(err) => {
-                format!("An I/O error occurred: {}. Please check your system and device connections.", err)
+            WriterError::IsoNotFound(path) => tr!("error.iso_not_found", path = path),
+            WriterError::DeviceNotFound(device) => tr!("error.device_not_found", device = device),
+            WriterError::DeviceMounted(device) => tr!("error.device_mounted", device = device),
+            WriterError::PermissionDenied => tr!("error.permission_denied"),
+            WriterError::InsufficientSpace(None) => tr!("error.insufficient_space"),
+            WriterError::InsufficientSpace(Some(offset)) => tr!("error.insufficient_space_at", offset = offset),
+            WriterError::PartitionTableTooLarge { declared, device } => {
+                tr!("error.partition_table_too_large", declared = declared, device = device)
             }
-            WriterError::Unknown(msg) => {
-                format!("An unexpected error occurred: {}", msg)
+            WriterError::VerificationFailed { offset } => tr!("error.verification_failed", offset = offset),
+            WriterError::HashMismatch { expected, actual } => tr!("error.hash_mismatch", expected = expected, actual = actual),
+            WriterError::DeviceBusy(target) => tr!("error.device_busy", target = target),
+            WriterError::DeviceDisconnected { device, offset: None } => tr!("error.device_disconnected", device = device),
+            WriterError::DeviceDisconnected { device, offset: Some(offset) } => {
+                tr!("error.device_disconnected_at", device = device, offset = offset)
             }
+            WriterError::DeviceReadOnly(device) => tr!("error.device_read_only", device = device),
+            WriterError::PersistenceFailed(reason) => tr!("error.persistence_failed", reason = reason),
+            WriterError::FormatFailed(reason) => tr!("error.format_failed", reason = reason),
+            WriterError::SourceIsTarget(device) => tr!("error.source_is_target", device = device),
+            WriterError::InvalidIsoFormat => tr!("error.invalid_iso_format"),
+            WriterError::UnsupportedImageFormat(reason) => tr!("error.unsupported_image_format", reason = reason),
+            WriterError::Cancelled => tr!("error.cancelled"),
+            WriterError::SystemDiskProtected(device) => tr!("error.system_disk_protected", device = device),
+            WriterError::DownloadFailed(reason) => tr!("error.download_failed", reason = reason),
+            WriterError::DetectionFailed(reason) => tr!("error.detection_failed", reason = reason),
+            WriterError::InvalidChecksum(reason) => tr!("error.invalid_checksum", reason = reason),
+            WriterError::InvalidBufferSize(size) => tr!("error.invalid_buffer_size", size = size),
+            WriterError::InvalidStartOffset(offset) => tr!("error.invalid_start_offset", offset = offset),
+            WriterError::IoError { message, .. } => tr!("error.io", message = message),
+            WriterError::Unknown(message) => tr!("error.unknown", message = message),
         }
     }
 }
@@ -94,7 +181,7 @@ impl From<io::Error> for WriterError {
             io::ErrorKind::NotFound => WriterError::DeviceNotFound("Device not found".to_string()),
             io::ErrorKind::PermissionDenied => WriterError::PermissionDenied,
             io::ErrorKind::InvalidData => WriterError::InvalidIsoFormat,
-            _ => WriterError::IoError(error.to_string()),
+            _ => WriterError::from_io(error),
         }
     }
 }
\ No newline at end of file
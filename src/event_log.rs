@@ -0,0 +1,81 @@
+use chrono::{DateTime, Local};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // pad() so the log lines can align the levels
+        f.pad(match self {
+            LogLevel::Info => "INFO",
+            LogLevel::Warning => "WARN",
+            LogLevel::Error => "ERROR",
+        })
+    }
+}
+
+// One line of the in-app log, kept so users can attach it to bug reports
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    pub timestamp: DateTime<Local>,
+    pub level: LogLevel,
+    pub message: String,
+}
+
+impl LogEntry {
+    pub fn new(level: LogLevel, message: impl Into<String>) -> Self {
+        Self {
+            timestamp: Local::now(),
+            level,
+            message: message.into(),
+        }
+    }
+
+    pub fn info(message: impl Into<String>) -> Self {
+        Self::new(LogLevel::Info, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(LogLevel::Warning, message)
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(LogLevel::Error, message)
+    }
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:<5} {}", self.timestamp.format("%Y-%m-%d %H:%M:%S"), self.level, self.message)
+    }
+}
+
+// The saved log file, one entry per line
+pub fn format_log(entries: &[LogEntry]) -> String {
+    entries.iter().map(|entry| format!("{}\n", entry)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_format_log() {
+        let timestamp = Local.with_ymd_and_hms(2024, 3, 9, 14, 5, 7).unwrap();
+        let entries = [
+            LogEntry { timestamp, level: LogLevel::Info, message: "Write started".to_string() },
+            LogEntry { timestamp, level: LogLevel::Error, message: "Write failed".to_string() },
+        ];
+
+        assert_eq!(
+            format_log(&entries),
+            "2024-03-09 14:05:07 INFO  Write started\n2024-03-09 14:05:07 ERROR Write failed\n"
+        );
+    }
+}
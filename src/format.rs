@@ -0,0 +1,166 @@
+use std::fmt;
+
+use crate::error::WriterError;
+
+// What a stick gets formatted with when it is reclaimed after flashing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Filesystem {
+    // Readable everywhere, but files are limited to 4 GB
+    #[default]
+    Fat32,
+    Exfat,
+}
+
+impl Filesystem {
+    pub const ALL: [Filesystem; 2] = [Filesystem::Fat32, Filesystem::Exfat];
+}
+
+impl fmt::Display for Filesystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filesystem::Fat32 => write!(f, "FAT32"),
+            Filesystem::Exfat => write!(f, "exFAT"),
+        }
+    }
+}
+
+impl std::str::FromStr for Filesystem {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "fat32" | "vfat" => Ok(Filesystem::Fat32),
+            "exfat" => Ok(Filesystem::Exfat),
+            _ => Err(format!("unknown filesystem '{}' (expected fat32 or exfat)", value)),
+        }
+    }
+}
+
+// Both filesystems take labels of up to 11 characters. FAT32 stores them in
+// upper case and only some ASCII, so they are checked to spare a tool
+// rejecting them after the partition table is already gone.
+pub fn validate_label(filesystem: Filesystem, label: &str) -> Result<String, WriterError> {
+    let label = label.trim();
+    if label.chars().count() > 11 {
+        return Err(WriterError::FormatFailed("labels can be at most 11 characters".to_string()));
+    }
+
+    match filesystem {
+        Filesystem::Fat32 => {
+            let allowed = |c: char| c.is_ascii_alphanumeric() || " _-!#$%&'()@^`{}~".contains(c);
+            if !label.chars().all(allowed) {
+                return Err(WriterError::FormatFailed(format!("'{}' has characters a FAT32 label can't hold", label)));
+            }
+            Ok(label.to_ascii_uppercase())
+        }
+        Filesystem::Exfat => Ok(label.to_string()),
+    }
+}
+
+// Wipe the partition table of the device at `device_path` and give it a
+// single partition spanning it, formatted with `filesystem`. The device has
+// to be unmounted first. Uses sfdisk and mkfs.vfat or mkfs.exfat on Linux,
+// diskutil on macOS and the Storage cmdlets on Windows.
+pub async fn format_device(device_path: &str, filesystem: Filesystem, label: &str) -> Result<(), WriterError> {
+    let label = validate_label(filesystem, label)?;
+    tracing::info!("Formatting {} as {} '{}'", device_path, filesystem, label);
+
+    #[cfg(target_os = "linux")]
+    {
+        use crate::partition::{partition_node, run_tool};
+
+        // Partitions start on the first MiB, like the partitioning tools place them
+        const PARTITION_START: u64 = 1024 * 1024;
+
+        wipe_partition_tables(device_path)?;
+
+        let partition_type = match filesystem {
+            Filesystem::Fat32 => "c",
+            Filesystem::Exfat => "7",
+        };
+        let script = format!("label: dos\nstart={}, type={}\n", PARTITION_START / 512, partition_type);
+        run_tool("sfdisk", &[device_path], Some(&script), WriterError::FormatFailed).await?;
+        let node = partition_node(device_path, PARTITION_START, WriterError::FormatFailed).await?;
+
+        let (program, mut args) = match filesystem {
+            Filesystem::Fat32 => ("mkfs.vfat", vec!["-F", "32", "-n"]),
+            Filesystem::Exfat => ("mkfs.exfat", vec!["-L"]),
+        };
+        args.extend([label.as_str(), node.as_str()]);
+        run_tool(program, &args, None, WriterError::FormatFailed).await?;
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        let format = match filesystem {
+            Filesystem::Fat32 => "FAT32",
+            Filesystem::Exfat => "ExFAT",
+        };
+        crate::partition::run_tool("diskutil", &["eraseDisk", format, label.as_str(), "MBRFormat", device_path], None, WriterError::FormatFailed).await?;
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        // \\.\PhysicalDrive2 is disk number 2 to the Storage cmdlets
+        let number = device_path.rsplit(|c: char| !c.is_ascii_digit()).next().unwrap_or_default();
+        if number.is_empty() {
+            return Err(WriterError::FormatFailed(format!("{} isn't a physical drive", device_path)));
+        }
+        let format = match filesystem {
+            Filesystem::Fat32 => "FAT32",
+            Filesystem::Exfat => "exFAT",
+        };
+        // Clear-Disk fails on a disk without a partition table, which is fine
+        let script = format!(
+            "$ErrorActionPreference = 'Stop'; \
+             Clear-Disk -Number {number} -RemoveData -RemoveOEM -Confirm:$false -ErrorAction SilentlyContinue; \
+             Initialize-Disk -Number {number} -PartitionStyle MBR; \
+             New-Partition -DiskNumber {number} -UseMaximumSize -AssignDriveLetter | \
+             Format-Volume -FileSystem {format} -NewFileSystemLabel '{label}' -Confirm:$false | Out-Null",
+            label = label.replace('\'', "''"),
+        );
+        crate::partition::run_tool("powershell", &["-NoProfile", "-NonInteractive", "-Command", &script], None, WriterError::FormatFailed).await?;
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+    {
+        let _ = device_path;
+        return Err(WriterError::FormatFailed("not supported on this platform".to_string()));
+    }
+
+    tracing::info!("Formatted {} as {}", device_path, filesystem);
+    Ok(())
+}
+
+// Zero the first and last MiB, which hold the MBR, both GPT copies and the
+// ISO 9660 volume descriptors, so nothing recognizes the old layout anymore
+#[cfg(target_os = "linux")]
+fn wipe_partition_tables(device_path: &str) -> Result<(), WriterError> {
+    use std::io::{Seek, SeekFrom, Write};
+
+    const WIPE_SPAN: u64 = 1024 * 1024;
+
+    let mut device = std::fs::OpenOptions::new().write(true).open(device_path).map_err(WriterError::from)?;
+    let size = device.seek(SeekFrom::End(0)).map_err(WriterError::from_io)?;
+    let zeros = vec![0u8; WIPE_SPAN.min(size) as usize];
+
+    device.seek(SeekFrom::Start(0)).map_err(WriterError::from_io)?;
+    device.write_all(&zeros).map_err(WriterError::from_io)?;
+    device.seek(SeekFrom::Start(size - zeros.len() as u64)).map_err(WriterError::from_io)?;
+    device.write_all(&zeros).map_err(WriterError::from_io)?;
+    device.sync_all().map_err(WriterError::from_io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_label() {
+        assert_eq!(validate_label(Filesystem::Fat32, " Stick 1 ").unwrap(), "STICK 1");
+        assert_eq!(validate_label(Filesystem::Exfat, "Foto's").unwrap(), "Foto's");
+
+        assert!(matches!(validate_label(Filesystem::Fat32, "Fotos.2025"), Err(WriterError::FormatFailed(_))));
+        assert!(matches!(validate_label(Filesystem::Exfat, "Much too long"), Err(WriterError::FormatFailed(_))));
+    }
+}
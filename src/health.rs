@@ -0,0 +1,102 @@
+use serde_json::Value;
+
+// SMART attribute ATA drives count remapped bad sectors in
+const REALLOCATED_SECTOR_COUNT: u64 = 5;
+
+// What a drive says about its own health, for the USB-SATA bridges that pass
+// SMART through. Only ever used to warn, never to refuse a write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthSummary {
+    // The drive's overall self-assessment
+    pub passed: bool,
+    pub reallocated_sectors: Option<u64>,
+}
+
+impl HealthSummary {
+    pub fn warning(&self) -> Option<String> {
+        if !self.passed {
+            return Some("Drive reports FAILING health. It may lose data soon.".to_string());
+        }
+
+        match self.reallocated_sectors {
+            Some(count) if count > 0 => Some(format!("Drive reports {} reallocated sectors, a sign it is wearing out.", count)),
+            _ => None,
+        }
+    }
+}
+
+// Ask smartctl (from smartmontools) for the drive's health. None when it
+// isn't installed, lacks the rights, or the bridge doesn't pass SMART through.
+#[cfg(feature = "smart")]
+pub async fn device_health(device_path: &str) -> Option<HealthSummary> {
+    // Some bridges stall on SMART commands instead of rejecting them
+    const SMARTCTL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+    let command = tokio::process::Command::new("smartctl")
+        .args(["--json", "--health", "--attributes", device_path])
+        .kill_on_drop(true)
+        .output();
+    let output = match tokio::time::timeout(SMARTCTL_TIMEOUT, command).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(error)) => {
+            tracing::debug!("smartctl isn't available: {}", error);
+            return None;
+        }
+        Err(_) => {
+            tracing::warn!("smartctl timed out on {}", device_path);
+            return None;
+        }
+    };
+
+    // The exit code is a bit mask that is non-zero for failing drives too,
+    // so the JSON decides
+    let health = parse_smartctl_output(&String::from_utf8_lossy(&output.stdout));
+    tracing::info!("SMART health of {}: {:?}", device_path, health);
+    health
+}
+
+// Built without SMART support
+#[cfg(not(feature = "smart"))]
+pub async fn device_health(_device_path: &str) -> Option<HealthSummary> {
+    None
+}
+
+// `smartctl --json --health --attributes` output
+pub fn parse_smartctl_output(json: &str) -> Option<HealthSummary> {
+    let output: Value = serde_json::from_str(json).ok()?;
+    let passed = output["smart_status"]["passed"].as_bool()?;
+    let reallocated_sectors = output["ata_smart_attributes"]["table"]
+        .as_array()
+        .and_then(|table| table.iter().find(|attribute| attribute["id"].as_u64() == Some(REALLOCATED_SECTOR_COUNT)))
+        .and_then(|attribute| attribute["raw"]["value"].as_u64());
+
+    Some(HealthSummary { passed, reallocated_sectors })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_smartctl_output() {
+        let failing = r#"{
+            "smart_status": {"passed": false},
+            "ata_smart_attributes": {"table": [
+                {"id": 1, "name": "Raw_Read_Error_Rate", "raw": {"value": 0}},
+                {"id": 5, "name": "Reallocated_Sector_Ct", "raw": {"value": 112}}
+            ]}
+        }"#;
+        assert_eq!(
+            parse_smartctl_output(failing),
+            Some(HealthSummary { passed: false, reallocated_sectors: Some(112) })
+        );
+        assert!(parse_smartctl_output(failing).unwrap().warning().unwrap().contains("FAILING"));
+
+        let healthy = r#"{"smart_status": {"passed": true}}"#;
+        assert_eq!(parse_smartctl_output(healthy).unwrap().warning(), None);
+
+        // A bridge that doesn't pass SMART through reports no status at all
+        let unsupported = r#"{"smartctl": {"exit_status": 2}}"#;
+        assert_eq!(parse_smartctl_output(unsupported), None);
+    }
+}
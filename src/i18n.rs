@@ -0,0 +1,344 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Language {
+    #[default]
+    #[serde(rename = "en")]
+    English,
+    #[serde(rename = "nl")]
+    Dutch,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::English, Language::Dutch];
+
+    // Locale names like "nl", "nl_BE.UTF-8" or "nl-NL"; anything without a
+    // table of its own gets English
+    pub fn from_locale(locale: &str) -> Self {
+        match locale.split(['_', '-', '.']).next().map(str::to_lowercase).as_deref() {
+            Some("nl") => Language::Dutch,
+            _ => Language::English,
+        }
+    }
+
+    // The desktop's language, as the usual locale variables give it. Windows
+    // and macOS don't normally set them, so there it ends up as English.
+    pub fn system() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .map_or(Language::English, |locale| Language::from_locale(&locale))
+    }
+
+    fn table(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Language::English => ENGLISH,
+            Language::Dutch => DUTCH,
+        }
+    }
+}
+
+impl std::fmt::Display for Language {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Language::English => "English",
+            Language::Dutch => "Nederlands",
+        })
+    }
+}
+
+static LANGUAGE: AtomicU8 = AtomicU8::new(0);
+
+pub fn set_language(language: Language) {
+    LANGUAGE.store(language as u8, Ordering::Relaxed);
+}
+
+pub fn language() -> Language {
+    match LANGUAGE.load(Ordering::Relaxed) {
+        1 => Language::Dutch,
+        _ => Language::English,
+    }
+}
+
+// Looks `key` up in the current language, e.g.
+// `tr!("error.device_busy", target = device_path)`
+#[macro_export]
+macro_rules! tr {
+    ($key:expr) => {
+        $crate::i18n::translate($key, &[])
+    };
+    ($key:expr, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::i18n::translate($key, &[$((stringify!($name), &$value as &dyn ::std::fmt::Display)),+])
+    };
+}
+
+pub fn translate(key: &str, args: &[(&str, &dyn Display)]) -> String {
+    translate_in(language(), key, args)
+}
+
+// Keys missing from a table fall back to English, and unknown keys show up
+// as themselves rather than as an empty message
+pub fn translate_in(language: Language, key: &str, args: &[(&str, &dyn Display)]) -> String {
+    let find = |language: Language| language.table().iter().find(|(name, _)| *name == key).map(|(_, text)| *text);
+    let template = find(language).or_else(|| find(Language::English)).unwrap_or(key);
+
+    // Placeholders are filled in a single pass, so a value that happens to
+    // contain "{name}" (say, a file name) is left as it is
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        text.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        match after.find('}').and_then(|close| Some((close, args.iter().find(|(name, _)| *name == &after[..close])?))) {
+            Some((close, (_, value))) => {
+                text.push_str(&value.to_string());
+                rest = &after[close + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = after;
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+const ENGLISH: &[(&str, &str)] = &[
+    // Errors, see WriterError::user_friendly_message
+    ("error.iso_not_found", "The image file '{path}' could not be found. Please check if the file exists and try again."),
+    ("error.device_not_found", "The USB device '{device}' could not be found. Please ensure the device is connected and try refreshing the device list."),
+    ("error.device_mounted", "The device '{device}' is currently mounted. Please unmount all partitions on this device before writing."),
+    ("error.permission_denied", "Permission denied. You may need to run this application with administrator/root privileges to write to USB devices."),
+    ("error.insufficient_space", "The USB device does not have enough space for this image. Please use a larger USB device."),
+    ("error.insufficient_space_at", "The USB device stopped accepting data at byte {offset}, even though it claims to be larger. It may be a counterfeit drive with only that much real capacity."),
+    ("error.partition_table_too_large", "The image's partition table expects a {declared} byte disk, but the USB device only has {device} bytes. The image would fit, but its partition table would be corrupt. Please use a larger USB device."),
+    ("error.verification_failed", "The write operation completed, but verification failed at byte {offset}. The data on the USB device may be corrupted. Please try again."),
+    ("error.hash_mismatch", "The data read back from the USB device does not match the image (expected {expected}, got {actual}). Please try writing again."),
+    ("error.device_busy", "The USB device is currently busy ({target}). Please close any programs using it, wait a moment and try again."),
+    ("error.device_disconnected", "{device} was disconnected; reconnect it and start over."),
+    ("error.device_disconnected_at", "{device} was disconnected at byte {offset}; reconnect it and start over."),
+    ("error.device_read_only", "{device} is write-protected. Slide the lock switch on the card (or its adapter) away from LOCK, reinsert it and try again."),
+    ("error.persistence_failed", "Couldn't add the persistence partition: {reason}."),
+    ("error.format_failed", "Formatting failed: {reason}."),
+    ("error.source_is_target", "The image is stored on {device} itself, so writing it there would destroy it partway through. Copy the image to another disk first."),
+    ("error.invalid_iso_format", "The selected file does not appear to be a valid disk image. Please select a proper .iso or .img image."),
+    ("error.unsupported_image_format", "The image can't be written directly: {reason}."),
+    ("error.cancelled", "The operation was cancelled by the user."),
+    ("error.system_disk_protected", "The device '{device}' holds your running system (/, /boot or /home is mounted from it) and cannot be overwritten."),
+    ("error.download_failed", "The image could not be downloaded: {reason}. Please check the URL and your network connection."),
+    ("error.detection_failed", "The USB devices could not be listed: {reason}. Devices that are plugged in may be missing from the list."),
+    ("error.invalid_checksum", "The expected checksum could not be used: {reason}. Use <algorithm>:<hex> or paste the distribution's checksum file."),
+    ("error.invalid_buffer_size", "The buffer size {size} is not valid. It must be a non-zero multiple of 512 bytes."),
+    ("error.invalid_start_offset", "The start offset {offset} is not valid. It must be a multiple of 512 bytes."),
+    ("error.io", "An I/O error occurred: {message}. Please check your system and device connections."),
+    ("error.unknown", "An unexpected error occurred: {message}"),
+    // Status line of the GUI
+    ("status.ready", "Ready to write a disk image to USB"),
+    ("status.error", "Error: {error}"),
+    ("status.image_selected", "Image file selected: {path}"),
+    ("status.unsupported_file", "Unsupported file: {path} (expected a disk image such as .iso or .img)"),
+    ("status.devices_found", "Found {count} USB devices"),
+    ("status.detection_unavailable", "Device detection unavailable in this environment: {reason}"),
+    ("status.unplugged", "{device} was unplugged"),
+    ("status.device_moved", "{device} is now {path} (was {previous})"),
+    ("status.device_selected", "Selected device: {device}"),
+    ("status.image_file_target", "Writing to image file: {path}"),
+    ("status.size_image", "Image {image}"),
+    ("status.size_image_device", "Image {image} → device {device}"),
+    ("status.size_from_byte", " from byte {offset}"),
+    ("status.size_short", "{sizes} ({short} short). Please select a larger device."),
+    ("status.size_unused", "{sizes} ({unused} unused)"),
+    ("status.waiting_for_confirmation", "Waiting for confirmation..."),
+    ("status.checking_device", "Checking device..."),
+    ("status.checking_signature", "Verifying image signature..."),
+    ("status.checking_size", "Checking size..."),
+    ("status.checking_resume", "Checking what the interrupted write left on the device..."),
+    ("status.hash_copied", "SHA-256 copied to the clipboard"),
+    ("status.creating_persistence", "Adding the persistence partition..."),
+    ("status.type_to_confirm", "{device} is a large disk. Type its size ({size}) or name to confirm."),
+    ("status.confirmation_mismatch", "That isn't the size ({size}) or name of {device}."),
+    ("status.write_cancelled", "Write cancelled"),
+    ("status.write_cancelled_inconsistent", "Write cancelled — device may be in an inconsistent state."),
+    ("status.cancelling_write", "Cancelling write..."),
+    ("status.cancelling_verification", "Cancelling verification..."),
+    ("status.cancelling_to_quit", "Cancelling before quitting..."),
+    ("status.paused", "Write paused"),
+    ("status.writing", "Writing image to USB device..."),
+    ("status.unmounting_and_writing", "Unmounting device and writing image..."),
+    ("status.dry_run_started", "Dry run: checking image and device without writing..."),
+    ("status.device_still_busy", "The device is still busy, waiting for it to be released (retry {retry})..."),
+    ("status.dry_run_ok", "Dry run OK: would write {bytes} bytes to {target}"),
+    ("status.written_full", "Image successfully written to {target} and verified!"),
+    ("status.written_inline", "Image successfully written to {target}, every block verified as it was written!"),
+    ("status.written_quick", "Image successfully written to {target}! Quick-verified (sampled blocks only)."),
+    ("status.written_unverified", "Image successfully written to {target} (not verified)."),
+    ("status.verification_cancelled", "Image written successfully; verification cancelled, so the device contents are unchecked."),
+    ("status.verification_failed_retry", "Verification failed at {offset} — retry this region?"),
+    ("status.write_error", "Error writing to {target}: {error}"),
+    ("status.relaunch_hint", " Relaunch as administrator to write to the device."),
+    ("status.flush_failed_hint", " Warning: flushing the device failed ({error}), eject it before unplugging."),
+    ("status.ejected_hint", " The device was ejected and can be unplugged."),
+    ("status.eject_failed_hint", " Warning: ejecting failed ({error}), eject it manually before unplugging."),
+    ("status.rewriting_region", "Rewriting the region at {offset}..."),
+    ("status.region_repaired", "Region rewritten and verified. Run a full write again if problems persist."),
+    ("status.region_still_fails", "Verification still fails at {offset} — the device may be faulty."),
+    ("status.loading_into_memory", "Reading the image into memory..."),
+    ("status.parallel_started", "0 of {total} done — writing all devices at once..."),
+    ("status.parallel_progress", "{done} of {total} done"),
+    ("status.parallel_device_starting", "Starting..."),
+    ("status.parallel_device_done", "Done"),
+    ("status.parallel_device_failed", "Failed: {error}"),
+    ("status.batch_progress", "{done} of {total} done — writing {device} ({name})..."),
+    ("status.batch_finished", "Batch finished: {succeeded} of {total} devices written successfully"),
+    ("status.capacity_testing", "Testing the capacity of {device}. This overwrites the whole device..."),
+    ("status.capacity_passed", "Capacity test passed: all {size} hold their data."),
+    ("status.capacity_failed", "Capacity test FAILED: only {usable} of the claimed {claimed} hold their data. This looks like a fake-capacity drive."),
+    ("status.capacity_cancelled", "Capacity test cancelled"),
+    ("status.formatting", "Formatting {device} as {filesystem}..."),
+    ("status.formatted", "Formatted as {filesystem}"),
+    ("status.format_cancelled", "Formatting cancelled"),
+    ("status.capacity_scrambled", "Capacity test cancelled — the device contents are now scrambled."),
+    ("status.comparing", "Comparing {device} against {image}..."),
+    ("status.verify_only_passed", "Verify Only passed: the device matches the image"),
+    ("status.verify_only_failed", "Verify Only FAILED: the device differs from the image at byte {offset} ({size})"),
+    ("status.relaunching", "Relaunching with administrator rights..."),
+    ("status.relaunch_failed", "Could not relaunch as administrator: {error}"),
+    ("error.relaunch_start_failed", "could not start {program}: {error}"),
+    ("error.relaunch_cancelled", "authentication was cancelled or failed"),
+    ("error.relaunch_exited", "the elevated instance exited with {status}"),
+];
+
+const DUTCH: &[(&str, &str)] = &[
+    ("error.iso_not_found", "Het imagebestand '{path}' is niet gevonden. Controleer of het bestand bestaat en probeer het opnieuw."),
+    ("error.device_not_found", "Het USB-apparaat '{device}' is niet gevonden. Controleer of het apparaat is aangesloten en vernieuw de lijst met apparaten."),
+    ("error.device_mounted", "Het apparaat '{device}' is gekoppeld. Ontkoppel alle partities op dit apparaat voordat je gaat schrijven."),
+    ("error.permission_denied", "Toegang geweigerd. Mogelijk moet je dit programma met beheerders- of rootrechten starten om naar USB-apparaten te schrijven."),
+    ("error.insufficient_space", "Het USB-apparaat heeft niet genoeg ruimte voor dit image. Gebruik een groter USB-apparaat."),
+    ("error.insufficient_space_at", "Het USB-apparaat nam vanaf byte {offset} geen gegevens meer aan, hoewel het groter zegt te zijn. Het is mogelijk een namaakstick met maar zoveel echte capaciteit."),
+    ("error.partition_table_too_large", "De partitietabel van het image verwacht een schijf van {declared} bytes, maar het USB-apparaat heeft er maar {device}. Het image zou passen, maar de partitietabel zou dan beschadigd zijn. Gebruik een groter USB-apparaat."),
+    ("error.verification_failed", "Het schrijven is voltooid, maar de verificatie is mislukt bij byte {offset}. De gegevens op het USB-apparaat zijn mogelijk beschadigd. Probeer het opnieuw."),
+    ("error.hash_mismatch", "De van het USB-apparaat teruggelezen gegevens komen niet overeen met het image (verwacht {expected}, gekregen {actual}). Probeer opnieuw te schrijven."),
+    ("error.device_busy", "Het USB-apparaat is bezet ({target}). Sluit programma's die het gebruiken, wacht even en probeer het opnieuw."),
+    ("error.device_disconnected", "{device} is losgekoppeld; sluit het opnieuw aan en begin opnieuw."),
+    ("error.device_disconnected_at", "{device} is bij byte {offset} losgekoppeld; sluit het opnieuw aan en begin opnieuw."),
+    ("error.device_read_only", "{device} is tegen schrijven beveiligd. Schuif het schakelaartje op de kaart (of de adapter) weg van LOCK, plaats hem opnieuw en probeer het nog eens."),
+    ("error.persistence_failed", "Kon de persistentiepartitie niet toevoegen: {reason}."),
+    ("error.format_failed", "Formatteren mislukt: {reason}."),
+    ("error.source_is_target", "Het image staat op {device} zelf, dus het daarheen schrijven zou het halverwege vernietigen. Kopieer het image eerst naar een andere schijf."),
+    ("error.invalid_iso_format", "Het gekozen bestand lijkt geen geldig schijfimage te zijn. Kies een echt .iso- of .img-image."),
+    ("error.unsupported_image_format", "Het image kan niet direct worden geschreven: {reason}."),
+    ("error.cancelled", "De bewerking is door de gebruiker geannuleerd."),
+    ("error.system_disk_protected", "Het apparaat '{device}' bevat je draaiende systeem (/, /boot of /home is ervan gekoppeld) en kan niet worden overschreven."),
+    ("error.download_failed", "Het image kon niet worden gedownload: {reason}. Controleer de URL en je netwerkverbinding."),
+    ("error.detection_failed", "De USB-apparaten konden niet worden opgesomd: {reason}. Aangesloten apparaten ontbreken mogelijk in de lijst."),
+    ("error.invalid_checksum", "De verwachte checksum kan niet worden gebruikt: {reason}. Gebruik <algoritme>:<hex> of plak het checksumbestand van de distributie."),
+    ("error.invalid_buffer_size", "De buffergrootte {size} is ongeldig. Die moet een veelvoud van 512 bytes zijn, en niet nul."),
+    ("error.invalid_start_offset", "De startpositie {offset} is ongeldig. Die moet een veelvoud van 512 bytes zijn."),
+    ("error.io", "Er is een I/O-fout opgetreden: {message}. Controleer je systeem en de verbinding met het apparaat."),
+    ("error.unknown", "Er is een onverwachte fout opgetreden: {message}"),
+    ("status.ready", "Klaar om een schijfimage naar USB te schrijven"),
+    ("status.error", "Fout: {error}"),
+    ("status.image_selected", "Imagebestand gekozen: {path}"),
+    ("status.unsupported_file", "Niet-ondersteund bestand: {path} (verwacht een schijfimage zoals .iso of .img)"),
+    ("status.devices_found", "{count} USB-apparaten gevonden"),
+    ("status.detection_unavailable", "Apparaatdetectie is in deze omgeving niet beschikbaar: {reason}"),
+    ("status.unplugged", "{device} is losgekoppeld"),
+    ("status.device_moved", "{device} is nu {path} (was {previous})"),
+    ("status.device_selected", "Gekozen apparaat: {device}"),
+    ("status.image_file_target", "Schrijven naar imagebestand: {path}"),
+    ("status.size_image", "Image {image}"),
+    ("status.size_image_device", "Image {image} → apparaat {device}"),
+    ("status.size_from_byte", " vanaf byte {offset}"),
+    ("status.size_short", "{sizes} ({short} te weinig). Kies een groter apparaat."),
+    ("status.size_unused", "{sizes} ({unused} ongebruikt)"),
+    ("status.waiting_for_confirmation", "Wachten op bevestiging..."),
+    ("status.checking_device", "Apparaat wordt gecontroleerd..."),
+    ("status.checking_signature", "Signatuur van het image wordt gecontroleerd..."),
+    ("status.checking_size", "Grootte wordt gecontroleerd..."),
+    ("status.checking_resume", "Wat de onderbroken schrijfactie achterliet wordt gecontroleerd..."),
+    ("status.hash_copied", "SHA-256 naar het klembord gekopieerd"),
+    ("status.creating_persistence", "De persistentiepartitie wordt toegevoegd..."),
+    ("status.type_to_confirm", "{device} is een grote schijf. Typ de grootte ({size}) of naam om te bevestigen."),
+    ("status.confirmation_mismatch", "Dat is niet de grootte ({size}) of naam van {device}."),
+    ("status.write_cancelled", "Schrijven geannuleerd"),
+    ("status.write_cancelled_inconsistent", "Schrijven geannuleerd — het apparaat kan in een inconsistente toestand zijn."),
+    ("status.cancelling_write", "Schrijven wordt geannuleerd..."),
+    ("status.cancelling_verification", "Verificatie wordt geannuleerd..."),
+    ("status.cancelling_to_quit", "Annuleren voor het afsluiten..."),
+    ("status.paused", "Schrijven gepauzeerd"),
+    ("status.writing", "Image wordt naar het USB-apparaat geschreven..."),
+    ("status.unmounting_and_writing", "Apparaat wordt ontkoppeld en image wordt geschreven..."),
+    ("status.dry_run_started", "Proefrun: image en apparaat controleren zonder te schrijven..."),
+    ("status.device_still_busy", "Het apparaat is nog bezet, wachten tot het wordt vrijgegeven (poging {retry})..."),
+    ("status.dry_run_ok", "Proefrun OK: zou {bytes} bytes naar {target} schrijven"),
+    ("status.written_full", "Image naar {target} geschreven en geverifieerd!"),
+    ("status.written_inline", "Image naar {target} geschreven, elk blok geverifieerd tijdens het schrijven!"),
+    ("status.written_quick", "Image naar {target} geschreven! Snel geverifieerd (alleen steekproefblokken)."),
+    ("status.written_unverified", "Image naar {target} geschreven (niet geverifieerd)."),
+    ("status.verification_cancelled", "Image geschreven; verificatie geannuleerd, dus de inhoud van het apparaat is niet gecontroleerd."),
+    ("status.verification_failed_retry", "Verificatie mislukt bij {offset} — dit gebied opnieuw proberen?"),
+    ("status.write_error", "Fout bij schrijven naar {target}: {error}"),
+    ("status.relaunch_hint", " Start opnieuw als beheerder om naar het apparaat te schrijven."),
+    ("status.flush_failed_hint", " Waarschuwing: het legen van de buffers is mislukt ({error}), werp het apparaat uit voordat je het loskoppelt."),
+    ("status.ejected_hint", " Het apparaat is uitgeworpen en kan worden losgekoppeld."),
+    ("status.eject_failed_hint", " Waarschuwing: uitwerpen mislukt ({error}), werp het zelf uit voordat je het loskoppelt."),
+    ("status.rewriting_region", "Het gebied bij {offset} wordt herschreven..."),
+    ("status.region_repaired", "Gebied herschreven en geverifieerd. Schrijf het hele image opnieuw als de problemen blijven."),
+    ("status.region_still_fails", "Verificatie mislukt nog steeds bij {offset} — het apparaat is mogelijk defect."),
+    ("status.loading_into_memory", "Het image wordt in het geheugen ingelezen..."),
+    ("status.parallel_started", "0 van {total} klaar — alle apparaten worden tegelijk geschreven..."),
+    ("status.parallel_progress", "{done} van {total} klaar"),
+    ("status.parallel_device_starting", "Bezig met starten..."),
+    ("status.parallel_device_done", "Klaar"),
+    ("status.parallel_device_failed", "Mislukt: {error}"),
+    ("status.batch_progress", "{done} van {total} klaar — {device} ({name}) wordt geschreven..."),
+    ("status.batch_finished", "Reeks klaar: {succeeded} van {total} apparaten geschreven"),
+    ("status.capacity_testing", "De capaciteit van {device} wordt getest. Dit overschrijft het hele apparaat..."),
+    ("status.capacity_passed", "Capaciteitstest geslaagd: alle {size} houden hun gegevens vast."),
+    ("status.capacity_failed", "Capaciteitstest MISLUKT: maar {usable} van de opgegeven {claimed} houden hun gegevens vast. Dit lijkt een stick met nepcapaciteit."),
+    ("status.capacity_cancelled", "Capaciteitstest geannuleerd"),
+    ("status.formatting", "{device} wordt geformatteerd als {filesystem}..."),
+    ("status.formatted", "Geformatteerd als {filesystem}"),
+    ("status.format_cancelled", "Formatteren geannuleerd"),
+    ("status.capacity_scrambled", "Capaciteitstest geannuleerd — de inhoud van het apparaat is nu onbruikbaar."),
+    ("status.comparing", "{device} wordt vergeleken met {image}..."),
+    ("status.verify_only_passed", "Alleen verifiëren geslaagd: het apparaat komt overeen met het image"),
+    ("status.verify_only_failed", "Alleen verifiëren MISLUKT: het apparaat wijkt af van het image bij byte {offset} ({size})"),
+    ("status.relaunching", "Opnieuw starten met beheerdersrechten..."),
+    ("status.relaunch_failed", "Kan niet opnieuw starten als beheerder: {error}"),
+    ("error.relaunch_start_failed", "kon {program} niet starten: {error}"),
+    ("error.relaunch_cancelled", "de authenticatie is geannuleerd of mislukt"),
+    ("error.relaunch_exited", "de verhoogde instantie stopte met {status}"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(
+            translate_in(Language::Dutch, "error.device_busy", &[("target", &"/dev/sdb")]),
+            "Het USB-apparaat is bezet (/dev/sdb). Sluit programma's die het gebruiken, wacht even en probeer het opnieuw."
+        );
+        assert_eq!(translate_in(Language::English, "status.unplugged", &[("device", &"{device}")]), "{device} was unplugged");
+        assert_eq!(translate_in(Language::Dutch, "status.no_such_key", &[]), "status.no_such_key");
+        assert_eq!(Language::from_locale("nl_BE.UTF-8"), Language::Dutch);
+        assert_eq!(Language::from_locale("C"), Language::English);
+
+        // Every message has a Dutch version that fills in the same placeholders
+        let placeholders = |text: &str| {
+            let mut names: Vec<String> = text.split('{').skip(1).filter_map(|part| Some(part.split_once('}')?.0.to_string())).collect();
+            names.sort();
+            names
+        };
+        for (key, english) in ENGLISH {
+            let dutch = DUTCH.iter().find(|(name, _)| name == key).map(|(_, text)| *text);
+            assert_eq!(dutch.map(placeholders), Some(placeholders(english)), "{}", key);
+        }
+    }
+}
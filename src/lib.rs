@@ -0,0 +1,19 @@
+// The writer as a library, so other programs can flash images without the
+// GUI. The schrijver binary (GUI and headless CLI) is built on top of it.
+pub mod checksum;
+pub mod device;
+pub mod download;
+pub mod error;
+pub mod format;
+pub mod health;
+pub mod i18n;
+pub mod partition;
+pub mod persistence;
+pub mod resume;
+#[cfg(target_os = "linux")]
+pub mod udisks;
+pub mod writer;
+
+pub use device::{detect_usb_devices, validate_device_for_writing, DeviceBackend, PartitionInfo, UsbDevice};
+pub use error::WriterError;
+pub use writer::{write_iso_to_device, CancelToken, PauseToken, UsbWriter, WriteOptions, WriteProgress, WriteSummary};
@@ -1,48 +1,392 @@
-use iced::widget::{button, column, container, row, text, progress_bar, pick_list};
-use iced::{Alignment, Application, Command, Element, Length, Settings, Theme};
-use rfd::AsyncFileDialog;
+use iced::widget::{button, checkbox, column, container, responsive, row, scrollable, text, text_input, progress_bar, pick_list, slider};
+use iced::futures::stream::{self, Stream, StreamExt};
+use iced::{event, window, Alignment, Application, Command, Element, Event, Length, Settings, Subscription, Theme};
+use rfd::{AsyncFileDialog, AsyncMessageDialog, MessageButtons, MessageDialogResult, MessageLevel};
+use std::collections::VecDeque;
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::EnvFilter;
 
-mod writer;
-mod device;
-mod error;
+use clap::Parser;
+use std::process::ExitCode;
 
-use writer::UsbWriter;
-use device::{UsbDevice, detect_usb_devices};
+mod cli;
+mod settings;
+mod event_log;
+mod elevate;
+mod about;
+
+use schrijver::{checksum, device, download, error, format, health, i18n, persistence, resume, tr, writer};
+
+use writer::{CancelToken, ImageBuffer, IoMode, PauseToken, Phase, PipelineStats, UsbWriter, ValidationStep, VerifyMode, VerifyOutcome, WriteOptions, WriteProgress, WriteSummary};
+use device::{DeviceBackend, UsbDevice};
+use format::Filesystem;
+use persistence::{PersistenceLayout, PersistenceOptions};
+use resume::ResumeState;
 use error::WriterError;
+use event_log::{LogEntry, LogLevel};
+use settings::{DeviceIdentity, ThemePreference, UserSettings, WindowSize};
+
+const DEFAULT_WINDOW_SIZE: iced::Size = iced::Size::new(800.0, 480.0);
+const MIN_WINDOW_SIZE: iced::Size = iced::Size::new(560.0, 360.0);
+// From this width on, the devices and options sit next to the write and its progress
+const TWO_PANE_MIN_WIDTH: f32 = 1000.0;
+
+pub fn main() -> ExitCode {
+    // Logs go to stderr; RUST_LOG overrides the default filter, e.g.
+    // RUST_LOG=schrijver=debug for per-chunk progress
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn,schrijver=info")))
+        .with_writer(std::io::stderr)
+        .init();
+
+    let args = cli::Cli::parse();
+
+    // Messages follow the desktop's language unless the settings pick one
+    let settings = UserSettings::load();
+    i18n::set_language(settings.language.unwrap_or_else(i18n::Language::system));
+
+    // Any write arguments switch to headless mode, otherwise launch the GUI
+    if args.is_headless() {
+        return cli::run(args);
+    }
 
-pub fn main() -> iced::Result {
-    SchrijverApplication::run(Settings {
+    let selection = StartupSelection {
+        iso_path: args.select_iso,
+        device_path: args.select_device,
+    };
+
+    let result = SchrijverApplication::run(Settings {
+        flags: selection,
         window: iced::window::Settings {
-            size: iced::Size::new(800.0, 480.0),
+            size: settings.window_size.map_or(DEFAULT_WINDOW_SIZE, |size| iced::Size::new(size.width as f32, size.height as f32)),
+            min_size: Some(MIN_WINDOW_SIZE),
+            resizable: true,
+            // Closing mid-write asks first and waits for the write to stop
+            exit_on_close_request: false,
             ..Default::default()
         },
         ..Default::default()
-    })
+    });
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
     SelectIsoFile,
     IsoFileSelected(Option<PathBuf>),
+    // The size is None for images that only reveal it once decompressed
+    IsoMetadataLoaded { path: PathBuf, size: Option<u64> },
+    UnsupportedFileDropped(PathBuf),
+    IsoUrlEntered(String),
+    ChecksumEntered(String),
+    HashOnSelectToggled(bool),
+    // Hashing of the image at this path, see hash_image_file
+    IsoHashProgress(PathBuf, u64, u64),
+    IsoHashComputed(PathBuf, Result<String, WriterError>),
+    CancelIsoHash,
+    CopyIsoHash,
     RefreshDevices,
-    DevicesDetected(Vec<UsbDevice>),
+    DevicesDetected(Result<Vec<UsbDevice>, WriterError>),
+    PollDevices,
+    DevicesPolled(Result<Vec<UsbDevice>, WriterError>),
     DeviceSelected(UsbDevice),
+    SelectImageFileTarget,
+    ImageFileTargetSelected(Option<PathBuf>),
+    BatchDeviceToggled(UsbDevice, bool),
+    ShowPartitionsToggled(bool),
+    StartOffsetEntered(String),
     StartWriting,
-    WriteProgress(f32),
-    WriteCompleted(Result<(), WriterError>),
+    UnmountAndWrite,
+    ConfirmWrite(bool),
+    TypedConfirmationChanged(String),
+    TypedConfirmationSubmitted,
+    TypedConfirmationCancelled,
+    // The interrupted write to carry on from, if the user chose to
+    ResumeChosen(Option<ResumeState>),
+    StartBatchWrite,
+    ConfirmBatchWrite(bool),
+    ParallelBatchToggled(bool),
+    BatchInMemoryToggled(bool),
+    // None when the image is over the memory cap, so each write reads it itself
+    BatchImageLoaded(Result<Option<ImageBuffer>, WriterError>),
+    // Progress and results of one device in a parallel batch, by index
+    ParallelWriteProgress(usize, WriteProgress),
+    ParallelWriteCompleted(usize, Result<WriteSummary, WriterError>),
+    IoModeSelected(IoMode),
+    AccurateProgressToggled(bool),
+    DryRunToggled(bool),
+    SpeedLimitChanged(f64),
+    AllowRawImageToggled(bool),
+    IgnoreSizeCheckToggled(bool),
+    VerifyModeSelected(VerifyMode),
+    BlockHashesToggled(bool),
+    ResumableToggled(bool),
+    PersistenceToggled(bool),
+    PersistenceLayoutSelected(PersistenceLayout),
+    PersistenceSizeSelected(PersistenceSize),
+    BufferSizeSelected(BufferSize),
+    CancelWriting,
+    PauseWriting,
+    ResumeWriting,
+    WriteProgress(WriteProgress),
+    WriteCompleted(Result<WriteSummary, WriterError>),
+    RepairRegion,
+    RepairCompleted(Result<(), WriterError>),
+    ToggleTheme,
+    EjectWhenDoneToggled(bool),
+    FormatFilesystemSelected(Filesystem),
+    FormatLabelChanged(String),
+    FormatDevice,
+    ConfirmFormat(bool),
+    FormatCompleted(Result<(), WriterError>),
+    TestCapacity,
+    ConfirmCapacityTest(bool),
+    CapacityTestCompleted(Result<u64, WriterError>),
+    // The flush after a successful write of this device
+    SyncCompleted(UsbDevice, Result<(), WriterError>),
+    EjectCompleted(Result<(), WriterError>),
+    VerifyOnly,
+    VerifyOnlyCompleted(Result<VerifyOutcome, WriterError>),
+    RelaunchElevated,
+    RelaunchFinished(Result<(), String>),
+    Log(LogEntry),
+    ToggleLogPanel,
+    SaveLog,
+    ShowAbout,
+    AboutClosed,
+    CloseRequested,
+    QuitConfirmed(bool),
+    WindowResized(WindowSize),
 }
 
 struct SchrijverApplication {
     iso_path: Option<PathBuf>,
+    iso_url: String,
+    // Bytes the image will take on the device, when known up front
+    iso_size: Option<u64>,
+    checksum_input: String,
+    // SHA-256 of the selected image once computed, and how far it got until then
+    iso_hash: Option<String>,
+    iso_hashing: Option<IsoHashing>,
     selected_device: Option<UsbDevice>,
     available_devices: Vec<UsbDevice>,
+    // Partitions of the detected disks, each with the path of its disk
+    available_partitions: Vec<(String, UsbDevice)>,
+    // Offer partitions in the picker, next to the whole disks
+    show_partitions: bool,
+    // Where on the device to start writing, e.g. "1M"; empty means 0
+    start_offset_input: String,
+    // Devices ticked for writing the same image to one after another
+    batch_selection: Vec<UsbDevice>,
+    // The running batch, and how the last one went once it has finished
+    batch: Option<BatchWrite>,
+    batch_results: Vec<(UsbDevice, Result<(), WriterError>)>,
+    // Write all ticked devices at once instead of in turn
+    parallel_batch: bool,
+    // Read the image once and hand the same copy to every parallel write
+    batch_in_memory: bool,
+    // The writes of a running parallel batch, streamed under `write_id`
+    parallel_writes: Vec<ParallelWrite>,
+    // Device detection and checks, swapped for a mock in tests
+    backend: Arc<dyn DeviceBackend>,
+    // Claimed from the first StartWriting until WriteCompleted, so at most
+    // one write can be in flight however messages get queued
     writer: Arc<Mutex<Option<UsbWriter>>>,
     write_progress: f32,
+    last_progress: Option<WriteProgress>,
     is_writing: bool,
+    cancel_token: CancelToken,
+    pause_token: PauseToken,
+    // The running write, streamed by subscription() under `write_id`
+    write_job: Option<WriteJob>,
+    write_id: u64,
+    // Claimed size of the device under a running capacity test
+    capacity_test: Option<u64>,
+    // A Verify Only pass is running, which has a single phase
+    verifying_only: bool,
+    // What to format the selected device with, and whether that is running
+    format_filesystem: Filesystem,
+    format_label: String,
+    formatting: bool,
+    unmount_requested: bool,
+    io_mode: IoMode,
+    accurate_progress: bool,
+    dry_run: bool,
+    eject_when_done: bool,
+    speed_limit_mbps: f64, // 0 means unlimited
+    allow_raw_image: bool,
+    ignore_size_check: bool,
+    verify_mode: VerifyMode,
+    block_hashes: bool,
+    // Save the progress so an interrupted write can be resumed, see resume.rs
+    resumable: bool,
+    // What the next write carries on from, once the user agreed to resume
+    resume_from: Option<ResumeState>,
+    // Add a partition for a live system's changes after the image
+    persistence: bool,
+    persistence_layout: PersistenceLayout,
+    persistence_size: PersistenceSize,
+    // Where the last verification failed, so just that region can be rewritten
+    verification_failure: Option<FailedRegion>,
+    final_sync: Option<FinalSync>,
+    // Set once the user chose to quit mid-write; the window closes as soon
+    // as the cancelled write has wound down
+    quit_requested: bool,
+    // Current size of the window, remembered in the settings when it closes
+    window_size: Option<WindowSize>,
+    // What has been typed so far to confirm erasing a large disk, while that is asked for
+    typed_confirmation: Option<String>,
+    // What the last successful write did, for the completion panel
+    completed_write: Option<WriteSummary>,
+    // Retries so far while the device was still busy, see retry_while_busy
+    busy_retries: u32,
+    // The status to go back to once the write proper starts, while the
+    // checks before it or a busy device are being reported instead
+    status_before_checks: Option<String>,
+    settings: UserSettings,
+    // Desktop theme at startup, used while the preference is System
+    system_theme: Theme,
     status_message: String,
+    // Whether the process can write to raw devices, see elevate::is_elevated
+    running_elevated: bool,
+    // The last operation failed for lack of privileges, so offer a relaunch
+    permission_denied: bool,
+    // Device to select once detected, from --select-device
+    pending_device: Option<String>,
+    // Lifecycle events shown in the log panel and saved for bug reports
+    log_entries: Vec<LogEntry>,
+    show_log: bool,
+    // Last progress milestone logged for the running write
+    logged_milestone: Option<(Phase, u32)>,
+}
+
+// What to select on startup, passed on the command line when relaunching
+#[derive(Debug, Clone, Default)]
+struct StartupSelection {
+    iso_path: Option<PathBuf>,
+    device_path: Option<String>,
+}
+
+// A queue of devices getting the same image, written one at a time through
+// the regular single-device pipeline
+#[derive(Debug, Clone, Default)]
+struct BatchWrite {
+    pending: VecDeque<UsbDevice>,
+    current: Option<UsbDevice>,
+    results: Vec<(UsbDevice, Result<(), WriterError>)>,
+}
+
+impl BatchWrite {
+    fn total(&self) -> usize {
+        self.pending.len() + usize::from(self.current.is_some()) + self.results.len()
+    }
+}
+
+// One device of a parallel batch, with its own job and progress bar
+#[derive(Debug, Clone)]
+struct ParallelWrite {
+    device: UsbDevice,
+    job: WriteJob,
+    progress: Option<WriteProgress>,
+    result: Option<Result<(), WriterError>>,
+}
+
+// Hashing the selected image in the background, for checking it against
+// published checksums before writing
+#[derive(Debug, Clone)]
+struct IsoHashing {
+    bytes_hashed: u64,
+    total: u64,
+    cancel_token: CancelToken,
+}
+
+// The flush after a successful write, until the device can be unplugged
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FinalSync {
+    Flushing,
+    SafeToRemove,
+}
+
+#[derive(Debug, Clone)]
+struct FailedRegion {
+    iso_path: PathBuf,
+    device_path: String,
+    start_offset: u64,
+    offset: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BufferSize(usize);
+
+const BUFFER_SIZE_CHOICES: [BufferSize; 5] = [
+    BufferSize(writer::AUTO_BUFFER_SIZE),
+    BufferSize(512 * 1024),
+    BufferSize(1024 * 1024),
+    BufferSize(4 * 1024 * 1024),
+    BufferSize(16 * 1024 * 1024),
+];
+
+impl std::fmt::Display for BufferSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0 == writer::AUTO_BUFFER_SIZE {
+            write!(f, "Auto")
+        } else if self.0.is_multiple_of(1024 * 1024) {
+            write!(f, "{} MB", self.0 / (1024 * 1024))
+        } else {
+            write!(f, "{} KB", self.0 / 1024)
+        }
+    }
+}
+
+// Size of the persistence partition; None takes the rest of the device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PersistenceSize(Option<u64>);
+
+const PERSISTENCE_SIZE_CHOICES: [PersistenceSize; 5] = [
+    PersistenceSize(None),
+    PersistenceSize(Some(1024 * 1024 * 1024)),
+    PersistenceSize(Some(4 * 1024 * 1024 * 1024)),
+    PersistenceSize(Some(8 * 1024 * 1024 * 1024)),
+    PersistenceSize(Some(16 * 1024 * 1024 * 1024)),
+];
+
+impl std::fmt::Display for PersistenceSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            None => write!(f, "Rest of the device"),
+            Some(size) => write!(f, "{} GB", size / (1024 * 1024 * 1024)),
+        }
+    }
+}
+
+// Entry in the device picker, noting whether the selected image fits
+#[derive(Debug, Clone, PartialEq)]
+struct DeviceChoice {
+    device: UsbDevice,
+    fits: Option<bool>,
+}
+
+impl std::fmt::Display for DeviceChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.device)?;
+        match self.fits {
+            Some(true) => write!(f, " ✓"),
+            Some(false) => write!(f, " (too small)"),
+            None => Ok(()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -58,12 +402,68 @@ impl Default for SchrijverApplication {
     fn default() -> Self {
         Self {
             iso_path: None,
+            iso_url: String::new(),
+            iso_size: None,
+            checksum_input: String::new(),
+            iso_hash: None,
+            iso_hashing: None,
             selected_device: None,
             available_devices: Vec::new(),
+            available_partitions: Vec::new(),
+            show_partitions: false,
+            start_offset_input: String::new(),
+            batch_selection: Vec::new(),
+            batch: None,
+            batch_results: Vec::new(),
+            parallel_batch: false,
+            batch_in_memory: true,
+            parallel_writes: Vec::new(),
+            backend: device::platform_backend(),
             writer: Arc::new(Mutex::new(None)),
             write_progress: 0.0,
+            last_progress: None,
             is_writing: false,
-            status_message: "Ready to write ISO to USB".to_string(),
+            cancel_token: CancelToken::new(),
+            pause_token: PauseToken::new(),
+            write_job: None,
+            write_id: 0,
+            capacity_test: None,
+            verifying_only: false,
+            format_filesystem: Filesystem::default(),
+            format_label: "USB".to_string(),
+            formatting: false,
+            unmount_requested: false,
+            io_mode: IoMode::Buffered,
+            accurate_progress: false,
+            dry_run: false,
+            eject_when_done: false,
+            speed_limit_mbps: 0.0,
+            allow_raw_image: false,
+            ignore_size_check: false,
+            verify_mode: VerifyMode::Full,
+            block_hashes: false,
+            resumable: false,
+            resume_from: None,
+            persistence: false,
+            persistence_layout: PersistenceLayout::default(),
+            persistence_size: PERSISTENCE_SIZE_CHOICES[0],
+            verification_failure: None,
+            final_sync: None,
+            quit_requested: false,
+            window_size: None,
+            completed_write: None,
+            typed_confirmation: None,
+            busy_retries: 0,
+            status_before_checks: None,
+            settings: UserSettings::default(),
+            system_theme: Theme::Light,
+            status_message: tr!("status.ready"),
+            running_elevated: false,
+            permission_denied: false,
+            pending_device: None,
+            log_entries: Vec::new(),
+            show_log: false,
+            logged_milestone: None,
         }
     }
 }
@@ -72,200 +472,2771 @@ impl Application for SchrijverApplication {
     type Executor = iced::executor::Default;
     type Message = Message;
     type Theme = Theme;
-    type Flags = ();
+    type Flags = StartupSelection;
+
+    fn new(selection: StartupSelection) -> (Self, Command<Message>) {
+        let mut app = Self {
+            settings: UserSettings::load(),
+            system_theme: detect_system_theme(),
+            running_elevated: elevate::is_elevated(),
+            pending_device: selection.device_path,
+            ..Self::default()
+        };
+
+        // Saved logs then say which build they came from
+        app.log(LogEntry::info(about::version_line()));
 
-    fn new(_flags: ()) -> (Self, Command<Message>) {
-        let app = Self::default();
-        (app, Command::perform(detect_usb_devices(), Message::DevicesDetected))
+        if cfg!(target_os = "linux") && !app.running_elevated {
+            app.log(LogEntry::info("Not running as root, devices will be opened through udisks2 where available"));
+        } else if cfg!(unix) && !app.running_elevated {
+            app.log(LogEntry::warning("Not running as root, writing to devices will likely be denied"));
+        }
+
+        let mut commands = vec![app.detect_devices(Message::DevicesDetected)];
+        if let Some(iso_path) = selection.iso_path {
+            commands.push(app.update(Message::IsoFileSelected(Some(iso_path))));
+        }
+        (app, Command::batch(commands))
     }
 
     fn title(&self) -> String {
-        String::from("ISO to USB Writer")
+        String::from("Disk Image to USB Writer")
+    }
+
+    fn theme(&self) -> Theme {
+        match self.settings.theme {
+            ThemePreference::System => self.system_theme.clone(),
+            ThemePreference::Light => Theme::Light,
+            ThemePreference::Dark => Theme::Dark,
+        }
+    }
+
+    fn subscription(&self) -> Subscription<Message> {
+        // Accept disk images dropped onto the window, same as picking them in
+        // the dialog, and vet closing it
+        let window_events = event::listen_with(|event, _status| match event {
+            Event::Window(_, window::Event::CloseRequested) => Some(Message::CloseRequested),
+            Event::Window(_, window::Event::Resized { width, height }) => Some(Message::WindowResized(WindowSize { width, height })),
+            Event::Window(_, window::Event::FileDropped(path)) => {
+                if writer::has_image_extension(&path) {
+                    Some(Message::IsoFileSelected(Some(path)))
+                } else {
+                    Some(Message::UnsupportedFileDropped(path))
+                }
+            }
+            _ => None,
+        });
+
+        // Pick up plugged and unplugged sticks, but leave the list alone mid-write
+        let poll_interval = self.settings.device_poll_interval_secs;
+        let device_polling = if poll_interval > 0 && !self.is_writing {
+            iced::time::every(Duration::from_secs(poll_interval)).map(|_| Message::PollDevices)
+        } else {
+            Subscription::none()
+        };
+
+        // Keyed per write, so the same running write keeps its stream across
+        // calls and a new write always gets a fresh one
+        let write_progress = match &self.write_job {
+            Some(job) => iced::subscription::run_with_id(("write", self.write_id), write_iso_to_usb(job.clone())),
+            None => Subscription::none(),
+        };
+
+        // One stream per device of a parallel batch, each tagged with its index
+        let parallel_writes = self.parallel_writes
+            .iter()
+            .enumerate()
+            .filter(|(_, write)| write.result.is_none())
+            .map(|(index, write)| {
+                let stream = write_iso_to_usb(write.job.clone()).map(move |message| match message {
+                    Message::WriteProgress(progress) => Message::ParallelWriteProgress(index, progress),
+                    Message::WriteCompleted(result) => Message::ParallelWriteCompleted(index, result),
+                    message => message,
+                });
+                iced::subscription::run_with_id(("parallel", self.write_id, index), stream)
+            });
+
+        Subscription::batch([window_events, device_polling, write_progress].into_iter().chain(parallel_writes))
+    }
+
+    fn view(&self) -> Element<'_, Message> {
+        responsive(|size| self.layout(size.width >= TWO_PANE_MIN_WIDTH)).into()
     }
 
     fn update(&mut self, message: Message) -> Command<Message> {
+        let command = self.handle_message(message);
+        if self.quit_requested && !self.has_work_in_flight() {
+            return self.close_window();
+        }
+        command
+    }
+}
+
+impl SchrijverApplication {
+    fn handle_message(&mut self, message: Message) -> Command<Message> {
         match message {
             Message::SelectIsoFile => {
-                return Command::perform(select_iso_file(), Message::IsoFileSelected);
+                return Command::perform(
+                    select_iso_file(self.settings.last_iso_dir.clone()),
+                    Message::IsoFileSelected,
+                );
             }
             Message::IsoFileSelected(path) => {
                 self.iso_path = path;
-                if self.iso_path.is_some() {
-                    self.status_message = format!("ISO file selected: {}",
-                                                  self.iso_path.as_ref().unwrap().display());
+                self.iso_size = None;
+                self.clear_iso_hash();
+                if let Some(iso_path) = self.iso_path.clone() {
+                    self.iso_url.clear();
+                    self.status_message = tr!("status.image_selected", path = iso_path.display());
+                    self.log(LogEntry::info(format!("Image selected: {}", iso_path.display())));
+                    self.settings.last_iso_dir = iso_path.parent().map(|dir| dir.to_path_buf());
+                    self.save_settings();
+                    let read_size = Command::perform(read_iso_size(iso_path), |(path, size)| Message::IsoMetadataLoaded { path, size });
+                    if self.settings.hash_on_select {
+                        return Command::batch([read_size, self.hash_selected_image()]);
+                    }
+                    return read_size;
+                }
+            }
+            Message::IsoMetadataLoaded { path, size } => {
+                // Ignore results for an image that has been replaced in the meantime
+                if self.iso_path.as_ref() == Some(&path) {
+                    self.iso_size = size;
+                    if let Some(device) = self.selected_device.as_mut().filter(|device| device.is_image_file) {
+                        device.size = image_file_size(size);
+                    }
+                    if let Some(preview) = self.size_preview() {
+                        self.status_message = preview.clone();
+                        self.log(LogEntry::new(
+                            if self.image_too_large() { LogLevel::Warning } else { LogLevel::Info },
+                            preview,
+                        ));
+                    }
+                }
+            }
+            Message::IsoUrlEntered(url) => {
+                // The URL doubles as the image path, which the writer streams from
+                let source = PathBuf::from(url.trim());
+                self.iso_size = None;
+                self.clear_iso_hash();
+                if download::is_url(&source) {
+                    self.iso_path = Some(source);
+                } else if self.iso_path.as_deref().is_some_and(download::is_url) {
+                    self.iso_path = None;
+                }
+                self.iso_url = url;
+            }
+            Message::ChecksumEntered(checksum) => {
+                self.checksum_input = checksum;
+            }
+            Message::HashOnSelectToggled(enabled) => {
+                self.settings.hash_on_select = enabled;
+                self.save_settings();
+                if enabled && self.iso_hash.is_none() && self.iso_hashing.is_none() {
+                    return self.hash_selected_image();
+                }
+            }
+            Message::IsoHashProgress(path, bytes_hashed, total) => {
+                if self.iso_path.as_ref() == Some(&path) {
+                    if let Some(hashing) = self.iso_hashing.as_mut() {
+                        hashing.bytes_hashed = bytes_hashed;
+                        hashing.total = total;
+                    }
+                }
+            }
+            Message::IsoHashComputed(path, result) => {
+                // Ignore results for an image that has been replaced in the
+                // meantime, and hashing that was cancelled for a fresh start
+                let cancelled = matches!(result, Err(WriterError::Cancelled));
+                let current = self.iso_hashing.as_ref().is_some_and(|hashing| !cancelled || hashing.cancel_token.is_cancelled());
+                if self.iso_path.as_ref() == Some(&path) && current {
+                    self.iso_hashing = None;
+                    match result {
+                        Ok(digest) => {
+                            self.log(LogEntry::info(format!("SHA-256 of {}: {}", path.display(), digest)));
+                            self.iso_hash = Some(digest);
+                        }
+                        Err(WriterError::Cancelled) => self.log(LogEntry::info("Hashing the image cancelled")),
+                        Err(error) => {
+                            self.status_message = tr!("status.error", error = error.user_friendly_message());
+                            self.log(LogEntry::error(self.status_message.clone()));
+                        }
+                    }
                 }
             }
+            Message::CancelIsoHash => {
+                if let Some(hashing) = &self.iso_hashing {
+                    hashing.cancel_token.cancel();
+                }
+            }
+            Message::CopyIsoHash => {
+                if let Some(digest) = self.iso_hash.clone() {
+                    self.status_message = tr!("status.hash_copied");
+                    return iced::clipboard::write(digest);
+                }
+            }
+            Message::UnsupportedFileDropped(path) => {
+                self.status_message = tr!("status.unsupported_file", path = path.display());
+            }
             Message::RefreshDevices => {
-                return Command::perform(detect_usb_devices(), Message::DevicesDetected);
+                return self.detect_devices(Message::DevicesDetected);
+            }
+            Message::DevicesDetected(Err(error)) => {
+                // The previous list stays, its devices are checked again before writing
+                self.status_message = tr!("status.error", error = error.user_friendly_message());
+                self.log(LogEntry::error(self.status_message.clone()));
             }
-            Message::DevicesDetected(devices) => {
+            Message::DevicesDetected(Ok(devices)) => {
+                self.available_partitions = devices
+                    .iter()
+                    .flat_map(|device| {
+                        self.backend
+                            .partitions(device)
+                            .into_iter()
+                            .map(|partition| (device.device_path.clone(), partition))
+                    })
+                    .collect();
                 self.available_devices = devices;
-                self.status_message = format!("Found {} USB devices", self.available_devices.len());
+                self.status_message = tr!("status.devices_found", count = self.available_devices.len());
+                if self.available_devices.is_empty() {
+                    if let device::DetectionDiagnostics::Unavailable(reason) = self.backend.diagnostics() {
+                        self.status_message = tr!("status.detection_unavailable", reason = reason);
+                        self.log(LogEntry::warning(self.status_message.clone()));
+                    }
+                }
+
+                // Unplugged sticks drop out of the batch, the rest pick up their new details
+                let batch_selection = std::mem::take(&mut self.batch_selection);
+                self.batch_selection = self.available_devices
+                    .iter()
+                    .filter(|device| batch_selection.iter().any(|selected| selected.is_same_device(device)))
+                    .cloned()
+                    .collect();
+
+                // Keep the selection if the same disk is still there, picking up
+                // the refreshed details (e.g. labels) as well. A replugged stick
+                // may come back under another path, and another disk under its old one.
+                if let Some(selected) = self.selected_device.clone().filter(|selected| !selected.is_image_file) {
+                    let partitions = self.available_partitions.iter().map(|(_, partition)| partition);
+                    match self.available_devices.iter().chain(partitions).find(|device| selected.is_same_device(device)).cloned() {
+                        Some(device) if device.device_path != selected.device_path => {
+                            // Whatever was confirmed was confirmed for the old path
+                            if self.typed_confirmation.take().is_some() {
+                                self.release_writer();
+                            }
+                            self.status_message = tr!("status.device_moved", device = device.name, path = device.device_path, previous = selected.device_path);
+                            self.log(LogEntry::warning(self.status_message.clone()));
+                            // Saved with the rest when the window closes
+                            self.settings.last_device = Some(DeviceIdentity::from_device(&device));
+                            self.selected_device = Some(device);
+                        }
+                        Some(device) => self.selected_device = Some(device),
+                        None => {
+                            self.status_message = tr!("status.unplugged", device = selected.name);
+                            self.log(LogEntry::warning(self.status_message.clone()));
+                            self.selected_device = None;
+                        }
+                    }
+                }
+
+                // A device passed on the command line wins over the remembered one
+                if let Some(device_path) = self.pending_device.take() {
+                    if let Some((_, partition)) = self.available_partitions
+                        .iter()
+                        .find(|(_, partition)| partition.device_path == device_path)
+                    {
+                        self.show_partitions = true;
+                        self.selected_device = Some(partition.clone());
+                    } else if let Some(device) = self.available_devices
+                        .iter()
+                        .find(|device| device.device_path == device_path)
+                    {
+                        self.selected_device = Some(device.clone());
+                    }
+                }
+
+                // Restore the last used device, but only if it is still the same disk
+                if self.selected_device.is_none() {
+                    if let Some(last_device) = &self.settings.last_device {
+                        self.selected_device = self.available_devices
+                            .iter()
+                            .find(|device| last_device.matches(device))
+                            .filter(|device| self.backend.exists(&device.device_path))
+                            .cloned();
+                    }
+                }
+            }
+            Message::PollDevices => {
+                return self.detect_devices(Message::DevicesPolled);
+            }
+            // A failing poll would repeat its error every few seconds, so
+            // errors only show up on the explicit refreshes
+            Message::DevicesPolled(Ok(devices)) => {
+                // Only touch the list when something changed, to avoid flicker
+                if devices != self.available_devices && !self.is_writing {
+                    return self.update(Message::DevicesDetected(Ok(devices)));
+                }
+            }
+            Message::DevicesPolled(Err(error)) => {
+                debug!("Device poll failed: {}", error);
             }
             Message::DeviceSelected(device) => {
+                // Another device needs confirming all over again
+                if self.typed_confirmation.take().is_some() {
+                    self.release_writer();
+                }
                 self.selected_device = Some(device.clone());
-                self.status_message = format!("Selected device: {}", device.name);
+                self.status_message = self.size_preview()
+                    .unwrap_or_else(|| tr!("status.device_selected", device = device.name));
+                self.log(LogEntry::info(format!("Device selected: {} at {}", device, device.device_path)));
+                self.settings.last_device = Some(DeviceIdentity::from_device(&device));
+                self.save_settings();
+            }
+            Message::SelectImageFileTarget => {
+                return Command::perform(select_image_file_target(), Message::ImageFileTargetSelected);
+            }
+            Message::ImageFileTargetSelected(Some(path)) => {
+                let device = UsbDevice::image_file(path.to_string_lossy(), image_file_size(self.iso_size));
+                self.status_message = tr!("status.image_file_target", path = device.device_path);
+                self.log(LogEntry::info(format!("Image file selected as the target: {}", device.device_path)));
+                self.selected_device = Some(device);
+            }
+            Message::ImageFileTargetSelected(None) => {}
+            Message::BatchDeviceToggled(device, checked) => {
+                self.batch_selection.retain(|selected| selected != &device);
+                if checked {
+                    self.batch_selection.push(device);
+                }
+            }
+            Message::ShowPartitionsToggled(show) => {
+                self.show_partitions = show;
+                // Back to whole disks only, so drop a selected partition
+                let partition_selected = self.selected_device.as_ref().is_some_and(|selected| {
+                    self.available_partitions.iter().any(|(_, partition)| partition == selected)
+                });
+                if !show && partition_selected {
+                    self.selected_device = None;
+                }
+            }
+            Message::StartOffsetEntered(offset) => {
+                self.start_offset_input = offset;
             }
             Message::StartWriting => {
-                if let (Some(iso_path), Some(device)) = (&self.iso_path, &self.selected_device) {
-                    self.is_writing = true;
-                    self.write_progress = 0.0;
-                    self.status_message = "Writing ISO to USB device...".to_string();
+                return self.confirm_writing(false);
+            }
+            Message::UnmountAndWrite => {
+                return self.confirm_writing(true);
+            }
+            Message::ConfirmWrite(confirmed) => {
+                if !confirmed {
+                    self.release_writer();
+                    self.status_message = tr!("status.write_cancelled");
+                    return Command::none();
+                }
+
+                // The device may have been unplugged while the dialog was open
+                if let Some(device) = &self.selected_device {
+                    if !device.is_image_file && !self.backend.exists(&device.device_path) {
+                        self.release_writer();
+                        self.status_message = tr!("status.error", error = WriterError::DeviceNotFound(device.device_path.clone()).user_friendly_message());
+                        return Command::none();
+                    }
+                }
+
+                let Some(device) = self.selected_device.clone() else {
+                    return Command::none();
+                };
+
+                // One click isn't enough to wipe what may be a backup drive
+                if self.settings.is_large_disk(&device) {
+                    self.typed_confirmation = Some(String::new());
+                    self.status_message = tr!("status.type_to_confirm", device = device.name, size = device.human_size());
+                    return Command::none();
+                }
+                return self.offer_resume(device);
+            }
+            Message::TypedConfirmationChanged(typed) => {
+                if self.typed_confirmation.is_some() {
+                    self.typed_confirmation = Some(typed);
+                }
+            }
+            Message::TypedConfirmationSubmitted => {
+                let (Some(typed), Some(device)) = (&self.typed_confirmation, self.selected_device.clone()) else {
+                    return Command::none();
+                };
+                if !device.is_confirmed_by(typed) {
+                    self.status_message = tr!("status.confirmation_mismatch", size = device.human_size(), device = device.name);
+                    return Command::none();
+                }
+
+                self.typed_confirmation = None;
+                return self.offer_resume(device);
+            }
+            Message::TypedConfirmationCancelled => {
+                if self.typed_confirmation.take().is_some() {
+                    self.release_writer();
+                    self.status_message = tr!("status.write_cancelled");
+                }
+            }
+            Message::ResumeChosen(state) => {
+                let Some(device) = self.selected_device.clone() else {
+                    return Command::none();
+                };
+                self.resume_from = state;
+                return self.start_writing(device, self.unmount_requested);
+            }
+            Message::StartBatchWrite => {
+                if !self.can_write_batch() {
+                    return Command::none();
+                }
+
+                if self.dry_run {
+                    return self.update(Message::ConfirmBatchWrite(true));
+                }
+                self.status_message = tr!("status.waiting_for_confirmation");
+                return Command::perform(confirm_batch_dialog(self.batch_selection.clone()), Message::ConfirmBatchWrite);
+            }
+            Message::ConfirmBatchWrite(confirmed) => {
+                if !confirmed || !self.can_write_batch() {
+                    self.status_message = tr!("status.write_cancelled");
+                    return Command::none();
+                }
+
+                self.batch_results.clear();
 
-                    let iso_path = iso_path.clone();
-                    let device_path = device.device_path.clone();
+                if self.parallel_batch {
+                    // Fresh tokens shared by all writes, so one Cancel stops them all
+                    self.cancel_token = CancelToken::new();
+                    self.pause_token = PauseToken::new();
+                    let Some(iso_path) = self.iso_path.clone().filter(|_| self.batch_in_memory) else {
+                        return self.start_parallel_batch(None);
+                    };
 
+                    self.reset_progress();
+                    self.status_message = tr!("status.loading_into_memory");
+                    let max_bytes = self.settings.memory_image_cap_mb * 1024 * 1024;
                     return Command::perform(
-                        write_iso_to_usb(iso_path, device_path),
-                        Message::WriteCompleted
+                        async move { writer::load_image_into_memory(&iso_path, max_bytes).await },
+                        Message::BatchImageLoaded,
                     );
                 }
+
+                self.batch = Some(BatchWrite {
+                    pending: self.batch_selection.iter().cloned().collect(),
+                    ..BatchWrite::default()
+                });
+                self.log(LogEntry::info(format!("Batch write started: {} devices", self.batch_selection.len())));
+                return self.start_next_in_batch();
             }
-            Message::WriteProgress(progress) => {
-                self.write_progress = progress;
+            Message::ParallelBatchToggled(enabled) => {
+                self.parallel_batch = enabled;
             }
-            Message::WriteCompleted(result) => {
+            Message::BatchInMemoryToggled(enabled) => {
+                self.batch_in_memory = enabled;
+            }
+            Message::BatchImageLoaded(result) => {
                 self.is_writing = false;
+                if self.cancel_token.is_cancelled() {
+                    self.status_message = tr!("status.write_cancelled");
+                    return Command::none();
+                }
                 match result {
-                    Ok(()) => {
-                        self.status_message = "ISO successfully written to USB device!".to_string();
-                        self.write_progress = 1.0;
+                    Ok(Some(image)) => {
+                        self.log(LogEntry::info(format!("Image loaded into memory: {}", device::human_size(image.size()))));
+                        return self.start_parallel_batch(Some(image));
+                    }
+                    Ok(None) => {
+                        self.log(LogEntry::info(format!(
+                            "Image is larger than the {} MB memory cap, each write reads it from disk",
+                            self.settings.memory_image_cap_mb
+                        )));
+                        return self.start_parallel_batch(None);
                     }
                     Err(error) => {
-                        self.status_message = format!("Error: {}", error);
-                        self.write_progress = 0.0;
+                        self.status_message = tr!("status.error", error = error.user_friendly_message());
+                        self.log(LogEntry::error(format!("Reading the image into memory failed: {}", error.user_friendly_message())));
                     }
                 }
             }
-        }
-        Command::none()
-    }
+            Message::ParallelWriteProgress(index, progress) => {
+                if let Some(write) = self.parallel_writes.get_mut(index) {
+                    write.progress = Some(progress);
+                }
+            }
+            Message::ParallelWriteCompleted(index, result) => {
+                let Some(write) = self.parallel_writes.get_mut(index) else {
+                    return Command::none();
+                };
+                let device_path = write.device.device_path.clone();
+                let entry = match &result {
+                    Ok(summary) => LogEntry::info(format!("{} finished: {} bytes, {} verification", device_path, summary.bytes_written, summary.verify_mode)),
+                    Err(WriterError::Cancelled) => LogEntry::warning(format!("{} cancelled", device_path)),
+                    Err(error) => LogEntry::error(format!("{} failed: {}", device_path, error.user_friendly_message())),
+                };
+                write.result = Some(result.map(|_| ()));
+                self.log(entry);
 
-    fn view(&self) -> Element<Message> {
-        let iso_section = column![
-            row![
-                text("1. Select ISO File").size(16),
-                button("Select ISO").on_press(Message::SelectIsoFile),
-                text(
-                    self.iso_path
-                        .as_ref()
-                        .map(|p| p.file_name().unwrap_or_default().to_string_lossy().to_string())
-                        .unwrap_or_else(|| "No file selected".to_string())
-                )
-                .size(14)
-            ]
-            .spacing(10)
-            .align_items(Alignment::Center),
-        ]
-            .spacing(10);
+                let done = self.parallel_writes.iter().filter(|write| write.result.is_some()).count();
+                if done < self.parallel_writes.len() {
+                    self.status_message = tr!("status.parallel_progress", done = done, total = self.parallel_writes.len());
+                    return Command::none();
+                }
 
-        let device_section = column![
-            row![
-                text("2. Select USB Device").size(16),
-                pick_list(
-                    self.available_devices.as_slice(),
-                    self.selected_device.clone(),
-                    Message::DeviceSelected
-                )
-                .placeholder("Select USB device..."),
-                button("Refresh").on_press(Message::RefreshDevices)
-            ]
-            .spacing(10)
-            .align_items(Alignment::Center),
-        ]
-            .spacing(10);
+                // The others kept going, so every device has its own result
+                self.is_writing = false;
+                let results = std::mem::take(&mut self.parallel_writes)
+                    .into_iter()
+                    .map(|write| (write.device, write.result.unwrap_or(Err(WriterError::Cancelled))))
+                    .collect();
+                self.finish_batch(results);
+            }
+            Message::IoModeSelected(io_mode) => {
+                self.io_mode = io_mode;
+            }
+            Message::AccurateProgressToggled(enabled) => {
+                self.accurate_progress = enabled;
+            }
+            Message::DryRunToggled(enabled) => {
+                self.dry_run = enabled;
+            }
+            Message::SpeedLimitChanged(limit) => {
+                self.speed_limit_mbps = limit;
+            }
+            Message::AllowRawImageToggled(enabled) => {
+                self.allow_raw_image = enabled;
+            }
+            Message::IgnoreSizeCheckToggled(enabled) => {
+                self.ignore_size_check = enabled;
+            }
+            Message::VerifyModeSelected(verify_mode) => {
+                self.verify_mode = verify_mode;
+            }
+            Message::BlockHashesToggled(enabled) => {
+                self.block_hashes = enabled;
+            }
+            Message::ResumableToggled(enabled) => {
+                self.resumable = enabled;
+            }
+            Message::PersistenceToggled(enabled) => {
+                self.persistence = enabled;
+            }
+            Message::PersistenceLayoutSelected(layout) => {
+                self.persistence_layout = layout;
+            }
+            Message::PersistenceSizeSelected(size) => {
+                self.persistence_size = size;
+            }
+            Message::BufferSizeSelected(BufferSize(buffer_size)) => {
+                self.settings.buffer_size = buffer_size;
+                self.save_settings();
+            }
+            Message::CancelWriting => {
+                if self.is_writing {
+                    self.cancel_token.cancel();
+                    self.status_message = match &self.last_progress {
+                        Some(progress) if progress.phase == Phase::Verifying => tr!("status.cancelling_verification"),
+                        _ => tr!("status.cancelling_write"),
+                    };
+                    self.log(LogEntry::info("Cancel requested"));
+                }
+            }
+            Message::PauseWriting => {
+                if self.is_writing {
+                    self.pause_token.pause();
+                    self.status_message = tr!("status.paused");
+                }
+            }
+            Message::ResumeWriting => {
+                if self.is_writing {
+                    self.pause_token.resume();
+                    self.status_message = tr!("status.writing");
+                }
+            }
+            Message::WriteProgress(progress) if progress.phase == Phase::Waiting => {
+                self.busy_retries += 1;
+                self.status_before_checks.get_or_insert_with(|| self.status_message.clone());
+                self.status_message = tr!("status.device_still_busy", retry = self.busy_retries);
+                self.log(LogEntry::warning(format!("Device busy, retry {} after {:.1} s", self.busy_retries, progress.elapsed_seconds)));
+            }
+            Message::WriteProgress(WriteProgress { phase: Phase::Validating(step), .. }) => {
+                self.status_before_checks.get_or_insert_with(|| self.status_message.clone());
+                self.status_message = match step {
+                    ValidationStep::Device => tr!("status.checking_device"),
+                    ValidationStep::Signature => tr!("status.checking_signature"),
+                    ValidationStep::Size => tr!("status.checking_size"),
+                    ValidationStep::Resume => tr!("status.checking_resume"),
+                };
+            }
+            Message::WriteProgress(WriteProgress { phase: Phase::Persistence, .. }) => {
+                self.status_message = tr!("status.creating_persistence");
+                self.log(LogEntry::info(format!("{}, {}", Phase::Persistence, self.persistence_layout)));
+            }
+            Message::WriteProgress(progress) => {
+                if let Some(status) = self.status_before_checks.take() {
+                    self.status_message = status;
+                }
+                if let Some(percent) = progress.progress_percent {
+                    self.write_progress = if self.verifying_only {
+                        (percent / 100.0).clamp(0.0, 1.0)
+                    } else {
+                        overall_progress(progress.phase, percent, self.verifies_after_write())
+                    };
 
-        let write_section = row![
-            text("3. Write ISO").size(16),
-            if self.can_write() {
-                button("Write ISO to USB Device")
-                    .on_press(Message::StartWriting)
-                    .style(iced::theme::Button::Primary)
-            } else {
-                button("Write ISO to USB Device")
-                    .style(iced::theme::Button::Secondary)
+                    // Note every quarter of each phase
+                    let milestone = (percent / 25.0).floor() as u32 * 25;
+                    let new_milestone = self.logged_milestone
+                        .is_none_or(|(phase, logged)| phase != progress.phase || milestone > logged);
+                    if milestone > 0 && new_milestone {
+                        self.logged_milestone = Some((progress.phase, milestone));
+                        let mut entry = format!("{}: {}% ({} at {})",
+                                                progress.phase, milestone,
+                                                format_bytes(progress.bytes_written),
+                                                format_speed(progress.instant_speed_mbps));
+                        if let Some(pipeline) = progress.pipeline {
+                            entry.push_str(&format!(", {}", pipeline_summary(progress.bytes_written, pipeline)));
+                        }
+                        self.log(LogEntry::info(entry));
+                    }
+                }
+                self.last_progress = Some(progress);
             }
-        ]
-            .spacing(10);
+            Message::WriteCompleted(result) => {
+                self.is_writing = false;
+                self.release_writer();
+                let finished_job = self.write_job.take();
+                // The write itself finished if the cancel came while reading it back
+                let verification_cancelled = matches!(result, Err(WriterError::Cancelled))
+                    && self.last_progress.as_ref().is_some_and(|progress| progress.phase == Phase::Verifying);
+                self.log(match &result {
+                    Err(WriterError::Cancelled) if verification_cancelled => LogEntry::warning("Verification cancelled, the image was written"),
+                    Ok(summary) if summary.dry_run => LogEntry::info(format!("Dry run finished: {} bytes read", summary.bytes_written)),
+                    Ok(summary) => LogEntry::info(format!("Write finished: {} bytes, {} verification, sha256 {}",
+                                                          summary.bytes_written, summary.verify_mode, summary.digest)),
+                    Err(WriterError::Cancelled) => LogEntry::warning("Write cancelled"),
+                    Err(error) => LogEntry::error(format!("Write failed: {}", error.user_friendly_message())),
+                });
+                if let Some(node) = result.as_ref().ok().and_then(|summary| summary.persistence_partition.as_ref()) {
+                    self.log(LogEntry::info(format!("Persistence partition {} created", node)));
+                }
 
-        let progress_section = if self.is_writing || self.write_progress > 0.0 {
-            column![
-                text("Progress").size(16),
-                progress_bar(0.0..=1.0, self.write_progress),
-                text(format!("{:.1}%", self.write_progress * 100.0))
-            ]
-                .spacing(5)
-        } else {
-            column![]
-        };
+                // A batch moves on to its next device instead of finishing here
+                if let Some(batch) = &mut self.batch {
+                    let cancelled = matches!(result, Err(WriterError::Cancelled));
+                    if let Some(device) = batch.current.take() {
+                        batch.results.push((device, result.map(|_| ())));
+                    }
+                    // Cancel stops the whole batch, not just the current device
+                    if cancelled {
+                        let skipped: Vec<UsbDevice> = batch.pending.drain(..).collect();
+                        batch.results.extend(skipped.into_iter().map(|device| (device, Err(WriterError::Cancelled))));
+                    }
+                    return self.start_next_in_batch();
+                }
 
-        let status_section = column![
-            text("Status").size(16),
-            text(&self.status_message).size(12),
-        ]
-            .spacing(5);
+                // Name the device that was written, even if the selection changed since
+                let target = finished_job
+                    .as_ref()
+                    .map_or_else(|| "USB device".to_string(), |job| target_name(&job.device));
+                match result {
+                    Ok(summary) if summary.dry_run => {
+                        self.status_message = tr!("status.dry_run_ok", bytes = summary.bytes_written, target = target);
+                        self.write_progress = 1.0;
+                    }
+                    Ok(summary) => {
+                        self.status_message = match summary.verify_mode {
+                            VerifyMode::Full => tr!("status.written_full", target = target),
+                            VerifyMode::Inline => tr!("status.written_inline", target = target),
+                            VerifyMode::Quick => tr!("status.written_quick", target = target),
+                            VerifyMode::None => tr!("status.written_unverified", target = target),
+                        };
+                        self.write_progress = 1.0;
+                        self.completed_write = Some(summary);
 
-        let content = column![
-            iso_section,
-            device_section,
-            write_section,
-            progress_section,
-            status_section,
-        ]
-            .spacing(20)
-            .padding(20);
+                        // The device's buffers may still be draining, so it
+                        // isn't safe to pull until they are flushed
+                        if let Some(job) = finished_job.filter(|job| !job.device.is_image_file) {
+                            self.final_sync = Some(FinalSync::Flushing);
+                            let device = job.device;
+                            return Command::perform(
+                                async move {
+                                    let result = writer::flush_device(device.device_path.clone()).await;
+                                    (device, result)
+                                },
+                                |(device, result)| Message::SyncCompleted(device, result),
+                            );
+                        }
+                    }
+                    // Leave the progress bar where verification stopped
+                    Err(WriterError::Cancelled) if verification_cancelled => {
+                        self.status_message = tr!("status.verification_cancelled");
+                    }
+                    Err(WriterError::Cancelled) => {
+                        self.status_message = tr!("status.write_cancelled_inconsistent");
+                        self.write_progress = 0.0;
+                    }
+                    // Only an image that can be read at an offset can have a region rewritten
+                    Err(WriterError::VerificationFailed { offset })
+                        if finished_job.as_ref().is_some_and(|job| writer::is_seekable_source(&job.iso_path)) =>
+                    {
+                        self.status_message = tr!("status.verification_failed_retry", offset = format_bytes(offset));
+                        // The job's own image and device, as the selection may have changed since
+                        if let Some(job) = finished_job {
+                            self.verification_failure = Some(FailedRegion {
+                                iso_path: job.iso_path,
+                                device_path: job.device.device_path,
+                                start_offset: job.options.start_offset,
+                                offset,
+                            });
+                        }
+                    }
+                    Err(error) => {
+                        self.permission_denied = matches!(error, WriterError::PermissionDenied);
+                        self.status_message = tr!("status.write_error", target = target, error = error.user_friendly_message());
+                        if self.permission_denied && !self.running_elevated {
+                            self.status_message.push_str(&tr!("status.relaunch_hint"));
+                        }
+                        self.write_progress = 0.0;
+                    }
+                }
+            }
+            Message::RepairRegion => {
+                let Some(region) = self.verification_failure.clone() else {
+                    return Command::none();
+                };
 
-        container(content)
-            .width(Length::Fill)
-            .height(Length::Fill)
-            .center_x()
-            .center_y()
-            .into()
-    }
+                self.is_writing = true;
+                self.status_message = tr!("status.rewriting_region", offset = format_bytes(region.offset));
+                self.log(LogEntry::info(format!("Rewriting the region at byte {} of {}", region.offset, region.device_path)));
+
+                return Command::perform(
+                    async move {
+                        writer::repair_device_region(&region.iso_path, &region.device_path, region.start_offset, region.offset).await
+                    },
+                    Message::RepairCompleted,
+                );
+            }
+            Message::RepairCompleted(result) => {
+                self.is_writing = false;
+                self.log(match &result {
+                    Ok(()) => LogEntry::info("Region rewritten and verified"),
+                    Err(error) => LogEntry::error(format!("Region repair failed: {}", error.user_friendly_message())),
+                });
+                match result {
+                    Ok(()) => {
+                        self.status_message = tr!("status.region_repaired");
+                        self.verification_failure = None;
+                    }
+                    Err(WriterError::VerificationFailed { offset }) => {
+                        self.status_message = tr!("status.region_still_fails", offset = format_bytes(offset));
+                        if let Some(region) = &mut self.verification_failure {
+                            region.offset = offset;
+                        }
+                    }
+                    Err(error) => {
+                        self.status_message = tr!("status.error", error = error.user_friendly_message());
+                    }
+                }
+            }
+            Message::EjectWhenDoneToggled(eject) => {
+                self.eject_when_done = eject;
+            }
+            // Ignored if another write has started since
+            Message::SyncCompleted(device, result) if self.final_sync == Some(FinalSync::Flushing) => {
+                match result {
+                    Ok(()) => {
+                        self.final_sync = Some(FinalSync::SafeToRemove);
+                        self.log(LogEntry::info(format!("{} flushed, safe to remove", device.device_path)));
+                    }
+                    Err(error) => {
+                        self.final_sync = None;
+                        self.log(LogEntry::warning(format!("Flushing {} failed: {}", device.device_path, error)));
+                        self.status_message.push_str(&tr!("status.flush_failed_hint", error = error.user_friendly_message()));
+                    }
+                }
+
+                // Eject the device that was written, even if the selection changed since
+                if self.eject_when_done {
+                    return Command::perform(
+                        async move { device::eject_device(&device.device_path).await },
+                        Message::EjectCompleted,
+                    );
+                }
+            }
+            Message::SyncCompleted(..) => {}
+            Message::EjectCompleted(result) => {
+                // The write itself already succeeded, so only tack on a note
+                self.log(match &result {
+                    Ok(()) => LogEntry::info("Device ejected"),
+                    Err(error) => LogEntry::warning(format!("Ejecting failed: {}", error)),
+                });
+                match result {
+                    Ok(()) => self.status_message.push_str(&tr!("status.ejected_hint")),
+                    Err(error) => self.status_message.push_str(&tr!("status.eject_failed_hint", error = error.user_friendly_message())),
+                }
+            }
+            Message::FormatFilesystemSelected(filesystem) => {
+                self.format_filesystem = filesystem;
+            }
+            Message::FormatLabelChanged(label) => {
+                self.format_label = label;
+            }
+            Message::FormatDevice => {
+                if let (Some(device), false) = (&self.selected_device, self.is_writing) {
+                    // A label the filesystem can't take is better refused before the dialog
+                    if let Err(error) = format::validate_label(self.format_filesystem, &self.format_label) {
+                        self.status_message = tr!("status.error", error = error.user_friendly_message());
+                        return Command::none();
+                    }
+                    self.status_message = tr!("status.waiting_for_confirmation");
+                    return Command::perform(confirm_write_dialog(device.clone()), Message::ConfirmFormat);
+                }
+            }
+            Message::ConfirmFormat(confirmed) => {
+                let Some(device) = self.selected_device.clone().filter(|_| confirmed && !self.is_writing) else {
+                    self.status_message = tr!("status.format_cancelled");
+                    return Command::none();
+                };
+
+                self.is_writing = true;
+                self.formatting = true;
+                self.write_progress = 0.0;
+                self.last_progress = None;
+                self.completed_write = None;
+                self.status_message = tr!("status.formatting", device = target_name(&device), filesystem = self.format_filesystem);
+                self.log(LogEntry::info(format!("Formatting {} as {} '{}'", device.device_path, self.format_filesystem, self.format_label.trim())));
+
+                let backend = self.backend.clone();
+                let (filesystem, label) = (self.format_filesystem, self.format_label.clone());
+                return Command::perform(
+                    async move {
+                        device::unmount_device_partitions(&device.device_path).await?;
+                        device::validate_device_for_writing(&*backend, &device).await?;
+                        format::format_device(&device.device_path, filesystem, &label).await
+                    },
+                    Message::FormatCompleted,
+                );
+            }
+            Message::FormatCompleted(result) => {
+                self.is_writing = false;
+                self.formatting = false;
+                self.permission_denied = matches!(result, Err(WriterError::PermissionDenied));
+                let formatted = result.is_ok();
+                let entry = match result {
+                    Ok(()) => {
+                        self.write_progress = 1.0;
+                        LogEntry::info(tr!("status.formatted", filesystem = self.format_filesystem))
+                    }
+                    Err(error) => LogEntry::error(tr!("status.error", error = error.user_friendly_message())),
+                };
+                self.status_message = entry.message.clone();
+                self.log(entry);
+
+                // The device now carries a new partition and label
+                let refresh = self.detect_devices(Message::DevicesDetected);
+                return match self.selected_device.clone().filter(|_| formatted && self.eject_when_done) {
+                    Some(device) => Command::batch([
+                        refresh,
+                        Command::perform(async move { device::eject_device(&device.device_path).await }, Message::EjectCompleted),
+                    ]),
+                    None => refresh,
+                };
+            }
+            Message::TestCapacity => {
+                if let (Some(device), false) = (&self.selected_device, self.is_writing) {
+                    self.status_message = tr!("status.waiting_for_confirmation");
+                    return Command::perform(confirm_write_dialog(device.clone()), Message::ConfirmCapacityTest);
+                }
+            }
+            Message::ConfirmCapacityTest(confirmed) => {
+                let Some(device) = self.selected_device.clone().filter(|_| confirmed && !self.is_writing) else {
+                    self.status_message = tr!("status.capacity_cancelled");
+                    return Command::none();
+                };
+
+                self.is_writing = true;
+                self.write_progress = 0.0;
+                self.last_progress = None;
+                self.capacity_test = Some(device.size);
+                self.cancel_token = CancelToken::new();
+                self.status_message = tr!("status.capacity_testing", device = device.device_path);
+                self.log(LogEntry::info(format!("Capacity test started on {}", device.device_path)));
+
+                return Command::run(
+                    capacity_test_stream(self.backend.clone(), device, self.cancel_token.clone()),
+                    |message| message,
+                );
+            }
+            Message::CapacityTestCompleted(result) => {
+                self.is_writing = false;
+                let claimed = self.capacity_test.take().unwrap_or_default();
+                self.permission_denied = matches!(result, Err(WriterError::PermissionDenied));
+                let level = match result {
+                    Ok(usable) if usable >= claimed => LogLevel::Info,
+                    _ => LogLevel::Error,
+                };
+                self.status_message = match result {
+                    Ok(usable) if usable >= claimed => tr!("status.capacity_passed", size = format_bytes(claimed)),
+                    Ok(usable) => tr!("status.capacity_failed", usable = format_bytes(usable), claimed = format_bytes(claimed)),
+                    Err(WriterError::Cancelled) => tr!("status.capacity_scrambled"),
+                    Err(error) => tr!("status.error", error = error.user_friendly_message()),
+                };
+                self.log(LogEntry::new(level, self.status_message.clone()));
+            }
+            Message::VerifyOnly => {
+                let (Some(iso_path), Some(device)) = (self.iso_path.clone(), self.selected_device.clone()) else {
+                    return Command::none();
+                };
+                if self.is_writing {
+                    return Command::none();
+                }
+                let start_offset = match self.start_offset() {
+                    Ok(start_offset) => start_offset,
+                    Err(error) => {
+                        self.status_message = tr!("status.error", error = error.user_friendly_message());
+                        return Command::none();
+                    }
+                };
+
+                self.is_writing = true;
+                self.verifying_only = true;
+                self.write_progress = 0.0;
+                self.last_progress = None;
+                self.status_message = tr!("status.comparing", device = device.device_path, image = iso_path.display());
+                self.log(LogEntry::info(format!("Verify Only started: {} against {}", device.device_path, iso_path.display())));
+
+                return Command::run(
+                    verify_only_stream(iso_path, device.device_path, start_offset),
+                    |message| message,
+                );
+            }
+            Message::VerifyOnlyCompleted(result) => {
+                self.is_writing = false;
+                self.verifying_only = false;
+                let entry = match result {
+                    Ok(VerifyOutcome::Match) => {
+                        self.write_progress = 1.0;
+                        LogEntry::info(tr!("status.verify_only_passed"))
+                    }
+                    Ok(VerifyOutcome::Mismatch { offset }) => {
+                        LogEntry::error(tr!("status.verify_only_failed", offset = offset, size = format_bytes(offset)))
+                    }
+                    Err(error) => LogEntry::error(tr!("status.error", error = error.user_friendly_message())),
+                };
+                self.status_message = entry.message.clone();
+                self.log(entry);
+            }
+            Message::RelaunchElevated => {
+                let iso_path = self.iso_path.clone();
+                let device_path = self.selected_device.as_ref().map(|device| device.device_path.clone());
+                self.status_message = tr!("status.relaunching");
+                self.log(LogEntry::info("Relaunching with administrator rights"));
+                return Command::perform(
+                    async move { elevate::relaunch_elevated(iso_path.as_deref(), device_path.as_deref()).await },
+                    Message::RelaunchFinished,
+                );
+            }
+            Message::RelaunchFinished(result) => match result {
+                // The elevated instance took over and has been closed, so we're done too
+                Ok(()) => return window::close(window::Id::MAIN),
+                Err(error) => {
+                    self.status_message = tr!("status.relaunch_failed", error = error);
+                    self.log(LogEntry::error(self.status_message.clone()));
+                }
+            },
+            Message::ToggleTheme => {
+                self.settings.theme = self.settings.theme.next();
+                self.save_settings();
+            }
+            Message::Log(entry) => {
+                self.log(entry);
+            }
+            Message::ToggleLogPanel => {
+                self.show_log = !self.show_log;
+            }
+            Message::SaveLog => {
+                return Command::perform(save_log(event_log::format_log(&self.log_entries)), Message::Log);
+            }
+            Message::ShowAbout => {
+                return Command::perform(about_dialog(), |_| Message::AboutClosed);
+            }
+            Message::AboutClosed => {}
+            Message::CloseRequested => {
+                if !self.has_work_in_flight() {
+                    return self.close_window();
+                }
+                // Already winding down after an earlier close
+                if !self.quit_requested {
+                    return Command::perform(confirm_quit_dialog(), Message::QuitConfirmed);
+                }
+            }
+            Message::QuitConfirmed(confirmed) => {
+                if confirmed && self.has_work_in_flight() {
+                    self.quit_requested = true;
+                    self.cancel_token.cancel();
+                    self.status_message = tr!("status.cancelling_to_quit");
+                    self.log(LogEntry::info("Quit requested, cancelling the running write"));
+                }
+            }
+            // Saved once on close rather than for every step of a drag
+            Message::WindowResized(size) => {
+                self.window_size = Some(size);
+            }
+        }
+        Command::none()
+    }
+
+    fn layout(&self, two_pane: bool) -> Element<'_, Message> {
+        let iso_section = column![
+            row![
+                text("1. Select Disk Image").size(16),
+                button("Select Image").on_press(Message::SelectIsoFile),
+                text(
+                    self.iso_path
+                        .as_ref()
+                        .map(|p| p.file_name().unwrap_or_default().to_string_lossy().to_string())
+                        .unwrap_or_else(|| "No file selected".to_string())
+                )
+                .size(14)
+            ]
+            .spacing(10)
+            .align_items(Alignment::Center),
+            row![
+                text("From URL").size(14),
+                text_input("https://example.com/image.iso", &self.iso_url)
+                    .on_input(Message::IsoUrlEntered)
+                    .width(Length::Fixed(400.0)),
+            ]
+            .spacing(10)
+            .align_items(Alignment::Center),
+            row![
+                text("Checksum").size(14),
+                text_input("sha256:<hex>, or paste SHA256SUMS", &self.checksum_input)
+                    .on_input(Message::ChecksumEntered)
+                    .width(Length::Fixed(400.0)),
+            ]
+            .spacing(10)
+            .align_items(Alignment::Center),
+            checkbox("Compute the image's SHA-256 when selecting it", self.settings.hash_on_select)
+                .on_toggle(Message::HashOnSelectToggled),
+        ]
+            .spacing(10)
+            .push_maybe(self.iso_hash_row());
+
+        // Each disk is followed by its partitions when those are shown
+        let device_choices: Vec<DeviceChoice> = self.available_devices
+            .iter()
+            .flat_map(|device| {
+                let partitions = self.available_partitions
+                    .iter()
+                    .filter(|(disk, _)| self.show_partitions && *disk == device.device_path)
+                    .map(|(_, partition)| partition);
+                std::iter::once(device).chain(partitions)
+            })
+            .map(|device| DeviceChoice {
+                device: device.clone(),
+                fits: self.iso_size.map(|size| size <= device.size),
+            })
+            .collect();
+        let selected_choice = self.selected_device
+            .as_ref()
+            .and_then(|selected| device_choices.iter().find(|choice| &choice.device == selected).cloned());
+
+        let mut device_section = column![
+            row![
+                text("2. Select USB Device").size(16),
+                pick_list(
+                    device_choices,
+                    selected_choice,
+                    |choice: DeviceChoice| Message::DeviceSelected(choice.device)
+                )
+                .placeholder("Select USB device..."),
+                button("Refresh").on_press(Message::RefreshDevices),
+                button("Write to image file…")
+                    .on_press_maybe((!self.is_writing).then_some(Message::SelectImageFileTarget))
+                    .style(iced::theme::Button::Secondary),
+                button("Test device capacity")
+                    .on_press_maybe((self.selected_device.is_some() && !self.is_writing).then_some(Message::TestCapacity))
+                    .style(iced::theme::Button::Secondary),
+            ]
+            .spacing(10)
+            .align_items(Alignment::Center),
+            row![
+                checkbox("Show partitions", self.show_partitions)
+                    .on_toggle_maybe((!self.is_writing).then_some(Message::ShowPartitionsToggled)),
+                text("Start offset").size(14),
+                text_input("0, or e.g. 1M", &self.start_offset_input)
+                    .on_input(Message::StartOffsetEntered)
+                    .width(Length::Fixed(120.0)),
+            ]
+            .spacing(10)
+            .align_items(Alignment::Center),
+        ]
+            .spacing(10);
+
+        // Reclaiming a stick after flashing, without writing an image
+        if self.selected_device.as_ref().is_some_and(|device| !device.is_image_file) {
+            device_section = device_section.push(
+                row![
+                    text("Format as").size(14),
+                    pick_list(&Filesystem::ALL[..], Some(self.format_filesystem), Message::FormatFilesystemSelected),
+                    text_input("Label", &self.format_label)
+                        .on_input(Message::FormatLabelChanged)
+                        .width(Length::Fixed(140.0)),
+                    button("Format device…")
+                        .on_press_maybe((!self.is_writing).then_some(Message::FormatDevice))
+                        .style(iced::theme::Button::Secondary),
+                ]
+                .spacing(10)
+                .align_items(Alignment::Center),
+            );
+        }
+
+        // Image files aren't in the device list, so name the target here
+        if let Some(device) = self.selected_device.as_ref().filter(|device| device.is_image_file) {
+            device_section = device_section.push(
+                text(format!("Target: {} ({})", device.device_path, match device.size {
+                    0 => "grows to fit the image".to_string(),
+                    size => device::human_size(size),
+                }))
+                .size(14),
+            );
+        }
+
+        // A small image on a big stick leaves most of it untouched, which the
+        // bar makes plain at a glance
+        if let Some(footprint) = self.device_footprint() {
+            device_section = device_section.push(
+                row![
+                    progress_bar(0.0..=1.0, footprint).width(Length::Fixed(300.0)).height(Length::Fixed(6.0)),
+                    text(format!("Image fills {:.1}% of the device", footprint * 100.0)).size(12),
+                ]
+                .spacing(10)
+                .align_items(Alignment::Center),
+            );
+        }
+
+        if let Some(warning) = self.selected_device.as_ref().and_then(existing_data_warning) {
+            device_section = device_section.push(
+                text(warning).size(14).style(iced::Color::from_rgb(0.85, 0.2, 0.2)),
+            );
+        }
+
+        // Ticking several sticks offers writing the image to all of them in turn
+        if self.available_devices.len() > 1 {
+            let batch_checkboxes = self.available_devices.iter().fold(
+                row![text("Batch").size(14)].spacing(10).align_items(Alignment::Center),
+                |checkboxes, device| {
+                    let device = device.clone();
+                    let checked = self.batch_selection.contains(&device);
+                    checkboxes.push(
+                        checkbox(device.device_path.clone(), checked)
+                            .on_toggle_maybe((!self.is_writing).then_some(move |checked| Message::BatchDeviceToggled(device.clone(), checked))),
+                    )
+                },
+            );
+            device_section = device_section.push(batch_checkboxes);
+        }
+
+        let speed_limit_label = if self.speed_limit_mbps > 0.0 {
+            format!("Speed limit: {:.0} MB/s", self.speed_limit_mbps)
+        } else {
+            "Speed limit: Unlimited".to_string()
+        };
+
+        let mut options_section = column![
+            row![
+                text("Buffer size").size(14),
+                pick_list(
+                    &BUFFER_SIZE_CHOICES[..],
+                    Some(BufferSize(self.settings.buffer_size)),
+                    Message::BufferSizeSelected
+                ),
+                pick_list(&IoMode::ALL[..], Some(self.io_mode), Message::IoModeSelected),
+                checkbox("Accurate progress (sync every 32 MB)", self.accurate_progress)
+                    .on_toggle_maybe((!self.is_writing).then_some(Message::AccurateProgressToggled)),
+                checkbox("Dry run", self.dry_run)
+                    .on_toggle_maybe((!self.is_writing).then_some(Message::DryRunToggled)),
+            ]
+                .spacing(10)
+                .align_items(Alignment::Center),
+            row![
+                text("Verification").size(14),
+                pick_list(&VerifyMode::ALL[..], Some(self.verify_mode), Message::VerifyModeSelected),
+                text(speed_limit_label).size(14),
+                slider(0.0..=100.0, self.speed_limit_mbps, Message::SpeedLimitChanged)
+                    .step(1.0)
+                    .width(Length::Fixed(200.0)),
+                button(text(format!("Theme: {}", self.settings.theme)).size(14))
+                    .on_press(Message::ToggleTheme)
+                    .style(iced::theme::Button::Secondary),
+            ]
+                .spacing(10)
+                .align_items(Alignment::Center),
+        ]
+            .spacing(10);
+
+        if cfg!(target_os = "linux") {
+            options_section = options_section.push(
+                checkbox("Eject when done", self.eject_when_done)
+                    .on_toggle_maybe((!self.is_writing).then_some(Message::EjectWhenDoneToggled)),
+            );
+        }
+
+        // Raw .img/.raw/.bin files may legitimately lack an ISO 9660 or boot signature
+        if self.iso_path.as_deref().is_some_and(writer::is_raw_image) {
+            options_section = options_section.push(
+                checkbox("Write even without a recognizable image signature", self.allow_raw_image)
+                    .on_toggle_maybe((!self.is_writing).then_some(Message::AllowRawImageToggled)),
+            );
+        }
+
+        // Hashing while writing spares a second read of an image on a slow disk
+        if self.verify_mode == VerifyMode::Full {
+            options_section = options_section.push(
+                checkbox("Verify without re-reading the image", self.block_hashes)
+                    .on_toggle_maybe((!self.is_writing).then_some(Message::BlockHashesToggled)),
+            );
+        }
+
+        // Pointless for a dry run, which writes nothing to carry on from
+        if !self.dry_run {
+            options_section = options_section.push(
+                checkbox("Keep track of the progress so the write can be resumed", self.resumable)
+                    .on_toggle_maybe((!self.is_writing).then_some(Message::ResumableToggled)),
+            );
+        }
+
+        // Live systems keep their changes in a partition of the space after
+        // the image. Needs the Linux partitioning tools and a real device.
+        let image_file_target = self.selected_device.as_ref().is_some_and(|device| device.is_image_file);
+        if cfg!(target_os = "linux") && !self.dry_run && !image_file_target {
+            options_section = options_section.push(
+                checkbox("Add a persistence partition for a live system after the image", self.persistence)
+                    .on_toggle_maybe((!self.is_writing).then_some(Message::PersistenceToggled)),
+            );
+        }
+        if self.persistence && !self.dry_run && !image_file_target {
+            options_section = options_section.push(
+                row![
+                    text("For").size(14),
+                    pick_list(&PersistenceLayout::ALL[..], Some(self.persistence_layout), Message::PersistenceLayoutSelected),
+                    text("Size").size(14),
+                    pick_list(&PERSISTENCE_SIZE_CHOICES[..], Some(self.persistence_size), Message::PersistenceSizeSelected),
+                ]
+                .spacing(10)
+                .align_items(Alignment::Center),
+            );
+            options_section = options_section.push(
+                text("Erases the rest of the device too. Boot the live system with persistence turned on to use it.").size(12),
+            );
+        }
+
+        // For devices that misreport their size; a real shortage still fails the write
+        options_section = options_section.push(
+            checkbox("Ignore size check", self.ignore_size_check)
+                .on_toggle_maybe((!self.is_writing).then_some(Message::IgnoreSizeCheckToggled)),
+        );
+
+        let mut write_section = row![
+            text("3. Write Image").size(16),
+            if self.can_write() {
+                button("Write Image to USB Device")
+                    .on_press(Message::StartWriting)
+                    .style(iced::theme::Button::Primary)
+            } else {
+                button("Write Image to USB Device")
+                    .style(iced::theme::Button::Secondary)
+            }
+        ]
+            .spacing(10);
+
+        if self.can_write() {
+            write_section = write_section.push(
+                button("Unmount & Write")
+                    .on_press(Message::UnmountAndWrite)
+                    .style(iced::theme::Button::Secondary)
+            );
+        }
+
+        if self.batch_selection.len() > 1 {
+            write_section = write_section.push(
+                button(text(format!("Write to {} devices", self.batch_selection.len())))
+                    .on_press_maybe(self.can_write_batch().then_some(Message::StartBatchWrite))
+                    .style(iced::theme::Button::Secondary)
+            );
+            write_section = write_section.push(
+                checkbox("In parallel", self.parallel_batch)
+                    .on_toggle_maybe((!self.is_writing).then_some(Message::ParallelBatchToggled)),
+            );
+            if self.parallel_batch {
+                write_section = write_section.push(
+                    checkbox("Image in memory", self.batch_in_memory)
+                        .on_toggle_maybe((!self.is_writing).then_some(Message::BatchInMemoryToggled)),
+                );
+            }
+        }
+
+        if self.permission_denied && !self.running_elevated && !self.is_writing {
+            write_section = write_section.push(
+                button("Relaunch as administrator")
+                    .on_press(Message::RelaunchElevated)
+                    .style(iced::theme::Button::Primary)
+            );
+        }
+
+        if self.verification_failure.is_some() && !self.is_writing {
+            write_section = write_section.push(
+                button("Retry region")
+                    .on_press(Message::RepairRegion)
+                    .style(iced::theme::Button::Secondary)
+            );
+        }
+
+        if self.can_verify_only() {
+            write_section = write_section.push(
+                button("Verify Only")
+                    .on_press(Message::VerifyOnly)
+                    .style(iced::theme::Button::Secondary)
+            );
+        }
+
+        // The capacity test can be cancelled, but not paused. Verify Only
+        // only reads, so there is nothing to pause or cancel safely.
+        if self.is_writing && self.capacity_test.is_none() && !self.verifying_only && !self.formatting {
+            let pause_button = if self.pause_token.is_paused() {
+                button("Resume").on_press(Message::ResumeWriting)
+            } else {
+                button("Pause").on_press(Message::PauseWriting)
+            };
+            write_section = write_section.push(pause_button.style(iced::theme::Button::Secondary));
+        }
+
+        if self.is_writing && !self.verifying_only && !self.formatting {
+            write_section = write_section.push(
+                button("Cancel")
+                    .on_press(Message::CancelWriting)
+                    .style(iced::theme::Button::Destructive)
+            );
+        }
+
+        let write_section: Element<'_, Message> = match &self.typed_confirmation {
+            Some(typed) => column![
+                write_section,
+                row![
+                    text_input("Size or name of the device", typed)
+                        .on_input(Message::TypedConfirmationChanged)
+                        .on_submit(Message::TypedConfirmationSubmitted)
+                        .width(Length::Fixed(300.0)),
+                    button("Erase and write")
+                        .on_press(Message::TypedConfirmationSubmitted)
+                        .style(iced::theme::Button::Destructive),
+                    button("Cancel")
+                        .on_press(Message::TypedConfirmationCancelled)
+                        .style(iced::theme::Button::Secondary),
+                ]
+                    .spacing(10)
+                    .align_items(Alignment::Center),
+            ]
+                .spacing(10)
+                .into(),
+            None => write_section.into(),
+        };
+
+        let progress_section = match &self.last_progress {
+            // A parallel batch gets a bar per device instead
+            _ if !self.parallel_writes.is_empty() => self.parallel_writes.iter().fold(
+                column![text("Progress").size(16)].spacing(5),
+                |section, write| {
+                    let verifies = !write.job.options.dry_run && write.job.options.verify_mode.has_verify_pass();
+                    let (fraction, label) = match (&write.result, &write.progress) {
+                        (Some(Ok(())), _) => (1.0, tr!("status.parallel_device_done")),
+                        (Some(Err(error)), _) => (0.0, tr!("status.parallel_device_failed", error = error.user_friendly_message())),
+                        (None, Some(progress)) => (
+                            overall_progress(progress.phase, progress.progress_percent.unwrap_or(0.0), verifies),
+                            format!("{} · {}", progress.phase, format_speed(progress.instant_speed_mbps)),
+                        ),
+                        (None, None) => (0.0, tr!("status.parallel_device_starting")),
+                    };
+                    section.push(
+                        row![
+                            text(&write.device.device_path).size(12).width(Length::Fixed(100.0)),
+                            progress_bar(0.0..=1.0, fraction).width(Length::Fixed(300.0)),
+                            text(label).size(12),
+                        ]
+                            .spacing(10)
+                            .align_items(Alignment::Center),
+                    )
+                },
+            ),
+            Some(progress) if self.is_writing || self.write_progress > 0.0 => {
+                let amount = match progress.total_bytes {
+                    Some(total) => format!("{} / {} ({:.1}%)",
+                                           format_bytes(progress.bytes_written),
+                                           format_bytes(total),
+                                           progress.progress_percent.unwrap_or(0.0)),
+                    // Compressed images don't tell us their final size up front
+                    None => format!("{} written", format_bytes(progress.bytes_written)),
+                };
+
+                let mut details = format!("{} · {} average · {} elapsed",
+                                          format_speed(progress.instant_speed_mbps),
+                                          format_speed(progress.speed_mbps),
+                                          format_duration(progress.elapsed_seconds));
+                if let Some(eta) = progress.eta_seconds.filter(|_| self.is_writing) {
+                    details.push_str(&format!(" · ~{} remaining", format_duration(eta)));
+                }
+
+                // Without a percentage, a sweeping bar shows the write is alive
+                let fraction = match progress.progress_percent {
+                    None if self.is_writing => indeterminate_progress(progress.elapsed_seconds),
+                    _ => self.write_progress,
+                };
+
+                let mut section = column![
+                    text(format!("Progress — {}", progress.phase)).size(16),
+                    progress_bar(0.0..=1.0, fraction),
+                    text(amount),
+                    text(details).size(12),
+                ]
+                    .spacing(5);
+                if let Some(pipeline) = progress.pipeline.filter(|_| self.is_writing) {
+                    section = section.push(text(pipeline_summary(progress.bytes_written, pipeline)).size(12));
+                }
+                section
+            }
+            _ if self.is_writing => column![
+                text("Progress").size(16),
+                progress_bar(0.0..=1.0, self.write_progress),
+            ]
+                .spacing(5),
+            _ => column![],
+        };
+
+        let mut status_section = column![
+            text("Status").size(16),
+            text(&self.status_message).size(12),
+        ]
+            .spacing(5);
+
+        if let Some(summary) = &self.completed_write {
+            status_section = status_section.push(completion_panel(summary));
+        }
+
+        match self.final_sync {
+            Some(FinalSync::Flushing) => {
+                status_section = status_section.push(text("Flushing caches…").size(14));
+            }
+            Some(FinalSync::SafeToRemove) => {
+                status_section = status_section.push(
+                    text("Safe to remove the USB device now.").size(14).style(iced::Color::from_rgb(0.1, 0.6, 0.2)),
+                );
+            }
+            None => {}
+        }
+
+        // Per-device outcome of the last batch, kept until the next one starts
+        if self.batch.is_none() {
+            for (device, result) in &self.batch_results {
+                let line = match result {
+                    Ok(()) => format!("✓ {} ({})", device.device_path, device.name),
+                    Err(WriterError::Cancelled) => format!("– {} ({}): cancelled", device.device_path, device.name),
+                    Err(error) => format!("✗ {} ({}): {}", device.device_path, device.name, error),
+                };
+                status_section = status_section.push(text(line).size(12));
+            }
+        }
+
+        let mut log_section = column![
+            row![
+                button(text(if self.show_log { "Hide log".to_string() } else { format!("Show log ({})", self.log_entries.len()) }).size(14))
+                    .on_press(Message::ToggleLogPanel)
+                    .style(iced::theme::Button::Secondary),
+                button(text("Save log…").size(14))
+                    .on_press_maybe((!self.log_entries.is_empty()).then_some(Message::SaveLog))
+                    .style(iced::theme::Button::Secondary),
+                button(text("About").size(14))
+                    .on_press(Message::ShowAbout)
+                    .style(iced::theme::Button::Secondary),
+            ]
+                .spacing(10),
+        ]
+            .spacing(5);
+
+        if self.show_log {
+            let lines = self.log_entries
+                .iter()
+                .fold(column![].spacing(2), |lines, entry| lines.push(text(entry.to_string()).size(12)));
+            log_section = log_section.push(scrollable(lines).height(Length::Fixed(120.0)).width(Length::Fill));
+        }
+
+        let content: Element<'_, Message> = if two_pane {
+            // Choosing what goes where on the left, writing it on the right
+            row![
+                column![iso_section, device_section, options_section]
+                    .spacing(20)
+                    .width(Length::FillPortion(1)),
+                column![write_section, progress_section, status_section, log_section]
+                    .spacing(20)
+                    .width(Length::FillPortion(1)),
+            ]
+                .spacing(30)
+                .padding(20)
+                .into()
+        } else {
+            column![
+                iso_section,
+                device_section,
+                options_section,
+                write_section,
+                progress_section,
+                status_section,
+                log_section,
+            ]
+                .spacing(20)
+                .padding(20)
+                .into()
+        };
+
+        // A window too small for everything scrolls instead of cutting it off
+        scrollable(container(content).width(Length::Fill).center_x()).into()
+    }
+
+    // The selected image's SHA-256, or how far computing it got
+    fn iso_hash_row(&self) -> Option<Element<'_, Message>> {
+        if let Some(hashing) = &self.iso_hashing {
+            let fraction = if hashing.total > 0 { hashing.bytes_hashed as f32 / hashing.total as f32 } else { 0.0 };
+            return Some(
+                row![
+                    text("Computing SHA-256…").size(14),
+                    progress_bar(0.0..=1.0, fraction).width(Length::Fixed(200.0)).height(Length::Fixed(6.0)),
+                    text(format!("{} of {}", format_bytes(hashing.bytes_hashed), format_bytes(hashing.total))).size(12),
+                    button("Cancel").on_press(Message::CancelIsoHash).style(iced::theme::Button::Secondary),
+                ]
+                .spacing(10)
+                .align_items(Alignment::Center)
+                .into(),
+            );
+        }
+
+        let digest = self.iso_hash.as_ref()?;
+        Some(
+            row![
+                text(format!("SHA-256: {}", digest)).size(12),
+                button("Copy").on_press(Message::CopyIsoHash).style(iced::theme::Button::Secondary),
+            ]
+            .spacing(10)
+            .align_items(Alignment::Center)
+            .into(),
+        )
+    }
+
+    fn close_window(&mut self) -> Command<Message> {
+        if self.window_size.is_some() && self.window_size != self.settings.window_size {
+            self.settings.window_size = self.window_size;
+            self.save_settings();
+        }
+        window::close(window::Id::MAIN)
+    }
+
+    // Writes and test passes as well as the flush after a write, any of
+    // which would be cut short by closing the window
+    fn has_work_in_flight(&self) -> bool {
+        (self.is_writing && !self.verifying_only) || self.final_sync == Some(FinalSync::Flushing)
+    }
+
+    fn can_write(&self) -> bool {
+        self.iso_path.is_some() && self.selected_device.is_some() && !self.is_writing && (self.ignore_size_check || !self.image_too_large())
+    }
+
+    // Verify Only doesn't care whether the image fits, only that both are chosen
+    fn can_verify_only(&self) -> bool {
+        self.iso_path.is_some() && self.selected_device.is_some() && !self.is_writing
+    }
+
+    // Parsed start offset, where an empty field means the start of the device
+    fn start_offset(&self) -> Result<u64, WriterError> {
+        let input = self.start_offset_input.trim();
+        if input.is_empty() {
+            return Ok(0);
+        }
+        let start_offset = cli::parse_size(input).map_err(WriterError::Unknown)? as u64;
+        writer::validate_start_offset(start_offset)
+    }
+
+    // Room for the image on the selected device, past the start offset
+    fn available_space(&self) -> Option<u64> {
+        let device = self.selected_device.as_ref()?;
+        Some(device.size.saturating_sub(self.start_offset().unwrap_or(0)))
+    }
+
+    // Only known once the image size has been read, so URLs and compressed
+    // images are left to the check at write time
+    fn image_too_large(&self) -> bool {
+        match (self.iso_size, self.available_space()) {
+            (Some(iso_size), Some(available)) => iso_size > available,
+            _ => false,
+        }
+    }
+
+    // How the selected image fits on the selected device, e.g.
+    // "Image 3.2 GB → device 14.9 GB (11.7 GB unused)"
+    fn size_preview(&self) -> Option<String> {
+        let iso_size = self.iso_size?;
+        let Some(device) = &self.selected_device else {
+            return Some(tr!("status.size_image", image = device::human_size(iso_size)));
+        };
+
+        let mut sizes = tr!("status.size_image_device", image = device::human_size(iso_size), device = device.human_size());
+        let start_offset = self.start_offset().unwrap_or(0);
+        if start_offset > 0 {
+            sizes.push_str(&tr!("status.size_from_byte", offset = start_offset));
+        }
+
+        let available = self.available_space().unwrap_or(device.size);
+        Some(if iso_size > available {
+            tr!("status.size_short", sizes = sizes, short = device::human_size(iso_size - available))
+        } else {
+            tr!("status.size_unused", sizes = sizes, unused = device::human_size(available - iso_size))
+        })
+    }
+
+    // Share of the device the image will take up, start offset included.
+    // Image files grow to fit, so there is nothing to show for them.
+    fn device_footprint(&self) -> Option<f32> {
+        let iso_size = self.iso_size?;
+        let device = self.selected_device.as_ref().filter(|device| !device.is_image_file && device.size > 0)?;
+        let used = iso_size.saturating_add(self.start_offset().unwrap_or(0));
+        Some((used as f64 / device.size as f64).min(1.0) as f32)
+    }
+
+    // Whether the running write is followed by a verify pass sharing the bar
+    fn verifies_after_write(&self) -> bool {
+        self.capacity_test.is_some() || self.write_job
+            .as_ref()
+            .is_some_and(|job| !job.options.dry_run && job.options.verify_mode.has_verify_pass())
+    }
+
+    // Adds an entry to the log panel, mirroring it to stderr
+    fn log(&mut self, entry: LogEntry) {
+        match entry.level {
+            LogLevel::Info => info!("{}", entry.message),
+            LogLevel::Warning => warn!("{}", entry.message),
+            LogLevel::Error => error!("{}", entry.message),
+        }
+        self.log_entries.push(entry);
+    }
+
+    // Detect devices off the update loop, through the backend
+    fn detect_devices(&self, on_detected: fn(Result<Vec<UsbDevice>, WriterError>) -> Message) -> Command<Message> {
+        let backend = self.backend.clone();
+        Command::perform(async move { backend.detect().await }, on_detected)
+    }
+
+    // Hash the selected local image in the background, in place of any
+    // earlier hashing. Downloads are checked against their checksum as they
+    // are written instead.
+    fn hash_selected_image(&mut self) -> Command<Message> {
+        self.clear_iso_hash();
+        let Some(iso_path) = self.iso_path.clone().filter(|path| !download::is_url(path)) else {
+            return Command::none();
+        };
+
+        let cancel_token = CancelToken::new();
+        self.iso_hashing = Some(IsoHashing { bytes_hashed: 0, total: 0, cancel_token: cancel_token.clone() });
+        Command::run(iso_hash_stream(iso_path, cancel_token), |message| message)
+    }
+
+    fn clear_iso_hash(&mut self) {
+        if let Some(hashing) = self.iso_hashing.take() {
+            hashing.cancel_token.cancel();
+        }
+        self.iso_hash = None;
+    }
+
+    fn save_settings(&self) {
+        if let Err(error) = self.settings.save() {
+            warn!("Failed to save settings: {}", error);
+        }
+    }
+
+    fn confirm_writing(&mut self, unmount_first: bool) -> Command<Message> {
+        let claimed = match self.selected_device.clone() {
+            Some(device) => self.can_write() && self.claim_writer(&device),
+            None => false,
+        };
+        if !claimed {
+            return Command::none();
+        }
+
+        let Some(device) = self.selected_device.clone() else {
+            return Command::none();
+        };
+
+        // Nothing gets erased in a dry run, so skip the warning and don't unmount
+        if self.dry_run {
+            return self.start_writing(device, false);
+        }
+
+        self.unmount_requested = unmount_first;
+        self.status_message = tr!("status.waiting_for_confirmation");
+
+        Command::perform(confirm_write_dialog(device), Message::ConfirmWrite)
+    }
+
+    // Every ticked device needs to fit the image, and at least two make a batch
+    fn can_write_batch(&self) -> bool {
+        self.iso_path.is_some()
+            && self.batch_selection.len() > 1
+            && !self.is_writing
+            && self.batch_selection
+                .iter()
+                .all(|device| self.ignore_size_check || self.iso_size.is_none_or(|size| size <= device.size))
+    }
+
+    // Start the batch's next device, after recording any that can't be
+    // started, or wrap the batch up once every device has had its turn
+    fn start_next_in_batch(&mut self) -> Command<Message> {
+        let Some(mut batch) = self.batch.take() else {
+            return Command::none();
+        };
+
+        while let Some(device) = batch.pending.pop_front() {
+            if !self.backend.exists(&device.device_path) {
+                let error = WriterError::DeviceNotFound(device.device_path.clone());
+                self.log(LogEntry::error(format!("Skipping {}: {}", device.device_path, error)));
+                batch.results.push((device, Err(error)));
+                continue;
+            }
+            if !self.claim_writer(&device) {
+                batch.results.push((device.clone(), Err(WriterError::DeviceBusy(device.device_path))));
+                continue;
+            }
+
+            batch.current = Some(device.clone());
+            let (done, total) = (batch.results.len(), batch.total());
+            self.batch = Some(batch);
+
+            // The batch was confirmed as a whole, so mounted sticks are unmounted
+            // rather than failing one by one
+            let command = self.start_writing(device.clone(), !self.dry_run);
+            if self.write_job.is_some() {
+                self.status_message = tr!("status.batch_progress", done = done, total = total, device = device.device_path, name = device.name);
+                return command;
+            }
+
+            // start_writing has said why, e.g. a bad checksum, which would fail
+            // the same way on every device, so leave the rest unwritten
+            let Some(mut batch) = self.batch.take() else {
+                return command;
+            };
+            let unwritten: Vec<UsbDevice> = batch.current.take().into_iter().chain(batch.pending.drain(..)).collect();
+            batch.results.extend(unwritten.into_iter().map(|device| (device, Err(WriterError::Cancelled))));
+            self.log(LogEntry::error(format!("Batch write stopped: {}", self.status_message)));
+            self.batch_results = batch.results;
+            return command;
+        }
+
+        self.finish_batch(batch.results);
+        Command::none()
+    }
+
+    fn finish_batch(&mut self, results: Vec<(UsbDevice, Result<(), WriterError>)>) {
+        let succeeded = results.iter().filter(|(_, result)| result.is_ok()).count();
+        self.status_message = tr!("status.batch_finished", succeeded = succeeded, total = results.len());
+        self.log(LogEntry::new(
+            if succeeded == results.len() { LogLevel::Info } else { LogLevel::Warning },
+            self.status_message.clone(),
+        ));
+        self.batch_results = results;
+    }
+
+    // Start a write for every ticked device at once, each with its own
+    // progress stream, sharing `image` when it was loaded into memory
+    fn start_parallel_batch(&mut self, image: Option<ImageBuffer>) -> Command<Message> {
+        let mut writes = Vec::new();
+        for device in self.batch_selection.clone() {
+            // Mounted sticks are unmounted, as for a sequential batch
+            match self.build_write_job(&device, !self.dry_run) {
+                Ok(Some(mut job)) => {
+                    job.options.image = image.clone();
+                    writes.push(ParallelWrite { device, job, progress: None, result: None });
+                }
+                Ok(None) => return Command::none(),
+                Err(error) => {
+                    self.status_message = tr!("status.error", error = error.user_friendly_message());
+                    return Command::none();
+                }
+            }
+        }
+
+        self.reset_progress();
+        self.status_message = tr!("status.parallel_started", total = writes.len());
+        self.log(LogEntry::info(format!("Parallel batch write started: {} devices", writes.len())));
+        for write in &writes {
+            self.log_write_started(&write.job);
+        }
+
+        self.write_id += 1;
+        self.parallel_writes = writes;
+        Command::none()
+    }
+
+    // Reserve the writer slot for a new write. Fails if another write has
+    // already claimed it, e.g. a second StartWriting queued behind the first.
+    fn claim_writer(&mut self, device: &UsbDevice) -> bool {
+        let Some(iso_path) = &self.iso_path else {
+            return false;
+        };
+        let Ok(mut slot) = self.writer.try_lock() else {
+            return false;
+        };
+        if slot.is_some() {
+            return false;
+        }
+
+        // Fresh tokens per write so an earlier cancel can't poison this one
+        self.cancel_token = CancelToken::new();
+        self.pause_token = PauseToken::new();
+
+        *slot = Some(
+            UsbWriter::new(iso_path.to_string_lossy().to_string(), device.device_path.clone(), self.io_mode)
+                .with_dry_run(self.dry_run)
+                .with_verify_mode(self.verify_mode)
+                .with_cancel_token(self.cancel_token.clone())
+                .with_pause_token(self.pause_token.clone()),
+        );
+        true
+    }
+
+    fn release_writer(&self) {
+        if let Ok(mut slot) = self.writer.try_lock() {
+            *slot = None;
+        }
+    }
+
+    // Before a resumable write, look for an interrupted one of the same image
+    // to the same device and ask whether to carry on after it
+    fn offer_resume(&mut self, device: UsbDevice) -> Command<Message> {
+        let found = (self.iso_path.clone(), self.resume_identity(&device), self.start_offset());
+        let (Some(iso_path), Some(identity), Ok(start_offset)) = found else {
+            return self.start_writing(device, self.unmount_requested);
+        };
+
+        Command::perform(ask_to_resume(iso_path, identity, start_offset, device.name), Message::ResumeChosen)
+    }
+
+    // What the progress of a write to `device` is saved under, when it is
+    // to be resumable
+    fn resume_identity(&self, device: &UsbDevice) -> Option<String> {
+        if !self.resumable || self.dry_run {
+            return None;
+        }
+        resume::device_identity(&device.device_path, &device.serial)
+    }
+
+    fn start_writing(&mut self, device: UsbDevice, unmount_first: bool) -> Command<Message> {
+        // A stray ConfirmWrite must not start a second write next to this one
+        if self.write_job.is_some() {
+            return Command::none();
+        }
+
+        let resume_from = self.resume_from.take();
+        let mut job = match self.build_write_job(&device, unmount_first) {
+            Ok(Some(job)) => job,
+            Ok(None) => return Command::none(),
+            Err(error) => {
+                self.release_writer();
+                self.status_message = tr!("status.error", error = error.user_friendly_message());
+                return Command::none();
+            }
+        };
+
+        self.reset_progress();
+        self.status_message = if self.dry_run {
+            tr!("status.dry_run_started")
+        } else if unmount_first {
+            tr!("status.unmounting_and_writing")
+        } else {
+            tr!("status.writing")
+        };
+        job.options.resume_from = resume_from;
+        self.log_write_started(&job);
+
+        // subscription() picks the job up and streams its progress until
+        // WriteCompleted clears it again
+        self.write_id += 1;
+        self.write_job = Some(job);
+        Command::none()
+    }
+
+    // The job for writing the selected image to `device` with the current
+    // options, or None without an image. Rejects a bad checksum or offset up
+    // front rather than after a full write.
+    fn build_write_job(&self, device: &UsbDevice, unmount_first: bool) -> Result<Option<WriteJob>, WriterError> {
+        let Some(iso_path) = &self.iso_path else {
+            return Ok(None);
+        };
+
+        let checksum = if self.checksum_input.trim().is_empty() {
+            None
+        } else {
+            let file_name = download::file_path(iso_path)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+
+            Some(checksum::parse_checksum(&self.checksum_input, &file_name).map_err(WriterError::InvalidChecksum)?)
+        };
+
+        let start_offset = self.start_offset()?;
+
+        Ok(Some(WriteJob {
+            iso_path: iso_path.clone(),
+            device: device.clone(),
+            backend: self.backend.clone(),
+            cancel_token: self.cancel_token.clone(),
+            pause_token: self.pause_token.clone(),
+            unmount_first,
+            options: WriteOptions {
+                buffer_size: self.settings.buffer_size,
+                io_mode: self.io_mode,
+                accurate_progress: self.accurate_progress,
+                start_offset,
+                dry_run: self.dry_run,
+                verify_mode: self.verify_mode,
+                block_hashes: self.block_hashes,
+                max_speed_mbps: (self.speed_limit_mbps > 0.0).then_some(self.speed_limit_mbps),
+                allow_raw_image: self.allow_raw_image && writer::is_raw_image(iso_path),
+                ignore_size_check: self.ignore_size_check,
+                checksum,
+                resume_device: self.resume_identity(device),
+                persistence: (self.persistence && !device.is_image_file).then_some(PersistenceOptions {
+                    layout: self.persistence_layout,
+                    size: self.persistence_size.0,
+                }),
+                ..WriteOptions::default()
+            },
+        }))
+    }
+
+    fn reset_progress(&mut self) {
+        self.is_writing = true;
+        self.write_progress = 0.0;
+        self.last_progress = None;
+        self.verification_failure = None;
+        self.final_sync = None;
+        self.completed_write = None;
+        self.busy_retries = 0;
+        self.status_before_checks = None;
+        self.permission_denied = false;
+        self.logged_milestone = None;
+    }
+
+    fn log_write_started(&mut self, job: &WriteJob) {
+        self.log(LogEntry::info(format!(
+            "{} started: {} → {} (buffer {}, {} verification{}{}{})",
+            if job.options.dry_run { "Dry run" } else { "Write" },
+            job.iso_path.display(),
+            target_name(&job.device),
+            BufferSize(job.options.buffer_size),
+            job.options.verify_mode,
+            match job.options.io_mode {
+                IoMode::Buffered => String::new(),
+                io_mode => format!(", {}", io_mode),
+            },
+            if job.unmount_first { ", unmounting first" } else { "" },
+            if job.options.image.is_some() { ", image in memory" } else { "" },
+        )));
+    }
 }
 
-impl SchrijverApplication {
-    fn can_write(&self) -> bool {
-        self.iso_path.is_some() && self.selected_device.is_some() && !self.is_writing
+// Details of a finished write: how much, how long, how fast and how it was checked
+fn completion_panel(summary: &WriteSummary) -> iced::widget::Column<'static, Message> {
+    let verification = if summary.verified() {
+        summary.verify_mode.to_string()
+    } else {
+        "Not verified".to_string()
+    };
+
+    column![
+        text(format!("Written: {}", format_bytes(summary.bytes_written))).size(12),
+        text(format!("Time: {} ({} average)",
+                     format_duration(summary.duration.as_secs_f64()),
+                     format_speed(summary.average_speed_mbps))).size(12),
+        text(format!("Verification: {}", verification)).size(12),
+        text(format!("SHA-256: {}", summary.digest)).size(12),
+    ]
+        .spacing(2)
+        .push_maybe(summary.persistence_partition.as_ref().map(|node| text(format!("Persistence partition: {}", node)).size(12)))
+}
+
+// e.g. "read 900 MB / written 850 MB (buffer 3/4 full)"
+fn pipeline_summary(bytes_written: u64, pipeline: PipelineStats) -> String {
+    format!("read {} / written {} (buffer {}/{} full)",
+            format_bytes(pipeline.bytes_read),
+            format_bytes(bytes_written),
+            pipeline.buffers_filled,
+            pipeline.depth)
+}
+
+// Position of the bar while the total is unknown, sweeping across every two seconds
+fn indeterminate_progress(elapsed_seconds: f64) -> f32 {
+    (elapsed_seconds / 2.0).fract() as f32
+}
+
+// Map a phase's own percentage onto the bar: writing fills the first half and
+// verifying the second, or writing takes the whole bar when nothing is verified
+fn overall_progress(phase: Phase, percent: f32, verifies: bool) -> f32 {
+    let fraction = (percent / 100.0).clamp(0.0, 1.0);
+    match (phase, verifies) {
+        (Phase::Writing, false) => fraction,
+        (Phase::Writing, true) => fraction * 0.5,
+        (Phase::Verifying, _) => 0.5 + fraction * 0.5,
+        (Phase::Waiting | Phase::Validating(_), _) => 0.0,
+        (Phase::Persistence, _) => 1.0,
+    }
+}
+
+// The device as the user picked it, with the raw path for reference, e.g.
+// "SanDisk Ultra 16 GB (/dev/sdb)"
+fn target_name(device: &UsbDevice) -> String {
+    format!("{} ({})", device.name, device.device_path)
+}
+
+fn format_bytes(bytes: u64) -> String {
+    let mb = bytes as f64 / (1024.0 * 1024.0);
+    if mb >= 1024.0 {
+        format!("{:.2} GB", mb / 1024.0)
+    } else {
+        format!("{:.1} MB", mb)
+    }
+}
+
+// Slow sticks and throttled writes can drop well below 1 MB/s
+fn format_speed(speed_mbps: f64) -> String {
+    if speed_mbps < 1.0 {
+        format!("{:.0} KB/s", speed_mbps * 1024.0)
+    } else {
+        format!("{:.1} MB/s", speed_mbps)
+    }
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, (seconds % 3600) / 60, seconds % 60);
+
+    if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
     }
 }
 
-async fn select_iso_file() -> Option<PathBuf> {
+// Compressed images only reveal their size once decompressed, so they are
+// reported as unknown
+async fn read_iso_size(path: PathBuf) -> (PathBuf, Option<u64>) {
+    let size = match writer::Compression::from_path(&path) {
+        writer::Compression::None => tokio::fs::metadata(&path).await.ok().map(|metadata| metadata.len()),
+        _ => None,
+    };
+    (path, size)
+}
+
+// Image file targets are sized like the image, rounded up to whole sectors.
+// Compressed images don't know their size, so the file grows as they're written.
+fn image_file_size(iso_size: Option<u64>) -> u64 {
+    iso_size.map_or(0, |size| size.div_ceil(512) * 512)
+}
+
+async fn select_image_file_target() -> Option<PathBuf> {
     AsyncFileDialog::new()
-        .add_filter("ISO Files", &["iso"])
-        .set_title("Select ISO File")
+        .add_filter("Disk Images", &["img", "raw", "bin"])
+        .set_title("Write to Image File")
+        .set_file_name("disk.img")
+        .save_file()
+        .await
+        .map(|file| file.path().to_path_buf())
+}
+
+async fn about_dialog() {
+    AsyncMessageDialog::new()
+        .set_level(MessageLevel::Info)
+        .set_title("About Schrijver")
+        .set_description(about::about_text())
+        .set_buttons(MessageButtons::Ok)
+        .show()
+        .await;
+}
+
+// The interrupted write to carry on from, if there is one and the user wants to
+async fn ask_to_resume(iso_path: PathBuf, device_serial: String, start_offset: u64, device_name: String) -> Option<ResumeState> {
+    let state = tokio::task::spawn_blocking(move || resume::find_interrupted(&iso_path, &device_serial, start_offset))
+        .await
+        .ok()
+        .flatten()?;
+
+    let result = AsyncMessageDialog::new()
+        .set_level(MessageLevel::Info)
+        .set_title("Resume the interrupted write?")
+        .set_description(format!(
+            "An earlier write of this image to {} stopped after {} of {}.\n\nCarry on from there? What is already on the device is checked first. Choose No to write the whole image again.",
+            device_name,
+            format_bytes(state.committed),
+            format_bytes(state.image_size),
+        ))
+        .set_buttons(MessageButtons::YesNo)
+        .show()
+        .await;
+
+    (result == MessageDialogResult::Yes).then_some(state)
+}
+
+async fn confirm_quit_dialog() -> bool {
+    let result = AsyncMessageDialog::new()
+        .set_level(MessageLevel::Warning)
+        .set_title("Writing in progress")
+        .set_description("Writing in progress — cancel and quit?\n\nThe write is cancelled and the window closes once it has stopped. The device won't hold a usable image.")
+        .set_buttons(MessageButtons::YesNo)
+        .show()
+        .await;
+
+    result == MessageDialogResult::Yes
+}
+
+// Save the log panel to a file of the user's choice, reporting the outcome as a
+// log entry of its own
+async fn save_log(contents: String) -> LogEntry {
+    let Some(file) = AsyncFileDialog::new()
+        .set_title("Save Log")
+        .set_file_name("schrijver.log")
+        .save_file()
+        .await
+    else {
+        return LogEntry::info("Saving the log was cancelled");
+    };
+
+    match tokio::fs::write(file.path(), contents).await {
+        Ok(()) => LogEntry::info(format!("Log saved to {}", file.path().display())),
+        Err(error) => LogEntry::error(format!("Failed to save the log to {}: {}", file.path().display(), error)),
+    }
+}
+
+fn detect_system_theme() -> Theme {
+    match dark_light::detect() {
+        dark_light::Mode::Dark => Theme::Dark,
+        dark_light::Mode::Light | dark_light::Mode::Default => Theme::Light,
+    }
+}
+
+async fn select_iso_file(last_dir: Option<PathBuf>) -> Option<PathBuf> {
+    let mut dialog = AsyncFileDialog::new()
+        .add_filter("Disk Images", &["iso", "img", "raw", "bin", "dmg", "vhd", "vhdx", "gz", "xz", "zst"])
+        .set_title("Select Disk Image");
+
+    if let Some(last_dir) = last_dir.filter(|dir| dir.is_dir()) {
+        dialog = dialog.set_directory(last_dir);
+    }
+
+    dialog
         .pick_file()
         .await
         .map(|file| file.path().to_path_buf())
 }
 
-async fn write_iso_to_usb(iso_path: PathBuf, device_path: String) -> Result<(), WriterError> {
-    //
-    // TODO: Write the ISO to the USB device!
+async fn confirm_write_dialog(device: UsbDevice) -> bool {
+    // Only informs, a drive that reports failing can still be written
+    let health_warning = if device.is_image_file {
+        None
+    } else {
+        health::device_health(&device.device_path).await.and_then(|health| health.warning())
+    };
+
+    let description = format!(
+        "All data on this device will be erased!\n\n\
+         {}{}\
+         Name: {}\n\
+         Path: {}\n\
+         Size: {}\n\
+         Vendor: {}\n\
+         Model: {}\n\
+         Serial: {}\n\
+         Labels: {}\n\n\
+         Do you want to continue?",
+        existing_data_warning(&device).map(|warning| format!("{}\n\n", warning)).unwrap_or_default(),
+        health_warning.map(|warning| format!("WARNING: {}\n\n", warning)).unwrap_or_default(),
+        device.name,
+        device.device_path,
+        device.human_size(),
+        device.vendor,
+        device.model,
+        if device.serial.is_empty() { "Unknown" } else { &device.serial },
+        if device.labels.is_empty() { "None".to_string() } else { device.labels.join(", ") },
+    );
+
+    let result = AsyncMessageDialog::new()
+        .set_level(MessageLevel::Warning)
+        .set_title("Confirm Write")
+        .set_description(description)
+        .set_buttons(MessageButtons::YesNo)
+        .show()
+        .await;
+
+    result == MessageDialogResult::Yes
+}
+
+// Spelled out in the confirmation, e.g. "WARNING: This device contains an
+// ext4 'backups' partition.", so a data drive picked by mistake stands out
+fn existing_data_warning(device: &UsbDevice) -> Option<String> {
+    let existing_data = device.existing_data();
+    match existing_data.as_slice() {
+        [] => None,
+        [single] => {
+            let article = if single.starts_with(['a', 'e', 'i', 'o', 'u']) { "an" } else { "a" };
+            Some(format!("WARNING: This device contains {} {} partition.", article, single))
+        }
+        several => Some(format!("WARNING: This device contains {} partitions: {}.", several.len(), several.join(", "))),
+    }
+}
+
+// One confirmation for the whole batch, listing every device that gets erased
+async fn confirm_batch_dialog(devices: Vec<UsbDevice>) -> bool {
+    let list = devices
+        .iter()
+        .map(|device| {
+            let mut line = format!("• {} — {} ({})", device.device_path, device.name, device.human_size());
+            let existing_data = device.existing_data();
+            if !existing_data.is_empty() {
+                line.push_str(&format!(" — CONTAINS {}", existing_data.join(", ")));
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let description = format!(
+        "All data on these {} devices will be erased, one after another!\n\n\
+         {}\n\n\
+         Their partitions are unmounted first. Do you want to continue?",
+        devices.len(),
+        list,
+    );
+
+    let result = AsyncMessageDialog::new()
+        .set_level(MessageLevel::Warning)
+        .set_title("Confirm Batch Write")
+        .set_description(description)
+        .set_buttons(MessageButtons::YesNo)
+        .show()
+        .await;
+
+    result == MessageDialogResult::Yes
+}
+
+#[derive(Debug, Clone)]
+struct WriteJob {
+    iso_path: PathBuf,
+    device: UsbDevice,
+    backend: Arc<dyn DeviceBackend>,
+    cancel_token: CancelToken,
+    pause_token: PauseToken,
+    unmount_first: bool,
+    options: WriteOptions,
+}
+
+enum WriteStream {
+    Pending(Box<WriteJob>),
+    Running(mpsc::UnboundedReceiver<WriteProgress>, JoinHandle<Result<WriteSummary, WriterError>>),
+    Finished,
+}
+
+// The write only starts once the subscription first polls the stream, since
+// subscription() builds a fresh (and then discarded) stream on every call
+fn write_iso_to_usb(job: WriteJob) -> impl Stream<Item = Message> {
+    stream::unfold(WriteStream::Pending(Box::new(job)), |mut state| async move {
+        loop {
+            match state {
+                WriteStream::Pending(job) => {
+                    let (progress_sender, progress_receiver) = mpsc::unbounded_channel();
+                    let handle = tokio::spawn(write_iso_with_progress(*job, progress_sender));
+                    state = WriteStream::Running(progress_receiver, handle);
+                }
+                WriteStream::Running(mut receiver, handle) => return match receiver.recv().await {
+                    Some(progress) => {
+                        let progress = latest_progress(progress, &mut receiver);
+                        Some((Message::WriteProgress(progress), WriteStream::Running(receiver, handle)))
+                    }
+                    None => {
+                        // All senders are gone, so every progress update (including the
+                        // final 100% frame) has been drained before we report completion
+                        drop(receiver);
+                        let result = handle
+                            .await
+                            .unwrap_or_else(|e| Err(WriterError::Unknown(e.to_string())));
+                        Some((Message::WriteCompleted(result), WriteStream::Finished))
+                    }
+                },
+                WriteStream::Finished => return None,
+            }
+        }
+    })
+}
+
+// Progress of a capacity test while it runs, then its result
+fn capacity_test_stream(
+    backend: Arc<dyn DeviceBackend>,
+    device: UsbDevice,
+    cancel_token: CancelToken,
+) -> impl Stream<Item = Message> {
+    let (progress_sender, progress_receiver) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(async move {
+        device::validate_device_for_writing(&*backend, &device).await?;
+        writer::test_device_capacity(&device.device_path, device.size, cancel_token, move |progress| {
+            let _ = progress_sender.send(progress);
+        }).await
+    });
+
+    task_stream(progress_receiver, handle, Message::WriteProgress, Message::CapacityTestCompleted)
+}
+
+// Progress of hashing an image while it runs, then its digest
+fn iso_hash_stream(iso_path: PathBuf, cancel_token: CancelToken) -> impl Stream<Item = Message> {
+    let (progress_sender, progress_receiver) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(writer::hash_image_file(iso_path.clone(), cancel_token, move |bytes_hashed, total| {
+        let _ = progress_sender.send((bytes_hashed, total));
+    }));
+
+    let done_path = iso_path.clone();
+    task_stream(
+        progress_receiver,
+        handle,
+        move |(bytes_hashed, total)| Message::IsoHashProgress(iso_path.clone(), bytes_hashed, total),
+        move |result| Message::IsoHashComputed(done_path, result),
+    )
+}
+
+// Progress of a Verify Only pass while it runs, then its outcome
+fn verify_only_stream(iso_path: PathBuf, device_path: String, start_offset: u64) -> impl Stream<Item = Message> {
+    let (progress_sender, progress_receiver) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(async move {
+        writer::verify_only(&iso_path, &device_path, start_offset, progress_sender).await
+    });
+
+    task_stream(progress_receiver, handle, Message::WriteProgress, Message::VerifyOnlyCompleted)
+}
+
+// Forward a background task's progress through `on_progress`, then its
+// result through `on_done`
+fn task_stream<P, T, F, D>(
+    progress_receiver: mpsc::UnboundedReceiver<P>,
+    handle: JoinHandle<Result<T, WriterError>>,
+    on_progress: F,
+    on_done: D,
+) -> impl Stream<Item = Message>
+where
+    P: Send + 'static,
+    T: Send + 'static,
+    F: Fn(P) -> Message + Send + 'static,
+    D: FnOnce(Result<T, WriterError>) -> Message + Send + 'static,
+{
+    stream::unfold(Some((progress_receiver, handle, on_progress, on_done)), |state| async move {
+        let (mut receiver, handle, on_progress, on_done) = state?;
+        match receiver.recv().await {
+            Some(progress) => {
+                let message = on_progress(latest_progress(progress, &mut receiver));
+                Some((message, Some((receiver, handle, on_progress, on_done))))
+            }
+            None => {
+                let result = handle
+                    .await
+                    .unwrap_or_else(|e| Err(WriterError::Unknown(e.to_string())));
+                Some((on_done(result), None))
+            }
+        }
+    })
+}
+
+// Skip to the newest of any updates that piled up since the last one was
+// handled, so a fast device can't flood the update loop
+fn latest_progress<P>(mut progress: P, receiver: &mut mpsc::UnboundedReceiver<P>) -> P {
+    while let Ok(newer) = receiver.try_recv() {
+        progress = newer;
+    }
+    progress
+}
+
+async fn write_iso_with_progress(
+    job: WriteJob,
+    progress_sender: mpsc::UnboundedSender<WriteProgress>,
+) -> Result<WriteSummary, WriterError> {
     //
     // 1. Validate the device is writable and not mounted
     // 2. Open both the ISO file and device for reading/writing
     // 3. Copy data in chunks while updating progress
     // 4. Verify the write was successful
     //
-    use crate::device::validate_device_for_writing;
-    use crate::writer::write_iso_to_device;
-    use std::path::Path;
-
-    // Validate...
-    let device = crate::device::UsbDevice {
-        name: "Selected Device".to_string(),
-        device_path: device_path.clone(),
-        size: 0, // do not need size for validation...?
-        vendor: "".to_string(),
-        model: "".to_string(),
-        is_removable: true,
+    use crate::device::{unmount_device_partitions, validate_device_for_writing};
+    use crate::writer::{check_source_not_on_device, retry_while_busy, validation_progress, waiting_progress, write_iso_to_device, ValidationStep};
+
+    let WriteJob { iso_path, device, backend, cancel_token, pause_token, unmount_first, options } = job;
+
+    // Unmounting the stick the image is on would take the image with it
+    check_source_not_on_device(&iso_path, &device.device_path).await?;
+
+    if unmount_first {
+        unmount_device_partitions(&device.device_path).await?;
+    }
+
+    let _ = progress_sender.send(validation_progress(ValidationStep::Device));
+
+    // Right after the unmount the device is often still busy for a moment
+    let report_waiting = |waited| {
+        let _ = progress_sender.send(waiting_progress(waited));
     };
+    retry_while_busy(options.busy_timeout, report_waiting, || validate_device_for_writing(&*backend, &device)).await?;
+
+    // Complete the write operation, returning the verified sha256 digest
+    write_iso_to_device(&iso_path, &device.device_path, options, cancel_token, pause_token, progress_sender).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_stick(path: &str, size: u64) -> UsbDevice {
+        UsbDevice {
+            name: "Test Stick".to_string(),
+            device_path: path.to_string(),
+            size,
+            vendor: String::new(),
+            model: String::new(),
+            serial: String::new(),
+            labels: Vec::new(),
+            partitions: Vec::new(),
+            is_removable: true,
+            is_image_file: false,
+        }
+    }
+
+    #[test]
+    fn test_second_start_writing_is_ignored() {
+        let device = test_stick("/dev/sdz", 1024 * 1024);
+        let mut app = SchrijverApplication {
+            iso_path: Some(PathBuf::from("test.iso")),
+            selected_device: Some(device.clone()),
+            backend: Arc::new(device::MockBackend { devices: vec![device], ..Default::default() }),
+            ..SchrijverApplication::default()
+        };
+
+        // Both arrive before the confirmation dialog has been answered
+        assert!(!app.update(Message::StartWriting).actions().is_empty());
+        assert!(app.update(Message::StartWriting).actions().is_empty());
+
+        let _ = app.update(Message::ConfirmWrite(true));
+        let _ = app.update(Message::ConfirmWrite(true));
+
+        assert_eq!(app.write_id, 1);
+        assert!(app.write_job.is_some());
+
+        // The status names the stick that was picked, not just its path
+        let _ = app.update(Message::WriteCompleted(Err(WriterError::PermissionDenied)));
+        assert!(app.status_message.starts_with("Error writing to Test Stick (/dev/sdz): "));
+    }
+
+    #[test]
+    fn test_safe_to_remove_only_after_flush() {
+        let device = test_stick("/dev/sdz", 1024 * 1024);
+        let mut app = SchrijverApplication {
+            iso_path: Some(PathBuf::from("test.iso")),
+            selected_device: Some(device.clone()),
+            backend: Arc::new(device::MockBackend { devices: vec![device.clone()], ..Default::default() }),
+            ..SchrijverApplication::default()
+        };
+        let _ = app.update(Message::StartWriting);
+        let _ = app.update(Message::ConfirmWrite(true));
 
-    validate_device_for_writing(&device).await?;
+        let summary = WriteSummary { bytes_written: 1024, digest: String::new(), dry_run: false, verify_mode: VerifyMode::Full, ..Default::default() };
+        assert!(!app.update(Message::WriteCompleted(Ok(summary))).actions().is_empty());
+        assert_eq!(app.final_sync, Some(FinalSync::Flushing));
+        assert_eq!(app.completed_write.as_ref().map(|summary| summary.bytes_written), Some(1024));
 
-    // Complete the write operation
-    write_iso_to_device(Path::new(&iso_path), &device_path).await?;
+        let _ = app.update(Message::SyncCompleted(device.clone(), Ok(())));
+        assert_eq!(app.final_sync, Some(FinalSync::SafeToRemove));
 
-    Ok(())
-}
\ No newline at end of file
+        // A late flush result doesn't touch the next write
+        let _ = app.update(Message::StartWriting);
+        let _ = app.update(Message::ConfirmWrite(true));
+        assert_eq!(app.final_sync, None);
+        assert!(app.completed_write.is_none());
+        let _ = app.update(Message::SyncCompleted(device, Ok(())));
+        assert_eq!(app.final_sync, None);
+    }
+
+    #[test]
+    fn test_repair_targets_the_written_device_not_the_selection() {
+        let device = test_stick("/dev/sdz", 1024 * 1024);
+        let other = test_stick("/dev/sdy", 1024 * 1024);
+        let mut app = SchrijverApplication {
+            iso_path: Some(PathBuf::from("test.iso")),
+            selected_device: Some(device.clone()),
+            backend: Arc::new(device::MockBackend { devices: vec![device, other.clone()], ..Default::default() }),
+            ..SchrijverApplication::default()
+        };
+        let _ = app.update(Message::StartWriting);
+        let _ = app.update(Message::ConfirmWrite(true));
+
+        // Picked while the write was running
+        app.iso_path = Some(PathBuf::from("other.iso"));
+        app.selected_device = Some(other);
+        let _ = app.update(Message::WriteCompleted(Err(WriterError::VerificationFailed { offset: 4096 })));
+
+        let region = app.verification_failure.as_ref().unwrap();
+        assert_eq!(region.iso_path, PathBuf::from("test.iso"));
+        assert_eq!(region.device_path, "/dev/sdz");
+        assert_eq!(region.offset, 4096);
+    }
+
+    #[test]
+    fn test_no_repair_offered_for_compressed_image() {
+        let device = test_stick("/dev/sdz", 1024 * 1024);
+        let mut app = SchrijverApplication {
+            iso_path: Some(PathBuf::from("test.img.xz")),
+            selected_device: Some(device.clone()),
+            backend: Arc::new(device::MockBackend { devices: vec![device], ..Default::default() }),
+            ..SchrijverApplication::default()
+        };
+        let _ = app.update(Message::StartWriting);
+        let _ = app.update(Message::ConfirmWrite(true));
+        let _ = app.update(Message::WriteCompleted(Err(WriterError::VerificationFailed { offset: 4096 })));
+
+        assert!(app.verification_failure.is_none());
+        assert!(app.status_message.starts_with("Error writing to Test Stick (/dev/sdz): "));
+    }
+
+    #[test]
+    fn test_large_disk_needs_typed_confirmation() {
+        let device = UsbDevice { name: "Backup Drive".to_string(), ..test_stick("/dev/sdz", 128 * 1024 * 1024 * 1024) };
+        let mut app = SchrijverApplication {
+            iso_path: Some(PathBuf::from("test.iso")),
+            selected_device: Some(device.clone()),
+            backend: Arc::new(device::MockBackend { devices: vec![device], ..Default::default() }),
+            settings: UserSettings::default(),
+            ..SchrijverApplication::default()
+        };
+
+        let _ = app.update(Message::StartWriting);
+        let _ = app.update(Message::ConfirmWrite(true));
+        assert!(app.write_job.is_none());
+        assert_eq!(app.typed_confirmation.as_deref(), Some(""));
+
+        let _ = app.update(Message::TypedConfirmationChanged("yes".to_string()));
+        let _ = app.update(Message::TypedConfirmationSubmitted);
+        assert!(app.write_job.is_none());
+
+        let _ = app.update(Message::TypedConfirmationChanged(" 128.0 gb ".to_string()));
+        let _ = app.update(Message::TypedConfirmationSubmitted);
+        assert!(app.write_job.is_some());
+        assert!(app.typed_confirmation.is_none());
+    }
+
+    #[test]
+    fn test_batch_write_continues_past_failures() {
+        let stick = |path: &str| test_stick(path, 1024 * 1024);
+        let devices = vec![stick("/dev/sdy"), stick("/dev/sdz")];
+        let mut app = SchrijverApplication {
+            iso_path: Some(PathBuf::from("test.iso")),
+            batch_selection: devices.clone(),
+            // Dry runs skip the confirmation dialog
+            dry_run: true,
+            backend: Arc::new(device::MockBackend { devices: devices.clone(), ..Default::default() }),
+            ..SchrijverApplication::default()
+        };
+
+        let _ = app.update(Message::StartBatchWrite);
+        assert_eq!(app.write_job.as_ref().unwrap().device.device_path, "/dev/sdy");
+        assert!(app.status_message.starts_with("0 of 2 done"));
+
+        let _ = app.update(Message::WriteCompleted(Err(WriterError::PermissionDenied)));
+        assert_eq!(app.write_job.as_ref().unwrap().device.device_path, "/dev/sdz");
+
+        let summary = WriteSummary { bytes_written: 0, digest: String::new(), dry_run: true, verify_mode: VerifyMode::None, ..Default::default() };
+        let _ = app.update(Message::WriteCompleted(Ok(summary)));
+        assert!(app.batch.is_none());
+        assert!(!app.is_writing);
+        assert!(matches!(app.batch_results[0], (_, Err(WriterError::PermissionDenied))));
+        assert!(matches!(app.batch_results[1], (_, Ok(()))));
+        assert_eq!(app.status_message, "Batch finished: 1 of 2 devices written successfully");
+    }
+
+    #[test]
+    fn test_parallel_batch_survives_one_failure() {
+        let stick = |path: &str| test_stick(path, 1024 * 1024);
+        let devices = vec![stick("/dev/sdx"), stick("/dev/sdy"), stick("/dev/sdz")];
+        let mut app = SchrijverApplication {
+            iso_path: Some(PathBuf::from("test.iso")),
+            batch_selection: devices.clone(),
+            parallel_batch: true,
+            batch_in_memory: false,
+            dry_run: true,
+            backend: Arc::new(device::MockBackend { devices, ..Default::default() }),
+            ..SchrijverApplication::default()
+        };
+
+        let _ = app.update(Message::StartBatchWrite);
+        assert_eq!(app.parallel_writes.len(), 3);
+        assert!(app.write_job.is_none());
+
+        let summary = || WriteSummary { bytes_written: 0, digest: String::new(), dry_run: true, verify_mode: VerifyMode::None, ..Default::default() };
+        let _ = app.update(Message::ParallelWriteCompleted(1, Err(WriterError::DeviceBusy("/dev/sdy".to_string()))));
+        let _ = app.update(Message::ParallelWriteCompleted(0, Ok(summary())));
+        assert!(app.is_writing);
+        assert_eq!(app.status_message, "2 of 3 done");
+
+        let _ = app.update(Message::ParallelWriteCompleted(2, Ok(summary())));
+        assert!(!app.is_writing);
+        assert!(app.parallel_writes.is_empty());
+        assert!(matches!(app.batch_results[1], (_, Err(WriterError::DeviceBusy(_)))));
+        assert_eq!(app.status_message, "Batch finished: 2 of 3 devices written successfully");
+    }
+
+    #[test]
+    fn test_unavailable_detection_is_explained() {
+        let mut app = SchrijverApplication {
+            backend: Arc::new(device::MockBackend {
+                unavailable: Some("sysfs is not mounted".to_string()),
+                ..Default::default()
+            }),
+            ..SchrijverApplication::default()
+        };
+
+        let _ = app.update(Message::DevicesDetected(Ok(Vec::new())));
+        assert_eq!(app.status_message, "Device detection unavailable in this environment: sysfs is not mounted");
+
+        // A list that couldn't be read at all isn't reported as empty
+        let error = WriterError::DetectionFailed("could not read /proc/partitions".to_string());
+        let _ = app.update(Message::DevicesDetected(Err(error)));
+        assert_eq!(app.status_message, "Error: The USB devices could not be listed: could not read /proc/partitions. Devices that are plugged in may be missing from the list.");
+    }
+
+    #[test]
+    fn test_selection_follows_serial_across_device_paths() {
+        let stick = |path: &str, serial: &str| UsbDevice {
+            vendor: "SanDisk".to_string(),
+            model: "Cruzer".to_string(),
+            serial: serial.to_string(),
+            ..test_stick(path, 16 * 1024 * 1024 * 1024)
+        };
+        let mut app = SchrijverApplication {
+            selected_device: Some(stick("/dev/sdb", "AAAA1111")),
+            backend: Arc::new(device::MockBackend::default()),
+            ..SchrijverApplication::default()
+        };
+
+        // Replugged as sdc, while an identical stick took over sdb
+        let _ = app.update(Message::DevicesDetected(Ok(vec![stick("/dev/sdb", "BBBB2222"), stick("/dev/sdc", "AAAA1111")])));
+        let selected = app.selected_device.clone().unwrap();
+        assert_eq!((selected.device_path.as_str(), selected.serial.as_str()), ("/dev/sdc", "AAAA1111"));
+        assert_eq!(app.status_message, "Test Stick is now /dev/sdc (was /dev/sdb)");
+
+        let _ = app.update(Message::DevicesDetected(Ok(vec![stick("/dev/sdc", "BBBB2222")])));
+        assert!(app.selected_device.is_none());
+        assert_eq!(app.status_message, "Test Stick was unplugged");
+    }
+
+    #[test]
+    fn test_device_table_and_json_list_every_field() {
+        let mut device = UsbDevice::image_file("/tmp/disk.img", 8 * 1024 * 1024);
+        device.serial = "AAAA1111".to_string();
+
+        assert_eq!(
+            cli::device_table(&[device.clone()]),
+            "PATH           SIZE     VENDOR  MODEL       SERIAL    REMOVABLE  NAME\n\
+             /tmp/disk.img  8388608  -       Image file  AAAA1111  no         Image file disk.img\n"
+        );
+
+        let json = serde_json::to_value(&device).unwrap();
+        assert_eq!(json["device_path"], "/tmp/disk.img");
+        assert_eq!(json["size"], 8 * 1024 * 1024);
+        assert_eq!(json["serial"], "AAAA1111");
+        assert_eq!(json["is_removable"], false);
+    }
+
+    #[test]
+    fn test_size_preview_blocks_oversized_image() {
+        let mut app = SchrijverApplication {
+            iso_path: Some(PathBuf::from("test.iso")),
+            iso_size: Some(3 * 1024 * 1024 * 1024),
+            selected_device: Some(test_stick("/dev/sdz", 16_008_609_792)),
+            ..SchrijverApplication::default()
+        };
+        assert_eq!(app.size_preview().unwrap(), "Image 3.0 GB → device 14.9 GB (11.9 GB unused)");
+        assert!((app.device_footprint().unwrap() - 0.2012).abs() < 0.001);
+        assert!(app.can_write());
+
+        app.iso_size = Some(20 * 1024 * 1024 * 1024);
+        assert!(app.size_preview().unwrap().starts_with("Image 20.0 GB → device 14.9 GB (5.1 GB short)"));
+        assert_eq!(app.device_footprint(), Some(1.0));
+        assert!(!app.can_write());
+    }
+
+    #[test]
+    fn test_failed_write_is_logged() {
+        let mut app = SchrijverApplication::default();
+        let _ = app.update(Message::WriteCompleted(Err(WriterError::PermissionDenied)));
+
+        let entry = app.log_entries.last().unwrap();
+        assert_eq!(entry.level, LogLevel::Error);
+        assert_eq!(entry.message, format!("Write failed: {}", WriterError::PermissionDenied.user_friendly_message()));
+    }
+
+    #[test]
+    fn test_quit_mid_write_cancels_it_first() {
+        let mut app = SchrijverApplication { is_writing: true, ..SchrijverApplication::default() };
+        let _ = app.update(Message::QuitConfirmed(false));
+        assert!(!app.quit_requested && !app.cancel_token.is_cancelled());
+
+        let _ = app.update(Message::QuitConfirmed(true));
+        assert!(app.quit_requested && app.cancel_token.is_cancelled());
+        assert!(app.has_work_in_flight());
+
+        let _ = app.update(Message::WriteCompleted(Err(WriterError::Cancelled)));
+        assert!(!app.has_work_in_flight());
+    }
+
+    #[test]
+    fn test_latest_progress_coalesces_backlog() {
+        let progress = |bytes_written| WriteProgress {
+            phase: Phase::Writing,
+            bytes_written,
+            total_bytes: Some(300),
+            progress_percent: None,
+            speed_mbps: 0.0,
+            instant_speed_mbps: 0.0,
+            eta_seconds: None,
+            elapsed_seconds: 0.0,
+            pipeline: None,
+        };
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        sender.send(progress(200)).unwrap();
+        sender.send(progress(300)).unwrap();
+
+        assert_eq!(latest_progress(progress(100), &mut receiver).bytes_written, 300);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_validation_steps_show_before_the_write() {
+        let mut app = SchrijverApplication {
+            is_writing: true,
+            status_message: "Writing image to USB device...".to_string(),
+            ..SchrijverApplication::default()
+        };
+        let _ = app.update(Message::WriteProgress(writer::validation_progress(ValidationStep::Signature)));
+        assert_eq!(app.status_message, "Verifying image signature...");
+        assert!(app.last_progress.is_none());
+
+        let _ = app.update(Message::WriteProgress(WriteProgress { phase: Phase::Writing, ..writer::waiting_progress(Duration::ZERO) }));
+        assert_eq!(app.status_message, "Writing image to USB device...");
+    }
+}
@@ -0,0 +1,141 @@
+use serde::Deserialize;
+
+use crate::error::WriterError;
+
+// The partition table as `sfdisk --json` reports it, in sectors
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PartitionTable {
+    // "dos" or "gpt"
+    pub label: String,
+    #[serde(default = "default_sector_size")]
+    pub sectorsize: u64,
+    #[serde(default)]
+    pub partitions: Vec<TableEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TableEntry {
+    pub node: String,
+    pub start: u64,
+    pub size: u64,
+}
+
+// Older versions of sfdisk leave the sector size out
+fn default_sector_size() -> u64 {
+    512
+}
+
+impl PartitionTable {
+    // Hybrid images may declare partitions their data doesn't reach, such
+    // as an EFI partition appended after the ISO 9660 filesystem
+    pub fn end(&self) -> u64 {
+        self.partitions
+            .iter()
+            .map(|partition| (partition.start + partition.size) * self.sectorsize)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+pub fn parse_sfdisk_json(json: &str) -> Result<PartitionTable, String> {
+    #[derive(Deserialize)]
+    struct SfdiskOutput {
+        partitiontable: PartitionTable,
+    }
+
+    serde_json::from_str::<SfdiskOutput>(json)
+        .map(|output| output.partitiontable)
+        .map_err(|error| error.to_string())
+}
+
+// Failures of the tools below are reported through `fail`, as the error of
+// whatever they are run for
+#[cfg(target_os = "linux")]
+pub(crate) async fn read_partition_table(device_path: &str, fail: fn(String) -> WriterError) -> Result<PartitionTable, WriterError> {
+    let output = run_tool("sfdisk", &["--json", device_path], None, fail).await?;
+    parse_sfdisk_json(&output).map_err(|error| fail(format!("unexpected sfdisk output: {}", error)))
+}
+
+// The device node of the partition starting `start` bytes into the device,
+// once udev has created it
+#[cfg(target_os = "linux")]
+pub(crate) async fn partition_node(device_path: &str, start: u64, fail: fn(String) -> WriterError) -> Result<String, WriterError> {
+    // The kernel learns of a new partition right away, udev takes a moment
+    crate::writer::settle_udev().await;
+    let table = read_partition_table(device_path, fail).await?;
+    let node = table
+        .partitions
+        .into_iter()
+        .find(|partition| partition.start * table.sectorsize == start)
+        .map(|partition| partition.node)
+        .ok_or_else(|| fail("the new partition didn't show up".to_string()))?;
+
+    for _ in 0..50 {
+        if std::path::Path::new(&node).exists() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    Ok(node)
+}
+
+// Run a partitioning or formatting tool, feeding it `input`, and return what
+// it printed
+pub(crate) async fn run_tool(program: &str, args: &[&str], input: Option<&str>, fail: fn(String) -> WriterError) -> Result<String, WriterError> {
+    use std::process::Stdio;
+    use tokio::io::AsyncWriteExt;
+
+    let mut child = tokio::process::Command::new(program)
+        .args(args)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| match error.kind() {
+            std::io::ErrorKind::NotFound => fail(format!("{} isn't installed", program)),
+            _ => WriterError::from_io(error),
+        })?;
+
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input.as_bytes()).await.map_err(WriterError::from_io)?;
+    }
+    let output = child.wait_with_output().await.map_err(WriterError::from_io)?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        if stderr.contains("Permission denied") {
+            return Err(WriterError::PermissionDenied);
+        }
+        return Err(fail(format!("{} failed: {}", program, stderr.trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sfdisk_json() {
+        // A hybrid ISO with its EFI partition appended, as sfdisk sees it
+        let json = r#"{
+            "partitiontable": {
+                "label": "dos",
+                "id": "0x3a8d12f4",
+                "device": "/dev/sdb",
+                "unit": "sectors",
+                "sectorsize": 512,
+                "partitions": [
+                    {"node": "/dev/sdb1", "start": 0, "size": 6092800, "bootable": true, "type": "0"},
+                    {"node": "/dev/sdb2", "start": 6092800, "size": 8192, "type": "ef"}
+                ]
+            }
+        }"#;
+        let table = parse_sfdisk_json(json).unwrap();
+        assert_eq!(table.label, "dos");
+        assert_eq!(table.partitions[1].node, "/dev/sdb2");
+        assert_eq!(table.end(), (6092800 + 8192) * 512);
+
+        assert!(parse_sfdisk_json("not json").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use std::fmt;
+
+use crate::device::human_size;
+use crate::error::WriterError;
+
+// Partitions start and end on whole MiB, like the partitioning tools place them
+const ALIGNMENT: u64 = 1024 * 1024;
+// Left free at the end of the device for the backup GPT
+const END_RESERVE: u64 = 1024 * 1024;
+// Anything smaller fills up before the live system has finished booting
+pub const MIN_PERSISTENCE_SIZE: u64 = 64 * 1024 * 1024;
+
+// Which live system the partition is for. Both find it by the label of its
+// filesystem, and still have to be booted with persistence turned on
+// (`persistent` for Ubuntu's casper, `persistence` for Debian's live-boot).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PersistenceLayout {
+    #[default]
+    Ubuntu,
+    // Also needs a persistence.conf saying what to keep
+    Debian,
+}
+
+impl PersistenceLayout {
+    pub const ALL: [PersistenceLayout; 2] = [PersistenceLayout::Ubuntu, PersistenceLayout::Debian];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            PersistenceLayout::Ubuntu => "casper-rw",
+            PersistenceLayout::Debian => "persistence",
+        }
+    }
+}
+
+impl fmt::Display for PersistenceLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PersistenceLayout::Ubuntu => write!(f, "Ubuntu (casper-rw)"),
+            PersistenceLayout::Debian => write!(f, "Debian (persistence)"),
+        }
+    }
+}
+
+impl std::str::FromStr for PersistenceLayout {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "ubuntu" | "casper-rw" => Ok(PersistenceLayout::Ubuntu),
+            "debian" | "persistence" => Ok(PersistenceLayout::Debian),
+            _ => Err(format!("unknown persistence layout '{}' (expected ubuntu or debian)", value)),
+        }
+    }
+}
+
+// A partition for the live system's changes, added after the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PersistenceOptions {
+    pub layout: PersistenceLayout,
+    // In bytes; None takes the rest of the device
+    pub size: Option<u64>,
+}
+
+// Where the partition goes, as (start, length) in bytes: from the first MiB
+// after what the image uses to `size` later, or to the end of the device
+pub fn persistence_span(image_end: u64, device_size: u64, size: Option<u64>) -> Result<(u64, u64), WriterError> {
+    let start = image_end.div_ceil(ALIGNMENT) * ALIGNMENT;
+    let room = device_size.saturating_sub(END_RESERVE).saturating_sub(start) / ALIGNMENT * ALIGNMENT;
+    let length = size.map_or(room, |size| size.div_ceil(ALIGNMENT) * ALIGNMENT);
+
+    if length > room || length < MIN_PERSISTENCE_SIZE {
+        return Err(WriterError::PersistenceFailed(format!(
+            "{} is left after the image, {} needed",
+            human_size(room),
+            human_size(length.max(MIN_PERSISTENCE_SIZE)),
+        )));
+    }
+    Ok((start, length))
+}
+
+// Add the partition to the image just written to `device_path`, which uses
+// its first `image_end` bytes, and format it ext4 with the label the live
+// system looks for. Returns the partition's device node. Needs sfdisk and
+// mkfs.ext4, and the rights to run them on the device.
+#[cfg(target_os = "linux")]
+pub async fn create_persistence_partition(device_path: &str, image_end: u64, options: PersistenceOptions) -> Result<String, WriterError> {
+    use crate::partition::{partition_node, read_partition_table, run_tool};
+
+    let fail = WriterError::PersistenceFailed;
+    let device_size = crate::device::get_device_size(device_path).map_err(WriterError::from_io)?;
+    let table = read_partition_table(device_path, fail).await?;
+    // A classic MBR has room for four entries and no more
+    if table.label == "dos" && table.partitions.len() >= 4 {
+        return Err(WriterError::PersistenceFailed("the image's partition table has no free entry".to_string()));
+    }
+
+    let (start, length) = persistence_span(image_end.max(table.end()), device_size, options.size)?;
+    tracing::info!("Adding a {} persistence partition at byte {} of {}", human_size(length), start, device_path);
+    let script = format!("start={}, size={}, type=L\n", start / table.sectorsize, length / table.sectorsize);
+    run_tool("sfdisk", &["--append", device_path], Some(&script), fail).await?;
+    let node = partition_node(device_path, start, fail).await?;
+
+    format_ext4(&node, options.layout).await?;
+    tracing::info!("Persistence partition {} created with label {}", node, options.layout.label());
+    Ok(node)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn create_persistence_partition(_device_path: &str, _image_end: u64, _options: PersistenceOptions) -> Result<String, WriterError> {
+    Err(WriterError::PersistenceFailed("only supported on Linux".to_string()))
+}
+
+#[cfg(target_os = "linux")]
+async fn format_ext4(node: &str, layout: PersistenceLayout) -> Result<(), WriterError> {
+    // Whatever was on the stick before may leave a filesystem signature in
+    // the new partition, which mkfs would otherwise ask about
+    let mut args = vec!["-F", "-L", layout.label()];
+
+    // mkfs copies the configuration in, sparing a mount
+    let config_dir = std::env::temp_dir().join(format!("schrijver-persistence-{}", std::process::id()));
+    let config_dir_arg = config_dir.to_string_lossy().to_string();
+    if layout == PersistenceLayout::Debian {
+        std::fs::create_dir_all(&config_dir).map_err(WriterError::from_io)?;
+        std::fs::write(config_dir.join("persistence.conf"), "/ union\n").map_err(WriterError::from_io)?;
+        args.extend(["-d", config_dir_arg.as_str()]);
+    }
+    args.push(node);
+
+    let result = crate::partition::run_tool("mkfs.ext4", &args, None, WriterError::PersistenceFailed).await;
+    let _ = std::fs::remove_dir_all(&config_dir);
+    result.map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_persistence_span() {
+        const MB: u64 = 1024 * 1024;
+        let device_size = 8 * 1024 * MB;
+
+        // Starts on the next MiB and leaves the last one free
+        assert_eq!(persistence_span(3 * MB + 1000, device_size, None).unwrap(), (4 * MB, device_size - 5 * MB));
+        assert_eq!(persistence_span(4 * MB, device_size, Some(1000 * MB)).unwrap(), (4 * MB, 1000 * MB));
+
+        assert!(matches!(persistence_span(4 * MB, device_size, Some(device_size)), Err(WriterError::PersistenceFailed(_))));
+        assert!(matches!(persistence_span(device_size - 32 * MB, device_size, None), Err(WriterError::PersistenceFailed(_))));
+    }
+}
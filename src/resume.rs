@@ -0,0 +1,121 @@
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+// How much of each end of the image goes into its fingerprint
+const FINGERPRINT_SPAN: u64 = 1024 * 1024;
+
+// How far an interrupted write got, kept in a small file next to the other
+// per-user data while writing so a later run can carry on from there. The
+// image and device it belongs to are part of the file name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResumeState {
+    // See image_fingerprint
+    pub image_fingerprint: String,
+    // See device_identity
+    pub device_serial: String,
+    pub start_offset: u64,
+    pub image_size: u64,
+    // Bytes of the image known to be on the medium, and their sha256
+    pub committed: u64,
+    pub digest: String,
+}
+
+impl ResumeState {
+    pub fn new(image_fingerprint: String, device_serial: String, start_offset: u64, image_size: u64) -> Self {
+        Self { image_fingerprint, device_serial, start_offset, image_size, committed: 0, digest: String::new() }
+    }
+
+    // The state left by an interrupted write of this image to this device,
+    // if there is one and it got somewhere
+    pub fn load(image_fingerprint: &str, device_serial: &str) -> Option<Self> {
+        let path = state_path(image_fingerprint, device_serial)?;
+        let content = fs::read_to_string(&path).ok()?;
+        let state: Self = match serde_json::from_str(&content) {
+            Ok(state) => state,
+            Err(error) => {
+                tracing::warn!("Ignoring unreadable resume state {}: {}", path.display(), error);
+                return None;
+            }
+        };
+
+        (state.committed > 0 && state.committed < state.image_size).then_some(state)
+    }
+
+    // Written to a temporary file first, so a crash mid-save leaves the
+    // previous state rather than half a file
+    pub fn save(&self) -> io::Result<()> {
+        let path = state_path(&self.image_fingerprint, &self.device_serial)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no data directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let content = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, content)?;
+        fs::rename(&temporary, &path)
+    }
+
+    pub fn remove(&self) {
+        if let Some(path) = state_path(&self.image_fingerprint, &self.device_serial) {
+            if fs::remove_file(&path).is_ok() {
+                tracing::debug!("Removed resume state {}", path.display());
+            }
+        }
+    }
+}
+
+// The state of an interrupted write of the local image at `iso_path`, if one
+// was left for this device at this offset
+pub fn find_interrupted(iso_path: &Path, device_serial: &str, start_offset: u64) -> Option<ResumeState> {
+    let fingerprint = image_fingerprint(iso_path).ok()?;
+    ResumeState::load(&fingerprint, device_serial).filter(|state| state.start_offset == start_offset)
+}
+
+// Identifies an image without hashing all of it: its size plus the first and
+// last megabyte, which is where a different build or a partial download shows
+pub fn image_fingerprint(iso_path: &Path) -> io::Result<String> {
+    let mut file = File::open(iso_path)?;
+    let size = file.metadata()?.len();
+
+    let mut hasher = Sha256::new();
+    hasher.update(size.to_le_bytes());
+    let mut buffer = Vec::new();
+    file.by_ref().take(FINGERPRINT_SPAN).read_to_end(&mut buffer)?;
+    hasher.update(&buffer);
+    if size > FINGERPRINT_SPAN {
+        buffer.clear();
+        file.seek(SeekFrom::Start(size.saturating_sub(FINGERPRINT_SPAN).max(FINGERPRINT_SPAN)))?;
+        file.read_to_end(&mut buffer)?;
+        hasher.update(&buffer);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// What a device is known by across runs. Device paths like /dev/sdb change
+// between plug-ins, so removable drives go by their serial; image files,
+// which have none, go by their full path.
+pub fn device_identity(device_path: &str, serial: &str) -> Option<String> {
+    if Path::new(device_path).is_file() {
+        return fs::canonicalize(device_path).ok().map(|path| path.to_string_lossy().to_string());
+    }
+    let serial = serial.trim();
+    (!serial.is_empty()).then(|| serial.to_string())
+}
+
+fn state_path(image_fingerprint: &str, device_serial: &str) -> Option<PathBuf> {
+    // Serials and paths may hold anything a file name can't
+    let device: String = device_serial
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    let fingerprint = &image_fingerprint[..image_fingerprint.len().min(16)];
+
+    ProjectDirs::from("", "", "schrijver")
+        .map(|dirs| dirs.data_local_dir().join("resume").join(format!("{}-{}.json", fingerprint, device)))
+}
@@ -0,0 +1,203 @@
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use schrijver::device::UsbDevice;
+use schrijver::i18n::Language;
+use schrijver::writer::{validate_buffer_size, AUTO_BUFFER_SIZE};
+
+// User preferences remembered between launches, kept as TOML so they're easy
+// to edit by hand. Missing fields fall back to their defaults and unknown
+// ones are ignored, so settings files from older and newer versions load.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserSettings {
+    pub buffer_size: usize,
+    pub last_iso_dir: Option<PathBuf>,
+    pub last_device: Option<DeviceIdentity>,
+    // How often the device list is refreshed; 0 turns polling off
+    pub device_poll_interval_secs: u64,
+    pub theme: ThemePreference,
+    // Largest image read into memory once for a parallel batch, in MB
+    pub memory_image_cap_mb: u64,
+    // Devices larger than this are often external drives rather than sticks,
+    // so erasing them takes typing their size or name, in GB
+    pub large_disk_threshold_gb: u64,
+    // "en" or "nl" for the status and error messages; unset follows the locale
+    pub language: Option<Language>,
+    // The window as the user last left it; unset uses the default size
+    pub window_size: Option<WindowSize>,
+    // Compute the SHA-256 of each image as it is selected
+    pub hash_on_select: bool,
+}
+
+impl Default for UserSettings {
+    fn default() -> Self {
+        Self {
+            buffer_size: AUTO_BUFFER_SIZE,
+            last_iso_dir: None,
+            last_device: None,
+            device_poll_interval_secs: 2,
+            theme: ThemePreference::System,
+            memory_image_cap_mb: 4096,
+            large_disk_threshold_gb: 64,
+            language: None,
+            window_size: None,
+            hash_on_select: false,
+        }
+    }
+}
+
+// In logical pixels, as the window reports them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemePreference {
+    // Follow the desktop's light/dark setting
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+impl ThemePreference {
+    pub fn next(self) -> Self {
+        match self {
+            ThemePreference::System => ThemePreference::Light,
+            ThemePreference::Light => ThemePreference::Dark,
+            ThemePreference::Dark => ThemePreference::System,
+        }
+    }
+}
+
+impl std::fmt::Display for ThemePreference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ThemePreference::System => "System",
+            ThemePreference::Light => "Light",
+            ThemePreference::Dark => "Dark",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Enough about a device to recognise it again. Paths like /dev/sdX get reused
+// after a replug, so the path alone must never be trusted to be the same disk.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceIdentity {
+    pub device_path: String,
+    pub vendor: String,
+    pub model: String,
+    pub size: u64,
+    #[serde(default)]
+    pub serial: String,
+}
+
+impl DeviceIdentity {
+    pub fn from_device(device: &UsbDevice) -> Self {
+        Self {
+            device_path: device.device_path.clone(),
+            vendor: device.vendor.clone(),
+            model: device.model.clone(),
+            size: device.size,
+            serial: device.serial.clone(),
+        }
+    }
+
+    pub fn matches(&self, device: &UsbDevice) -> bool {
+        *self == Self::from_device(device)
+    }
+}
+
+impl UserSettings {
+    pub fn is_large_disk(&self, device: &UsbDevice) -> bool {
+        !device.is_image_file && device.size > self.large_disk_threshold_gb.saturating_mul(1024 * 1024 * 1024)
+    }
+
+    fn config_dir() -> Option<PathBuf> {
+        ProjectDirs::from("", "", "schrijver").map(|dirs| dirs.config_dir().to_path_buf())
+    }
+
+    fn path() -> Option<PathBuf> {
+        Self::config_dir().map(|dir| dir.join("settings.toml"))
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(content)
+    }
+
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string_pretty(self)
+    }
+
+    pub fn load() -> Self {
+        let from_toml = Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| Self::from_toml(&content).ok());
+        // Earlier versions kept the same fields in settings.json, which is
+        // picked up until the first save writes the TOML file
+        let mut settings = from_toml
+            .or_else(|| {
+                Self::config_dir()
+                    .and_then(|dir| fs::read_to_string(dir.join("settings.json")).ok())
+                    .and_then(|content| serde_json::from_str(&content).ok())
+            })
+            .unwrap_or_default();
+
+        // Ignore a hand-edited file with an unusable buffer size
+        if settings.buffer_size != AUTO_BUFFER_SIZE && validate_buffer_size(settings.buffer_size).is_err() {
+            settings.buffer_size = AUTO_BUFFER_SIZE;
+        }
+
+        settings
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No config directory available"))?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let content = self.to_toml().map_err(io::Error::other)?;
+        fs::write(path, content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_toml_round_trip() {
+        let settings = UserSettings {
+            last_iso_dir: Some(PathBuf::from("/home/user/Downloads")),
+            last_device: Some(DeviceIdentity {
+                device_path: "/dev/sdb".to_string(),
+                vendor: "SanDisk".to_string(),
+                model: "Cruzer".to_string(),
+                size: 16_000_000_000,
+                serial: "4C530001".to_string(),
+            }),
+            theme: ThemePreference::Dark,
+            language: Some(Language::Dutch),
+            window_size: Some(WindowSize { width: 1280, height: 720 }),
+            hash_on_select: true,
+            ..Default::default()
+        };
+        let content = settings.to_toml().unwrap();
+        assert_eq!(UserSettings::from_toml(&content).unwrap(), settings);
+
+        // A file from a newer version, missing fields this one knows about
+        let settings = UserSettings::from_toml("theme = \"light\"\nsplash_screen = true\n").unwrap();
+        assert_eq!(settings, UserSettings { theme: ThemePreference::Light, ..Default::default() });
+    }
+}
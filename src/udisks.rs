@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use tracing::info;
+use zbus::zvariant::{OwnedFd, Value};
+use crate::error::WriterError;
+
+// Flatpak puts this file at the root of every sandbox. There /dev and /sys
+// are hidden, so devices have to be opened by udisks2 on the host instead.
+pub fn in_flatpak() -> bool {
+    Path::new("/.flatpak-info").exists()
+}
+
+// udisks2 names block device objects after the kernel name, with anything
+// that isn't a letter or digit escaped as _xx (so dm-0 becomes dm_2d0)
+pub fn block_object_path(device_path: &str) -> String {
+    let name = device_path.strip_prefix("/dev/").unwrap_or(device_path);
+    let escaped: String = name
+        .bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => (byte as char).to_string(),
+            _ => format!("_{:02x}", byte),
+        })
+        .collect();
+    format!("/org/freedesktop/UDisks2/block_devices/{}", escaped)
+}
+
+// Ask udisks2 to open the device read-write and hand over the descriptor.
+// It checks with polkit first, which may prompt for a password.
+pub async fn open_device(device_path: &str) -> Result<File, WriterError> {
+    let connection = zbus::Connection::system().await.map_err(dbus_error)?;
+    let proxy = zbus::Proxy::new(
+        &connection,
+        "org.freedesktop.UDisks2",
+        block_object_path(device_path),
+        "org.freedesktop.UDisks2.Block",
+    )
+        .await
+        .map_err(dbus_error)?;
+
+    // O_EXCL fails the open while anything still has the device mounted
+    let mut options: HashMap<&str, Value> = HashMap::new();
+    options.insert("flags", Value::from(libc::O_EXCL));
+
+    let fd: OwnedFd = match proxy.call("OpenDevice", &("rw", &options)).await {
+        Ok(fd) => fd,
+        // OpenDevice arrived in udisks 2.7.3, older versions only have this
+        Err(zbus::Error::MethodError(name, _, _)) if name.as_str() == "org.freedesktop.DBus.Error.UnknownMethod" => {
+            proxy.call("OpenForRestore", &(HashMap::<&str, Value>::new(),)).await.map_err(dbus_error)?
+        }
+        Err(e) => return Err(dbus_error(e)),
+    };
+
+    info!("Opened {} through udisks2", device_path);
+    Ok(File::from(std::os::fd::OwnedFd::from(fd)))
+}
+
+fn dbus_error(error: zbus::Error) -> WriterError {
+    match &error {
+        zbus::Error::MethodError(name, _, _) if name.as_str().starts_with("org.freedesktop.UDisks2.Error.NotAuthorized") => {
+            WriterError::PermissionDenied
+        }
+        zbus::Error::MethodError(name, _, _) if name.as_str() == "org.freedesktop.DBus.Error.UnknownObject" => {
+            WriterError::DeviceNotFound(error.to_string())
+        }
+        // EBUSY from the O_EXCL open
+        zbus::Error::MethodError(_, Some(message), _) if message.contains("Device or resource busy") => {
+            WriterError::DeviceBusy(message.clone())
+        }
+        _ => WriterError::Unknown(format!("udisks2: {}", error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_object_path() {
+        assert_eq!(block_object_path("/dev/sdb"), "/org/freedesktop/UDisks2/block_devices/sdb");
+        assert_eq!(block_object_path("/dev/dm-0"), "/org/freedesktop/UDisks2/block_devices/dm_2d0");
+    }
+}
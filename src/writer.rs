@@ -1,370 +1,4292 @@
 use std::fs::{File, OpenOptions};
-use std::io::{self, Read, Write, Seek, SeekFrom};
-use std::path::Path;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::fmt;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use sha2::{Digest, Sha256};
 use tokio::task;
 use tokio::sync::mpsc;
+use tracing::{debug, error, field, info, instrument, warn, Span};
+use crate::checksum::{ChecksumAlgorithm, ExpectedChecksum};
+use crate::download;
+use crate::device::IoSizes;
 use crate::error::WriterError;
+use crate::persistence::{create_persistence_partition, persistence_span, PersistenceOptions};
+use crate::resume::ResumeState;
 
-const BUFFER_SIZE: usize = 1024 * 1024; // 1MB buffer
+pub const BUFFER_SIZE: usize = 1024 * 1024; // 1MB buffer
+
+// Buffer size setting that picks one from the device's I/O hints instead
+pub const AUTO_BUFFER_SIZE: usize = 0;
+
+// What Auto uses at least, and when the device doesn't report its I/O sizes
+const AUTO_BUFFER_FALLBACK: usize = 4 * 1024 * 1024;
+
+// Auto starts this small and grows the buffer while that speeds the write up,
+// up to this many times the Auto size, see BufferTuner
+const ADAPTIVE_BUFFER_GROWTH: usize = 4;
+
+// How often progress is reported by default. The first (0%) and last (100%)
+// reports are always sent, whatever the trigger.
+pub const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+pub const DEFAULT_PROGRESS_TRIGGER: ProgressTrigger = ProgressTrigger::EveryDuration(DEFAULT_PROGRESS_INTERVAL);
+
+// When to send a progress report. Time suits a live display, percentages give
+// logs the same number of lines whatever the device's speed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProgressTrigger {
+    EveryDuration(Duration),
+    // Each time the progress passes a multiple of this many percent
+    EveryPercent(f32),
+    // Whichever of the two comes first
+    Both(Duration, f32),
+}
+
+impl ProgressTrigger {
+    // Whether a report is due, given the time since the last report and the
+    // bytes done then and now. Without a known total there are no
+    // percentages, so those fall back to DEFAULT_PROGRESS_INTERVAL.
+    fn is_due(&self, since_last: Duration, last_bytes: u64, bytes: u64, total: Option<u64>) -> bool {
+        let every_percent = |step: f32| match total {
+            Some(total) if total > 0 && step > 0.0 => {
+                let bucket = |bytes: u64| (bytes as f64 * 100.0 / total as f64 / step as f64).floor();
+                bucket(bytes) > bucket(last_bytes)
+            }
+            _ => since_last > DEFAULT_PROGRESS_INTERVAL,
+        };
+
+        match *self {
+            ProgressTrigger::EveryDuration(interval) => since_last > interval,
+            ProgressTrigger::EveryPercent(step) => every_percent(step),
+            ProgressTrigger::Both(interval, step) => since_last > interval || every_percent(step),
+        }
+    }
+}
+
+// Transient write errors are retried this many times, backing off
+// exponentially from RETRY_BACKOFF between attempts
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+// Buffers in flight between the image reader and the device writer
+const PIPELINE_DEPTH: usize = 4;
 
 pub struct UsbWriter {
     iso_path: String,
     device_path: String,
     buffer_size: usize,
+    max_buffer_size: Option<usize>,
+    io_mode: IoMode,
+    dry_run: bool,
+    max_retries: u32,
+    max_speed_mbps: Option<f64>,
+    accurate_progress: bool,
+    progress_trigger: ProgressTrigger,
+    // Where on the device the image starts; 0 writes from the start of the disk
+    start_offset: u64,
+    verify_mode: VerifyMode,
+    cancel_token: CancelToken,
+    pause_token: PauseToken,
+    // Copy of the image already in memory, used instead of reading iso_path
+    image: Option<ImageBuffer>,
+    // Device opened by someone else (udisks2), used instead of opening
+    // device_path
+    device_file: Option<Arc<File>>,
+    hash_blocks: bool,
+    // Filled in by the write when hash_blocks is set, see with_block_hashes
+    block_hashes: Arc<Mutex<Vec<BlockHash>>>,
+    // See with_resume
+    resume_skip: u64,
+    resume_state: Option<ResumeState>,
+}
+
+// Shared flag used to ask an in-progress write to stop. Create a fresh token
+// for every write so a previous cancellation can't leak into the next run.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// Shared flag used to hold an in-progress write between two chunks. The
+// writer sleeps on the condition variable until it is resumed.
+#[derive(Debug, Clone, Default)]
+pub struct PauseToken(Arc<(Mutex<bool>, Condvar)>);
+
+impl PauseToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn pause(&self) {
+        self.set_paused(true);
+    }
+
+    pub fn resume(&self) {
+        self.set_paused(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.0.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn set_paused(&self, paused: bool) {
+        let (lock, condvar) = &*self.0;
+        *lock.lock().unwrap_or_else(PoisonError::into_inner) = paused;
+        condvar.notify_all();
+    }
+
+    // Block while paused and return how long that took, or None if the write
+    // wasn't paused. Cancelling doesn't signal the condvar, so wake up every
+    // so often to check for it.
+    fn wait_while_paused(&self, cancel_token: &CancelToken) -> Option<Duration> {
+        let (lock, condvar) = &*self.0;
+        let mut paused = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        if !*paused {
+            return None;
+        }
+
+        let started = Instant::now();
+        while *paused && !cancel_token.is_cancelled() {
+            paused = condvar.wait_timeout(paused, Duration::from_millis(100))
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        Some(started.elapsed())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Phase {
+    #[default]
+    Writing,
+    // Reading the device back, either to compare or to hash it
+    Verifying,
+    // The device is still busy, see retry_while_busy
+    Waiting,
+    // Checks before anything is written, reported so that a slow scan of
+    // sysfs or the mounts doesn't look like a hang
+    Validating(ValidationStep),
+    // Adding the partition after the image, see persistence.rs
+    Persistence,
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Phase::Writing => write!(f, "Writing"),
+            Phase::Verifying => write!(f, "Verifying"),
+            Phase::Waiting => write!(f, "Waiting for the device"),
+            Phase::Validating(step) => write!(f, "{}", step),
+            Phase::Persistence => write!(f, "Adding the persistence partition"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationStep {
+    // Mounts, system disk, permissions and whether it is busy
+    Device,
+    Signature,
+    // Whether the image fits on the device
+    Size,
+    // What an interrupted write left on the device, before carrying on after it
+    Resume,
 }
 
+impl std::fmt::Display for ValidationStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ValidationStep::Device => "Checking device",
+            ValidationStep::Signature => "Verifying image signature",
+            ValidationStep::Size => "Checking size",
+            ValidationStep::Resume => "Checking what the interrupted write left",
+        })
+    }
+}
+
+// `total_bytes` and `progress_percent` are `None` when the decompressed size
+// of the source isn't known up front (e.g. `.xz` or `.zst` images).
+// `progress_percent` is also `None` for an empty source, until it is done.
+// `speed_mbps` is the average over the phase so far and `instant_speed_mbps`
+// the smoothed current rate, which shows a slow stick's write cache filling
+// up long before the average does. While verifying, `bytes_written` counts
+// the bytes checked so far.
 #[derive(Debug, Clone)]
 pub struct WriteProgress {
+    pub phase: Phase,
     pub bytes_written: u64,
-    pub total_bytes: u64,
-    pub progress_percent: f32,
+    pub total_bytes: Option<u64>,
+    pub progress_percent: Option<f32>,
     pub speed_mbps: f64,
+    pub instant_speed_mbps: f64,
+    pub eta_seconds: Option<f64>,
+    pub elapsed_seconds: f64,
+    // How far reading the image is ahead of the device, while writing
+    pub pipeline: Option<PipelineStats>,
 }
 
-impl UsbWriter {
-    pub fn new(iso_path: String, device_path: String) -> Self {
-        Self {
-            iso_path,
-            device_path,
-            buffer_size: BUFFER_SIZE,
-        }
+// The state of the pipeline between image reader and device writer, for
+// telling which of the two holds a write up. A reader that keeps the buffers
+// full is waiting on the device; empty buffers mean the image is the slow end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineStats {
+    // Includes what is still waiting in the buffers
+    pub bytes_read: u64,
+    // Buffers read and waiting for the device, out of `depth`
+    pub buffers_filled: usize,
+    pub depth: usize,
+}
+
+// Sent before each retry of a busy device, `waited` after the first attempt
+pub fn waiting_progress(waited: Duration) -> WriteProgress {
+    WriteProgress {
+        phase: Phase::Waiting,
+        bytes_written: 0,
+        total_bytes: None,
+        progress_percent: None,
+        speed_mbps: 0.0,
+        instant_speed_mbps: 0.0,
+        eta_seconds: None,
+        elapsed_seconds: waited.as_secs_f64(),
+        pipeline: None,
     }
+}
 
-    pub async fn write_iso(&self) -> Result<(), WriterError> {
-        let iso_path = self.iso_path.clone();
-        let device_path = self.device_path.clone();
-        let buffer_size = self.buffer_size;
+// Sent as each pre-write check starts, before any byte is written
+pub fn validation_progress(step: ValidationStep) -> WriteProgress {
+    WriteProgress { phase: Phase::Validating(step), ..waiting_progress(Duration::ZERO) }
+}
 
-        task::spawn_blocking(move || {
-            Self::write_iso_sync(&iso_path, &device_path, buffer_size)
-        })
-            .await
-            .map_err(|e| WriterError::IoError(io::Error::new(io::ErrorKind::Other, e).to_string()))?
+// Share of `total` done, if there is a total to divide by
+fn percent_done(bytes: u64, total: Option<u64>) -> Option<f32> {
+    total
+        .filter(|&total| total > 0)
+        .map(|total| bytes as f32 / total as f32 * 100.0)
+}
+
+// Window over which the ETA speed is averaged. Long enough to ride out USB
+// write-back cache flushes, short enough to track the device's current rate.
+const SPEED_WINDOW: Duration = Duration::from_millis(500);
+
+// Sliding window of (time, bytes written) progress samples used to estimate
+// the recent write speed, rather than the cumulative average which lags badly
+// near the end of slow writes.
+struct SpeedWindow {
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl SpeedWindow {
+    fn new() -> Self {
+        Self { samples: VecDeque::new() }
     }
 
-    pub async fn write_iso_with_progress<F>(&self, progress_callback: F) -> Result<(), WriterError>
-    where
-        F: Fn(WriteProgress) + Send + Sync + 'static,
-    {
-        let iso_path = self.iso_path.clone();
-        let device_path = self.device_path.clone();
-        let buffer_size = self.buffer_size;
-        let callback = Arc::new(progress_callback);
+    fn record(&mut self, time: Instant, bytes_written: u64) {
+        self.samples.push_back((time, bytes_written));
 
-        task::spawn_blocking(move || {
-            Self::write_iso_with_progress_sync(&iso_path, &device_path, buffer_size, callback)
-        })
-            .await
-            .map_err(|e| WriterError::IoError(io::Error::new(io::ErrorKind::Other, e).to_string()))?
+        // Drop samples outside the window, but always keep one to diff against
+        while self.samples.len() > 2
+            && time.duration_since(self.samples[1].0) >= SPEED_WINDOW
+        {
+            self.samples.pop_front();
+        }
     }
 
-    fn write_iso_sync(
-        iso_path: &str,
-        device_path: &str,
-        buffer_size: usize
-    ) -> Result<(), WriterError> {
-        Self::write_iso_with_progress_sync(
-            iso_path,
-            device_path,
-            buffer_size,
-            Arc::new(|_| {}) 
-        )
+    // Bytes per second across the window, once there are at least two samples
+    fn speed(&self) -> Option<f64> {
+        let (first_time, first_bytes) = *self.samples.front()?;
+        let (last_time, last_bytes) = *self.samples.back()?;
+        let elapsed = last_time.duration_since(first_time).as_secs_f64();
+
+        if self.samples.len() < 2 || elapsed <= 0.0 {
+            return None;
+        }
+
+        Some(last_bytes.saturating_sub(first_bytes) as f64 / elapsed)
     }
 
-    fn write_iso_with_progress_sync<F>(
-        iso_path: &str,
-        device_path: &str,
-        buffer_size: usize,
-        progress_callback: Arc<F>
-    ) -> Result<(), WriterError>
-    where
-        F: Fn(WriteProgress) + Send + Sync,
-    {
-        // Open ISO file for reading
-        let mut iso_file = File::open(iso_path)
-            .map_err(|e| {
-                eprintln!("Failed to open ISO file: {}", e);
-                WriterError::IoError(e.to_string())
-            })?;
+    fn eta_seconds(&self, bytes_written: u64, total_bytes: Option<u64>) -> Option<f64> {
+        let remaining = total_bytes?.saturating_sub(bytes_written);
+        let speed = self.speed()?;
 
-        // Open device file for writing (requires ROOT!))
-        let mut device_file = OpenOptions::new()
-            .write(true)
-            .create(false)
-            .truncate(false)
-            .open(device_path)
-            .map_err(|e| {
-                eprintln!("Failed to open device {}: {}", device_path, e);
-                match e.kind() {
-                    io::ErrorKind::PermissionDenied => WriterError::PermissionDenied,
-                    io::ErrorKind::NotFound => WriterError::DeviceNotFound(device_path.to_string()),
-                    _ => WriterError::IoError(e.to_string()),
-                }
-            })?;
+        if speed <= 0.0 {
+            return None;
+        }
 
-        // Get ISO file size
-        let iso_size = iso_file.metadata()
-            .map_err(|e| WriterError::IoError(e.to_string()))?
-            .len();
+        Some(remaining as f64 / speed)
+    }
+}
 
-        println!("Starting write: {} bytes to {}", iso_size, device_path);
+// How quickly the smoothed speed follows a change in rate: after this long at
+// a new rate it has moved about two thirds of the way there. Long enough not
+// to jump with every block, short enough to show a cache filling up.
+const SPEED_SMOOTHING: Duration = Duration::from_secs(2);
 
-        // Perform the actual writing with progress reporting
-        Self::copy_with_progress(iso_file, device_file, buffer_size, iso_size, progress_callback)?;
+// Exponential moving average of the speed between consecutive progress
+// samples, weighted by the time between them so it behaves the same however
+// often progress is reported
+struct SmoothedSpeed {
+    last_sample: (Instant, u64),
+    speed: Option<f64>,
+}
 
-        println!("Write completed successfully");
-        Ok(())
+impl SmoothedSpeed {
+    fn new(time: Instant, bytes: u64) -> Self {
+        Self { last_sample: (time, bytes), speed: None }
     }
 
-    fn copy_with_progress<R, W, F>(
-        mut reader: R,
-        mut writer: W,
-        buffer_size: usize,
-        total_size: u64,
-        progress_callback: Arc<F>,
-    ) -> Result<(), WriterError>
-    where
-        R: Read,
-        W: Write,
-        F: Fn(WriteProgress),
-    {
-        let mut buffer = vec![0u8; buffer_size];
-        let mut bytes_written = 0u64;
-        let start_time = std::time::Instant::now();
-        let mut last_progress_time = start_time;
+    // Bytes per second, starting from the rate up to the first sample
+    fn record(&mut self, time: Instant, bytes: u64) -> Option<f64> {
+        let (last_time, last_bytes) = self.last_sample;
+        let elapsed = time.duration_since(last_time).as_secs_f64();
+        if elapsed <= 0.0 {
+            return self.speed;
+        }
 
-        loop {
-            let bytes_read = reader.read(&mut buffer)
-                .map_err(|e| WriterError::IoError(e.to_string()))?;
+        let rate = bytes.saturating_sub(last_bytes) as f64 / elapsed;
+        let weight = 1.0 - (-elapsed / SPEED_SMOOTHING.as_secs_f64()).exp();
+        self.speed = Some(self.speed.map_or(rate, |speed| speed + weight * (rate - speed)));
+        self.last_sample = (time, bytes);
+        self.speed
+    }
+}
 
-            if bytes_read == 0 {
-                break; // EOF reached
-            }
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Xz,
+    Zstd,
+}
 
-            // Write data to the device
-            writer.write_all(&buffer[..bytes_read])
-                .map_err(|e| {
-                    eprintln!("Write error: {}", e);
-                    WriterError::IoError(e.to_string())
-                })?;
+impl Compression {
+    pub fn from_path(path: &Path) -> Self {
+        let extension = download::file_path(path)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
 
-            bytes_written += bytes_read as u64;
-            let now = std::time::Instant::now();
+        match extension.as_str() {
+            "gz" => Compression::Gzip,
+            "xz" => Compression::Xz,
+            "zst" => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
 
-            // Report progress every 100ms
-            if now.duration_since(last_progress_time).as_millis() > 100 {
-                let elapsed = now.duration_since(start_time).as_secs_f64();
-                let speed_mbps = if elapsed > 0.0 {
-                    (bytes_written as f64) / (1024.0 * 1024.0) / elapsed
-                } else {
-                    0.0
-                };
+// Containers a disk image may come in, told apart by content rather than by
+// extension. Ones that aren't raw-compatible are refused in detect().
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImageContainer {
+    // Written as is. Includes .dmg images that are raw inside (no UDIF trailer).
+    Raw,
+    // A raw disk followed by a 512-byte footer
+    FixedVhd,
+}
 
-                let progress = WriteProgress {
-                    bytes_written,
-                    total_bytes: total_size,
-                    progress_percent: (bytes_written as f32 / total_size as f32) * 100.0,
-                    speed_mbps,
-                };
+const VHD_FOOTER_SIZE: u64 = 512;
+const VHD_COOKIE: &[u8] = b"conectix";
+const VHD_DISK_TYPE_OFFSET: usize = 60;
+const VHD_DISK_TYPE_FIXED: u32 = 2;
+const VHDX_SIGNATURE: &[u8] = b"vhdxfile";
+// Trailer of UDIF .dmg images, whose data is stored in (usually compressed) chunks
+const UDIF_SIGNATURE: &[u8] = b"koly";
+const UDIF_TRAILER_SIZE: u64 = 512;
 
-                progress_callback(progress);
-                last_progress_time = now;
-            }
+impl ImageContainer {
+    // Leaves `file` at the start of the image
+    fn detect(file: &mut File, file_size: u64) -> Result<Self, WriterError> {
+        let mut signature = [0u8; 8];
+        let signature_len = read_full(file, &mut signature).map_err(WriterError::from_io)?;
+        if signature[..signature_len] == *VHDX_SIGNATURE {
+            return Err(WriterError::UnsupportedImageFormat(
+                "VHDX images need converting to a raw image first, e.g. with `qemu-img convert -O raw`".to_string(),
+            ));
         }
 
-        // Ensure all data is written to the device
-        writer.flush().map_err(|e| WriterError::IoError(e.to_string()))?;
+        let container = if file_size >= VHD_FOOTER_SIZE.max(UDIF_TRAILER_SIZE) {
+            let mut trailer = [0u8; VHD_FOOTER_SIZE as usize];
+            file.seek(SeekFrom::End(-(VHD_FOOTER_SIZE as i64))).map_err(WriterError::from_io)?;
+            file.read_exact(&mut trailer).map_err(WriterError::from_io)?;
 
-        // Final progress report
-        let elapsed = std::time::Instant::now().duration_since(start_time).as_secs_f64();
-        let speed_mbps = if elapsed > 0.0 {
-            (bytes_written as f64) / (1024.0 * 1024.0) / elapsed
+            if trailer.starts_with(VHD_COOKIE) {
+                let disk_type = u32::from_be_bytes(trailer[VHD_DISK_TYPE_OFFSET..VHD_DISK_TYPE_OFFSET + 4].try_into().unwrap());
+                if disk_type != VHD_DISK_TYPE_FIXED {
+                    return Err(WriterError::UnsupportedImageFormat(
+                        "dynamic and differencing VHD images need converting to a raw image first, e.g. with `qemu-img convert -O raw`".to_string(),
+                    ));
+                }
+                ImageContainer::FixedVhd
+            } else if trailer.starts_with(UDIF_SIGNATURE) {
+                return Err(WriterError::UnsupportedImageFormat(
+                    "this .dmg is a compressed UDIF image. Convert it to a raw one first, e.g. with `hdiutil convert -format UDTO`".to_string(),
+                ));
+            } else {
+                ImageContainer::Raw
+            }
         } else {
-            0.0
+            ImageContainer::Raw
         };
 
-        progress_callback(WriteProgress {
-            bytes_written,
-            total_bytes: total_size,
-            progress_percent: 100.0,
-            speed_mbps,
-        });
-
-        println!("Wrote {} bytes in {:.1} seconds ({:.1} MB/s)",
-                 bytes_written, elapsed, speed_mbps);
+        file.seek(SeekFrom::Start(0)).map_err(WriterError::from_io)?;
+        if container != ImageContainer::Raw {
+            info!("Image is a {:?} container", container);
+        }
+        Ok(container)
+    }
 
-        Ok(())
+    // How much of a `file_size` byte file is the disk itself
+    fn payload_size(self, file_size: u64) -> u64 {
+        match self {
+            ImageContainer::Raw => file_size,
+            ImageContainer::FixedVhd => file_size - VHD_FOOTER_SIZE,
+        }
     }
+}
 
-    pub async fn verify_write(&self) -> Result<bool, WriterError> {
-        let iso_path = self.iso_path.clone();
-        let device_path = self.device_path.clone();
+// Size of the disk held in an uncompressed local image, without any container
+// footer
+pub fn image_size(path: &Path) -> Result<u64, WriterError> {
+    let mut file = File::open(path)
+        .map_err(|_| WriterError::IsoNotFound(path.to_string_lossy().to_string()))?;
+    let file_size = file.metadata().map_err(WriterError::from_io)?.len();
+    Ok(ImageContainer::detect(&mut file, file_size)?.payload_size(file_size))
+}
+
+// Decoders report truncated or corrupt streams as UnexpectedEof/InvalidInput,
+// which we normalise to InvalidData so they surface as InvalidIsoFormat.
+struct DecoderReader<R: Read>(R);
 
-        task::spawn_blocking(move || {
-            Self::verify_write_sync(&iso_path, &device_path)
+impl<R: Read> Read for DecoderReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => {
+                io::Error::new(io::ErrorKind::InvalidData, e)
+            }
+            _ => e,
         })
-            .await
-            .map_err(|e| WriterError::IoError(io::Error::new(io::ErrorKind::Other, e).to_string()))?
     }
+}
+
+// Settings for a single copy_with_progress run. `io_mode` only applies when
+// the device is opened for a real write.
+#[derive(Debug, Clone, Copy)]
+struct CopyConfig {
+    buffer_size: usize,
+    io_mode: IoMode,
+    alignment: usize,
+    total_size: Option<u64>,
+    max_retries: u32,
+    max_speed_mbps: Option<f64>,
+    // Flush the writer every this many bytes and only report what has been
+    // flushed, so progress tracks the device rather than the page cache
+    checkpoint_bytes: Option<u64>,
+    progress_trigger: ProgressTrigger,
+    // Grow the buffer up to this size while it pays off, see BufferTuner
+    max_buffer_size: Option<usize>,
+    // Read every block back as soon as it is written, see InlineVerifier
+    inline_verify: bool,
+    // Bytes of the image an interrupted write already put on the device,
+    // see ResumeWriter
+    resume_skip: u64,
+}
 
-    fn verify_write_sync(iso_path: &str, device_path: &str) -> Result<bool, WriterError> {
-        let mut iso_file = File::open(iso_path)
-            .map_err(|e| WriterError::IoError(e.to_string()))?;
+// How often accurate progress forces the data out to the device. Smaller
+// steps track the device more closely but keep it from batching writes.
+pub const ACCURATE_PROGRESS_CHECKPOINT: u64 = 32 * 1024 * 1024;
 
-        let mut device_file = File::open(device_path)
-            .map_err(|e| WriterError::IoError(e.to_string()))?;
+// What writing to a device that was unplugged returns, rather than EIO
+fn is_disconnect_error(error: &io::Error) -> bool {
+    #[cfg(unix)]
+    let codes = [libc::ENODEV, libc::ENXIO];
+    // ERROR_NO_SUCH_DEVICE and ERROR_DEVICE_NOT_CONNECTED
+    #[cfg(windows)]
+    let codes = [433, 1167];
+    #[cfg(not(any(unix, windows)))]
+    let codes: [i32; 0] = [];
 
-        let iso_size = iso_file.metadata()
-            .map_err(|e| WriterError::IoError(e.to_string()))?
-            .len();
+    error.raw_os_error().is_some_and(|code| codes.contains(&code))
+}
 
-        let buffer_size = 64 * 1024; // 64KB for verification
-        let mut iso_buffer = vec![0u8; buffer_size];
-        let mut device_buffer = vec![0u8; buffer_size];
-        let mut bytes_verified = 0u64;
+// Unplugging mostly shows up as a plain EIO, so an I/O error from a device
+// that is gone now is put down to the disconnect
+fn disconnected_if_gone(error: WriterError, device_path: &str, backend: &dyn crate::device::DeviceBackend) -> WriterError {
+    match error {
+        WriterError::IoError { .. } if !backend.exists(device_path) => {
+            warn!("{} disappeared: {}", device_path, error);
+            WriterError::DeviceDisconnected { device: device_path.to_string(), offset: None }
+        }
+        error => error,
+    }
+}
 
-        println!("Verifying write...");
+fn is_transient_write_error(error: &io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::Interrupted | io::ErrorKind::TimedOut)
+}
 
-        loop {
-            let iso_bytes = iso_file.read(&mut iso_buffer)
-                .map_err(|e| WriterError::IoError(e.to_string()))?;
-            let device_bytes = device_file.read(&mut device_buffer)
-                .map_err(|e| WriterError::IoError(e.to_string()))?;
+// Like write_all, but retries transient failures with exponential backoff.
+// A failed write() call hasn't consumed any bytes, so retrying simply resumes
+// from the first byte that didn't land. `offset` is where the chunk starts on
+// the device; errors come back with the offset of the first unwritten byte.
+fn write_with_retry<W: Write>(writer: &mut W, chunk: &[u8], offset: u64, max_retries: u32) -> Result<(), (io::Error, u64)> {
+    let mut written = 0;
+    let mut attempts = 0;
+    let mut first_error = None;
 
-            if iso_bytes == 0 {
-                break; // EOF reached
+    while written < chunk.len() {
+        match writer.write(&chunk[written..]) {
+            Ok(0) => {
+                let error = io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer");
+                return Err((error, offset + written as u64));
             }
-
-            if iso_bytes != device_bytes {
-                eprintln!("Verification failed: byte count mismatch");
-                return Ok(false);
+            Ok(n) => written += n,
+            Err(e) if is_transient_write_error(&e) && attempts < max_retries => {
+                attempts += 1;
+                warn!("Retrying block at offset {} (attempt {}/{}): {}",
+                      offset + written as u64, attempts, max_retries, e);
+                std::thread::sleep(RETRY_BACKOFF * 2u32.pow(attempts - 1));
+                first_error.get_or_insert(e);
             }
+            // Report the error that started the retries, not the last one
+            Err(e) => return Err((first_error.unwrap_or(e), offset + written as u64)),
+        }
+    }
 
-            if iso_buffer[..iso_bytes] != device_buffer[..device_bytes] {
-                eprintln!("Verification failed: data mismatch at byte {}", bytes_verified);
-                return Ok(false);
-            }
+    Ok(())
+}
 
-            bytes_verified += iso_bytes as u64;
-            let progress = bytes_verified as f64 / iso_size as f64;
-            print!("\rVerification: {:.1}%", progress * 100.0);
-            io::stdout().flush().unwrap();
+// Token-bucket style throttle: sleep until the average rate since `start`
+// is back under the cap. Sleeps in short slices so a cancel request is still
+// noticed promptly, however low the cap is set.
+fn throttle(start: Instant, bytes_written: u64, max_speed_mbps: f64, cancel_token: &CancelToken) {
+    let allowed_elapsed = bytes_written as f64 / (max_speed_mbps * 1024.0 * 1024.0);
+
+    while !cancel_token.is_cancelled() {
+        let remaining = allowed_elapsed - start.elapsed().as_secs_f64();
+        if remaining <= 0.0 {
+            break;
+        }
+        std::thread::sleep(Duration::from_secs_f64(remaining.min(0.1)));
+    }
+}
+
+// The image as the bytes to write, whatever it is stored in: downloads,
+// compressed images and containers like fixed VHDs all go through here. The
+// size is None where it isn't known before reading everything.
+fn image_reader(path: &Path) -> Result<(Box<dyn Read + Send>, Option<u64>), WriterError> {
+    let compression = Compression::from_path(path);
+    let (file, file_size): (Box<dyn Read + Send>, Option<u64>) = if download::is_url(path) {
+        download::open_url(&path.to_string_lossy())?
+    } else {
+        let mut file = File::open(path)
+            .map_err(|e| {
+                error!("Failed to open ISO file: {}", e);
+                WriterError::from_io(e)
+            })?;
+
+        let file_size = file.metadata()
+            .map_err(WriterError::from_io)?
+            .len();
+
+        // Containers inside compressed images aren't looked into
+        let payload_size = match compression {
+            Compression::None => ImageContainer::detect(&mut file, file_size)?.payload_size(file_size),
+            _ => file_size,
+        };
+
+        (Box::new(file.take(payload_size)), Some(payload_size))
+    };
+
+    let reader: (Box<dyn Read + Send>, Option<u64>) = match compression {
+        Compression::None => (file, file_size),
+        Compression::Gzip => (Box::new(DecoderReader(flate2::read::MultiGzDecoder::new(file))), None),
+        Compression::Xz => (Box::new(DecoderReader(xz2::read::XzDecoder::new_multi_decoder(file))), None),
+        Compression::Zstd => {
+            let decoder = zstd::Decoder::new(file)
+                .map_err(WriterError::from_io)?;
+            (Box::new(DecoderReader(decoder)), None)
         }
+    };
+
+    Ok(reader)
+}
 
-        println!(); // New line after progress
-        println!("Verification successful: {} bytes verified", bytes_verified);
-        Ok(true)
+// Whether offsets in the image are offsets in the file it is stored in, so a
+// part of it can be read again without starting from the beginning
+pub fn is_seekable_source(path: &Path) -> bool {
+    !download::is_url(path) && Compression::from_path(path) == Compression::None
+}
+
+// An image read into memory once and shared by several writes, so flashing
+// many devices at once doesn't read, download or decompress it for each one
+#[derive(Clone)]
+pub struct ImageBuffer(Arc<[u8]>);
+
+impl ImageBuffer {
+    pub fn size(&self) -> u64 {
+        self.0.len() as u64
+    }
+
+    fn reader(&self) -> Cursor<Arc<[u8]>> {
+        Cursor::new(self.0.clone())
     }
 }
 
-pub async fn write_iso_to_device(
-    iso_path: &Path,
-    device_path: &str,
-) -> Result<(), WriterError> {
-    // Validate that ISO file exists and is readable
-    if !iso_path.exists() {
-        return Err(WriterError::IsoNotFound(iso_path.to_string_lossy().to_string()));
+// The contents would flood the logs, so only show the size
+impl fmt::Debug for ImageBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ImageBuffer({} bytes)", self.size())
     }
+}
+
+// Read the whole (decompressed) image into memory, or None if it turns out
+// to be larger than `max_bytes`
+pub async fn load_image_into_memory(path: &Path, max_bytes: u64) -> Result<Option<ImageBuffer>, WriterError> {
+    let path = path.to_path_buf();
+    spawn_in_span(move || {
+        let (reader, size) = image_reader(&path)?;
+        if size.is_some_and(|size| size > max_bytes) {
+            return Ok(None);
+        }
+
+        // Read one byte past the limit to tell an exact fit from a larger image
+        let mut data = Vec::with_capacity(size.unwrap_or(0) as usize);
+        reader.take(max_bytes + 1).read_to_end(&mut data).map_err(WriterError::from_io)?;
+        if data.len() as u64 > max_bytes {
+            return Ok(None);
+        }
+
+        info!("Loaded {} bytes of {} into memory", data.len(), path.display());
+        Ok(Some(ImageBuffer(data.into())))
+    })
+        .await
+        .map_err(|e| WriterError::from_io(io::Error::other(e)))?
+}
 
-    // Check if ISO file is actually an ISO (basic check)
-    if let Some(extension) = iso_path.extension() {
-        if extension.to_string_lossy().to_lowercase() != "iso" {
-            eprintln!("Warning: File doesn't have .iso extension");
+// The image verification compares against: the local file, or its copy in
+// memory when one was loaded
+enum ImageSource {
+    File(File),
+    Memory(Cursor<Arc<[u8]>>),
+}
+
+impl ImageSource {
+    fn open(iso_path: &str, image: Option<ImageBuffer>) -> Result<Self, WriterError> {
+        match image {
+            Some(image) => Ok(ImageSource::Memory(image.reader())),
+            None => File::open(iso_path).map(ImageSource::File).map_err(WriterError::from_io),
         }
     }
 
-    // Validate that device exists
-    if !Path::new(device_path).exists() {
-        return Err(WriterError::DeviceNotFound(device_path.to_string()));
+    // The image as the write reads it, decompressing on the fly if needed
+    fn open_streaming(iso_path: &str, image: Option<&ImageBuffer>) -> Result<(Box<dyn Read + Send>, Option<u64>), WriterError> {
+        match image {
+            Some(image) => Ok((Box::new(image.reader()), Some(image.size()))),
+            None => image_reader(Path::new(iso_path)),
+        }
     }
+}
 
-    // Check device size vs ISO size
-    let iso_size = std::fs::metadata(iso_path)
-        .map_err(|e| WriterError::IoError(e.to_string()))?
-        .len();
+impl Read for ImageSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ImageSource::File(file) => file.read(buf),
+            ImageSource::Memory(cursor) => cursor.read(buf),
+        }
+    }
+}
 
-    // Try to get device size (this is Linux-specific)
-    if let Ok(device_size) = get_device_size(device_path) {
-        if iso_size > device_size {
-            return Err(WriterError::InsufficientSpace);
+impl Seek for ImageSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            ImageSource::File(file) => file.seek(pos),
+            ImageSource::Memory(cursor) => cursor.seek(pos),
         }
-        println!("Device size: {} bytes, ISO size: {} bytes", device_size, iso_size);
     }
+}
 
-    let writer = UsbWriter::new(
-        iso_path.to_string_lossy().to_string(),
-        device_path.to_string(),
-    );
+// Heap buffer whose start address is aligned to `alignment`, as required for
+// O_DIRECT writes. The length is rounded up to a multiple of the alignment.
+struct AlignedBuffer {
+    storage: Vec<u8>,
+    offset: usize,
+    len: usize,
+}
 
-    // Write the ISO
-    writer.write_iso().await?;
+impl AlignedBuffer {
+    fn new(len: usize, alignment: usize) -> Self {
+        let alignment = alignment.max(1);
+        let len = len.div_ceil(alignment) * alignment;
+        let storage = vec![0u8; len + alignment];
+        let offset = storage.as_ptr().align_offset(alignment);
 
-    println!("Write completed, starting verification...");
+        Self { storage, offset, len }
+    }
 
-    // Verify the write
-    if writer.verify_write().await? {
-        println!("Verification successful!");
-    } else {
-        return Err(WriterError::VerificationFailed);
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.storage[self.offset..self.offset + self.len]
     }
+}
 
-    Ok(())
+// Device file opened either through the page cache or with O_DIRECT
+struct DeviceWriter {
+    file: File,
+    block_size: usize,
+    direct: bool,
+    // Make flush() wait until the data is on the medium (fdatasync)
+    sync_on_flush: bool,
 }
 
-#[cfg(target_os = "linux")]
-fn get_device_size(device_path: &str) -> Result<u64, io::Error> {
-    use std::fs::File;
-    use std::os::unix::io::AsRawFd;
+impl DeviceWriter {
+    fn open(device_path: &str, io_mode: IoMode) -> Result<Self, WriterError> {
+        let map_open_error = |e: io::Error| {
+            error!("Failed to open device {}: {}", device_path, e);
+            match e.kind() {
+                io::ErrorKind::PermissionDenied => WriterError::PermissionDenied,
+                io::ErrorKind::NotFound => WriterError::DeviceNotFound(device_path.to_string()),
+                // ERROR_SHARING_VIOLATION
+                _ if cfg!(target_os = "windows") && e.raw_os_error() == Some(32) => {
+                    WriterError::DeviceBusy(format!("{} is in use by another program", device_path))
+                }
+                #[cfg(unix)]
+                _ if e.raw_os_error() == Some(libc::EBUSY) => WriterError::DeviceBusy(format!("{} is still in use", device_path)),
+                // When the lock switch went unnoticed until now; ERROR_WRITE_PROTECT on Windows
+                #[cfg(unix)]
+                _ if e.raw_os_error() == Some(libc::EROFS) => WriterError::DeviceReadOnly(device_path.to_string()),
+                _ if cfg!(target_os = "windows") && e.raw_os_error() == Some(19) => WriterError::DeviceReadOnly(device_path.to_string()),
+                _ => WriterError::from_io(e),
+            }
+        };
 
-    // Define the BLKGETSIZE64 ioctl command (not provided by libc)
-    const BLKGETSIZE64: libc::c_ulong = 0x80081272;
+        let mut options = OpenOptions::new();
+        options.write(true).create(false).truncate(false);
 
-    let file = File::open(device_path)?;
-    let fd = file.as_raw_fd();
+        // Windows has no flock, see DeviceLock. Not sharing write access
+        // keeps others from writing while we do, and fails if someone
+        // already is.
+        #[cfg(target_os = "windows")]
+        {
+            use std::os::windows::fs::OpenOptionsExt;
+            use windows::Win32::Storage::FileSystem::FILE_SHARE_READ;
 
-    // Use ioctl to get device size
-    unsafe {
-        let mut size: u64 = 0;
-        let result = libc::ioctl(fd, BLKGETSIZE64, &mut size);
-        if result == -1 {
-            return Err(io::Error::last_os_error());
+            options.share_mode(FILE_SHARE_READ.0);
         }
-        Ok(size)
-    }
-}
 
-#[cfg(not(target_os = "linux"))]
-fn get_device_size(_device_path: &str) -> Result<u64, io::Error> {
-    // Fallback for non-Linux systems
-    Err(io::Error::new(io::ErrorKind::Unsupported, "Device size detection not supported on this platform"))
-}
+        if io_mode == IoMode::Sync {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Cursor;
+                options.custom_flags(libc::O_SYNC);
+            }
 
-    #[test]
-    fn test_copy_with_progress() {
-        let test_data = b"Hello, World! This is test data for USB writing.";
-        let mut reader = Cursor::new(test_data);
-        let mut writer = Vec::new();
+            #[cfg(target_os = "windows")]
+            {
+                use std::os::windows::fs::OpenOptionsExt;
+                use windows::Win32::Storage::FileSystem::FILE_FLAG_WRITE_THROUGH;
 
-        let callback = Arc::new(|progress: WriteProgress| {
-            println!("Progress: {:.1}%", progress.progress_percent);
-        });
+                options.custom_flags(FILE_FLAG_WRITE_THROUGH.0);
+            }
 
-        let result = UsbWriter::copy_with_progress(
-            &mut reader,
-            &mut writer,
-            16,
-            test_data.len() as u64,
-            callback
-        );
+            info!("Opening {} for synchronous writes", device_path);
+        }
+
+        #[cfg(target_os = "linux")]
+        if io_mode == IoMode::Direct {
+            use std::os::unix::fs::OpenOptionsExt;
+
+            let mut direct_options = options.clone();
+            direct_options.custom_flags(libc::O_DIRECT);
+
+            match direct_options.open(device_path) {
+                Ok(file) => {
+                    let block_size = get_logical_block_size(&file).unwrap_or(512);
+                    info!("Opened {} with O_DIRECT (block size {})", device_path, block_size);
+                    return Ok(Self { file, block_size, direct: true, sync_on_flush: false });
+                }
+                Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+                    warn!("O_DIRECT not supported for {}, falling back to buffered writes", device_path);
+                }
+                Err(e) => return Err(map_open_error(e)),
+            }
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        if io_mode == IoMode::Direct {
+            warn!("Direct I/O is only supported on Linux, using buffered writes");
+        }
+
+        // Open device file for writing (requires ROOT!))
+        let file = options.open(device_path).map_err(map_open_error)?;
+
+        Ok(Self { file, block_size: 1, direct: false, sync_on_flush: false })
+    }
+
+    // A descriptor opened elsewhere. Its duplicates share the O_DIRECT flag,
+    // which would break the unaligned reads of verification, so it is
+    // always written buffered.
+    fn from_file(file: File, io_mode: IoMode) -> Result<Self, WriterError> {
+        if io_mode != IoMode::Buffered {
+            info!("{} isn't used for an already open device, using buffered writes", io_mode);
+        }
+        Ok(Self { file, block_size: 1, direct: false, sync_on_flush: false })
+    }
+
+    // O_DIRECT only accepts block-aligned lengths. The final chunk is padded,
+    // but a short write() can still leave a partial block behind, so drop back
+    // to buffered mode for that rather than failing the write
+    #[cfg(target_os = "linux")]
+    fn disable_direct(&mut self) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        let fd = self.file.as_raw_fd();
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags == -1 || libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_DIRECT) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        self.direct = false;
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn disable_direct(&mut self) -> io::Result<()> {
+        self.direct = false;
+        Ok(())
+    }
+}
+
+impl Write for DeviceWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.direct && !buf.len().is_multiple_of(self.block_size) {
+            self.disable_direct()?;
+        }
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.sync_on_flush {
+            self.file.sync_data()
+        } else {
+            self.file.flush()
+        }
+    }
+}
+
+impl Seek for DeviceWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+// Whatever the copy writes into: the device, possibly behind an InlineVerifier
+trait SeekWrite: Write + Seek {}
+
+impl<T: Write + Seek> SeekWrite for T {}
+
+// Reads back each write as soon as it has reached the medium and compares it
+// with what was sent, so a bad block is caught at its exact offset while
+// writing instead of in a second pass. Reads go through their own descriptor
+// at explicit offsets, which leaves the writer's position (and O_DIRECT
+// alignment) alone.
+struct InlineVerifier<W> {
+    inner: W,
+    reader: File,
+    // Where the image starts on the device, and how far into it we are
+    start: u64,
+    position: u64,
+    readback: Vec<u8>,
+}
+
+// The payload of the io::Error an InlineVerifier fails with, so the copy can
+// report it as a verification failure rather than a write error
+#[derive(Debug)]
+struct InlineMismatch {
+    offset: u64,
+}
+
+impl std::fmt::Display for InlineMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "read back different data at byte {}", self.offset)
+    }
+}
+
+impl std::error::Error for InlineMismatch {}
+
+impl<W: Write> InlineVerifier<W> {
+    fn new(inner: W, reader: File, start: u64) -> Self {
+        Self { inner, reader, start, position: 0, readback: Vec::new() }
+    }
+
+    // Dropping the just-synced range from the page cache makes the read come
+    // from the medium. Other platforms have no per-range equivalent.
+    #[cfg(target_os = "linux")]
+    fn drop_cached(&self, offset: u64, len: usize) {
+        use std::os::unix::io::AsRawFd;
+
+        unsafe {
+            libc::posix_fadvise(self.reader.as_raw_fd(), offset as libc::off_t, len as libc::off_t, libc::POSIX_FADV_DONTNEED);
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn drop_cached(&self, _offset: u64, _len: usize) {}
+}
+
+impl<W: Write> Write for InlineVerifier<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.inner.flush()?;
+
+        let device_offset = self.start + self.position;
+        self.drop_cached(device_offset, n);
+        self.readback.resize(n, 0);
+        read_exact_at(&self.reader, &mut self.readback, device_offset)?;
+
+        if let Some(index) = buf[..n].iter().zip(&self.readback).position(|(sent, read)| sent != read) {
+            let offset = self.position + index as u64;
+            error!("Inline verification failed at byte {}", offset);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, InlineMismatch { offset }));
+        }
+
+        self.position += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Seek> Seek for InlineVerifier<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let offset = self.inner.seek(pos)?;
+        self.position = offset.saturating_sub(self.start);
+        Ok(offset)
+    }
+}
+
+// Carries on where an interrupted write stopped. Chunks that are already on
+// the device (checked against the saved digest before writing) are hashed but
+// not written again. With a state to record, every flush saves how far the
+// data has made it to the medium, so this write can be resumed in turn.
+struct ResumeWriter<W> {
+    inner: W,
+    start_offset: u64,
+    // Image bytes passed through so far, written or skipped
+    position: u64,
+    skip: u64,
+    hasher: Sha256,
+    state: Option<ResumeState>,
+}
+
+impl<W: Write + Seek> ResumeWriter<W> {
+    fn new(inner: W, start_offset: u64, skip: u64, state: Option<ResumeState>) -> Self {
+        Self { inner, start_offset, position: 0, skip, hasher: Sha256::new(), state }
+    }
+}
+
+impl<W: Write + Seek> Write for ResumeWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.position + buf.len() as u64 <= self.skip {
+            self.hasher.update(buf);
+            self.position += buf.len() as u64;
+            return Ok(buf.len());
+        }
+
+        // The chunk that reaches past the skipped part is written whole, from
+        // where it starts, which keeps O_DIRECT writes block-aligned
+        if self.skip > 0 {
+            info!("Resuming the write at byte {}", self.position);
+            self.inner.seek(SeekFrom::Start(self.start_offset + self.position))?;
+            self.skip = 0;
+        }
+
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+
+        // A failed save only costs the chance to resume, not the write
+        if let Some(state) = self.state.as_mut().filter(|state| self.position > state.committed) {
+            state.committed = self.position;
+            state.digest = format!("{:x}", self.hasher.clone().finalize());
+            match state.save() {
+                Ok(()) => debug!(committed = state.committed, "Saved the resume state"),
+                Err(error) => warn!("Could not save the resume state: {}", error),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            n => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl UsbWriter {
+    pub fn new(iso_path: String, device_path: String, io_mode: IoMode) -> Self {
+        Self {
+            iso_path,
+            device_path,
+            buffer_size: BUFFER_SIZE,
+            max_buffer_size: None,
+            io_mode,
+            dry_run: false,
+            max_retries: DEFAULT_MAX_RETRIES,
+            max_speed_mbps: None,
+            accurate_progress: false,
+            progress_trigger: DEFAULT_PROGRESS_TRIGGER,
+            start_offset: 0,
+            verify_mode: VerifyMode::Full,
+            cancel_token: CancelToken::new(),
+            pause_token: PauseToken::new(),
+            image: None,
+            device_file: None,
+            hash_blocks: false,
+            block_hashes: Arc::new(Mutex::new(Vec::new())),
+            resume_skip: 0,
+            resume_state: None,
+        }
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    // Cap the average write speed so slow shared hubs stay responsive.
+    // Non-positive or non-finite limits are treated as no limit.
+    pub fn with_max_speed(mut self, max_speed_mbps: Option<f64>) -> Self {
+        self.max_speed_mbps = max_speed_mbps.filter(|limit| limit.is_finite() && *limit > 0.0);
+        self
+    }
+
+    // Read the whole image and open the device read-only, but never write to it
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    // Slow USB 2.0 sticks tend to prefer smaller buffers while fast enclosures
+    // want several MB. The size must be a non-zero multiple of 512 bytes.
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> Result<Self, WriterError> {
+        self.buffer_size = validate_buffer_size(buffer_size)?;
+        Ok(self)
+    }
+
+    // Let the buffer grow from the buffer size up to `max_buffer_size` while
+    // bigger buffers clearly write faster, or keep it fixed with None. The
+    // maximum follows the same rules as with_buffer_size.
+    pub fn with_adaptive_buffer(mut self, max_buffer_size: Option<usize>) -> Result<Self, WriterError> {
+        self.max_buffer_size = max_buffer_size.map(validate_buffer_size).transpose()?;
+        Ok(self)
+    }
+
+    // How often progress is reported, for both writing and verifying
+    pub fn with_progress_trigger(mut self, progress_trigger: ProgressTrigger) -> Self {
+        self.progress_trigger = progress_trigger;
+        self
+    }
+
+    // With write-back caching the page cache accepts data long before the
+    // stick has it, so the bar hits 100% and then stalls in the final flush.
+    // This syncs at regular checkpoints and reports committed bytes instead.
+    pub fn with_accurate_progress(mut self, accurate_progress: bool) -> Self {
+        self.accurate_progress = accurate_progress;
+        self
+    }
+
+    // Write the image this many bytes into the device, e.g. into an existing
+    // partition layout. Must be a multiple of 512 so O_DIRECT stays aligned.
+    pub fn with_start_offset(mut self, start_offset: u64) -> Result<Self, WriterError> {
+        self.start_offset = validate_start_offset(start_offset)?;
+        Ok(self)
+    }
+
+    pub fn with_verify_mode(mut self, verify_mode: VerifyMode) -> Self {
+        self.verify_mode = verify_mode;
+        self
+    }
+
+    // Write (and verify against) an image loaded with load_image_into_memory
+    // instead of opening iso_path again
+    pub fn with_image(mut self, image: Option<ImageBuffer>) -> Self {
+        self.image = image;
+        self
+    }
+
+    // Write to (and verify) an already open device, for when device_path
+    // itself can't be opened from this process
+    pub fn with_device_file(mut self, device_file: Option<File>) -> Self {
+        self.device_file = device_file.map(Arc::new);
+        self
+    }
+
+    // Hash every verification block while writing, so a full verification
+    // only reads the device back instead of the image too. Worth it when the
+    // image sits on a slow disk or network share.
+    pub fn with_block_hashes(mut self, hash_blocks: bool) -> Self {
+        self.hash_blocks = hash_blocks;
+        self
+    }
+
+    // Carry on after the first `skip` bytes, which an interrupted write already
+    // put on the device, and keep `state` up to date as this write goes. Saving
+    // it needs the data on the medium, so the write checkpoints as with
+    // accurate progress.
+    pub fn with_resume(mut self, skip: u64, state: Option<ResumeState>) -> Self {
+        self.resume_skip = skip;
+        self.resume_state = state;
+        self
+    }
+
+    pub fn with_cancel_token(mut self, cancel_token: CancelToken) -> Self {
+        self.cancel_token = cancel_token;
+        self
+    }
+
+    pub fn with_pause_token(mut self, pause_token: PauseToken) -> Self {
+        self.pause_token = pause_token;
+        self
+    }
+
+    // Returns the number of bytes written and their SHA-256
+    pub async fn write_iso_with_progress<F>(&self, progress_callback: F) -> Result<(u64, String), WriterError>
+    where
+        F: Fn(WriteProgress) + Send + Sync + 'static,
+    {
+        let iso_path = self.iso_path.clone();
+        let device_path = self.device_path.clone();
+        let start_offset = self.start_offset;
+        let dry_run = self.dry_run;
+        let cancel_token = self.cancel_token.clone();
+        let pause_token = self.pause_token.clone();
+        let image = self.image.clone();
+        let device_file = self.device_file.clone();
+        let hash_blocks = self.hash_blocks;
+        let recorded_hashes = self.block_hashes.clone();
+        let resume_state = self.resume_state.clone();
+        let callback = Arc::new(progress_callback);
+
+        // Alignment and size are filled in once the device and image are open
+        let config = CopyConfig {
+            buffer_size: self.buffer_size,
+            io_mode: self.io_mode,
+            alignment: 1,
+            total_size: None,
+            max_retries: self.max_retries,
+            max_speed_mbps: self.max_speed_mbps,
+            checkpoint_bytes: (self.accurate_progress || self.resume_state.is_some()).then_some(ACCURATE_PROGRESS_CHECKPOINT),
+            progress_trigger: self.progress_trigger,
+            max_buffer_size: self.max_buffer_size,
+            inline_verify: self.verify_mode == VerifyMode::Inline,
+            resume_skip: self.resume_skip,
+        };
+
+        let path = iso_path.clone();
+        let source = on_source(Path::new(&iso_path), move || ImageSource::open_streaming(&path, image.as_ref())).await?;
+
+        spawn_in_span(move || {
+            let target = DeviceTarget { path: &device_path, start_offset, file: device_file.as_deref() };
+            if dry_run {
+                Self::dry_run_sync(source, target, config, &cancel_token, &pause_token, callback)
+            } else {
+                let mut block_hashes = BlockHashes::default();
+                let result = Self::write_iso_with_progress_sync(
+                    source,
+                    target,
+                    config,
+                    WriteRecords { block_hashes: hash_blocks.then_some(&mut block_hashes), resume_state },
+                    &cancel_token,
+                    &pause_token,
+                    callback,
+                );
+                if hash_blocks && result.is_ok() {
+                    *recorded_hashes.lock().unwrap_or_else(PoisonError::into_inner) = block_hashes.finish();
+                }
+                result
+            }
+        })
+            .await
+            .map_err(|e| WriterError::from_io(io::Error::other(e)))?
+    }
+
+    fn write_iso_with_progress_sync<F>(
+        source: (Box<dyn Read + Send>, Option<u64>),
+        target: DeviceTarget,
+        config: CopyConfig,
+        records: WriteRecords,
+        cancel_token: &CancelToken,
+        pause_token: &PauseToken,
+        progress_callback: Arc<F>
+    ) -> Result<(u64, String), WriterError>
+    where
+        F: Fn(WriteProgress) + Send + Sync,
+    {
+        let (iso_reader, iso_size) = source;
+        let iso_reader: Box<dyn Read + Send + '_> = match records.block_hashes {
+            Some(hashes) => Box::new(HashingReader { inner: iso_reader, hashes }),
+            None => iso_reader,
+        };
+
+        let device_path = target.path;
+        let mut device = target.open_writer(config.io_mode)?;
+        // Inline verification needs each block on the medium before reading it back
+        device.sync_on_flush = config.checkpoint_bytes.is_some() || config.inline_verify;
+        if target.start_offset > 0 {
+            device.file.seek(SeekFrom::Start(target.start_offset)).map_err(WriterError::from_io)?;
+            info!("Writing from byte {} of {}", target.start_offset, device_path);
+        }
+        let config = CopyConfig {
+            alignment: device.block_size,
+            total_size: iso_size,
+            ..config
+        };
+
+        match iso_size {
+            Some(size) => {
+                Span::current().record("iso_size", size);
+                info!("Starting write: {} bytes to {}", size, device_path);
+            }
+            None => info!("Starting write: compressed image of unknown size to {}", device_path),
+        }
+
+        let sink: Box<dyn SeekWrite + '_> = if config.inline_verify {
+            info!("Reading every block back as it is written");
+            let reader = target.open_reader()?;
+            Box::new(InlineVerifier::new(&mut device, reader.inner, target.start_offset))
+        } else {
+            Box::new(&mut device)
+        };
+
+        // Perform the actual writing with progress reporting
+        let result = if config.resume_skip > 0 || records.resume_state.is_some() {
+            let sink = ResumeWriter::new(sink, target.start_offset, config.resume_skip, records.resume_state);
+            Self::copy_with_progress(iso_reader, sink, config, cancel_token, pause_token, progress_callback)
+        } else {
+            Self::copy_with_progress(iso_reader, sink, config, cancel_token, pause_token, progress_callback)
+        };
+        let (bytes_written, digest) = result.map_err(|error| match error {
+            WriterError::DeviceDisconnected { offset, .. } => WriterError::DeviceDisconnected { device: device_path.to_string(), offset },
+            error => error,
+        })?;
+
+        // Make sure the data has actually reached the device, and drop the
+        // kernel's buffer cache so verification reads from the medium itself
+        sync_device(&mut device.file)?;
+
+        info!("Write completed successfully (sha256 {})", digest);
+        Ok((bytes_written, digest))
+    }
+
+    // Same pipeline as a real write, with the device opened read-only and the
+    // data discarded instead of written
+    fn dry_run_sync<F>(
+        source: (Box<dyn Read + Send>, Option<u64>),
+        target: DeviceTarget,
+        config: CopyConfig,
+        cancel_token: &CancelToken,
+        pause_token: &PauseToken,
+        progress_callback: Arc<F>
+    ) -> Result<(u64, String), WriterError>
+    where
+        F: Fn(WriteProgress) + Send + Sync,
+    {
+        let (iso_reader, iso_size) = source;
+        let device_path = target.path;
+
+        // Opening the device still proves it is there and accessible
+        let _device = target.open_reader()?;
+
+        info!("Dry run: reading the image without writing to {}", device_path);
+
+        Self::copy_with_progress(
+            iso_reader,
+            io::sink(),
+            CopyConfig { total_size: iso_size, ..config },
+            cancel_token,
+            pause_token,
+            progress_callback,
+        )
+    }
+
+    fn copy_with_progress<R, W, F>(
+        mut reader: R,
+        mut writer: W,
+        config: CopyConfig,
+        cancel_token: &CancelToken,
+        pause_token: &PauseToken,
+        progress_callback: Arc<F>,
+    ) -> Result<(u64, String), WriterError>
+    where
+        R: Read + Send,
+        W: Write,
+        F: Fn(WriteProgress),
+    {
+        let CopyConfig { buffer_size, alignment, total_size, max_retries, max_speed_mbps, checkpoint_bytes, progress_trigger, max_buffer_size, .. } = config;
+
+        // A fixed set of buffers circulates between the reader and the writer:
+        // `filled` carries data to the device, `free` hands emptied buffers
+        // back. Both are bounded, so at most PIPELINE_DEPTH buffers are alive.
+        let (filled_tx, filled_rx) = mpsc::channel::<io::Result<(AlignedBuffer, usize)>>(PIPELINE_DEPTH);
+        let (free_tx, mut free_rx) = mpsc::channel::<AlignedBuffer>(PIPELINE_DEPTH);
+        for _ in 0..PIPELINE_DEPTH {
+            free_tx.try_send(AlignedBuffer::new(buffer_size, alignment))
+                .expect("free channel holds PIPELINE_DEPTH buffers");
+        }
+
+        let mut hasher = Sha256::new();
+        let mut bytes_written = 0u64;
+        // What the last checkpoint flushed; only tracked with checkpoint_bytes
+        let mut bytes_committed = 0u64;
+        let mut start_time = Instant::now();
+        // Counted by the reader thread, for PipelineStats
+        let image_bytes_read = AtomicU64::new(0);
+
+        // Start at 0% right away, before the first interval has passed
+        progress_callback(WriteProgress {
+            phase: Phase::Writing,
+            bytes_written: 0,
+            total_bytes: total_size,
+            progress_percent: percent_done(0, total_size),
+            speed_mbps: 0.0,
+            instant_speed_mbps: 0.0,
+            eta_seconds: None,
+            elapsed_seconds: 0.0,
+            pipeline: Some(PipelineStats { bytes_read: 0, buffers_filled: 0, depth: PIPELINE_DEPTH }),
+        });
+
+        // The reader runs on a scoped thread so it can borrow from the caller
+        // (the writer side already runs on a spawn_blocking task). When the
+        // writer stops early it drops its channel ends, which ends the reader.
+        thread::scope(|scope| {
+            let reader_bytes = &image_bytes_read;
+            scope.spawn(move || {
+                while let Some(mut buffer) = free_rx.blocking_recv() {
+                    // Fill the whole buffer so every write but the last stays block-aligned
+                    match read_full(&mut reader, buffer.as_mut_slice()) {
+                        Ok(0) => break, // EOF reached
+                        Ok(n) => {
+                            reader_bytes.fetch_add(n as u64, Ordering::Relaxed);
+                            if filled_tx.blocking_send(Ok((buffer, n))).is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            let _ = filled_tx.blocking_send(Err(e));
+                            break;
+                        }
+                    }
+                }
+            });
+
+            // Owned here so an early return drops them before the scope joins
+            let mut filled_rx = filled_rx;
+            let free_tx = free_tx;
+            let mut last_progress_time = start_time;
+            let mut last_progress_bytes = 0;
+            let mut speed_window = SpeedWindow::new();
+            let mut smoothed_speed = SmoothedSpeed::new(start_time, 0);
+            let mut tuner = max_buffer_size
+                .filter(|&max| max > buffer_size)
+                .map(|max| BufferTuner::new(buffer_size, max, start_time));
+
+            loop {
+                if cancel_token.is_cancelled() {
+                    // Push out whatever has been written so far before bailing out
+                    writer.flush().map_err(WriterError::from_io)?;
+                    info!("Write cancelled after {} bytes", bytes_written);
+                    return Err(WriterError::Cancelled);
+                }
+
+                if let Some(paused) = pause_token.wait_while_paused(cancel_token) {
+                    // Move the start forward by the pause so speed, ETA and the
+                    // throttle are based on active time only
+                    start_time += paused;
+                    last_progress_time = Instant::now();
+                    speed_window = SpeedWindow::new();
+                    smoothed_speed = SmoothedSpeed::new(last_progress_time, bytes_written);
+                    if let Some(tuner) = &mut tuner {
+                        tuner.restart(last_progress_time, bytes_written);
+                    }
+                    info!("Write resumed after a {:.1} second pause", paused.as_secs_f64());
+                    continue;
+                }
+
+                let Some(chunk) = filled_rx.blocking_recv() else {
+                    break; // Reader hit EOF
+                };
+                let (mut buffer, bytes_read) = chunk.map_err(|e| match e.kind() {
+                    io::ErrorKind::InvalidData => WriterError::InvalidIsoFormat,
+                    _ => WriterError::from_io(e),
+                })?;
+
+                // Only the final chunk can be short. O_DIRECT needs whole blocks,
+                // so zero-pad it up to the alignment; the padding lands past the
+                // end of the image and isn't counted, hashed or verified.
+                let padded_len = bytes_read.div_ceil(alignment) * alignment;
+                let slice = buffer.as_mut_slice();
+                slice[bytes_read..padded_len].fill(0);
+
+                // Write data to the device
+                write_with_retry(&mut writer, &slice[..padded_len], bytes_written, max_retries)
+                    .map_err(|(e, failed_at)| {
+                        if let Some(mismatch) = e.get_ref().and_then(|inner| inner.downcast_ref::<InlineMismatch>()) {
+                            return WriterError::VerificationFailed { offset: mismatch.offset };
+                        }
+                        error!("Write error at byte {}: {}", failed_at, e);
+                        match e.kind() {
+                            // Past the upfront size check, this means the device
+                            // holds less than it claims (e.g. a counterfeit stick)
+                            io::ErrorKind::StorageFull | io::ErrorKind::WriteZero => {
+                                WriterError::InsufficientSpace(Some(failed_at))
+                            }
+                            // The caller knows which device this was
+                            _ if is_disconnect_error(&e) => {
+                                WriterError::DeviceDisconnected { device: String::new(), offset: Some(failed_at) }
+                            }
+                            _ => WriterError::from_io(e),
+                        }
+                    })?;
+
+                hasher.update(&slice[..bytes_read]);
+                bytes_written += bytes_read as u64;
+                debug!(bytes_written, chunk = bytes_read, "Chunk written");
+
+                // Buffers are replaced by bigger ones as they come back, so
+                // the reader never sees a buffer change under it. Both sizes
+                // are whole blocks, which keeps O_DIRECT writes aligned.
+                if let Some(new_size) = tuner.as_mut().and_then(|tuner| tuner.update(Instant::now(), bytes_written)) {
+                    info!("Growing the write buffer to {} bytes", new_size);
+                }
+                if let Some(tuner) = tuner.as_ref().filter(|tuner| buffer.len < tuner.size) {
+                    buffer = AlignedBuffer::new(tuner.size, alignment);
+                }
+
+                // The reader may already be gone after a read error; that
+                // error is waiting in `filled` and surfaces on the next pass
+                let _ = free_tx.try_send(buffer);
+
+                if let Some(max_speed_mbps) = max_speed_mbps {
+                    throttle(start_time, bytes_written, max_speed_mbps, cancel_token);
+                }
+
+                let reported_bytes = match checkpoint_bytes {
+                    Some(checkpoint) => {
+                        if bytes_written - bytes_committed >= checkpoint {
+                            writer.flush().map_err(WriterError::from_io)?;
+                            bytes_committed = bytes_written;
+                            debug!(bytes_committed, "Checkpoint reached the device");
+                        }
+                        bytes_committed
+                    }
+                    None => bytes_written,
+                };
+
+                let now = Instant::now();
+
+                // The final report below covers the end of the transfer
+                let finished = total_size == Some(reported_bytes);
+                if !finished && progress_trigger.is_due(now.duration_since(last_progress_time), last_progress_bytes, reported_bytes, total_size) {
+                    let elapsed = now.duration_since(start_time).as_secs_f64();
+                    let speed_mbps = if elapsed > 0.0 {
+                        (reported_bytes as f64) / (1024.0 * 1024.0) / elapsed
+                    } else {
+                        0.0
+                    };
+
+                    speed_window.record(now, reported_bytes);
+                    let instant_speed = smoothed_speed.record(now, reported_bytes);
+
+                    let progress = WriteProgress {
+                        phase: Phase::Writing,
+                        bytes_written: reported_bytes,
+                        total_bytes: total_size,
+                        progress_percent: percent_done(reported_bytes, total_size),
+                        speed_mbps,
+                        instant_speed_mbps: instant_speed
+                            .map(|speed| speed / (1024.0 * 1024.0))
+                            .unwrap_or(speed_mbps),
+                        eta_seconds: speed_window.eta_seconds(reported_bytes, total_size),
+                        elapsed_seconds: elapsed,
+                        pipeline: Some(PipelineStats {
+                            bytes_read: image_bytes_read.load(Ordering::Relaxed),
+                            buffers_filled: filled_rx.len(),
+                            depth: PIPELINE_DEPTH,
+                        }),
+                    };
+
+                    progress_callback(progress);
+                    last_progress_time = now;
+                    last_progress_bytes = reported_bytes;
+                }
+            }
+
+            Ok(())
+        })?;
+
+        // Ensure all data is written to the device
+        writer.flush().map_err(WriterError::from_io)?;
+
+        // Final progress report
+        let elapsed = Instant::now().duration_since(start_time).as_secs_f64();
+        let speed_mbps = if elapsed > 0.0 {
+            (bytes_written as f64) / (1024.0 * 1024.0) / elapsed
+        } else {
+            0.0
+        };
+
+        progress_callback(WriteProgress {
+            phase: Phase::Writing,
+            bytes_written,
+            total_bytes: total_size,
+            progress_percent: Some(100.0),
+            speed_mbps,
+            instant_speed_mbps: speed_mbps,
+            eta_seconds: Some(0.0),
+            elapsed_seconds: elapsed,
+            pipeline: Some(PipelineStats { bytes_read: bytes_written, buffers_filled: 0, depth: PIPELINE_DEPTH }),
+        });
+
+        info!("Wrote {} bytes in {:.1} seconds ({:.1} MB/s)",
+              bytes_written, elapsed, speed_mbps);
+
+        Ok((bytes_written, format!("{:x}", hasher.finalize())))
+    }
+
+    // Check what landed on the device according to the verify mode. Returns
+    // the mode actually used: Quick needs to seek in the source image, so
+    // compressed and downloaded images fall back to a Full hash instead.
+    pub async fn verify<F>(&self, length: u64, expected: String, progress_callback: Arc<F>) -> Result<VerifyMode, WriterError>
+    where
+        F: Fn(WriteProgress) + Send + Sync + 'static,
+    {
+        let seekable = self.image.is_some() || is_seekable_source(Path::new(&self.iso_path));
+
+        match self.verify_mode {
+            VerifyMode::None => {
+                info!("Skipping verification");
+                Ok(VerifyMode::None)
+            }
+            // Every block was already read back while writing
+            VerifyMode::Inline => Ok(VerifyMode::Inline),
+            VerifyMode::Quick if seekable => {
+                let iso_path = self.iso_path.clone();
+                let device_path = self.device_path.clone();
+                let start_offset = self.start_offset;
+                let device_file = self.device_file.clone();
+                let cancel_token = self.cancel_token.clone();
+                let image = self.image.clone();
+                let progress = PhaseProgress::new(Phase::Verifying, progress_callback, 0, self.progress_trigger);
+
+                spawn_in_span(move || {
+                    let iso_file = ImageSource::open(&iso_path, image)?;
+                    let target = DeviceTarget { path: &device_path, start_offset, file: device_file.as_deref() };
+                    Self::verify_write_quick_sync(iso_file, target, length, &cancel_token, progress)
+                })
+                    .await
+                    .map_err(|e| WriterError::from_io(io::Error::other(e)))??;
+                Ok(VerifyMode::Quick)
+            }
+            // A local image can be compared byte for byte, which pinpoints
+            // where a mismatch is so that region can be rewritten. Hashes
+            // taken while writing find the same block without the image.
+            VerifyMode::Full if seekable => {
+                let outcome = match self.recorded_block_hashes(length) {
+                    Some(hashes) => self.verify_block_hashes(length, hashes, progress_callback).await?,
+                    None => self.verify_write(length, progress_callback).await?,
+                };
+                match outcome {
+                    VerifyOutcome::Match => Ok(VerifyMode::Full),
+                    VerifyOutcome::Mismatch { offset } => Err(WriterError::VerificationFailed { offset }),
+                }
+            }
+            VerifyMode::Quick | VerifyMode::Full => {
+                let expected = ExpectedChecksum { algorithm: ChecksumAlgorithm::Sha256, hex: expected };
+                self.verify_write_hash(length, expected, progress_callback).await?;
+                Ok(VerifyMode::Full)
+            }
+        }
+    }
+
+    fn verify_write_quick_sync<F>(
+        mut iso_file: ImageSource,
+        target: DeviceTarget,
+        length: u64,
+        cancel_token: &CancelToken,
+        mut progress: PhaseProgress<F>,
+    ) -> Result<(), WriterError>
+    where
+        F: Fn(WriteProgress),
+    {
+        if cancel_token.is_cancelled() {
+            return Err(WriterError::Cancelled);
+        }
+
+        let mut device_file = target.open_reader()?;
+
+        let ranges = quick_verify_ranges(length);
+        info!("Quick verification of {} regions...", ranges.len());
+
+        // The sampled regions only take a moment, so just mark start and end
+        progress.total_bytes = ranges.iter().map(|&(_, len)| len).sum();
+        progress.update(0);
+        compare_ranges(&mut iso_file, &mut device_file, &ranges)?;
+        progress.finish();
+
+        info!("Quick verification successful");
+        Ok(())
+    }
+
+    // Hash the first `length` bytes of the device and compare against the
+    // expected digest, either the one computed while writing or one supplied
+    // by the user. Returns the device digest on success.
+    pub async fn verify_write_hash<F>(&self, length: u64, expected: ExpectedChecksum, progress_callback: Arc<F>) -> Result<String, WriterError>
+    where
+        F: Fn(WriteProgress) + Send + Sync + 'static,
+    {
+        let device_path = self.device_path.clone();
+        let start_offset = self.start_offset;
+        let device_file = self.device_file.clone();
+        let cancel_token = self.cancel_token.clone();
+        let progress = PhaseProgress::new(Phase::Verifying, progress_callback, length, self.progress_trigger);
+
+        spawn_in_span(move || {
+            let target = DeviceTarget { path: &device_path, start_offset, file: device_file.as_deref() };
+            Self::verify_write_hash_sync(target, length, &expected, &cancel_token, progress)
+        })
+            .await
+            .map_err(|e| WriterError::from_io(io::Error::other(e)))?
+    }
+
+    fn verify_write_hash_sync<F>(
+        target: DeviceTarget,
+        length: u64,
+        expected: &ExpectedChecksum,
+        cancel_token: &CancelToken,
+        mut progress: PhaseProgress<F>,
+    ) -> Result<String, WriterError>
+    where
+        F: Fn(WriteProgress),
+    {
+        let device_file = target.open_reader()?;
+
+        info!("Verifying write ({})...", expected.algorithm);
+
+        progress.update(0);
+        let result = Self::hash_reader(device_file.take(length), expected.algorithm, cancel_token, |bytes| progress.update(bytes));
+        let actual = progress.track(result)?;
+        progress.finish();
+        if actual != expected.hex {
+            error!("Verification failed: expected {}, got {}", expected, actual);
+            return Err(WriterError::HashMismatch {
+                expected: expected.hex.clone(),
+                actual,
+            });
+        }
+
+        info!("Verification successful: {} {}", expected.algorithm, actual);
+        Ok(actual)
+    }
+
+    fn hash_reader<R, P>(mut reader: R, algorithm: ChecksumAlgorithm, cancel_token: &CancelToken, mut on_progress: P) -> Result<String, WriterError>
+    where
+        R: Read,
+        P: FnMut(u64),
+    {
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut hasher = algorithm.hasher();
+        let mut bytes_hashed = 0u64;
+
+        loop {
+            if cancel_token.is_cancelled() {
+                info!("Hashing cancelled after {} bytes", bytes_hashed);
+                return Err(WriterError::Cancelled);
+            }
+
+            let bytes_read = reader.read(&mut buffer)
+                .map_err(WriterError::from_io)?;
+
+            if bytes_read == 0 {
+                break; // EOF reached
+            }
+
+            hasher.update(&buffer[..bytes_read]);
+            bytes_hashed += bytes_read as u64;
+            on_progress(bytes_hashed);
+        }
+
+        Ok(hasher.finalize_hex())
+    }
+
+    // Byte-for-byte compare of the first `length` bytes of the image and the
+    // device, reporting the exact offset of the first difference
+    pub async fn verify_write<F>(&self, length: u64, progress_callback: Arc<F>) -> Result<VerifyOutcome, WriterError>
+    where
+        F: Fn(WriteProgress) + Send + Sync + 'static,
+    {
+        let iso_path = self.iso_path.clone();
+        let device_path = self.device_path.clone();
+        let start_offset = self.start_offset;
+        let device_file = self.device_file.clone();
+        let cancel_token = self.cancel_token.clone();
+        let image = self.image.clone();
+        let progress = PhaseProgress::new(Phase::Verifying, progress_callback, length, self.progress_trigger);
+
+        spawn_in_span(move || {
+            let iso_file = ImageSource::open(&iso_path, image)?;
+            let target = DeviceTarget { path: &device_path, start_offset, file: device_file.as_deref() };
+            Self::verify_write_sync(iso_file, target, length, &cancel_token, progress)
+        })
+            .await
+            .map_err(|e| WriterError::from_io(io::Error::other(e)))?
+    }
+
+    fn verify_write_sync<F>(
+        iso_file: ImageSource,
+        target: DeviceTarget,
+        length: u64,
+        cancel_token: &CancelToken,
+        mut progress: PhaseProgress<F>,
+    ) -> Result<VerifyOutcome, WriterError>
+    where
+        F: Fn(WriteProgress),
+    {
+        let device_file = target.open_reader()?;
+
+        info!("Verifying write...");
+
+        progress.update(0);
+        let result = compare_streams(iso_file, device_file, length, cancel_token, |bytes| progress.update(bytes));
+        let outcome = progress.track(result)?;
+        if outcome == VerifyOutcome::Match {
+            progress.finish();
+        }
+
+        match outcome {
+            VerifyOutcome::Match => info!("Verification successful: {} bytes verified", length),
+            VerifyOutcome::Mismatch { offset } => error!("Verification failed: data mismatch at byte {}", offset),
+        }
+        Ok(outcome)
+    }
+
+    // The hashes from the last write, if they cover `length` bytes
+    fn recorded_block_hashes(&self, length: u64) -> Option<Vec<BlockHash>> {
+        let hashes = self.block_hashes.lock().unwrap_or_else(PoisonError::into_inner);
+        (self.hash_blocks && hashes.len() as u64 == length.div_ceil(VERIFY_BUFFER_SIZE as u64))
+            .then(|| hashes.clone())
+    }
+
+    async fn verify_block_hashes<F>(&self, length: u64, hashes: Vec<BlockHash>, progress_callback: Arc<F>) -> Result<VerifyOutcome, WriterError>
+    where
+        F: Fn(WriteProgress) + Send + Sync + 'static,
+    {
+        let device_path = self.device_path.clone();
+        let start_offset = self.start_offset;
+        let device_file = self.device_file.clone();
+        let cancel_token = self.cancel_token.clone();
+        let mut progress = PhaseProgress::new(Phase::Verifying, progress_callback, length, self.progress_trigger);
+
+        spawn_in_span(move || {
+            let target = DeviceTarget { path: &device_path, start_offset, file: device_file.as_deref() };
+            let device_file = target.open_reader()?;
+
+            info!("Verifying write against the hashes taken while writing...");
+
+            progress.update(0);
+            let result = compare_block_hashes(device_file, &hashes, length, &cancel_token, |bytes| progress.update(bytes));
+            let outcome = progress.track(result)?;
+            match outcome {
+                VerifyOutcome::Match => {
+                    progress.finish();
+                    info!("Verification successful: {} bytes verified", length);
+                }
+                VerifyOutcome::Mismatch { offset } => error!("Verification failed: the block at byte {} differs", offset),
+            }
+            Ok(outcome)
+        })
+            .await
+            .map_err(|e| WriterError::from_io(io::Error::other(e)))?
+    }
+
+    // Rewrite the verification block containing `offset` from the image and
+    // check it again, instead of restarting the whole write
+    pub async fn repair_region(&self, offset: u64) -> Result<(), WriterError> {
+        // The block would be read from the compressed file at the offset of
+        // the decompressed data
+        if self.image.is_none() && !is_seekable_source(Path::new(&self.iso_path)) {
+            return Err(WriterError::UnsupportedImageFormat(
+                "a compressed or downloaded image can only be rewritten as a whole".to_string(),
+            ));
+        }
+
+        let iso_path = self.iso_path.clone();
+        let device_path = self.device_path.clone();
+        let start_offset = self.start_offset;
+        let device_file = self.device_file.clone();
+        let image = self.image.clone();
+
+        spawn_in_span(move || {
+            let target = DeviceTarget { path: &device_path, start_offset, file: device_file.as_deref() };
+            Self::repair_region_sync(&iso_path, image, target, offset)
+        })
+            .await
+            .map_err(|e| WriterError::from_io(io::Error::other(e)))?
+    }
+
+    fn repair_region_sync(iso_path: &str, image: Option<ImageBuffer>, target: DeviceTarget, offset: u64) -> Result<(), WriterError> {
+        // The bytes the write put on the device, so never a container's footer
+        let iso_size = match &image {
+            Some(image) => image.size(),
+            None => image_size(Path::new(iso_path))?,
+        };
+        let mut iso_file = ImageSource::open(iso_path, image)?;
+
+        // Same block boundaries the verification pass compared in
+        let (start, length) = repair_range(offset, iso_size);
+        info!("Rewriting {} bytes at offset {}", length, start);
+
+        let mut data = vec![0u8; length as usize];
+        iso_file.seek(SeekFrom::Start(start)).map_err(WriterError::from_io)?;
+        iso_file.read_exact(&mut data).map_err(WriterError::from_io)?;
+
+        let file = match target.file {
+            Some(file) => file.try_clone(),
+            None => OpenOptions::new().read(true).write(true).open(target.path),
+        }
+            .map_err(WriterError::from_io)?;
+        let mut device = AtOffset::new(file, target.start_offset).map_err(WriterError::from_io)?;
+        device.seek(SeekFrom::Start(start)).map_err(WriterError::from_io)?;
+        device.write_all(&data).map_err(WriterError::from_io)?;
+        sync_device(&mut device.inner)?;
+
+        compare_ranges(&mut iso_file, &mut device, &[(start, length)])?;
+
+        info!("Region at offset {} repaired", start);
+        Ok(())
+    }
+}
+
+// The device a write goes to, and where on it the image starts
+#[derive(Debug, Clone, Copy)]
+struct DeviceTarget<'a> {
+    path: &'a str,
+    start_offset: u64,
+    // Already open device to use instead of opening `path`
+    file: Option<&'a File>,
+}
+
+impl DeviceTarget<'_> {
+    fn open_writer(&self, io_mode: IoMode) -> Result<DeviceWriter, WriterError> {
+        match self.file {
+            Some(file) => DeviceWriter::from_file(file.try_clone().map_err(WriterError::from_io)?, io_mode),
+            None => DeviceWriter::open(self.path, io_mode),
+        }
+    }
+
+    fn open_reader(&self) -> Result<AtOffset<File>, WriterError> {
+        match self.file {
+            Some(file) => AtOffset::new(file.try_clone()?, self.start_offset).map_err(WriterError::from_io),
+            None => AtOffset::open(self.path, self.start_offset),
+        }
+    }
+}
+
+// A view of a device that starts `start` bytes in, so offsets within the image
+// line up with offsets on the device when writing to a partition or offset
+struct AtOffset<T> {
+    inner: T,
+    start: u64,
+}
+
+impl<T: Seek> AtOffset<T> {
+    fn new(mut inner: T, start: u64) -> io::Result<Self> {
+        inner.seek(SeekFrom::Start(start))?;
+        Ok(Self { inner, start })
+    }
+}
+
+impl AtOffset<File> {
+    fn open(device_path: &str, start: u64) -> Result<Self, WriterError> {
+        let file = File::open(device_path).map_err(WriterError::from_io)?;
+        Self::new(file, start).map_err(WriterError::from_io)
+    }
+}
+
+impl<T: Read> Read for AtOffset<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<T: Write> Write for AtOffset<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Seek> Seek for AtOffset<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => SeekFrom::Start(self.start + offset),
+            relative => relative,
+        };
+        Ok(self.inner.seek(pos)?.saturating_sub(self.start))
+    }
+}
+
+pub fn validate_start_offset(start_offset: u64) -> Result<u64, WriterError> {
+    if !start_offset.is_multiple_of(512) {
+        return Err(WriterError::InvalidStartOffset(start_offset));
+    }
+    Ok(start_offset)
+}
+
+// Result of comparing the image against what was read back from the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyOutcome {
+    Match,
+    // Byte offset from the start of the image of the first difference, or of
+    // the first differing block when checked against block hashes
+    Mismatch { offset: u64 },
+}
+
+const VERIFY_BUFFER_SIZE: usize = 64 * 1024; // 64KB for verification
+
+// The block-aligned range a repair rewrites for a mismatch at `offset`
+fn repair_range(offset: u64, iso_size: u64) -> (u64, u64) {
+    let block = VERIFY_BUFFER_SIZE as u64;
+    let start = (offset - offset % block).min(iso_size);
+    (start, block.min(iso_size - start))
+}
+
+type BlockHash = [u8; 32];
+
+// What a write keeps track of on the side, besides the image's digest
+struct WriteRecords<'a> {
+    // See with_block_hashes
+    block_hashes: Option<&'a mut BlockHashes>,
+    // See with_resume
+    resume_state: Option<ResumeState>,
+}
+
+// SHA-256 of each VERIFY_BUFFER_SIZE block of the image, collected as the
+// image is read for writing. The blocks are the ones repair_range rewrites.
+#[derive(Default)]
+struct BlockHashes {
+    hashes: Vec<BlockHash>,
+    current: Sha256,
+    current_len: usize,
+}
+
+impl BlockHashes {
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = (VERIFY_BUFFER_SIZE - self.current_len).min(data.len());
+            self.current.update(&data[..take]);
+            self.current_len += take;
+            data = &data[take..];
+
+            if self.current_len == VERIFY_BUFFER_SIZE {
+                self.hashes.push(self.current.finalize_reset().into());
+                self.current_len = 0;
+            }
+        }
+    }
+
+    // The final block is usually short
+    fn finish(mut self) -> Vec<BlockHash> {
+        if self.current_len > 0 {
+            self.hashes.push(self.current.finalize().into());
+        }
+        self.hashes
+    }
+}
+
+// Passes everything read on to the block hashes
+struct HashingReader<'a, R> {
+    inner: R,
+    hashes: &'a mut BlockHashes,
+}
+
+impl<R: Read> Read for HashingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.hashes.update(&buf[..bytes_read]);
+        Ok(bytes_read)
+    }
+}
+
+// Read the device back block by block and check each against the hash taken
+// while writing it
+fn compare_block_hashes<B, P>(
+    mut device: B,
+    hashes: &[BlockHash],
+    length: u64,
+    cancel_token: &CancelToken,
+    mut on_progress: P,
+) -> Result<VerifyOutcome, WriterError>
+where
+    B: Read,
+    P: FnMut(u64),
+{
+    let mut buffer = vec![0u8; VERIFY_BUFFER_SIZE];
+    let mut bytes_verified = 0u64;
+
+    for expected in hashes {
+        if cancel_token.is_cancelled() {
+            info!("Verification cancelled after {} bytes", bytes_verified);
+            return Err(WriterError::Cancelled);
+        }
+
+        let chunk = (length - bytes_verified).min(VERIFY_BUFFER_SIZE as u64) as usize;
+        let bytes_read = read_full(&mut device, &mut buffer[..chunk]).map_err(WriterError::from_io)?;
+        let actual: BlockHash = Sha256::digest(&buffer[..bytes_read]).into();
+        if bytes_read < chunk || actual != *expected {
+            return Ok(VerifyOutcome::Mismatch { offset: bytes_verified });
+        }
+
+        bytes_verified += chunk as u64;
+        on_progress(bytes_verified);
+    }
+
+    Ok(VerifyOutcome::Match)
+}
+
+// Both readers are owned, so returning early on a cancel closes the files
+fn compare_streams<A, B, P>(
+    mut source: A,
+    mut device: B,
+    length: u64,
+    cancel_token: &CancelToken,
+    mut on_progress: P,
+) -> Result<VerifyOutcome, WriterError>
+where
+    A: Read,
+    B: Read,
+    P: FnMut(u64),
+{
+    let mut source_buffer = vec![0u8; VERIFY_BUFFER_SIZE];
+    let mut device_buffer = vec![0u8; VERIFY_BUFFER_SIZE];
+    let mut bytes_verified = 0u64;
+
+    while bytes_verified < length {
+        if cancel_token.is_cancelled() {
+            info!("Verification cancelled after {} bytes", bytes_verified);
+            return Err(WriterError::Cancelled);
+        }
+
+        let chunk = (length - bytes_verified).min(VERIFY_BUFFER_SIZE as u64) as usize;
+        let source_bytes = read_full(&mut source, &mut source_buffer[..chunk])
+            .map_err(WriterError::from_io)?;
+        let device_bytes = read_full(&mut device, &mut device_buffer[..source_bytes])
+            .map_err(WriterError::from_io)?;
+
+        if source_bytes == 0 {
+            break; // EOF reached
+        }
+
+        // Offsets are relative to the image start, not to this buffer
+        if let Some(index) = first_difference(&source_buffer[..source_bytes], &device_buffer[..device_bytes]) {
+            return Ok(VerifyOutcome::Mismatch { offset: bytes_verified + index as u64 });
+        }
+
+        bytes_verified += source_bytes as u64;
+        debug!("Verification: {:.1}%", bytes_verified as f64 / length as f64 * 100.0);
+        on_progress(bytes_verified);
+    }
+
+    Ok(VerifyOutcome::Match)
+}
+
+// Progress reports for a pass over the device that isn't the main write loop,
+// sent on the same cadence
+struct PhaseProgress<F> {
+    phase: Phase,
+    callback: Arc<F>,
+    total_bytes: u64,
+    trigger: ProgressTrigger,
+    start_time: Instant,
+    // When the last report was sent, and how far along it was
+    last_report: Option<(Instant, u64)>,
+    bytes_done: u64,
+    smoothed_speed: SmoothedSpeed,
+}
+
+impl<F: Fn(WriteProgress)> PhaseProgress<F> {
+    fn new(phase: Phase, callback: Arc<F>, total_bytes: u64, trigger: ProgressTrigger) -> Self {
+        let start_time = Instant::now();
+        Self { phase, callback, total_bytes, trigger, start_time, last_report: None, bytes_done: 0, smoothed_speed: SmoothedSpeed::new(start_time, 0) }
+    }
+
+    fn update(&mut self, bytes_verified: u64) {
+        self.bytes_done = bytes_verified;
+        let now = Instant::now();
+        if let Some((last_time, last_bytes)) = self.last_report {
+            if !self.trigger.is_due(now.duration_since(last_time), last_bytes, bytes_verified, Some(self.total_bytes)) {
+                return;
+            }
+        }
+        self.last_report = Some((now, bytes_verified));
+        self.report(bytes_verified);
+    }
+
+    // Unless update() already reported the end of the pass
+    fn finish(&mut self) {
+        if self.last_report.is_none_or(|(_, bytes)| bytes != self.total_bytes) {
+            self.report(self.total_bytes);
+        }
+    }
+
+    // Updates are throttled, so when the pass stops early report how far it
+    // actually got before passing the result on
+    fn track<T>(&mut self, result: Result<T, WriterError>) -> Result<T, WriterError> {
+        if result.is_err() {
+            self.report(self.bytes_done);
+        }
+        result
+    }
+
+    fn report(&mut self, bytes_verified: u64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.start_time).as_secs_f64();
+        let speed = if elapsed > 0.0 { bytes_verified as f64 / elapsed } else { 0.0 };
+        let instant_speed = self.smoothed_speed.record(now, bytes_verified).unwrap_or(speed);
+        let remaining = self.total_bytes.saturating_sub(bytes_verified);
+
+        (self.callback)(WriteProgress {
+            phase: self.phase,
+            bytes_written: bytes_verified,
+            total_bytes: Some(self.total_bytes),
+            progress_percent: Some(if self.total_bytes > 0 {
+                bytes_verified as f32 / self.total_bytes as f32 * 100.0
+            } else {
+                100.0
+            }),
+            speed_mbps: speed / (1024.0 * 1024.0),
+            instant_speed_mbps: instant_speed / (1024.0 * 1024.0),
+            eta_seconds: (speed > 0.0).then(|| remaining as f64 / speed),
+            elapsed_seconds: elapsed,
+            pipeline: None,
+        });
+    }
+}
+
+// Index of the first differing byte, counting a short device read as a
+// difference at the point where it ran out
+fn first_difference(source: &[u8], device: &[u8]) -> Option<usize> {
+    source
+        .iter()
+        .zip(device)
+        .position(|(a, b)| a != b)
+        .or((device.len() < source.len()).then_some(device.len()))
+}
+
+// Lowercase extension of the image itself, looking past any compression
+// suffix such as `.iso.gz`
+fn image_extension(path: &Path) -> Option<String> {
+    let path = download::file_path(path);
+    let image_path = match Compression::from_path(&path) {
+        Compression::None => path,
+        _ => path.with_extension(""),
+    };
+
+    image_path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+}
+
+// Extensions of the disk images we expect to be written, before any
+// compression suffix
+pub const IMAGE_EXTENSIONS: [&str; 7] = ["iso", "img", "raw", "bin", "dmg", "vhd", "vhdx"];
+
+// Extensions that are clearly not disk images, such as documents and archives
+// that would need unpacking first
+const NON_IMAGE_EXTENSIONS: [&str; 12] = [
+    "txt", "md", "pdf", "html", "zip", "rar", "7z", "tar", "exe", "msi", "deb", "rpm",
+];
+
+// True for .iso/.img/.raw/.bin files and the containers in IMAGE_EXTENSIONS
+pub fn has_image_extension(path: &Path) -> bool {
+    image_extension(path).is_some_and(|e| IMAGE_EXTENSIONS.contains(&e.as_str()))
+}
+
+// Raw disk images don't necessarily carry any signature we can check
+pub fn is_raw_image(path: &Path) -> bool {
+    image_extension(path).is_some_and(|e| e != "iso" && IMAGE_EXTENSIONS.contains(&e.as_str()))
+}
+
+// The writer treats the source as an opaque byte stream, so only warn about
+// files that are obviously something else
+fn is_clearly_not_image(path: &Path) -> bool {
+    image_extension(path).is_some_and(|e| NON_IMAGE_EXTENSIONS.contains(&e.as_str()))
+}
+
+// The primary volume descriptor of an ISO 9660 image starts at sector 16,
+// with the "CD001" standard identifier one byte in
+const ISO9660_MAGIC_OFFSET: usize = 0x8001;
+const ISO9660_MAGIC: &[u8] = b"CD001";
+const MBR_SIGNATURE_OFFSET: usize = 510;
+// Primary GPT header, in LBA 1 of a 512-byte sector image
+const GPT_HEADER_OFFSET: usize = 512;
+const GPT_SIGNATURE: &[u8] = b"EFI PART";
+const GPT_SECTOR_SIZE: u64 = 512;
+
+// True if the header holds an ISO 9660 volume descriptor or an MBR/GPT boot
+// signature, as found on hybrid ISOs and bootable raw disk images
+fn has_image_signature(header: &[u8]) -> bool {
+    let iso9660 = header
+        .get(ISO9660_MAGIC_OFFSET..ISO9660_MAGIC_OFFSET + ISO9660_MAGIC.len())
+        .is_some_and(|magic| magic == ISO9660_MAGIC);
+    let boot_sector = header
+        .get(MBR_SIGNATURE_OFFSET..MBR_SIGNATURE_OFFSET + 2)
+        .is_some_and(|signature| signature == [0x55, 0xAA]);
+
+    iso9660 || boot_sector
+}
+
+// Catch files that merely have an image extension, e.g. a renamed .zip.
+// Compressed images are checked after decompression.
+pub fn validate_iso_signature(path: &Path) -> Result<(), WriterError> {
+    let (mut reader, _) = image_reader(path)?;
+    let mut header = vec![0u8; ISO9660_MAGIC_OFFSET + ISO9660_MAGIC.len()];
+    let bytes_read = read_full(&mut reader, &mut header)?;
+
+    if !has_image_signature(&header[..bytes_read]) {
+        return Err(WriterError::InvalidIsoFormat);
+    }
+    Ok(())
+}
+
+// Size in bytes the image's GPT expects the disk to have, from the backup
+// header location (normally the last LBA). None if there is no GPT.
+fn gpt_declared_size(header: &[u8]) -> Option<u64> {
+    let gpt = header.get(GPT_HEADER_OFFSET..GPT_HEADER_OFFSET + 56)?;
+    if &gpt[..8] != GPT_SIGNATURE {
+        return None;
+    }
+
+    let read_lba = |offset: usize| u64::from_le_bytes(gpt[offset..offset + 8].try_into().unwrap());
+    let backup_lba = read_lba(32);
+    let last_usable_lba = read_lba(48);
+
+    backup_lba.max(last_usable_lba)
+        .checked_add(1)?
+        .checked_mul(GPT_SECTOR_SIZE)
+}
+
+// A hybrid ISO whose GPT was laid out for a disk bigger than the device copies
+// fine, but leaves the backup header past the end and a corrupt partition table
+fn validate_partition_layout(path: &Path, device_size: u64) -> Result<(), WriterError> {
+    let mut file = File::open(path).map_err(WriterError::from_io)?;
+    let mut header = vec![0u8; GPT_HEADER_OFFSET + GPT_SECTOR_SIZE as usize];
+    let bytes_read = read_full(&mut file, &mut header).map_err(WriterError::from_io)?;
+
+    match gpt_declared_size(&header[..bytes_read]) {
+        Some(declared) if declared > device_size => {
+            Err(WriterError::PartitionTableTooLarge { declared, device: device_size })
+        }
+        _ => Ok(()),
+    }
+}
+
+// Both the image and its partition table have to fit in what the device
+// reports. Some USB bridges and loop devices misreport their size and sparse
+// files can grow, so the check can be skipped; a real shortage then still
+// fails the write with the offset where the device filled up.
+fn check_device_fits(iso_path: &Path, iso_size: u64, available: u64, ignore_size_check: bool) -> Result<(), WriterError> {
+    let result = if iso_size > available {
+        Err(WriterError::InsufficientSpace(None))
+    } else {
+        validate_partition_layout(iso_path, available)
+    };
+
+    match result {
+        Err(error) if ignore_size_check => {
+            warn!("Ignoring the device size check: {}", error);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+// Flash controllers write fastest in whole multiples of their optimal I/O
+// size, so round the fallback size up to one; devices that only report a
+// minimum get a multiple of that, and those reporting nothing the fallback
+pub fn auto_buffer_size(io_sizes: Option<IoSizes>) -> usize {
+    round_to_io_unit(AUTO_BUFFER_FALLBACK, io_sizes)
+}
+
+// Where Auto starts and how far it may grow: from 1 MB to four times the Auto
+// size, both whole multiples of the device's I/O size
+pub fn adaptive_buffer_bounds(io_sizes: Option<IoSizes>) -> (usize, usize) {
+    let max = auto_buffer_size(io_sizes) * ADAPTIVE_BUFFER_GROWTH;
+    (round_to_io_unit(BUFFER_SIZE, io_sizes), max)
+}
+
+fn round_to_io_unit(size: usize, io_sizes: Option<IoSizes>) -> usize {
+    let unit = match io_sizes {
+        Some(IoSizes { optimal, .. }) if optimal > 0 => optimal,
+        Some(IoSizes { minimum, .. }) if minimum > 0 => minimum,
+        _ => return size,
+    };
+
+    // Keep O_DIRECT alignment even for odd hints
+    let unit = unit.div_ceil(512) * 512;
+    ((size as u64).div_ceil(unit) * unit) as usize
+}
+
+// How long each buffer size is measured before deciding whether to grow it
+const BUFFER_TUNING_INTERVAL: Duration = Duration::from_secs(1);
+// How much faster a bigger buffer has to be to be worth growing further
+const BUFFER_TUNING_MIN_GAIN: f64 = 1.1;
+
+// Feedback loop for the adaptive buffer: measure the speed at each size for
+// a second, and double the buffer while that keeps making the write clearly
+// faster. Settles for good once it doesn't, or at the maximum, so it only
+// runs during the first few seconds.
+struct BufferTuner {
+    size: usize,
+    max: usize,
+    measuring_since: (Instant, u64),
+    last_speed: Option<f64>,
+    settled: bool,
+}
+
+impl BufferTuner {
+    fn new(size: usize, max: usize, now: Instant) -> Self {
+        Self { size, max, measuring_since: (now, 0), last_speed: None, settled: false }
+    }
+
+    // Measure afresh, e.g. after a pause
+    fn restart(&mut self, now: Instant, bytes_written: u64) {
+        self.measuring_since = (now, bytes_written);
+    }
+
+    // The new size, when this measurement calls for growing the buffer
+    fn update(&mut self, now: Instant, bytes_written: u64) -> Option<usize> {
+        let (since, bytes_before) = self.measuring_since;
+        let elapsed = now.duration_since(since);
+        if self.settled || elapsed < BUFFER_TUNING_INTERVAL {
+            return None;
+        }
+
+        let speed = (bytes_written - bytes_before) as f64 / elapsed.as_secs_f64();
+        self.measuring_since = (now, bytes_written);
+        let faster = self.last_speed.is_none_or(|last_speed| speed > last_speed * BUFFER_TUNING_MIN_GAIN);
+        if !faster || self.size >= self.max {
+            self.settled = true;
+            info!("Write buffer settled at {} bytes", self.size);
+            return None;
+        }
+
+        self.last_speed = Some(speed);
+        self.size = (self.size * 2).min(self.max);
+        Some(self.size)
+    }
+}
+
+pub fn validate_buffer_size(buffer_size: usize) -> Result<usize, WriterError> {
+    if buffer_size == 0 || !buffer_size.is_multiple_of(512) {
+        return Err(WriterError::InvalidBufferSize(buffer_size));
+    }
+    Ok(buffer_size)
+}
+
+// How the device is opened for writing. Whichever it is, the write ends with
+// an fsync, so the data has reached the device before it is verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IoMode {
+    // Through the page cache
+    #[default]
+    Buffered,
+    // O_DIRECT, bypassing the page cache (Linux only)
+    Direct,
+    // O_SYNC, so each write waits for the device
+    Sync,
+}
+
+impl IoMode {
+    pub const ALL: [IoMode; 3] = [IoMode::Buffered, IoMode::Direct, IoMode::Sync];
+}
+
+impl std::fmt::Display for IoMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            IoMode::Buffered => "Buffered I/O",
+            IoMode::Direct => "Direct I/O",
+            IoMode::Sync => "Synchronous I/O",
+        })
+    }
+}
+
+impl std::str::FromStr for IoMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "buffered" => Ok(IoMode::Buffered),
+            "direct" => Ok(IoMode::Direct),
+            "sync" => Ok(IoMode::Sync),
+            _ => Err(format!("unknown I/O mode '{}' (expected buffered, direct or sync)", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VerifyMode {
+    None,
+    // The first and last few MB plus a handful of pseudo-random blocks
+    Quick,
+    // Hash the whole written range
+    #[default]
+    Full,
+    // Read each block back right after writing it, with no second pass
+    Inline,
+}
+
+impl VerifyMode {
+    pub const ALL: [VerifyMode; 4] = [VerifyMode::None, VerifyMode::Quick, VerifyMode::Full, VerifyMode::Inline];
+
+    // Whether a separate pass reads the device back once the write is done
+    pub fn has_verify_pass(self) -> bool {
+        matches!(self, VerifyMode::Quick | VerifyMode::Full)
+    }
+}
+
+impl std::fmt::Display for VerifyMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            VerifyMode::None => "No verification",
+            VerifyMode::Quick => "Quick verify",
+            VerifyMode::Full => "Full verify",
+            VerifyMode::Inline => "Inline verify",
+        })
+    }
+}
+
+impl std::str::FromStr for VerifyMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "none" => Ok(VerifyMode::None),
+            "quick" => Ok(VerifyMode::Quick),
+            "full" => Ok(VerifyMode::Full),
+            "inline" => Ok(VerifyMode::Inline),
+            _ => Err(format!("unknown verify mode '{}' (expected none, quick, full or inline)", value)),
+        }
+    }
+}
+
+const QUICK_VERIFY_EDGE: u64 = 4 * 1024 * 1024;
+const QUICK_VERIFY_BLOCK: u64 = 64 * 1024;
+const QUICK_VERIFY_SAMPLES: usize = 16;
+
+// (offset, length) regions checked by a quick verify. The random blocks are
+// seeded from the image size, so a failing check is reproducible.
+// splitmix64 output function: scrambles `z` into a well-mixed 64-bit value
+fn splitmix64(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+fn quick_verify_ranges(length: u64) -> Vec<(u64, u64)> {
+    let mut ranges = vec![(0, length.min(QUICK_VERIFY_EDGE))];
+
+    if length > QUICK_VERIFY_EDGE {
+        let tail = QUICK_VERIFY_EDGE.min(length - QUICK_VERIFY_EDGE);
+        ranges.push((length - tail, tail));
+    }
+
+    // Sample whole blocks from the middle region with a splitmix64 sequence
+    let blocks = length.saturating_sub(2 * QUICK_VERIFY_EDGE) / QUICK_VERIFY_BLOCK;
+    let mut state = length;
+    for _ in 0..QUICK_VERIFY_SAMPLES.min(blocks as usize) {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let z = splitmix64(state);
+
+        ranges.push((QUICK_VERIFY_EDGE + (z % blocks) * QUICK_VERIFY_BLOCK, QUICK_VERIFY_BLOCK));
+    }
+
+    ranges
+}
+
+fn compare_ranges<A, B>(source: &mut A, device: &mut B, ranges: &[(u64, u64)]) -> Result<(), WriterError>
+where
+    A: Read + Seek,
+    B: Read + Seek,
+{
+    let mut source_buffer = Vec::new();
+    let mut device_buffer = Vec::new();
+
+    for &(offset, length) in ranges {
+        source_buffer.resize(length as usize, 0);
+        device_buffer.resize(length as usize, 0);
+
+        source.seek(SeekFrom::Start(offset)).map_err(WriterError::from_io)?;
+        device.seek(SeekFrom::Start(offset)).map_err(WriterError::from_io)?;
+        source.read_exact(&mut source_buffer).map_err(WriterError::from_io)?;
+        device.read_exact(&mut device_buffer).map_err(WriterError::from_io)?;
+
+        if let Some(index) = first_difference(&source_buffer, &device_buffer) {
+            let offset = offset + index as u64;
+            error!("Verification failed: data mismatch at byte {}", offset);
+            return Err(WriterError::VerificationFailed { offset });
+        }
+    }
+
+    Ok(())
+}
+
+// Settings for a single write, shared by the GUI and the headless CLI
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    pub buffer_size: usize,
+    pub io_mode: IoMode,
+    pub verify_mode: VerifyMode,
+    pub dry_run: bool,
+    pub max_retries: u32,
+    pub max_speed_mbps: Option<f64>,
+    // Report progress from data synced to the device, see with_accurate_progress
+    pub accurate_progress: bool,
+    // How often progress is reported, see with_progress_trigger
+    pub progress_trigger: ProgressTrigger,
+    // Byte offset on the device to write the image at
+    pub start_offset: u64,
+    // Full verification against hashes taken while writing, see with_block_hashes
+    pub block_hashes: bool,
+    // How long a busy device is retried before giving up, see retry_while_busy
+    pub busy_timeout: Duration,
+    // Write images that have neither an ISO 9660 nor a boot signature
+    pub allow_raw_image: bool,
+    // Write even if the device reports being too small for the image
+    pub ignore_size_check: bool,
+    // Published checksum the written data must match, e.g. from SHA256SUMS
+    pub checksum: Option<ExpectedChecksum>,
+    // The image already in memory, shared between parallel writes
+    pub image: Option<ImageBuffer>,
+    // Save the progress under this device identity, so an interrupted write
+    // can be resumed, see resume::device_identity. Only local, uncompressed
+    // images are tracked.
+    pub resume_device: Option<String>,
+    // What an interrupted write of the same image to the same device left,
+    // checked before carrying on after it
+    pub resume_from: Option<ResumeState>,
+    // Add a persistence partition after the image once it checks out
+    pub persistence: Option<PersistenceOptions>,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            buffer_size: BUFFER_SIZE,
+            io_mode: IoMode::Buffered,
+            verify_mode: VerifyMode::Full,
+            dry_run: false,
+            max_retries: DEFAULT_MAX_RETRIES,
+            max_speed_mbps: None,
+            accurate_progress: false,
+            progress_trigger: DEFAULT_PROGRESS_TRIGGER,
+            start_offset: 0,
+            block_hashes: false,
+            busy_timeout: DEFAULT_BUSY_TIMEOUT,
+            allow_raw_image: false,
+            ignore_size_check: false,
+            checksum: None,
+            image: None,
+            resume_device: None,
+            resume_from: None,
+            persistence: None,
+        }
+    }
+}
+
+// Result of a successful write (or dry run). `digest` is the image's sha256.
+#[derive(Debug, Clone, Default)]
+pub struct WriteSummary {
+    pub bytes_written: u64,
+    pub digest: String,
+    pub dry_run: bool,
+    // How the result was actually checked on the device
+    pub verify_mode: VerifyMode,
+    // The whole job, from opening the device to the end of the verification
+    pub duration: Duration,
+    // Over the write alone, in MB/s
+    pub average_speed_mbps: f64,
+    // Device node of the persistence partition, if one was added
+    pub persistence_partition: Option<String>,
+}
+
+impl WriteSummary {
+    pub fn verified(&self) -> bool {
+        self.verify_mode != VerifyMode::None
+    }
+}
+
+#[instrument(name = "write_iso", skip_all, fields(device = %device_path, iso_size = field::Empty))]
+pub async fn write_iso_to_device(
+    iso_path: &Path,
+    device_path: &str,
+    options: WriteOptions,
+    cancel_token: CancelToken,
+    pause_token: PauseToken,
+    progress_sender: mpsc::UnboundedSender<WriteProgress>,
+) -> Result<WriteSummary, WriterError> {
+    let started = Instant::now();
+
+    // URLs are streamed straight to the device, so the local file checks
+    // below don't apply. Their size, if known, comes from Content-Length.
+    let is_url = download::is_url(iso_path);
+
+    // Validate that ISO file exists and is readable
+    if !is_url {
+        let path = iso_path.to_path_buf();
+        if !on_source(iso_path, move || Ok(path.exists())).await? {
+            return Err(WriterError::IsoNotFound(iso_path.to_string_lossy().to_string()));
+        }
+
+        check_source_not_on_device(iso_path, device_path).await?;
+    }
+
+    let compression = Compression::from_path(iso_path);
+    if is_clearly_not_image(iso_path) {
+        warn!("{} doesn't look like a disk image", iso_path.display());
+    }
+
+    // Peeking at a download would mean fetching it twice, so URLs skip this
+    if !is_url {
+        let _ = progress_sender.send(validation_progress(ValidationStep::Signature));
+        let path = iso_path.to_path_buf();
+        if let Err(error) = on_source(iso_path, move || Ok(validate_iso_signature(&path))).await? {
+            if !options.allow_raw_image {
+                return Err(error);
+            }
+            warn!("No ISO 9660 or boot signature found, writing anyway");
+        }
+    }
+
+    let start_offset = validate_start_offset(options.start_offset)?;
+
+    let backend = crate::device::platform_backend();
+    let image_file = Path::new(device_path).is_file();
+
+    // Rather than after a write that then can't be finished
+    if options.persistence.is_some() && !cfg!(target_os = "linux") {
+        return Err(WriterError::PersistenceFailed("only supported on Linux".to_string()));
+    }
+    if options.persistence.is_some() && image_file {
+        return Err(WriterError::PersistenceFailed("image files can't have one".to_string()));
+    }
+
+    // The lock is held until the end of the verification
+    let report_waiting = |waited| {
+        let _ = progress_sender.send(waiting_progress(waited));
+    };
+    let (device_file, _lock) = retry_while_busy(options.busy_timeout, report_waiting, || {
+        open_and_lock(device_path, image_file, &*backend)
+    }).await?;
+
+    let (buffer_size, max_buffer_size) = match options.buffer_size {
+        AUTO_BUFFER_SIZE => {
+            let io_sizes = backend.io_sizes(device_path).ok();
+            let (buffer_size, max_buffer_size) = adaptive_buffer_bounds(io_sizes);
+            info!("Auto buffer size: {} bytes, growing up to {} (device I/O sizes: {:?})",
+                  buffer_size, max_buffer_size, io_sizes);
+            (buffer_size, Some(max_buffer_size))
+        }
+        buffer_size => (buffer_size, None),
+    };
+
+    // Try to get device size. The decompressed size of
+    // a compressed image isn't known, so those rely on the write failing instead.
+    let device_size = match device_file.as_ref() {
+        // Seeking to the end is the one size query that works on any descriptor
+        Some(mut file) => file.seek(SeekFrom::End(0)),
+        None if image_file => std::fs::metadata(device_path).map(|metadata| metadata.len()),
+        None => backend.size(device_path),
+    };
+    if let (false, Compression::None, Ok(device_size)) = (is_url, compression, device_size) {
+        let _ = progress_sender.send(validation_progress(ValidationStep::Size));
+        // Only the part of the device past the offset is available
+        let available = device_size.saturating_sub(start_offset);
+        let (path, ignore_size_check) = (iso_path.to_path_buf(), options.ignore_size_check);
+        let iso_size = on_source(iso_path, move || {
+            let iso_size = image_size(&path)?;
+            check_device_fits(&path, iso_size, available, ignore_size_check)?;
+            Ok(iso_size)
+        }).await?;
+        if let Some(persistence) = options.persistence {
+            persistence_span(start_offset + iso_size, device_size, persistence.size)?;
+        }
+        Span::current().record("iso_size", iso_size);
+        info!("Device size: {} bytes, ISO size: {} bytes, start offset: {}", device_size, iso_size, start_offset);
+    }
+
+    let writer = UsbWriter::new(
+        iso_path.to_string_lossy().to_string(),
+        device_path.to_string(),
+        options.io_mode,
+    )
+        .with_buffer_size(buffer_size)?
+        .with_adaptive_buffer(max_buffer_size)?
+        .with_dry_run(options.dry_run)
+        .with_max_retries(options.max_retries)
+        .with_max_speed(options.max_speed_mbps)
+        .with_accurate_progress(options.accurate_progress)
+        .with_progress_trigger(options.progress_trigger)
+        .with_start_offset(start_offset)?
+        .with_verify_mode(options.verify_mode)
+        .with_block_hashes(options.block_hashes)
+        .with_image(options.image)
+        .with_device_file(device_file)
+        .with_cancel_token(cancel_token)
+        .with_pause_token(pause_token);
+
+    // Only a local file can be fingerprinted and read back with its size known
+    let resume_state = match &options.resume_device {
+        Some(device_serial) if !is_url && compression == Compression::None && !options.dry_run => {
+            let path = iso_path.to_path_buf();
+            let fingerprint = on_source(iso_path, move || {
+                Ok(match crate::resume::image_fingerprint(&path) {
+                    Ok(fingerprint) => Ok((fingerprint, image_size(&path)?)),
+                    Err(error) => Err(error),
+                })
+            }).await?;
+            match fingerprint {
+                Ok((fingerprint, iso_size)) => Some(ResumeState::new(fingerprint, device_serial.clone(), start_offset, iso_size)),
+                Err(error) => {
+                    warn!("Can't keep track of the write to resume it later: {}", error);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    // What was already written is only skipped if the device still holds it
+    let resume_skip = match (options.resume_from, &resume_state) {
+        (Some(from), Some(state)) if from.image_fingerprint == state.image_fingerprint
+            && from.device_serial == state.device_serial
+            && from.start_offset == start_offset =>
+        {
+            let _ = progress_sender.send(validation_progress(ValidationStep::Resume));
+            let expected = ExpectedChecksum { algorithm: ChecksumAlgorithm::Sha256, hex: from.digest };
+            match writer.verify_write_hash(from.committed, expected, Arc::new(|_: WriteProgress| {})).await {
+                Ok(_) => {
+                    info!("The first {} bytes are already on the device, resuming after them", from.committed);
+                    from.committed
+                }
+                Err(WriterError::HashMismatch { .. }) => {
+                    warn!("The device no longer holds what the interrupted write left, starting over");
+                    0
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        (Some(_), _) => {
+            warn!("The interrupted write was of another image or device, starting over");
+            0
+        }
+        (None, _) => 0,
+    };
+    let writer = writer.with_resume(resume_skip, resume_state.clone());
+
+    // Write the ISO, forwarding progress to the receiver. A closed receiver
+    // only means nobody is listening anymore, so send errors are ignored.
+    let progress_callback = Arc::new(move |progress: WriteProgress| {
+        let _ = progress_sender.send(progress);
+    });
+    let write_callback = progress_callback.clone();
+    let write_started = Instant::now();
+    // Only a device that could be seen to begin with can be seen to vanish,
+    // which rules out image files and devices hidden in a Flatpak
+    let watch_removal = !image_file && backend.exists(device_path);
+    let check_removal = |error| match watch_removal {
+        true => disconnected_if_gone(error, device_path, &*backend),
+        false => error,
+    };
+
+    let (bytes_written, iso_digest) = writer.write_iso_with_progress(move |progress| {
+        write_callback(progress);
+    }).await.map_err(check_removal)?;
+    // Nothing left to resume
+    if let Some(state) = &resume_state {
+        state.remove();
+    }
+    let write_seconds = write_started.elapsed().as_secs_f64();
+    let average_speed_mbps = if write_seconds > 0.0 {
+        bytes_written as f64 / (1024.0 * 1024.0) / write_seconds
+    } else {
+        0.0
+    };
+
+    if options.dry_run {
+        info!("Dry run completed, nothing was written");
+        return Ok(WriteSummary {
+            bytes_written,
+            digest: iso_digest,
+            dry_run: true,
+            verify_mode: VerifyMode::None,
+            duration: started.elapsed(),
+            average_speed_mbps,
+            persistence_partition: None,
+        });
+    }
+
+    info!("Write completed, starting verification...");
+
+    // Verify the write by checking what actually landed on the device
+    let verify_mode = writer.verify(bytes_written, iso_digest.clone(), progress_callback.clone()).await.map_err(check_removal)?;
+
+    // Published checksums cover the whole image, so hash exactly that much
+    if let Some(checksum) = options.checksum {
+        info!("Checking device contents against {}", checksum);
+        writer.verify_write_hash(bytes_written, checksum, progress_callback.clone()).await.map_err(check_removal)?;
+    }
+
+    // The partitioning tools get the device to themselves, and udev has to
+    // be able to lock it to probe the new partition
+    let persistence_partition = match options.persistence {
+        Some(persistence) => {
+            drop(writer);
+            #[cfg(unix)]
+            drop(_lock);
+            progress_callback(WriteProgress { phase: Phase::Persistence, ..waiting_progress(Duration::ZERO) });
+            Some(create_persistence_partition(device_path, start_offset + bytes_written, persistence).await?)
+        }
+        None => None,
+    };
+
+    Ok(WriteSummary {
+        bytes_written,
+        digest: iso_digest,
+        dry_run: false,
+        verify_mode,
+        duration: started.elapsed(),
+        average_speed_mbps,
+        persistence_partition,
+    })
+}
+
+// Push out whatever the OS still holds for the device, so it can be unplugged.
+// The write syncs its own descriptor, but the kernel (or udisks2, rereading
+// the partition table) may still have dirty buffers for it.
+pub async fn flush_device(device_path: String) -> Result<(), WriterError> {
+    spawn_in_span(move || {
+        // Also covers devices only udisks2 could open for us
+        #[cfg(unix)]
+        unsafe {
+            libc::sync();
+        }
+
+        match OpenOptions::new().write(true).open(&device_path) {
+            #[cfg(target_os = "linux")]
+            Ok(mut file) => sync_device(&mut file),
+            #[cfg(not(target_os = "linux"))]
+            Ok(file) => file.sync_all().map_err(WriterError::from_io),
+            Err(e) if cfg!(unix) && e.kind() == io::ErrorKind::PermissionDenied => Ok(()),
+            Err(e) => Err(WriterError::from(e)),
+        }
+    })
+        .await
+        .map_err(|e| WriterError::from_io(io::Error::other(e)))?
+}
+
+// Open the device (through udisks2 where needed) and lock it
+async fn open_and_lock(
+    device_path: &str,
+    image_file: bool,
+    backend: &dyn crate::device::DeviceBackend,
+) -> Result<(Option<File>, DeviceLock), WriterError> {
+    #[cfg(target_os = "linux")]
+    let device_file = open_through_udisks(device_path).await?;
+    #[cfg(not(target_os = "linux"))]
+    let device_file: Option<File> = None;
+
+    // Validate that device exists. Image files aren't detected as devices.
+    if device_file.is_none() && !image_file && !backend.exists(device_path) {
+        return Err(WriterError::DeviceNotFound(device_path.to_string()));
+    }
+
+    // udisks2 already opened it exclusively
+    if device_file.is_none() {
+        crate::device::check_not_busy(device_path)?;
+    }
+
+    let lock = DeviceLock::acquire(device_path, device_file.as_ref()).await?;
+    Ok((device_file, lock))
+}
+
+// How long a device that reports being busy is retried before giving up.
+// Right after its partitions were unmounted, the kernel may not have let go
+// of it yet and udev is usually still probing it.
+pub const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(2);
+const BUSY_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+// Run `attempt` again while it fails with DeviceBusy, until `timeout` has
+// passed. Any other error is returned straight away, since waiting won't fix
+// it. `on_retry` is told how long we have waited so far before each retry.
+pub async fn retry_while_busy<T, F, Fut>(
+    timeout: Duration,
+    mut on_retry: impl FnMut(Duration),
+    mut attempt: F,
+) -> Result<T, WriterError>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, WriterError>>,
+{
+    let started = Instant::now();
+    loop {
+        match attempt().await {
+            Err(WriterError::DeviceBusy(reason)) if started.elapsed() + BUSY_RETRY_DELAY <= timeout => {
+                warn!("{}, retrying", reason);
+                on_retry(started.elapsed());
+                settle_udev().await;
+                tokio::time::sleep(BUSY_RETRY_DELAY).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Let udev finish handling the events an unmount caused, so its probing
+// doesn't keep the device busy. Best effort, udevadm may not be installed.
+#[cfg(target_os = "linux")]
+pub(crate) async fn settle_udev() {
+    let _ = tokio::process::Command::new("udevadm")
+        .args(["settle", "--timeout=1"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .await;
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) async fn settle_udev() {}
+
+// Exclusive advisory lock on the device, so a second instance (or another
+// tool that locks, like systemd-udevd) can't write to the same device at the
+// same time. Released when dropped.
+struct DeviceLock {
+    #[cfg(unix)]
+    _file: File,
+}
+
+impl DeviceLock {
+    // A descriptor opened by udisks2 is locked itself, since the path may not
+    // be openable. Its duplicate shares the lock.
+    #[cfg(unix)]
+    async fn acquire(device_path: &str, device_file: Option<&File>) -> Result<Self, WriterError> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = match device_file {
+            Some(file) => file.try_clone().map_err(WriterError::from_io)?,
+            None => File::open(device_path).map_err(WriterError::from)?,
+        };
+
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+            debug!("Locked {}", device_path);
+            return Ok(Self { _file: file });
+        }
+
+        // udevd holds a shared lock for a moment while it probes a device,
+        // which retry_while_busy waits out
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::EWOULDBLOCK) {
+            return Err(WriterError::from_io(error));
+        }
+        Err(WriterError::DeviceBusy(format!("{} is locked by another program", device_path)))
+    }
+
+    // Windows refuses to share an open device instead, see DeviceWriter::open
+    #[cfg(not(unix))]
+    async fn acquire(_device_path: &str, _device_file: Option<&File>) -> Result<Self, WriterError> {
+        Ok(Self {})
+    }
+}
+
+// udisks2 opens devices for an unprivileged user after a polkit prompt, so
+// root is only needed where it isn't running. Inside a Flatpak the device
+// node isn't visible at all, which leaves udisks2 as the only way in.
+#[cfg(target_os = "linux")]
+async fn open_through_udisks(device_path: &str) -> Result<Option<File>, WriterError> {
+    if crate::udisks::in_flatpak() {
+        info!("Running in a Flatpak, opening {} through udisks2", device_path);
+        return crate::udisks::open_device(device_path).await.map(Some);
+    }
+
+    // Image files and the like are opened directly
+    let is_root = unsafe { libc::geteuid() == 0 };
+    if is_root || !device_path.starts_with("/dev/") {
+        return Ok(None);
+    }
+
+    match crate::udisks::open_device(device_path).await {
+        Ok(file) => Ok(Some(file)),
+        Err(e) => {
+            warn!("udisks2 could not open {} ({}), opening it directly", device_path, e);
+            Ok(None)
+        }
+    }
+}
+
+// Fill `buffer` with the capacity test pattern for the bytes starting at
+// `offset`. Every 8-byte word depends on its absolute position, so a drive
+// that wraps writes around to an earlier address reads back the wrong data.
+fn fill_capacity_pattern(offset: u64, buffer: &mut [u8]) {
+    for (index, word) in buffer.chunks_mut(8).enumerate() {
+        let value = splitmix64(offset + index as u64 * 8).to_le_bytes();
+        word.copy_from_slice(&value[..word.len()]);
+    }
+}
+
+// Destructive check for fake-capacity drives: fill the claimed `size` with a
+// position-dependent pattern, read it all back and return how many bytes
+// actually hold their data. Anything less than `size` means the drive lies.
+pub async fn test_device_capacity<F>(
+    device_path: &str,
+    size: u64,
+    cancel_token: CancelToken,
+    progress_callback: F,
+) -> Result<u64, WriterError>
+where
+    F: Fn(WriteProgress) + Send + Sync + 'static,
+{
+    let device_path = device_path.to_string();
+    let progress_callback = Arc::new(progress_callback);
+
+    spawn_in_span(move || test_device_capacity_sync(&device_path, size, &cancel_token, progress_callback))
+        .await
+        .map_err(|e| WriterError::from_io(io::Error::other(e)))?
+}
+
+fn test_device_capacity_sync<F>(
+    device_path: &str,
+    size: u64,
+    cancel_token: &CancelToken,
+    progress_callback: Arc<F>,
+) -> Result<u64, WriterError>
+where
+    F: Fn(WriteProgress),
+{
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut device = DeviceWriter::open(device_path, IoMode::Buffered)?;
+    info!("Testing capacity of {} ({} bytes claimed)", device_path, size);
+
+    let mut progress = PhaseProgress::new(Phase::Writing, progress_callback.clone(), size, DEFAULT_PROGRESS_TRIGGER);
+    let mut written = 0u64;
+    while written < size {
+        if cancel_token.is_cancelled() {
+            return Err(WriterError::Cancelled);
+        }
+
+        let len = (size - written).min(BUFFER_SIZE as u64) as usize;
+        fill_capacity_pattern(written, &mut buffer[..len]);
+
+        match write_with_retry(&mut device, &buffer[..len], written, DEFAULT_MAX_RETRIES) {
+            Ok(()) => written += len as u64,
+            // The drive refused to take more, so that is as far as it goes
+            Err((e, failed_at)) if matches!(e.kind(), io::ErrorKind::StorageFull | io::ErrorKind::WriteZero) => {
+                warn!("{} stopped accepting data at byte {}", device_path, failed_at);
+                written = failed_at;
+                break;
+            }
+            Err((e, _)) => return Err(WriterError::from_io(e)),
+        }
+        progress.update(written);
+    }
+
+    device.flush().map_err(WriterError::from_io)?;
+    sync_device(&mut device.file)?;
+    drop(device);
+
+    let mut reader = File::open(device_path).map_err(WriterError::from_io)?;
+    let mut expected = vec![0u8; BUFFER_SIZE];
+    let mut progress = PhaseProgress::new(Phase::Verifying, progress_callback, written, DEFAULT_PROGRESS_TRIGGER);
+    let mut verified = 0u64;
+    while verified < written {
+        if cancel_token.is_cancelled() {
+            return Err(WriterError::Cancelled);
+        }
+
+        let len = (written - verified).min(BUFFER_SIZE as u64) as usize;
+        fill_capacity_pattern(verified, &mut expected[..len]);
+        let bytes_read = read_full(&mut reader, &mut buffer[..len]).map_err(WriterError::from_io)?;
+
+        if let Some(index) = first_difference(&expected[..len], &buffer[..bytes_read]) {
+            let usable = verified + index as u64;
+            warn!("{} only holds {} of its claimed {} bytes", device_path, usable, size);
+            return Ok(usable);
+        }
+
+        verified += len as u64;
+        progress.update(verified);
+    }
+
+    progress.finish();
+    info!("Capacity test passed: {} bytes usable", written);
+    Ok(written)
+}
+
+// Compare a device flashed earlier (possibly by another tool) against the
+// image, without writing anything. Needs an uncompressed local image, whose
+// length says how much of the device to read back.
+#[instrument(name = "verify_only", skip_all, fields(device = %device_path))]
+pub async fn verify_only(
+    iso_path: &Path,
+    device_path: &str,
+    start_offset: u64,
+    progress_sender: mpsc::UnboundedSender<WriteProgress>,
+) -> Result<VerifyOutcome, WriterError> {
+    if download::is_url(iso_path) || Compression::from_path(iso_path) != Compression::None {
+        return Err(WriterError::Unknown("Verify Only needs an uncompressed local image".to_string()));
+    }
+    let path = iso_path.to_path_buf();
+    let iso_size = on_source(iso_path, move || image_size(&path)).await?;
+
+    let writer = UsbWriter::new(iso_path.to_string_lossy().to_string(), device_path.to_string(), IoMode::Buffered)
+        .with_start_offset(start_offset)?;
+    let progress_callback = Arc::new(move |progress: WriteProgress| {
+        let _ = progress_sender.send(progress);
+    });
+
+    writer.verify_write(iso_size, progress_callback).await
+}
+
+// Rewrite just the block that failed verification, then check it again
+pub async fn repair_device_region(iso_path: &Path, device_path: &str, start_offset: u64, offset: u64) -> Result<(), WriterError> {
+    UsbWriter::new(iso_path.to_string_lossy().to_string(), device_path.to_string(), IoMode::Buffered)
+        .with_start_offset(start_offset)?
+        .repair_region(offset)
+        .await
+}
+
+// The SHA-256 of a local image file as stored, which is what SHA256SUMS
+// lists, for checking a download before it is written. Reports the bytes
+// hashed so far and the file's size.
+pub async fn hash_image_file<F>(iso_path: PathBuf, cancel_token: CancelToken, on_progress: F) -> Result<String, WriterError>
+where
+    F: Fn(u64, u64) + Send + 'static,
+{
+    spawn_in_span(move || {
+        let file = File::open(&iso_path).map_err(|_| WriterError::IsoNotFound(iso_path.display().to_string()))?;
+        let total = file.metadata().map_err(WriterError::from_io)?.len();
+        let digest = UsbWriter::hash_reader(file, ChecksumAlgorithm::Sha256, &cancel_token, |bytes_hashed| on_progress(bytes_hashed, total))?;
+        info!("SHA-256 of {}: {}", iso_path.display(), digest);
+        Ok(digest)
+    })
+        .await
+        .map_err(|e| WriterError::from_io(io::Error::other(e)))?
+}
+
+// How long a file system call on the image may take before the share or
+// mount it is on is taken for dead
+const SOURCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Run `f`, which touches the image, off the async executor, so an image on a
+// slow network share can't stall it, and give up on one that stops responding.
+// The call itself stays stuck on its thread until the mount comes back.
+async fn on_source<T, F>(iso_path: &Path, f: F) -> Result<T, WriterError>
+where
+    F: FnOnce() -> Result<T, WriterError> + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::time::timeout(SOURCE_TIMEOUT, spawn_in_span(f)).await {
+        Ok(result) => result.map_err(|e| WriterError::from_io(io::Error::other(e)))?,
+        Err(_) => {
+            warn!("{} didn't respond within {} s", iso_path.display(), SOURCE_TIMEOUT.as_secs());
+            Err(WriterError::IoError {
+                kind: io::ErrorKind::TimedOut,
+                message: format!("image source unreachable: {} didn't respond within {} s", iso_path.display(), SOURCE_TIMEOUT.as_secs()),
+            })
+        }
+    }
+}
+
+// Writing would overwrite the image while it is still being read
+pub async fn check_source_not_on_device(iso_path: &Path, device_path: &str) -> Result<(), WriterError> {
+    let (path, device) = (iso_path.to_path_buf(), device_path.to_string());
+    if on_source(iso_path, move || Ok(crate::device::is_source_on_device(&path, &device))).await? {
+        return Err(WriterError::SourceIsTarget(device_path.to_string()));
+    }
+    Ok(())
+}
+
+// spawn_blocking doesn't carry the caller's span over to the pool thread, so
+// enter it again there to keep the write_iso fields on every event
+fn spawn_in_span<F, T>(f: F) -> task::JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let span = Span::current();
+    task::spawn_blocking(move || span.in_scope(f))
+}
+
+#[cfg(target_os = "linux")]
+fn sync_device(file: &mut File) -> Result<(), WriterError> {
+    use std::os::unix::io::AsRawFd;
+
+    // Define the BLKFLSBUF ioctl command (not provided by libc)
+    const BLKFLSBUF: libc::c_ulong = 0x1261;
+
+    let fd = file.as_raw_fd();
+
+    if unsafe { libc::fsync(fd) } == -1 {
+        let error = io::Error::last_os_error();
+        return Err(WriterError::IoError {
+            kind: error.kind(),
+            message: format!("fsync failed: {}", error),
+        });
+    }
+
+    if unsafe { libc::ioctl(fd, BLKFLSBUF, 0) } == -1 {
+        // Not a block device (e.g. a regular image file), so there's no buffer cache to drop
+        let error = io::Error::last_os_error();
+        if !matches!(error.raw_os_error(), Some(libc::ENOTTY) | Some(libc::EINVAL)) {
+            warn!("Failed to flush device buffers: {}", error);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sync_device(file: &mut File) -> Result<(), WriterError> {
+    file.flush().map_err(WriterError::from_io)
+}
+
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(filled)
+}
+
+#[cfg(target_os = "linux")]
+fn get_logical_block_size(file: &File) -> Result<usize, io::Error> {
+    use std::os::unix::io::AsRawFd;
+
+    // Define the BLKSSZGET ioctl command (not provided by libc)
+    const BLKSSZGET: libc::c_ulong = 0x1268;
+
+    unsafe {
+        let mut size: libc::c_int = 0;
+        let result = libc::ioctl(file.as_raw_fd(), BLKSSZGET, &mut size);
+        if result == -1 || size <= 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(size as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn test_config(buffer_size: usize, total_size: Option<u64>) -> CopyConfig {
+        CopyConfig {
+            buffer_size,
+            io_mode: IoMode::Buffered,
+            alignment: 1,
+            total_size,
+            max_retries: DEFAULT_MAX_RETRIES,
+            max_speed_mbps: None,
+            checkpoint_bytes: None,
+            progress_trigger: DEFAULT_PROGRESS_TRIGGER,
+            max_buffer_size: None,
+            inline_verify: false,
+            resume_skip: 0,
+        }
+    }
+
+    // Fails the first `failures` writes with a timeout, then accepts everything
+    struct FlakyWriter {
+        failures: u32,
+        data: Vec<u8>,
+    }
+
+    impl Write for FlakyWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "device timed out"));
+            }
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_copy_with_progress() {
+        let test_data = b"Hello, World! This is test data for USB writing.";
+        let mut reader = Cursor::new(test_data);
+        let mut writer = Vec::new();
+
+        // Reading is never behind what has been written
+        let callback = Arc::new(|progress: WriteProgress| {
+            println!("Progress: {:.1}%", progress.progress_percent.unwrap_or(0.0));
+            let pipeline = progress.pipeline.unwrap();
+            assert!(pipeline.bytes_read >= progress.bytes_written);
+            assert!(pipeline.buffers_filled <= pipeline.depth);
+        });
+
+        let result = UsbWriter::copy_with_progress(
+            &mut reader,
+            &mut writer,
+            test_config(16, Some(test_data.len() as u64)),
+            &CancelToken::new(),
+            &PauseToken::new(),
+            callback
+        );
+
+        let (bytes_written, digest) = result.unwrap();
+        assert_eq!(bytes_written, test_data.len() as u64);
+        assert_eq!(digest, format!("{:x}", Sha256::digest(test_data)));
+        assert_eq!(writer, test_data);
+    }
+
+    #[test]
+    fn test_copy_pads_unaligned_tail() {
+        let test_data = [7u8; 50];
+        let mut writer = Vec::new();
+
+        let result = UsbWriter::copy_with_progress(
+            Cursor::new(test_data),
+            &mut writer,
+            CopyConfig { alignment: 16, ..test_config(32, Some(test_data.len() as u64)) },
+            &CancelToken::new(),
+            &PauseToken::new(),
+            Arc::new(|_| {})
+        );
+
+        // Every write is a whole number of blocks, but only the real bytes count
+        let (bytes_written, digest) = result.unwrap();
+        assert_eq!(bytes_written, test_data.len() as u64);
+        assert_eq!(digest, format!("{:x}", Sha256::digest(test_data)));
+        assert_eq!(writer.len(), 64);
+        assert!(writer[test_data.len()..].iter().all(|&byte| byte == 0));
+        assert_eq!(
+            compare_streams(&test_data[..], &writer[..], bytes_written, &CancelToken::new(), |_| {}).unwrap(),
+            VerifyOutcome::Match
+        );
+    }
+
+    #[test]
+    fn test_copy_flushes_at_checkpoints() {
+        // Remembers how much had been written at every flush
+        struct CheckpointLog {
+            data: Vec<u8>,
+            flushed_at: Vec<usize>,
+        }
+
+        impl Write for CheckpointLog {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.data.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.flushed_at.push(self.data.len());
+                Ok(())
+            }
+        }
+
+        let test_data = [3u8; 100];
+        let mut writer = CheckpointLog { data: Vec::new(), flushed_at: Vec::new() };
+
+        let result = UsbWriter::copy_with_progress(
+            Cursor::new(test_data),
+            &mut writer,
+            CopyConfig { checkpoint_bytes: Some(32), ..test_config(16, Some(test_data.len() as u64)) },
+            &CancelToken::new(),
+            &PauseToken::new(),
+            Arc::new(|_| {})
+        );
+
+        assert_eq!(result.unwrap().0, test_data.len() as u64);
+        assert_eq!(writer.flushed_at, vec![32, 64, 96, 100]);
+    }
+
+    #[test]
+    fn test_copy_reports_start_and_end_of_tiny_transfer() {
+        let test_data = [5u8; 10];
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let callback_reports = reports.clone();
+
+        // Far too short for the interval to ever pass
+        let result = UsbWriter::copy_with_progress(
+            Cursor::new(test_data),
+            io::sink(),
+            CopyConfig { progress_trigger: ProgressTrigger::EveryDuration(Duration::from_secs(3600)), ..test_config(16, Some(test_data.len() as u64)) },
+            &CancelToken::new(),
+            &PauseToken::new(),
+            Arc::new(move |progress: WriteProgress| callback_reports.lock().unwrap().push(progress.progress_percent))
+        );
 
         assert!(result.is_ok());
-        assert_eq!(writer, test_data);
+        assert_eq!(*reports.lock().unwrap(), vec![Some(0.0), Some(100.0)]);
+    }
+
+    #[test]
+    fn test_copy_reports_every_percent() {
+        let test_data = [5u8; 64];
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let callback_reports = reports.clone();
+
+        // Each 16 byte chunk is a quarter of the transfer
+        let result = UsbWriter::copy_with_progress(
+            Cursor::new(test_data),
+            io::sink(),
+            CopyConfig { progress_trigger: ProgressTrigger::EveryPercent(25.0), ..test_config(16, Some(test_data.len() as u64)) },
+            &CancelToken::new(),
+            &PauseToken::new(),
+            Arc::new(move |progress: WriteProgress| callback_reports.lock().unwrap().push(progress.progress_percent))
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(*reports.lock().unwrap(), vec![Some(0.0), Some(25.0), Some(50.0), Some(75.0), Some(100.0)]);
+    }
+
+    #[test]
+    fn test_progress_trigger() {
+        let second = Duration::from_secs(1);
+        let total = Some(1000);
+
+        let time = ProgressTrigger::EveryDuration(second);
+        assert!(!time.is_due(Duration::from_millis(500), 0, 900, total));
+        assert!(time.is_due(Duration::from_millis(1500), 0, 1, total));
+
+        // Only crossing a multiple of 10% counts, however far the last report was
+        let percent = ProgressTrigger::EveryPercent(10.0);
+        assert!(!percent.is_due(Duration::from_secs(60), 150, 199, total));
+        assert!(percent.is_due(Duration::ZERO, 199, 200, total));
+        assert!(!percent.is_due(Duration::ZERO, 0, 1, None));
+        assert!(percent.is_due(Duration::from_secs(1), 0, 1, None));
+
+        let both = ProgressTrigger::Both(second, 10.0);
+        assert!(both.is_due(Duration::ZERO, 199, 200, total));
+        assert!(both.is_due(Duration::from_secs(2), 150, 199, total));
+        assert!(!both.is_due(Duration::ZERO, 150, 199, total));
+    }
+
+    #[test]
+    fn test_copy_empty_source() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let callback_reports = reports.clone();
+
+        let result = UsbWriter::copy_with_progress(
+            Cursor::new(Vec::new()),
+            io::sink(),
+            test_config(16, Some(0)),
+            &CancelToken::new(),
+            &PauseToken::new(),
+            Arc::new(move |progress: WriteProgress| callback_reports.lock().unwrap().push(progress))
+        );
+
+        assert_eq!(result.unwrap().0, 0);
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.first().unwrap().progress_percent, None);
+        assert_eq!(reports.last().unwrap().progress_percent, Some(100.0));
+        assert!(reports.iter().all(|progress| progress.progress_percent.is_none_or(f32::is_finite)));
+    }
+
+    #[test]
+    fn test_copy_retries_transient_write_errors() {
+        let test_data = b"Hello, World! This is test data for USB writing.";
+        let mut writer = FlakyWriter { failures: 2, data: Vec::new() };
+
+        let result = UsbWriter::copy_with_progress(
+            Cursor::new(test_data),
+            &mut writer,
+            test_config(16, Some(test_data.len() as u64)),
+            &CancelToken::new(),
+            &PauseToken::new(),
+            Arc::new(|_| {})
+        );
+
+        assert_eq!(result.unwrap().0, test_data.len() as u64);
+        assert_eq!(writer.data, test_data);
+    }
+
+    #[test]
+    fn test_copy_write_error_stops_reader() {
+        struct BrokenWriter;
+
+        impl Write for BrokenWriter {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("device gone"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // Far more data than the pipeline holds, so the reader is blocked on
+        // the channel when the writer gives up
+        let result = UsbWriter::copy_with_progress(
+            io::repeat(0).take(1024 * 1024),
+            BrokenWriter,
+            test_config(16, None),
+            &CancelToken::new(),
+            &PauseToken::new(),
+            Arc::new(|_| {})
+        );
+
+        assert!(matches!(result, Err(WriterError::IoError { .. })));
+    }
+
+    #[test]
+    fn test_copy_reports_where_device_filled_up() {
+        // Accepts 40 bytes, then fails like a stick with less real capacity
+        struct TinyDevice(Vec<u8>);
+
+        impl Write for TinyDevice {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let room = 40 - self.0.len();
+                if room == 0 {
+                    return Err(io::Error::from(io::ErrorKind::StorageFull));
+                }
+                let n = room.min(buf.len());
+                self.0.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let result = UsbWriter::copy_with_progress(
+            Cursor::new([1u8; 64]),
+            TinyDevice(Vec::new()),
+            test_config(16, Some(64)),
+            &CancelToken::new(),
+            &PauseToken::new(),
+            Arc::new(|_| {})
+        );
+
+        assert!(matches!(result, Err(WriterError::InsufficientSpace(Some(40)))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_reports_unplugged_device() {
+        // Takes the first chunk, then vanishes the way a pulled stick does
+        struct UnpluggedDevice(bool);
+
+        impl Write for UnpluggedDevice {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if std::mem::replace(&mut self.0, true) {
+                    return Err(io::Error::from_raw_os_error(libc::ENODEV));
+                }
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let result = UsbWriter::copy_with_progress(
+            Cursor::new([1u8; 64]),
+            UnpluggedDevice(false),
+            test_config(16, Some(64)),
+            &CancelToken::new(),
+            &PauseToken::new(),
+            Arc::new(|_| {})
+        );
+
+        match result {
+            Err(error @ WriterError::DeviceDisconnected { offset: Some(16), .. }) => {
+                assert_eq!(error.exit_code(), 16);
+            }
+            other => panic!("expected a disconnect, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_copy_with_progress_cancelled() {
+        let test_data = b"Hello, World! This is test data for USB writing.";
+        let mut reader = Cursor::new(test_data);
+        let mut writer = Vec::new();
+
+        let cancel_token = CancelToken::new();
+        cancel_token.cancel();
+
+        let result = UsbWriter::copy_with_progress(
+            &mut reader,
+            &mut writer,
+            test_config(16, Some(test_data.len() as u64)),
+            &cancel_token,
+            &PauseToken::new(),
+            Arc::new(|_| {})
+        );
+
+        assert!(matches!(result, Err(WriterError::Cancelled)));
+        assert!(writer.is_empty());
+    }
+
+    #[test]
+    fn test_copy_paused_time_excluded_from_elapsed() {
+        let test_data = b"Hello, World! This is test data for USB writing.";
+        let pause_token = PauseToken::new();
+        pause_token.pause();
+
+        let resumer = pause_token.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            resumer.resume();
+        });
+
+        let last_progress = Arc::new(Mutex::new(None));
+        let progress_slot = last_progress.clone();
+        let result = UsbWriter::copy_with_progress(
+            Cursor::new(test_data),
+            Vec::new(),
+            test_config(16, Some(test_data.len() as u64)),
+            &CancelToken::new(),
+            &pause_token,
+            Arc::new(move |progress: WriteProgress| {
+                *progress_slot.lock().unwrap() = Some(progress);
+            })
+        );
+        handle.join().unwrap();
+
+        assert_eq!(result.unwrap().0, test_data.len() as u64);
+        let elapsed = last_progress.lock().unwrap().as_ref().unwrap().elapsed_seconds;
+        assert!(elapsed < 0.2, "paused time counted as active: {}", elapsed);
+    }
+
+    #[test]
+    fn test_copy_truncated_gzip_is_invalid_format() {
+        use flate2::write::GzEncoder;
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&[7u8; 64 * 1024]).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let truncated = &compressed[..compressed.len() / 2];
+
+        let reader = DecoderReader(flate2::read::MultiGzDecoder::new(truncated));
+        let result = UsbWriter::copy_with_progress(
+            reader,
+            Vec::new(),
+            test_config(16, None),
+            &CancelToken::new(),
+            &PauseToken::new(),
+            Arc::new(|_| {})
+        );
+
+        assert!(matches!(result, Err(WriterError::InvalidIsoFormat)));
+    }
+
+    #[test]
+    fn test_has_image_signature() {
+        let mut iso = vec![0u8; 0x8800];
+        iso[0x8001..0x8006].copy_from_slice(b"CD001");
+        assert!(has_image_signature(&iso));
+
+        let mut disk_image = vec![0u8; 512];
+        disk_image[510..].copy_from_slice(&[0x55, 0xAA]);
+        assert!(has_image_signature(&disk_image));
+
+        let mut zip = b"PK\x03\x04".to_vec();
+        zip.resize(0x8800, 0);
+        assert!(!has_image_signature(&zip));
+        assert!(!has_image_signature(b"too short"));
+    }
+
+    #[test]
+    fn test_image_containers() {
+        let dir = std::env::temp_dir().join(format!("schrijver-containers-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let payload: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        let vhd = |disk_type: u32| {
+            let mut footer = vec![0u8; 512];
+            footer[..8].copy_from_slice(VHD_COOKIE);
+            footer[VHD_DISK_TYPE_OFFSET..VHD_DISK_TYPE_OFFSET + 4].copy_from_slice(&disk_type.to_be_bytes());
+            [payload.clone(), footer].concat()
+        };
+
+        // A fixed VHD is written without its footer
+        let fixed = dir.join("fixed.vhd");
+        std::fs::write(&fixed, vhd(VHD_DISK_TYPE_FIXED)).unwrap();
+        let (mut reader, size) = image_reader(&fixed).unwrap();
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!((size, data), (Some(4096), payload.clone()));
+        assert_eq!(image_size(&fixed).unwrap(), 4096);
+
+        let dynamic = dir.join("dynamic.vhd");
+        std::fs::write(&dynamic, vhd(3)).unwrap();
+        assert!(matches!(image_reader(&dynamic), Err(WriterError::UnsupportedImageFormat(_))));
+
+        let vhdx = dir.join("disk.vhdx");
+        std::fs::write(&vhdx, [VHDX_SIGNATURE, &payload].concat()).unwrap();
+        assert!(matches!(image_reader(&vhdx), Err(WriterError::UnsupportedImageFormat(_))));
+
+        // A raw .dmg has no trailer and is written as is
+        let dmg = dir.join("raw.dmg");
+        std::fs::write(&dmg, &payload).unwrap();
+        assert_eq!(image_size(&dmg).unwrap(), 4096);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_image_extensions() {
+        assert!(has_image_extension(Path::new("raspios.img.xz")));
+        assert!(has_image_extension(Path::new("disk.raw")));
+        assert!(is_raw_image(Path::new("firmware.BIN")));
+        assert!(!is_raw_image(Path::new("debian.iso.gz")));
+        assert!(is_clearly_not_image(Path::new("notes.txt")));
+        assert!(is_clearly_not_image(Path::new("images.zip")));
+        assert!(!is_clearly_not_image(Path::new("debian.iso")));
+        assert!(!is_clearly_not_image(Path::new("image")));
+    }
+
+    #[test]
+    fn test_at_offset_lines_up_image_and_device() {
+        let image: Vec<u8> = (0..200u8).collect();
+        let mut device = vec![0xAAu8; 1024];
+        device[512..712].copy_from_slice(&image);
+
+        // Offsets are relative to the start of the image on the device
+        let mut at_offset = AtOffset::new(Cursor::new(device), 512).unwrap();
+        assert!(compare_ranges(&mut Cursor::new(&image), &mut at_offset, &[(0, 64), (136, 64)]).is_ok());
+        assert_eq!(at_offset.seek(SeekFrom::Start(10)).unwrap(), 10);
+
+        assert!(matches!(validate_start_offset(1000), Err(WriterError::InvalidStartOffset(1000))));
+        assert_eq!(validate_start_offset(1024 * 1024).unwrap(), 1024 * 1024);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_verify_only_reports_first_difference() {
+        let dir = std::env::temp_dir().join(format!("schrijver-verify-only-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let image: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        let mut device = image.clone();
+        device.extend_from_slice(&[0u8; 4096]);
+        std::fs::write(dir.join("image.img"), &image).unwrap();
+        std::fs::write(dir.join("device"), &device).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let verify = || {
+            let (sender, _receiver) = mpsc::unbounded_channel();
+            let device_path = dir.join("device").to_string_lossy().to_string();
+            runtime.block_on(verify_only(&dir.join("image.img"), &device_path, 0, sender))
+        };
+
+        assert_eq!(verify().unwrap(), VerifyOutcome::Match);
+        device[70_000] ^= 0xFF;
+        std::fs::write(dir.join("device"), &device).unwrap();
+        assert_eq!(verify().unwrap(), VerifyOutcome::Mismatch { offset: 70_000 });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_repair_region_only_for_seekable_images() {
+        let dir = std::env::temp_dir().join(format!("schrijver-repair-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let image: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        let mut device = image.clone();
+        device[70_000] ^= 0xFF;
+        std::fs::write(dir.join("image.img"), &image).unwrap();
+        std::fs::write(dir.join("image.img.xz"), &image).unwrap();
+        let mut footer = vec![0u8; VHD_FOOTER_SIZE as usize];
+        footer[..8].copy_from_slice(VHD_COOKIE);
+        footer[VHD_DISK_TYPE_OFFSET..VHD_DISK_TYPE_OFFSET + 4].copy_from_slice(&VHD_DISK_TYPE_FIXED.to_be_bytes());
+        std::fs::write(dir.join("image.vhd"), [image.clone(), footer].concat()).unwrap();
+        std::fs::write(dir.join("device"), &device).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let device_path = dir.join("device").to_string_lossy().to_string();
+        let repair = |image| runtime.block_on(repair_device_region(&dir.join(image), &device_path, 0, 70_000));
+
+        // Refused by its extension, before anything is read or written
+        assert!(matches!(repair("image.img.xz"), Err(WriterError::UnsupportedImageFormat(_))));
+        assert_eq!(std::fs::read(dir.join("device")).unwrap(), device);
+
+        repair("image.img").unwrap();
+        assert_eq!(std::fs::read(dir.join("device")).unwrap(), image);
+
+        // The last block of a fixed VHD ends where its payload does, so the
+        // footer isn't copied over whatever follows on the device
+        let trailing = vec![0xEE; VHD_FOOTER_SIZE as usize];
+        std::fs::write(dir.join("device"), [device.clone(), trailing.clone()].concat()).unwrap();
+        repair("image.vhd").unwrap();
+        assert_eq!(std::fs::read(dir.join("device")).unwrap(), [image.clone(), trailing].concat());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_hash_image_file() {
+        let path = std::env::temp_dir().join(format!("schrijver-hash-{}.iso", std::process::id()));
+        let image: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &image).unwrap();
+        let size = image.len() as u64;
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let reported = Arc::new(AtomicU64::new(0));
+        let on_progress = {
+            let reported = reported.clone();
+            move |bytes_hashed, total| {
+                assert_eq!(total, size);
+                reported.store(bytes_hashed, Ordering::Relaxed);
+            }
+        };
+        let digest = runtime.block_on(hash_image_file(path.clone(), CancelToken::new(), on_progress)).unwrap();
+        assert_eq!(digest, format!("{:x}", Sha256::digest(&image)));
+        assert_eq!(reported.load(Ordering::Relaxed), size);
+
+        let cancel_token = CancelToken::new();
+        cancel_token.cancel();
+        let result = runtime.block_on(hash_image_file(path.clone(), cancel_token, |_, _| {}));
+        assert!(matches!(result, Err(WriterError::Cancelled)));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_verify_from_block_hashes() {
+        let dir = std::env::temp_dir().join(format!("schrijver-block-hashes-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let image: Vec<u8> = (0..150_000u32).map(|i| (i % 251) as u8).collect();
+        let image_path = dir.join("image.img");
+        std::fs::write(&image_path, &image).unwrap();
+        let device_path = dir.join("device");
+        std::fs::write(&device_path, vec![0u8; 256 * 1024]).unwrap();
+
+        // A buffer that doesn't line up with the verification blocks
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let writer = UsbWriter::new(image_path.to_string_lossy().to_string(), device_path.to_string_lossy().to_string(), IoMode::Buffered)
+            .with_buffer_size(12288)
+            .unwrap()
+            .with_block_hashes(true);
+        let (bytes_written, digest) = runtime.block_on(writer.write_iso_with_progress(|_| {})).unwrap();
+
+        // The image isn't needed to verify
+        std::fs::remove_file(&image_path).unwrap();
+        let verify = || runtime.block_on(writer.verify(bytes_written, digest.clone(), Arc::new(|_| {})));
+        assert_eq!(verify().unwrap(), VerifyMode::Full);
+
+        let mut device = std::fs::read(&device_path).unwrap();
+        device[70_000] ^= 0xFF;
+        std::fs::write(&device_path, &device).unwrap();
+        assert!(matches!(verify(), Err(WriterError::VerificationFailed { offset: 65536 })));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_inline_verify_catches_bad_block_while_writing() {
+        // Flips one byte on its way to the device, like a failing cell would
+        struct CorruptingDevice {
+            file: File,
+            position: u64,
+            bad_byte: u64,
+        }
+
+        impl Write for CorruptingDevice {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let mut data = buf.to_vec();
+                if (self.position..self.position + buf.len() as u64).contains(&self.bad_byte) {
+                    data[(self.bad_byte - self.position) as usize] ^= 0xFF;
+                }
+                self.file.write_all(&data)?;
+                self.position += buf.len() as u64;
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let device_path = std::env::temp_dir().join(format!("schrijver-inline-{}", std::process::id()));
+        let copy = |bad_byte| {
+            let file = File::create(&device_path).unwrap();
+            let device = CorruptingDevice { file, position: 0, bad_byte };
+            let reader = File::open(&device_path).unwrap();
+            UsbWriter::copy_with_progress(
+                Cursor::new([7u8; 64]),
+                InlineVerifier::new(device, reader, 0),
+                test_config(16, Some(64)),
+                &CancelToken::new(),
+                &PauseToken::new(),
+                Arc::new(|_| {})
+            )
+        };
+
+        assert!(copy(u64::MAX).is_ok());
+        // Caught in the second chunk, before the rest is written
+        assert!(matches!(copy(21), Err(WriterError::VerificationFailed { offset: 21 })));
+        assert_eq!(std::fs::metadata(&device_path).unwrap().len(), 32);
+
+        std::fs::remove_file(&device_path).unwrap();
+    }
+
+    #[test]
+    fn test_resume_skips_what_is_already_on_the_device() {
+        let image: Vec<u8> = (0..64).collect();
+        // Written at offset 8, interrupted 24 bytes into the image. Whatever
+        // is already there is left alone, made visible here as 0xAA.
+        let mut device = Cursor::new(vec![0xAA; 80]);
+        device.set_position(8);
+
+        let (bytes_written, digest) = UsbWriter::copy_with_progress(
+            Cursor::new(image.clone()),
+            ResumeWriter::new(&mut device, 8, 24, None),
+            test_config(16, Some(64)),
+            &CancelToken::new(),
+            &PauseToken::new(),
+            Arc::new(|_| {})
+        ).unwrap();
+        assert_eq!(bytes_written, 64);
+        assert_eq!(digest, format!("{:x}", Sha256::digest(&image)));
+
+        // The chunk the interruption fell in is written again from its start
+        let device = device.into_inner();
+        assert!(device[..24].iter().all(|&byte| byte == 0xAA));
+        assert_eq!(&device[24..72], &image[16..]);
+        assert!(device[72..].iter().all(|&byte| byte == 0xAA));
+    }
+
+    #[test]
+    fn test_write_in_each_io_mode() {
+        let dir = std::env::temp_dir().join(format!("schrijver-io-mode-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let image: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let image_path = dir.join("image.img");
+        std::fs::write(&image_path, &image).unwrap();
+
+        // Direct I/O falls back to buffered writes where the filesystem refuses it
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        for io_mode in IoMode::ALL {
+            let device_path = dir.join(format!("device-{:?}", io_mode));
+            std::fs::write(&device_path, vec![0u8; 128 * 1024]).unwrap();
+            let writer = UsbWriter::new(image_path.to_string_lossy().to_string(), device_path.to_string_lossy().to_string(), io_mode);
+            let (bytes_written, digest) = runtime.block_on(writer.write_iso_with_progress(|_| {})).unwrap();
+            assert_eq!(runtime.block_on(writer.verify(bytes_written, digest, Arc::new(|_| {}))).unwrap(), VerifyMode::Full);
+            assert_eq!(&std::fs::read(&device_path).unwrap()[..image.len()], &image[..]);
+        }
+
+        assert_eq!("sync".parse::<IoMode>(), Ok(IoMode::Sync));
+        assert!("raw".parse::<IoMode>().is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_device_lock_is_exclusive() {
+        let path = std::env::temp_dir().join(format!("schrijver-lock-{}", std::process::id()));
+        std::fs::write(&path, [0u8; 512]).unwrap();
+        let device_path = path.to_string_lossy().to_string();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let acquire = || runtime.block_on(DeviceLock::acquire(&device_path, None));
+
+        let lock = acquire().unwrap();
+        assert!(matches!(acquire(), Err(WriterError::DeviceBusy(_))));
+        drop(lock);
+        assert!(acquire().is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_retry_while_busy() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let retry = |timeout, failures: Vec<WriterError>| {
+            let mut failures = failures.into_iter();
+            let mut retries = 0;
+            let result = runtime.block_on(retry_while_busy(timeout, |_| retries += 1, || {
+                let next = failures.next();
+                async move { next.map_or(Ok(()), Err) }
+            }));
+            (result, retries)
+        };
+        let busy = || WriterError::DeviceBusy("/dev/sdz".to_string());
+
+        // Busy for a moment, then free
+        assert!(matches!(retry(Duration::from_secs(2), vec![busy(), busy()]), (Ok(()), 2)));
+        // Busy for longer than the timeout
+        assert!(matches!(retry(Duration::from_millis(300), vec![busy(), busy(), busy()]), (Err(WriterError::DeviceBusy(_)), 1)));
+        // Other errors aren't retried
+        assert!(matches!(retry(Duration::from_secs(2), vec![WriterError::PermissionDenied, busy()]), (Err(WriterError::PermissionDenied), 0)));
+        assert!(matches!(retry(Duration::ZERO, vec![busy()]), (Err(WriterError::DeviceBusy(_)), 0)));
+    }
+
+    #[test]
+    fn test_write_from_image_in_memory() {
+        let dir = std::env::temp_dir().join(format!("schrijver-in-memory-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let image: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&image).unwrap();
+        let image_path = dir.join("image.img.gz");
+        std::fs::write(&image_path, encoder.finish().unwrap()).unwrap();
+        let device_path = dir.join("device");
+        std::fs::write(&device_path, vec![0u8; 256 * 1024]).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        assert!(runtime.block_on(load_image_into_memory(&image_path, 100_000)).unwrap().is_none());
+        let loaded = runtime.block_on(load_image_into_memory(&image_path, image.len() as u64)).unwrap().unwrap();
+        assert_eq!(loaded.size(), image.len() as u64);
+
+        // Compressed on disk, but the copy in memory can be compared byte for byte
+        let writer = UsbWriter::new(image_path.to_string_lossy().to_string(), device_path.to_string_lossy().to_string(), IoMode::Buffered)
+            .with_image(Some(loaded));
+        let (bytes_written, digest) = runtime.block_on(writer.write_iso_with_progress(|_| {})).unwrap();
+        assert_eq!(bytes_written, image.len() as u64);
+        let verify_mode = runtime.block_on(writer.verify(bytes_written, digest, Arc::new(|_| {}))).unwrap();
+        assert_eq!(verify_mode, VerifyMode::Full);
+        assert_eq!(&std::fs::read(&device_path).unwrap()[..image.len()], &image[..]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_buffer_tuner_grows_while_faster() {
+        let start = Instant::now();
+        let at = |seconds: u64| start + Duration::from_secs(seconds);
+        let mb = |mb: u64| mb * 1024 * 1024;
+        let mut tuner = BufferTuner::new(1024 * 1024, 8 * 1024 * 1024, start);
+
+        // Not measured long enough yet
+        assert_eq!(tuner.update(start + Duration::from_millis(500), mb(5)), None);
+        // 10 MB/s at 1 MB, then 20 MB/s at 2 MB: both call for a bigger buffer
+        assert_eq!(tuner.update(at(1), mb(10)), Some(2 * 1024 * 1024));
+        assert_eq!(tuner.update(at(2), mb(30)), Some(4 * 1024 * 1024));
+        // 21 MB/s at 4 MB is no clear gain, so the size stays put for good
+        assert_eq!(tuner.update(at(3), mb(51)), None);
+        assert_eq!(tuner.update(at(4), mb(151)), None);
+        assert_eq!(tuner.size, 4 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_auto_buffer_size() {
+        let sizes = |minimum, optimal| Some(IoSizes { minimum, optimal });
+
+        assert_eq!(auto_buffer_size(None), 4 * 1024 * 1024);
+        assert_eq!(auto_buffer_size(sizes(0, 0)), 4 * 1024 * 1024);
+        assert_eq!(auto_buffer_size(sizes(512, 0)), 4 * 1024 * 1024);
+        // 3 MB doesn't divide 4 MB, so two of them
+        assert_eq!(auto_buffer_size(sizes(4096, 3 * 1024 * 1024)), 6 * 1024 * 1024);
+        assert_eq!(auto_buffer_size(sizes(4096, 8 * 1024 * 1024)), 8 * 1024 * 1024);
+        assert_eq!(auto_buffer_size(sizes(0, 1000)) % 512, 0);
+        assert_eq!(adaptive_buffer_bounds(None), (1024 * 1024, 16 * 1024 * 1024));
+        assert_eq!(adaptive_buffer_bounds(sizes(4096, 3 * 1024 * 1024)), (3 * 1024 * 1024, 24 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_gpt_declared_size() {
+        let mut header = vec![0u8; 1024];
+        assert_eq!(gpt_declared_size(&header), None);
+
+        header[512..520].copy_from_slice(b"EFI PART");
+        header[544..552].copy_from_slice(&8191u64.to_le_bytes()); // backup LBA
+        header[560..568].copy_from_slice(&8158u64.to_le_bytes()); // last usable LBA
+        assert_eq!(gpt_declared_size(&header), Some(8192 * 512));
+
+        // Truncated header
+        assert_eq!(gpt_declared_size(&header[..540]), None);
+    }
+
+    #[test]
+    fn test_ignore_size_check() {
+        let path = std::env::temp_dir().join(format!("schrijver-size-check-{}.img", std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+
+        assert!(check_device_fits(&path, 4096, 4096, false).is_ok());
+        assert!(matches!(check_device_fits(&path, 4096, 1024, false), Err(WriterError::InsufficientSpace(None))));
+        assert!(check_device_fits(&path, 4096, 1024, true).is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_capacity_pattern_depends_on_offset() {
+        let mut first = vec![0u8; 64];
+        let mut second = vec![0u8; 64];
+        fill_capacity_pattern(0, &mut first);
+        fill_capacity_pattern(1024 * 1024, &mut second);
+        assert_ne!(first, second);
+
+        // A chunk starting mid-way matches the same bytes of a bigger chunk
+        let mut tail = vec![0u8; 20];
+        fill_capacity_pattern(40, &mut tail);
+        assert_eq!(tail, first[40..60]);
+    }
+
+    #[test]
+    fn test_quick_verify() {
+        let length = 16 * 1024 * 1024;
+        let ranges = quick_verify_ranges(length);
+
+        assert_eq!(ranges, quick_verify_ranges(length));
+        assert_eq!(ranges.len(), 2 + QUICK_VERIFY_SAMPLES);
+        assert!(ranges.iter().all(|&(offset, len)| offset + len <= length));
+
+        let source: Vec<u8> = (0..length).map(|i| (i % 251) as u8).collect();
+        let mut device = source.clone();
+        assert!(compare_ranges(&mut Cursor::new(&source), &mut Cursor::new(&device), &ranges).is_ok());
+
+        device[length as usize - 1] ^= 0xFF;
+        let result = compare_ranges(&mut Cursor::new(&source), &mut Cursor::new(&device), &ranges);
+        assert!(matches!(result, Err(WriterError::VerificationFailed { offset }) if offset == length - 1));
+
+        // Images smaller than the edge regions are checked in one piece
+        assert_eq!(quick_verify_ranges(1000), vec![(0, 1000)]);
+    }
+
+    #[test]
+    fn test_compare_streams_reports_exact_offset() {
+        let source = vec![7u8; 3 * VERIFY_BUFFER_SIZE];
+        let mut device = source.clone();
+        let length = source.len() as u64;
+
+        assert_eq!(compare_streams(&source[..], &device[..], length, &CancelToken::new(), |_| {}).unwrap(), VerifyOutcome::Match);
+
+        // Past the first verification buffer, so the buffer offset must be added
+        let bad = VERIFY_BUFFER_SIZE + 1234;
+        device[bad] = 0;
+        assert_eq!(
+            compare_streams(&source[..], &device[..], length, &CancelToken::new(), |_| {}).unwrap(),
+            VerifyOutcome::Mismatch { offset: bad as u64 }
+        );
+        assert_eq!(repair_range(bad as u64, length), (VERIFY_BUFFER_SIZE as u64, VERIFY_BUFFER_SIZE as u64));
+
+        // A device that comes up short fails where its data ends
+        assert_eq!(
+            compare_streams(&source[..], &source[..1000], length, &CancelToken::new(), |_| {}).unwrap(),
+            VerifyOutcome::Mismatch { offset: 1000 }
+        );
+    }
+
+    #[test]
+    fn test_compare_streams_stops_when_cancelled() {
+        let source = vec![7u8; 3 * VERIFY_BUFFER_SIZE];
+        let cancel_token = CancelToken::new();
+        let mut reported = 0;
+
+        // Cancel once the first buffer has been compared
+        let result = compare_streams(&source[..], &source[..], source.len() as u64, &cancel_token, |bytes| {
+            reported = bytes;
+            cancel_token.cancel();
+        });
+
+        assert!(matches!(result, Err(WriterError::Cancelled)));
+        assert_eq!(reported, VERIFY_BUFFER_SIZE as u64);
+    }
+
+    #[test]
+    fn test_smoothed_speed_follows_rate_change() {
+        const MB: u64 = 1024 * 1024;
+        let start = Instant::now();
+        let mut speed = SmoothedSpeed::new(start, 0);
+        let at = |seconds: f64| start + Duration::from_secs_f64(seconds);
+
+        // 20 MB/s until the stick's cache fills, then 5 MB/s
+        assert_eq!(speed.record(at(0.5), 10 * MB), Some(20.0 * MB as f64));
+        let mut bytes = 10 * MB;
+        for step in 1..=4 {
+            bytes += 10 * MB;
+            speed.record(at(0.5 + step as f64 * 0.5), bytes);
+        }
+        let mut rates = Vec::new();
+        for step in 1..=20 {
+            bytes += MB / 2;
+            rates.push(speed.record(at(2.5 + step as f64 * 0.1), bytes).unwrap() / MB as f64);
+        }
+
+        // Falls steadily instead of jumping, and is close to the new rate
+        // within a few seconds, unlike an average since the start
+        assert!(rates.windows(2).all(|pair| pair[1] < pair[0]));
+        assert!(rates[0] > 15.0, "dropped too fast: {}", rates[0]);
+        assert!(rates[19] < 12.0, "didn't follow: {}", rates[19]);
+        let average = bytes as f64 / MB as f64 / 4.5;
+        assert!(rates[19] < average);
+    }
+
+    #[test]
+    fn test_speed_window_eta() {
+        let start = Instant::now();
+        let mut window = SpeedWindow::new();
+
+        window.record(start, 0);
+        assert_eq!(window.eta_seconds(0, Some(1000)), None);
+
+        // A slow start followed by 100 bytes/s; the early samples fall out of the window
+        window.record(start + Duration::from_millis(1000), 10);
+        window.record(start + Duration::from_millis(2000), 110);
+        window.record(start + Duration::from_millis(2500), 160);
+
+        let eta = window.eta_seconds(160, Some(1160)).unwrap();
+        assert!((eta - 10.0).abs() < 0.01, "unexpected eta {}", eta);
+        assert_eq!(window.eta_seconds(160, None), None);
+    }
+}
@@ -0,0 +1,102 @@
+// The whole write and verification, through the library's public API, with
+// a regular file standing in for the device
+use std::fs;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use schrijver::writer::{IoMode, VerifyMode, VerifyOutcome};
+use schrijver::{write_iso_to_device, CancelToken, PauseToken, UsbWriter, WriteOptions};
+use tokio::sync::mpsc;
+
+const IMAGE_SIZE: usize = 3 * 1024 * 1024 + 1000;
+const DEVICE_SIZE: u64 = 4 * 1024 * 1024;
+
+// An image and a device file in a directory of their own, removed afterwards
+struct Fixture {
+    dir: PathBuf,
+    iso_path: PathBuf,
+    device_path: PathBuf,
+    image: Vec<u8>,
+}
+
+impl Fixture {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("schrijver-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        // Random-looking content, with the ISO 9660 signature the write checks for
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut image: Vec<u8> = (0..IMAGE_SIZE)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        image[0x8001..0x8006].copy_from_slice(b"CD001");
+
+        let iso_path = dir.join("test.iso");
+        fs::write(&iso_path, &image).unwrap();
+        let device_path = dir.join("device.img");
+        fs::File::create(&device_path).unwrap().set_len(DEVICE_SIZE).unwrap();
+
+        Self { dir, iso_path, device_path, image }
+    }
+
+    fn device(&self) -> String {
+        self.device_path.to_string_lossy().to_string()
+    }
+
+    fn write(&self, options: WriteOptions) -> schrijver::WriteSummary {
+        let (progress_sender, _progress_receiver) = mpsc::unbounded_channel();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime
+            .block_on(write_iso_to_device(&self.iso_path, &self.device(), options, CancelToken::new(), PauseToken::new(), progress_sender))
+            .unwrap()
+    }
+
+    fn verify(&self) -> VerifyOutcome {
+        let writer = UsbWriter::new(self.iso_path.to_string_lossy().to_string(), self.device(), IoMode::Buffered);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(writer.verify_write(IMAGE_SIZE as u64, Arc::new(|_| {}))).unwrap()
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn test_write_and_verify_image() {
+    let fixture = Fixture::new("write-flow");
+
+    let summary = fixture.write(WriteOptions { verify_mode: VerifyMode::Full, ..WriteOptions::default() });
+    assert_eq!(summary.bytes_written, IMAGE_SIZE as u64);
+    assert_eq!(summary.verify_mode, VerifyMode::Full);
+    assert!(!summary.dry_run);
+
+    // The image at the start, the rest of the device as it was
+    let device = fs::read(&fixture.device_path).unwrap();
+    assert_eq!(device.len() as u64, DEVICE_SIZE);
+    assert_eq!(&device[..IMAGE_SIZE], &fixture.image[..]);
+    assert!(device[IMAGE_SIZE..].iter().all(|&byte| byte == 0));
+    assert_eq!(fixture.verify(), VerifyOutcome::Match);
+}
+
+#[test]
+fn test_verify_finds_corrupted_byte() {
+    let fixture = Fixture::new("write-flow-corrupt");
+    fixture.write(WriteOptions { verify_mode: VerifyMode::None, ..WriteOptions::default() });
+
+    let offset = 1_234_567;
+    let mut device = fs::OpenOptions::new().write(true).open(&fixture.device_path).unwrap();
+    device.seek(SeekFrom::Start(offset)).unwrap();
+    device.write_all(&[!fixture.image[offset as usize]]).unwrap();
+    drop(device);
+
+    assert_eq!(fixture.verify(), VerifyOutcome::Mismatch { offset });
+}